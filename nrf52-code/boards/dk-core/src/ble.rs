@@ -0,0 +1,506 @@
+//! Bluetooth Low Energy advertising packets
+//!
+//! An advertising channel PDU is a two byte header (PDU type, address
+//! types, length), the advertiser's address, then up to 31 bytes of
+//! advertising data, made of AD structures (length, type, data). The radio
+//! adds the preamble, the access address and the CRC, and does the whitening.
+//!
+//! | Field       | Length  | Notes                                          |
+//! |-------------|---------|------------------------------------------------|
+//! | Header      | 1       | PDU type in bits 0-3, TxAdd in bit 6           |
+//! | Length      | 1       | Length of the payload (address and data)       |
+//! | AdvA        | 6       | The advertiser's address, least significant byte first |
+//! | AdvData     | 0 to 31 | AD structures                                  |
+//!
+//! We handle the legacy advertising PDUs which carry advertising data.
+//!
+//! ```
+//! use dk_core::ble::{AdBuilder, AdStructure, Advertisement, ad_type, flags};
+//!
+//! let mut data = [0; 31];
+//! let mut builder = AdBuilder::new(&mut data);
+//! builder.flags(flags::BR_EDR_NOT_SUPPORTED).unwrap();
+//! builder.complete_local_name("dk").unwrap();
+//! let data = builder.as_bytes();
+//!
+//! let advertisement = Advertisement::non_connectable([0xc0, 1, 2, 3, 4, 5], data);
+//! let mut pdu = [0; 39];
+//! let len = advertisement.write(&mut pdu).unwrap();
+//! assert_eq!(&pdu[..len], b"\x42\x0d\x05\x04\x03\x02\x01\xc0\x02\x01\x04\x03\x09dk");
+//!
+//! let parsed = Advertisement::parse(&pdu[..len]).unwrap();
+//! let name = parsed.ad_structures().find(|ad| ad.ad_type == ad_type::COMPLETE_LOCAL_NAME);
+//! assert_eq!(name, Some(AdStructure { ad_type: ad_type::COMPLETE_LOCAL_NAME, data: b"dk" }));
+//! ```
+
+/// The access address of every advertising packet
+pub const ACCESS_ADDRESS: u32 = 0x8E89_BED6;
+
+/// The CRC initial value for advertising packets
+pub const CRC_INIT: u32 = 0x55_5555;
+
+/// The CRC polynomial, x²⁴ + x¹⁰ + x⁹ + x⁶ + x⁴ + x³ + x + 1 (without the x²⁴ term)
+pub const CRC_POLY: u32 = 0x00_065B;
+
+/// The most advertising data a legacy advertising PDU can carry, in bytes
+pub const MAX_DATA_LEN: usize = 31;
+
+/// The longest legacy advertising PDU, header included, in bytes
+pub const MAX_PDU_LEN: usize = 2 + 6 + MAX_DATA_LEN;
+
+/// Why a PDU or its advertising data could not be parsed or written
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The PDU ended before its header or address did
+    Truncated,
+    /// The length field doesn't match the PDU
+    BadLength,
+    /// The PDU type is one that carries no advertising data, or is reserved
+    UnsupportedPduType(u8),
+    /// There is more than `MAX_DATA_LEN` bytes of advertising data
+    DataTooLong,
+    /// The buffer is too small
+    BufferTooSmall,
+}
+
+/// The three advertising channels
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AdvChannel {
+    /// 2_402 MHz
+    _37 = 37,
+    /// 2_426 MHz
+    _38 = 38,
+    /// 2_480 MHz
+    _39 = 39,
+}
+
+impl AdvChannel {
+    /// All of them, in the order advertisers use them
+    pub const ALL: [AdvChannel; 3] = [AdvChannel::_37, AdvChannel::_38, AdvChannel::_39];
+
+    /// The frequency, in MHz above 2_400 MHz
+    pub const fn frequency_offset(self) -> u8 {
+        match self {
+            AdvChannel::_37 => 2,
+            AdvChannel::_38 => 26,
+            AdvChannel::_39 => 80,
+        }
+    }
+
+    /// The channel index, which also seeds the data whitening
+    pub const fn index(self) -> u8 {
+        self as u8
+    }
+}
+
+/// Advertising channel PDU types
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PduType {
+    /// Connectable and scannable
+    AdvInd = 0,
+    /// Neither connectable nor scannable: a beacon
+    AdvNonconnInd = 2,
+    /// A reply to a scan request
+    ScanRsp = 4,
+    /// Scannable, but not connectable
+    AdvScanInd = 6,
+}
+
+impl PduType {
+    fn from_bits(bits: u8) -> Result<PduType, Error> {
+        match bits {
+            0 => Ok(PduType::AdvInd),
+            2 => Ok(PduType::AdvNonconnInd),
+            4 => Ok(PduType::ScanRsp),
+            6 => Ok(PduType::AdvScanInd),
+            other => Err(Error::UnsupportedPduType(other)),
+        }
+    }
+}
+
+/// An advertising channel PDU which carries advertising data
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Advertisement<'a> {
+    /// What kind of advertisement it is
+    pub pdu_type: PduType,
+    /// The address is a random address, rather than a public one
+    pub random_address: bool,
+    /// The advertiser's address, most significant byte first, as you'd
+    /// write it down (`c0:01:02:03:04:05`)
+    pub address: [u8; 6],
+    /// The AD structures
+    pub data: &'a [u8],
+}
+
+impl<'a> Advertisement<'a> {
+    const TYPE_MASK: u8 = 0b1111;
+    const TX_ADD: u8 = 1 << 6;
+
+    /// A non-connectable advertisement from a random address
+    pub const fn non_connectable(address: [u8; 6], data: &'a [u8]) -> Self {
+        Self {
+            pdu_type: PduType::AdvNonconnInd,
+            random_address: true,
+            address,
+            data,
+        }
+    }
+
+    /// Parses a PDU, header included
+    pub fn parse(pdu: &'a [u8]) -> Result<Self, Error> {
+        let [header, len, rest @ ..] = pdu else {
+            return Err(Error::Truncated);
+        };
+        let pdu_type = PduType::from_bits(header & Self::TYPE_MASK)?;
+        let payload = rest.get(..usize::from(*len)).ok_or(Error::BadLength)?;
+        if payload.len() < 6 {
+            return Err(Error::Truncated);
+        }
+        let (address, data) = payload.split_at(6);
+        if data.len() > MAX_DATA_LEN {
+            return Err(Error::DataTooLong);
+        }
+        let mut msb_first = [0; 6];
+        for (to, from) in msb_first.iter_mut().zip(address.iter().rev()) {
+            *to = *from;
+        }
+        Ok(Self {
+            pdu_type,
+            random_address: header & Self::TX_ADD != 0,
+            address: msb_first,
+            data,
+        })
+    }
+
+    /// The length of the PDU, header included
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        2 + 6 + self.data.len()
+    }
+
+    /// Writes the PDU, header included, into `buffer`, returning its length
+    pub fn write(&self, buffer: &mut [u8]) -> Result<usize, Error> {
+        if self.data.len() > MAX_DATA_LEN {
+            return Err(Error::DataTooLong);
+        }
+        let len = self.len();
+        let pdu = buffer.get_mut(..len).ok_or(Error::BufferTooSmall)?;
+        let tx_add = if self.random_address { Self::TX_ADD } else { 0 };
+        pdu[0] = self.pdu_type as u8 | tx_add;
+        pdu[1] = (len - 2) as u8;
+        for (to, from) in pdu[2..8].iter_mut().zip(self.address.iter().rev()) {
+            *to = *from;
+        }
+        pdu[8..].copy_from_slice(self.data);
+        Ok(len)
+    }
+
+    /// The AD structures in the advertising data
+    pub fn ad_structures(&self) -> AdStructures<'a> {
+        AdStructures { data: self.data }
+    }
+}
+
+/// A static random device address, made from a unique device ID
+///
+/// The top two bits of a static address are always set.
+pub const fn static_random_address(device_id: u64) -> [u8; 6] {
+    let bytes = device_id.to_be_bytes();
+    [
+        bytes[2] | 0xc0,
+        bytes[3],
+        bytes[4],
+        bytes[5],
+        bytes[6],
+        bytes[7],
+    ]
+}
+
+/// AD types we know about
+pub mod ad_type {
+    /// Flags; see [`flags`](super::flags)
+    pub const FLAGS: u8 = 0x01;
+    /// The start of the device's name
+    pub const SHORTENED_LOCAL_NAME: u8 = 0x08;
+    /// The device's name
+    pub const COMPLETE_LOCAL_NAME: u8 = 0x09;
+    /// Manufacturer specific data, starting with a (little-endian) company ID
+    pub const MANUFACTURER_SPECIFIC_DATA: u8 = 0xff;
+}
+
+/// Bits in the flags AD structure
+pub mod flags {
+    /// LE Limited Discoverable Mode
+    pub const LE_LIMITED_DISCOVERABLE: u8 = 1 << 0;
+    /// LE General Discoverable Mode
+    pub const LE_GENERAL_DISCOVERABLE: u8 = 1 << 1;
+    /// BR/EDR (classic Bluetooth) Not Supported
+    pub const BR_EDR_NOT_SUPPORTED: u8 = 1 << 2;
+}
+
+/// The company ID for testing, which no product may ship with
+pub const TEST_COMPANY_ID: u16 = 0xffff;
+
+/// One AD structure
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AdStructure<'a> {
+    /// What it is; see [`ad_type`]
+    pub ad_type: u8,
+    /// Its contents
+    pub data: &'a [u8],
+}
+
+/// The AD structures in some advertising data
+///
+/// Iteration stops at a zero length (the rest is padding) or at a structure
+/// which runs off the end of the data.
+#[derive(Debug, Clone)]
+pub struct AdStructures<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for AdStructures<'a> {
+    type Item = AdStructure<'a>;
+
+    fn next(&mut self) -> Option<AdStructure<'a>> {
+        let (&len, rest) = self.data.split_first()?;
+        let len = usize::from(len);
+        if len == 0 || rest.len() < len {
+            self.data = &[];
+            return None;
+        }
+        let (structure, rest) = rest.split_at(len);
+        self.data = rest;
+        Some(AdStructure {
+            ad_type: structure[0],
+            data: &structure[1..],
+        })
+    }
+}
+
+/// Builds advertising data, one AD structure at a time
+pub struct AdBuilder<'a> {
+    buffer: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> AdBuilder<'a> {
+    /// Build into `buffer`, which needs to be no bigger than `MAX_DATA_LEN`
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        Self { buffer, len: 0 }
+    }
+
+    /// Adds an AD structure
+    pub fn push(&mut self, ad_type: u8, data: &[u8]) -> Result<&mut Self, Error> {
+        let end = self.len + 2 + data.len();
+        if end > MAX_DATA_LEN {
+            return Err(Error::DataTooLong);
+        }
+        let structure = self
+            .buffer
+            .get_mut(self.len..end)
+            .ok_or(Error::BufferTooSmall)?;
+        structure[0] = (1 + data.len()) as u8;
+        structure[1] = ad_type;
+        structure[2..].copy_from_slice(data);
+        self.len = end;
+        Ok(self)
+    }
+
+    /// Adds the flags; see [`flags`]
+    pub fn flags(&mut self, flags: u8) -> Result<&mut Self, Error> {
+        self.push(ad_type::FLAGS, &[flags])
+    }
+
+    /// Adds the device's name
+    pub fn complete_local_name(&mut self, name: &str) -> Result<&mut Self, Error> {
+        self.push(ad_type::COMPLETE_LOCAL_NAME, name.as_bytes())
+    }
+
+    /// Adds manufacturer specific data, for the company `company_id`
+    pub fn manufacturer_specific_data(
+        &mut self,
+        company_id: u16,
+        data: &[u8],
+    ) -> Result<&mut Self, Error> {
+        let end = self.len + 4 + data.len();
+        if end > MAX_DATA_LEN {
+            return Err(Error::DataTooLong);
+        }
+        let structure = self
+            .buffer
+            .get_mut(self.len..end)
+            .ok_or(Error::BufferTooSmall)?;
+        structure[0] = (3 + data.len()) as u8;
+        structure[1] = ad_type::MANUFACTURER_SPECIFIC_DATA;
+        structure[2..4].copy_from_slice(&company_id.to_le_bytes());
+        structure[4..].copy_from_slice(data);
+        self.len = end;
+        Ok(self)
+    }
+
+    /// The advertising data so far
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: [u8; 6] = [0xc0, 0xff, 0xee, 0x00, 0x11, 0x22];
+
+    #[test]
+    fn round_trip() {
+        let mut data = [0; MAX_DATA_LEN];
+        let mut builder = AdBuilder::new(&mut data);
+        builder
+            .flags(flags::LE_GENERAL_DISCOVERABLE | flags::BR_EDR_NOT_SUPPORTED)
+            .unwrap()
+            .manufacturer_specific_data(TEST_COMPANY_ID, b"puzzle 3/5")
+            .unwrap();
+        let data = builder.as_bytes();
+        assert_eq!(data.len(), 3 + 14);
+
+        for pdu_type in [
+            PduType::AdvInd,
+            PduType::AdvNonconnInd,
+            PduType::ScanRsp,
+            PduType::AdvScanInd,
+        ] {
+            for random_address in [false, true] {
+                let advertisement = Advertisement {
+                    pdu_type,
+                    random_address,
+                    address: ADDRESS,
+                    data,
+                };
+                let mut pdu = [0; MAX_PDU_LEN];
+                let len = advertisement.write(&mut pdu).unwrap();
+                assert_eq!(len, advertisement.len());
+                assert_eq!(Advertisement::parse(&pdu[..len]), Ok(advertisement));
+            }
+        }
+    }
+
+    #[test]
+    fn header_and_address_layout() {
+        let mut pdu = [0; MAX_PDU_LEN];
+        let len = Advertisement::non_connectable(ADDRESS, b"")
+            .write(&mut pdu)
+            .unwrap();
+        assert_eq!(&pdu[..len], b"\x42\x06\x22\x11\x00\xee\xff\xc0");
+
+        let public = Advertisement {
+            random_address: false,
+            ..Advertisement::non_connectable(ADDRESS, b"")
+        };
+        public.write(&mut pdu).unwrap();
+        assert_eq!(pdu[0], 0x02);
+    }
+
+    #[test]
+    fn ad_structures() {
+        let data = b"\x02\x01\x06\x05\xff\xff\xff\x01\x02\x03\x09dk\x00\x00";
+        let advertisement = Advertisement::non_connectable(ADDRESS, data);
+        let mut structures = advertisement.ad_structures();
+        assert_eq!(
+            structures.next(),
+            Some(AdStructure {
+                ad_type: ad_type::FLAGS,
+                data: b"\x06"
+            })
+        );
+        assert_eq!(
+            structures.next(),
+            Some(AdStructure {
+                ad_type: ad_type::MANUFACTURER_SPECIFIC_DATA,
+                data: b"\xff\xff\x01\x02"
+            })
+        );
+        assert_eq!(
+            structures.next(),
+            Some(AdStructure {
+                ad_type: ad_type::COMPLETE_LOCAL_NAME,
+                data: b"dk"
+            })
+        );
+        // then padding
+        assert_eq!(structures.next(), None);
+
+        // a structure which runs off the end
+        let broken = Advertisement::non_connectable(ADDRESS, b"\x05\x09dk");
+        assert_eq!(broken.ad_structures().next(), None);
+    }
+
+    #[test]
+    fn too_much_data() {
+        let mut data = [0; MAX_DATA_LEN];
+        let mut builder = AdBuilder::new(&mut data);
+        builder
+            .push(ad_type::COMPLETE_LOCAL_NAME, &[b'x'; 29])
+            .unwrap();
+        assert_eq!(builder.flags(0).err(), Some(Error::DataTooLong));
+        assert_eq!(builder.as_bytes().len(), 31);
+
+        let mut small = [0; 4];
+        let mut builder = AdBuilder::new(&mut small);
+        assert_eq!(
+            builder.complete_local_name("dk!").err(),
+            Some(Error::BufferTooSmall)
+        );
+
+        let long = [0; MAX_DATA_LEN + 1];
+        let mut pdu = [0; 64];
+        assert_eq!(
+            Advertisement::non_connectable(ADDRESS, &long).write(&mut pdu),
+            Err(Error::DataTooLong)
+        );
+        assert_eq!(
+            Advertisement::non_connectable(ADDRESS, b"").write(&mut pdu[..7]),
+            Err(Error::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn bad_pdus() {
+        assert_eq!(Advertisement::parse(b"\x42"), Err(Error::Truncated));
+        assert_eq!(
+            Advertisement::parse(b"\x42\x06\x22\x11\x00"),
+            Err(Error::BadLength)
+        );
+        assert_eq!(
+            Advertisement::parse(b"\x42\x03\x22\x11\x00"),
+            Err(Error::Truncated)
+        );
+        // SCAN_REQ carries two addresses and no data
+        assert_eq!(
+            Advertisement::parse(b"\x03\x0c\0\0\0\0\0\0\0\0\0\0\0\0"),
+            Err(Error::UnsupportedPduType(3))
+        );
+        // trailing bytes beyond the length field are ignored
+        let parsed = Advertisement::parse(b"\x42\x06\x22\x11\x00\xee\xff\xc0junk").unwrap();
+        assert_eq!(parsed.data, b"");
+    }
+
+    #[test]
+    fn channels() {
+        let offsets = AdvChannel::ALL.map(AdvChannel::frequency_offset);
+        assert_eq!(offsets, [2, 26, 80]);
+        assert_eq!(AdvChannel::_38.index(), 38);
+    }
+
+    #[test]
+    fn static_address() {
+        assert_eq!(
+            static_random_address(0x1234_0102_0304_0506),
+            [0xc1, 0x02, 0x03, 0x04, 0x05, 0x06]
+        );
+    }
+}
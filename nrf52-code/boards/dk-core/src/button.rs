@@ -0,0 +1,284 @@
+//! Turning a bouncy button into presses, releases, long presses and double
+//! clicks
+//!
+//! A button's contacts bounce for a few milliseconds when it's pressed or
+//! released, so the pin flickers between high and low. [`Debouncer`] only
+//! believes a new level once it has held for `DebounceConfig::debounce_us`,
+//! and then works out what the user did. Feed it the pin level (`true` for
+//! pressed) and the time whenever the pin changes, and at the latest by
+//! [`Debouncer::next_deadline`].
+//!
+//! ```
+//! use dk_core::button::{ButtonEvent, DebounceConfig, Debouncer};
+//!
+//! let mut button = Debouncer::new(DebounceConfig::new());
+//! // the contacts bounce...
+//! assert_eq!(button.update(0, true), None);
+//! assert_eq!(button.update(1_000, false), None);
+//! assert_eq!(button.update(2_000, true), None);
+//! // ...then settle
+//! assert_eq!(button.update(22_000, true), Some(ButtonEvent::Pressed));
+//! assert_eq!(button.next_deadline(), Some(802_000));
+//! assert_eq!(button.update(802_000, true), Some(ButtonEvent::LongPress));
+//! ```
+
+/// What the user did with a button
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ButtonEvent {
+    /// The button went down
+    Pressed,
+    /// The button came up
+    Released,
+    /// The button has been held down for `DebounceConfig::long_press_us`,
+    /// and is still down
+    LongPress,
+    /// The button went down for the second time, soon after a short press;
+    /// reported instead of `Pressed`
+    DoubleClick,
+}
+
+/// How long things take, in microseconds
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DebounceConfig {
+    /// How long the pin has to hold a new level before we believe it
+    pub debounce_us: u32,
+    /// How long the button has to be held down for a `LongPress`
+    pub long_press_us: u32,
+    /// The longest gap between releasing the button and pressing it again
+    /// for a `DoubleClick`
+    pub double_click_us: u32,
+}
+
+impl DebounceConfig {
+    /// 20 ms to settle, 800 ms for a long press, and up to 300 ms between the
+    /// clicks of a double click
+    pub const fn new() -> Self {
+        Self {
+            debounce_us: 20_000,
+            long_press_us: 800_000,
+            double_click_us: 300_000,
+        }
+    }
+}
+
+impl Default for DebounceConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Debounces one button, starting released
+#[derive(Debug, Clone)]
+pub struct Debouncer {
+    config: DebounceConfig,
+    /// The level the pin had at the last update, and since when
+    raw: bool,
+    raw_since: u64,
+    /// The level we believe, and since when
+    pressed: bool,
+    pressed_since: u64,
+    long_press_sent: bool,
+    double_click: bool,
+    /// When the last short press ended, if it could start a double click
+    click_ended: Option<u64>,
+}
+
+impl Debouncer {
+    /// A released button
+    pub const fn new(config: DebounceConfig) -> Self {
+        Self {
+            config,
+            raw: false,
+            raw_since: 0,
+            pressed: false,
+            pressed_since: 0,
+            long_press_sent: false,
+            double_click: false,
+            click_ended: None,
+        }
+    }
+
+    /// The pin reads `raw` (`true` for pressed) at `now`, in microseconds
+    ///
+    /// Returns what the user did, if anything. Times are taken from when the
+    /// pin settled, not from when you noticed, so polling slowly doesn't
+    /// spoil a double click.
+    pub fn update(&mut self, now: u64, raw: bool) -> Option<ButtonEvent> {
+        if raw != self.raw {
+            self.raw = raw;
+            self.raw_since = now;
+        }
+
+        if self.raw != self.pressed
+            && now.saturating_sub(self.raw_since) >= u64::from(self.config.debounce_us)
+        {
+            self.pressed = self.raw;
+            self.pressed_since = self.raw_since;
+            return Some(if self.pressed {
+                self.long_press_sent = false;
+                let gap = self.click_ended.take().map(|ended| self.raw_since - ended);
+                self.double_click =
+                    matches!(gap, Some(gap) if gap <= u64::from(self.config.double_click_us));
+                if self.double_click {
+                    ButtonEvent::DoubleClick
+                } else {
+                    ButtonEvent::Pressed
+                }
+            } else {
+                // only a short, single click can start a double click
+                let short_click = !self.long_press_sent && !self.double_click;
+                self.click_ended = short_click.then_some(self.raw_since);
+                ButtonEvent::Released
+            });
+        }
+
+        if self.pressed
+            && !self.long_press_sent
+            && now.saturating_sub(self.pressed_since) >= u64::from(self.config.long_press_us)
+        {
+            self.long_press_sent = true;
+            return Some(ButtonEvent::LongPress);
+        }
+
+        None
+    }
+
+    /// Whether the button is down, as far as we believe
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    /// When to call `update` next, if the pin doesn't change before then
+    ///
+    /// `None` means only when the pin changes.
+    pub fn next_deadline(&self) -> Option<u64> {
+        if self.raw != self.pressed {
+            Some(self.raw_since + u64::from(self.config.debounce_us))
+        } else if self.pressed && !self.long_press_sent {
+            Some(self.pressed_since + u64::from(self.config.long_press_us))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000;
+
+    /// Holds the button at `raw` from `from` for `duration`, updating every
+    /// millisecond, and returns the events with their times
+    fn hold(
+        button: &mut Debouncer,
+        from: u64,
+        duration: u64,
+        raw: bool,
+        events: &mut [(u64, ButtonEvent)],
+    ) -> usize {
+        let mut count = 0;
+        for now in (from..from + duration).step_by(MS as usize) {
+            if let Some(event) = button.update(now, raw) {
+                events[count] = (now, event);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn bounces_are_ignored() {
+        let mut button = Debouncer::new(DebounceConfig::new());
+        for (i, now) in (0..15 * MS).step_by(MS as usize).enumerate() {
+            assert_eq!(button.update(now, i % 2 == 0), None);
+        }
+        assert!(!button.is_pressed());
+        assert_eq!(button.update(20 * MS, false), None);
+    }
+
+    #[test]
+    fn press_and_release() {
+        let mut button = Debouncer::new(DebounceConfig::new());
+        let mut events = [(0, ButtonEvent::Pressed); 4];
+        assert_eq!(hold(&mut button, 0, 100 * MS, true, &mut events), 1);
+        assert_eq!(events[0], (20 * MS, ButtonEvent::Pressed));
+        assert!(button.is_pressed());
+        assert_eq!(hold(&mut button, 100 * MS, 100 * MS, false, &mut events), 1);
+        assert_eq!(events[0], (120 * MS, ButtonEvent::Released));
+        assert!(!button.is_pressed());
+        assert_eq!(button.next_deadline(), None);
+    }
+
+    #[test]
+    fn long_press() {
+        let mut button = Debouncer::new(DebounceConfig::new());
+        let mut events = [(0, ButtonEvent::Pressed); 4];
+        assert_eq!(hold(&mut button, 0, 2_000 * MS, true, &mut events), 2);
+        assert_eq!(events[1], (800 * MS, ButtonEvent::LongPress));
+        assert_eq!(button.next_deadline(), None);
+        assert_eq!(
+            hold(&mut button, 2_000 * MS, 50 * MS, false, &mut events),
+            1
+        );
+        assert_eq!(events[0].1, ButtonEvent::Released);
+
+        // a long press doesn't start a double click
+        assert_eq!(hold(&mut button, 2_050 * MS, 50 * MS, true, &mut events), 1);
+        assert_eq!(events[0].1, ButtonEvent::Pressed);
+    }
+
+    #[test]
+    fn double_click() {
+        let mut button = Debouncer::new(DebounceConfig::new());
+        let mut events = [(0, ButtonEvent::Pressed); 4];
+        hold(&mut button, 0, 100 * MS, true, &mut events);
+        hold(&mut button, 100 * MS, 200 * MS, false, &mut events);
+        assert_eq!(hold(&mut button, 300 * MS, 100 * MS, true, &mut events), 1);
+        assert_eq!(events[0], (320 * MS, ButtonEvent::DoubleClick));
+        hold(&mut button, 400 * MS, 100 * MS, false, &mut events);
+
+        // a third click starts again
+        assert_eq!(hold(&mut button, 500 * MS, 100 * MS, true, &mut events), 1);
+        assert_eq!(events[0].1, ButtonEvent::Pressed);
+        hold(&mut button, 600 * MS, 400 * MS, false, &mut events);
+
+        // too slow
+        assert_eq!(
+            hold(&mut button, 1_000 * MS, 100 * MS, true, &mut events),
+            1
+        );
+        assert_eq!(events[0].1, ButtonEvent::Pressed);
+    }
+
+    #[test]
+    fn slow_polling_keeps_the_times() {
+        let mut button = Debouncer::new(DebounceConfig::new());
+        // we see the press late, but the pin has held for ages
+        button.update(0, true);
+        assert_eq!(button.update(500 * MS, true), Some(ButtonEvent::Pressed));
+        // so the long press is measured from when it settled, at 0
+        assert_eq!(button.next_deadline(), Some(800 * MS));
+        button.update(600 * MS, false);
+        assert_eq!(button.update(700 * MS, false), Some(ButtonEvent::Released));
+        // released at 600 ms, pressed again at 850 ms
+        button.update(850 * MS, true);
+        assert_eq!(
+            button.update(1_000 * MS, true),
+            Some(ButtonEvent::DoubleClick)
+        );
+    }
+
+    #[test]
+    fn next_deadline_while_settling() {
+        let mut button = Debouncer::new(DebounceConfig::new());
+        assert_eq!(button.next_deadline(), None);
+        button.update(5 * MS, true);
+        assert_eq!(button.next_deadline(), Some(25 * MS));
+        // it bounced back
+        button.update(6 * MS, false);
+        assert_eq!(button.next_deadline(), None);
+    }
+}
@@ -0,0 +1,232 @@
+//! Which received frames `Radio::recv` hands back
+//!
+//! In IEEE 802.15.4 mode the nRF52840 RADIO has no address matching in
+//! hardware, so the driver receives everything and drops the frames that
+//! [`Filter::accepts`] turns down, then listens again. We can't trust the
+//! address in a frame with a bad CRC, so [`Filter::Address`] drops it, as a
+//! MAC would. [`Filter::Promiscuous`] and [`Filter::Prefix`] hand it back
+//! instead, as a CRC error: whoever asked for the prefix wants to know that a
+//! reply may have been mangled.
+//!
+//! ```
+//! use dk_core::filter::Filter;
+//!
+//! let filter = Filter::prefix(b"\x01\x02\x03\x04\x05\x06");
+//! assert!(filter.accepts(b"\x01\x02\x03\x04\x05\x06hello", true));
+//! assert!(!filter.accepts(b"\x0a\x0b\x0c\x0d\x0e\x0fhello", true));
+//! // it might have been for us
+//! assert!(filter.accepts(b"\x0a\x0b\x0c\x0d\x0e\x0fhello", false));
+//! ```
+
+use crate::mac;
+
+/// The PAN ID every device listens to
+pub const BROADCAST_PAN_ID: u16 = 0xffff;
+
+/// What to receive
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Filter {
+    /// Everything on the air, even frames with a bad CRC; what the radio has
+    /// always done, and what you want for sniffing
+    #[default]
+    Promiscuous,
+    /// MAC frames for us, as a MAC would filter them: frames sent to our
+    /// short or extended address, or broadcast, on our PAN (or to every PAN);
+    /// beacons from our PAN; and every ACK
+    Address {
+        /// Our PAN ID, or `BROADCAST_PAN_ID` to hear every PAN
+        pan_id: u16,
+        /// Our short address
+        short: u16,
+        /// Our extended (EUI-64) address
+        extended: u64,
+    },
+    /// Frames which start with these bytes, like the device address at the
+    /// start of a `send_recv` reply, and every frame with a bad CRC
+    Prefix(Prefix),
+}
+
+impl Filter {
+    /// Frames which start with `bytes`
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `bytes` is longer than `Prefix::CAPACITY`
+    pub fn prefix(bytes: &[u8]) -> Filter {
+        Filter::Prefix(Prefix::new(bytes))
+    }
+
+    /// Whether to hand back the frame `psdu`, whose CRC was `crc_ok`
+    pub fn accepts(&self, psdu: &[u8], crc_ok: bool) -> bool {
+        match self {
+            Filter::Promiscuous => true,
+            // the CRC comes first: the prefix of a bad frame means nothing
+            Filter::Prefix(prefix) => !crc_ok || psdu.starts_with(prefix.as_bytes()),
+            _ if !crc_ok => false,
+            Filter::Address {
+                pan_id,
+                short,
+                extended,
+            } => {
+                let Ok(frame) = mac::Frame::parse(psdu) else {
+                    return false;
+                };
+                let pan_matches = |id: u16| {
+                    *pan_id == BROADCAST_PAN_ID || id == BROADCAST_PAN_ID || id == *pan_id
+                };
+                match (frame.frame_type, frame.dst, frame.src) {
+                    // ACKs carry no addresses; `send_with_ack` checks their sequence number
+                    (mac::FrameType::Ack, _, _) => true,
+                    (mac::FrameType::Beacon, _, Some(src)) => pan_matches(src.pan_id),
+                    (_, Some(dst), _) => {
+                        pan_matches(dst.pan_id)
+                            && match dst.address {
+                                mac::Address::Short(address) => {
+                                    address == *short || dst.address == mac::Address::BROADCAST
+                                }
+                                mac::Address::Extended(address) => address == *extended,
+                            }
+                    }
+                    // with no destination, it's for the PAN coordinator, which we aren't
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+/// The bytes a `Filter::Prefix` looks for
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Prefix {
+    bytes: [u8; Prefix::CAPACITY],
+    len: u8,
+}
+
+impl Prefix {
+    /// The longest prefix we can look for, in bytes
+    pub const CAPACITY: usize = 16;
+
+    /// Look for `bytes`
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `bytes` is longer than `Self::CAPACITY`
+    pub fn new(bytes: &[u8]) -> Prefix {
+        assert!(bytes.len() <= Self::CAPACITY, "prefix too long");
+        let mut prefix = Prefix {
+            bytes: [0; Self::CAPACITY],
+            len: bytes.len() as u8,
+        };
+        prefix.bytes[..bytes.len()].copy_from_slice(bytes);
+        prefix
+    }
+
+    /// The bytes we look for
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..usize::from(self.len)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mac::{Address, Frame};
+
+    const US: Filter = Filter::Address {
+        pan_id: 0xabcd,
+        short: 0x0001,
+        extended: 0x0011_2233_4455_6677,
+    };
+
+    fn accepts(filter: &Filter, frame: &Frame) -> bool {
+        let mut buffer = [0; 127];
+        let len = frame.write(&mut buffer).unwrap();
+        filter.accepts(&buffer[..len], true)
+    }
+
+    #[test]
+    fn promiscuous_takes_everything() {
+        let filter = Filter::default();
+        assert_eq!(filter, Filter::Promiscuous);
+        assert!(filter.accepts(b"", true));
+        assert!(filter.accepts(b"\xff\xffjunk", false));
+    }
+
+    #[test]
+    fn prefix() {
+        let filter = Filter::prefix(b"abc");
+        assert!(filter.accepts(b"abc", true));
+        assert!(filter.accepts(b"abcdef", true));
+        assert!(!filter.accepts(b"ab", true));
+        assert!(!filter.accepts(b"xabc", true));
+        // bad frames come back, as CRC errors, whatever they start with
+        assert!(filter.accepts(b"abcdef", false));
+        assert!(filter.accepts(b"xyz", false));
+        // an empty prefix matches every good frame
+        assert!(Filter::prefix(b"").accepts(b"anything", true));
+    }
+
+    #[test]
+    #[should_panic(expected = "prefix too long")]
+    fn prefix_too_long() {
+        Filter::prefix(&[0; Prefix::CAPACITY + 1]);
+    }
+
+    #[test]
+    fn frames_for_us() {
+        let to = |pan_id, address| Frame::data(1, b"hi").with_dst(pan_id, address);
+        assert!(accepts(&US, &to(0xabcd, Address::Short(0x0001))));
+        assert!(accepts(&US, &to(0xabcd, Address::BROADCAST)));
+        assert!(accepts(&US, &to(0xffff, Address::BROADCAST)));
+        assert!(accepts(
+            &US,
+            &to(0xabcd, Address::Extended(0x0011_2233_4455_6677))
+        ));
+        assert!(accepts(&US, &Frame::ack(1, false)));
+        let beacon = Frame {
+            frame_type: mac::FrameType::Beacon,
+            ..Frame::data(1, b"")
+        };
+        assert!(accepts(&US, &beacon.with_src(0xabcd, Address::Short(0))));
+    }
+
+    #[test]
+    fn frames_for_others() {
+        let to = |pan_id, address| Frame::data(1, b"hi").with_dst(pan_id, address);
+        assert!(!accepts(&US, &to(0xabcd, Address::Short(0x0002))));
+        assert!(!accepts(&US, &to(0x1234, Address::Short(0x0001))));
+        assert!(!accepts(&US, &to(0x1234, Address::BROADCAST)));
+        assert!(!accepts(&US, &to(0xabcd, Address::Extended(0x42))));
+        // to the coordinator
+        assert!(!accepts(&US, &Frame::data(1, b"hi")));
+        let beacon = Frame {
+            frame_type: mac::FrameType::Beacon,
+            ..Frame::data(1, b"")
+        };
+        assert!(!accepts(&US, &beacon.with_src(0x1234, Address::Short(0))));
+        // not a MAC frame at all
+        assert!(!US.accepts(b"\x07", true));
+    }
+
+    #[test]
+    fn every_pan() {
+        let anywhere = Filter::Address {
+            pan_id: BROADCAST_PAN_ID,
+            short: 0x0001,
+            extended: 0,
+        };
+        let frame = Frame::data(1, b"hi").with_dst(0x1234, Address::Short(0x0001));
+        assert!(accepts(&anywhere, &frame));
+        assert!(!accepts(&US, &frame));
+    }
+
+    #[test]
+    fn bad_crc() {
+        let frame = Frame::data(1, b"hi").with_dst(0xabcd, Address::Short(0x0001));
+        let mut buffer = [0; 127];
+        let len = frame.write(&mut buffer).unwrap();
+        assert!(!US.accepts(&buffer[..len], false));
+    }
+}
@@ -0,0 +1,18 @@
+//! The parts of the `dk` Board Support Package which don't need the hardware
+//!
+//! `dk` re-exports these, so you don't normally need to depend on this crate
+//! yourself. Keeping them here means they can be tested on the host.
+
+#![no_std]
+#![deny(missing_docs)]
+
+pub mod ble;
+pub mod button;
+pub mod filter;
+pub mod log;
+pub mod mac;
+pub mod pattern;
+pub mod radio;
+pub mod retry;
+pub mod sensor;
+pub mod timer;
@@ -0,0 +1,214 @@
+//! A queue of log frames, waiting to go out over a UART or USB
+//!
+//! `dk::log` encodes each defmt log message into a frame, and puts the frame
+//! in a [`FrameQueue`] until the transport can send it. If a frame doesn't
+//! fit, the whole frame is dropped and counted, rather than sending half of
+//! it: the host's decoder would make no sense of a half frame, or of the
+//! frame that follows it.
+//!
+//! ```
+//! use dk_core::log::FrameQueue;
+//!
+//! let mut queue = FrameQueue::<8>::new();
+//! queue.start_frame();
+//! queue.write(b"hello");
+//! // nothing to send until the frame is finished
+//! assert_eq!(queue.peek(), b"");
+//! queue.end_frame();
+//! assert_eq!(queue.peek(), b"hello");
+//!
+//! // this one doesn't fit
+//! queue.start_frame();
+//! queue.write(b"world");
+//! queue.end_frame();
+//! assert_eq!(queue.dropped_frames(), 1);
+//!
+//! queue.consume(5);
+//! assert!(queue.is_empty());
+//! ```
+
+/// Up to `N` bytes of whole frames, first in first out
+#[derive(Debug, Clone)]
+pub struct FrameQueue<const N: usize> {
+    buffer: [u8; N],
+    /// The oldest byte
+    head: usize,
+    /// Bytes from `head` that are part of finished frames
+    committed: usize,
+    /// Bytes from `head` including the frame being written
+    len: usize,
+    /// The frame being written didn't fit
+    overflowed: bool,
+    dropped_frames: u32,
+}
+
+impl<const N: usize> FrameQueue<N> {
+    /// An empty queue
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; N],
+            head: 0,
+            committed: 0,
+            len: 0,
+            overflowed: false,
+            dropped_frames: 0,
+        }
+    }
+
+    /// Starts a new frame, throwing away any unfinished one
+    pub fn start_frame(&mut self) {
+        self.len = self.committed;
+        self.overflowed = false;
+    }
+
+    /// Adds bytes to the frame; if they don't fit, the frame is dropped
+    pub fn write(&mut self, bytes: &[u8]) {
+        if self.overflowed {
+            return;
+        }
+        if bytes.len() > N - self.len {
+            self.overflowed = true;
+            self.len = self.committed;
+            self.dropped_frames = self.dropped_frames.wrapping_add(1);
+            return;
+        }
+        for &byte in bytes {
+            self.buffer[(self.head + self.len) % N] = byte;
+            self.len += 1;
+        }
+    }
+
+    /// Finishes the frame, so it can be sent
+    pub fn end_frame(&mut self) {
+        self.committed = self.len;
+    }
+
+    /// The oldest finished bytes which sit next to each other in memory
+    ///
+    /// This may be less than all of them, when they wrap around the end of
+    /// the buffer; `consume` them and `peek` again for the rest.
+    pub fn peek(&self) -> &[u8] {
+        let end = (self.head + self.committed).min(N);
+        &self.buffer[self.head..end]
+    }
+
+    /// Drops the first `count` bytes that `peek` returned, as they're sent
+    ///
+    /// # Panics
+    ///
+    /// This function panics if there aren't `count` finished bytes
+    pub fn consume(&mut self, count: usize) {
+        assert!(count <= self.committed, "consumed more than was queued");
+        self.head = (self.head + count) % N;
+        self.committed -= count;
+        self.len -= count;
+    }
+
+    /// Copies out as many finished bytes as fit in `buf`, oldest first, and
+    /// returns how many
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let mut count = 0;
+        while count < buf.len() {
+            let chunk = self.peek();
+            let n = chunk.len().min(buf.len() - count);
+            if n == 0 {
+                break;
+            }
+            buf[count..count + n].copy_from_slice(&chunk[..n]);
+            self.consume(n);
+            count += n;
+        }
+        count
+    }
+
+    /// Whether there are no finished bytes to send
+    pub fn is_empty(&self) -> bool {
+        self.committed == 0
+    }
+
+    /// How many frames didn't fit, since the queue was made
+    pub fn dropped_frames(&self) -> u32 {
+        self.dropped_frames
+    }
+}
+
+impl<const N: usize> Default for FrameQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame<const N: usize>(queue: &mut FrameQueue<N>, parts: &[&[u8]]) {
+        queue.start_frame();
+        for part in parts {
+            queue.write(part);
+        }
+        queue.end_frame();
+    }
+
+    #[test]
+    fn frames_in_order() {
+        let mut queue = FrameQueue::<16>::new();
+        frame(&mut queue, &[b"ab", b"c\0"]);
+        frame(&mut queue, &[b"de\0"]);
+        let mut buf = [0; 16];
+        assert_eq!(queue.read(&mut buf), 7);
+        assert_eq!(&buf[..7], b"abc\0de\0");
+        assert!(queue.is_empty());
+        assert_eq!(queue.read(&mut buf), 0);
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut queue = FrameQueue::<8>::new();
+        frame(&mut queue, &[b"12345"]);
+        queue.consume(5);
+        frame(&mut queue, &[b"abcdef"]);
+        // the end of the buffer, then the start
+        assert_eq!(queue.peek(), b"abc");
+        queue.consume(3);
+        assert_eq!(queue.peek(), b"def");
+
+        let mut buf = [0; 2];
+        assert_eq!(queue.read(&mut buf), 2);
+        assert_eq!(&buf, b"de");
+        assert_eq!(queue.peek(), b"f");
+    }
+
+    #[test]
+    fn a_frame_that_doesnt_fit_is_dropped_whole() {
+        let mut queue = FrameQueue::<8>::new();
+        frame(&mut queue, &[b"abc"]);
+        frame(&mut queue, &[b"def", b"ghi", b"j"]);
+        assert_eq!(queue.dropped_frames(), 1);
+        assert_eq!(queue.peek(), b"abc");
+        // and there's still room for the next one
+        frame(&mut queue, &[b"xyz"]);
+        let mut buf = [0; 8];
+        assert_eq!(queue.read(&mut buf), 6);
+        assert_eq!(&buf[..6], b"abcxyz");
+    }
+
+    #[test]
+    fn unfinished_frames_are_not_sent() {
+        let mut queue = FrameQueue::<8>::new();
+        queue.start_frame();
+        queue.write(b"half");
+        assert!(queue.is_empty());
+        // the logger was interrupted before it finished: start again
+        frame(&mut queue, &[b"whole"]);
+        assert_eq!(queue.peek(), b"whole");
+    }
+
+    #[test]
+    #[should_panic]
+    fn consuming_too_much() {
+        let mut queue = FrameQueue::<8>::new();
+        frame(&mut queue, &[b"ab"]);
+        queue.consume(3);
+    }
+}
@@ -0,0 +1,677 @@
+//! IEEE 802.15.4 MAC frames
+//!
+//! A MAC frame is the PSDU of a radio packet: a header, a payload, then the
+//! CRC (which the radio adds and checks for us, so it isn't included here).
+//!
+//! | Field            | Length     | Notes                                        |
+//! |------------------|------------|----------------------------------------------|
+//! | Frame control    | 2          | See [`FrameControl`]                         |
+//! | Sequence number  | 1          |                                              |
+//! | Destination PAN  | 0 or 2     | Present if there is a destination address    |
+//! | Destination      | 0, 2 or 8  |                                              |
+//! | Source PAN       | 0 or 2     | Left out if it is the same as the destination PAN (PAN ID compression) |
+//! | Source           | 0, 2 or 8  |                                              |
+//! | Payload          | the rest   |                                              |
+//!
+//! Multi-byte fields are little-endian. We handle the 2003 and 2006 frame
+//! versions, without security.
+//!
+//! ```
+//! use dk_core::mac::{Address, Frame};
+//!
+//! let frame = Frame::data(7, b"hello")
+//!     .with_dst(0xabcd, Address::BROADCAST)
+//!     .with_src(0xabcd, Address::Short(0x1234));
+//! let mut buffer = [0u8; 125];
+//! let len = frame.write(&mut buffer).unwrap();
+//! assert_eq!(&buffer[..len], b"\x41\x98\x07\xcd\xab\xff\xff\x34\x12hello");
+//! assert_eq!(Frame::parse(&buffer[..len]), Ok(frame));
+//! ```
+
+/// Why a frame could not be parsed or written
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The frame ended before its header did
+    Truncated,
+    /// The frame type is one of the reserved values
+    ReservedFrameType(u8),
+    /// An addressing mode is the reserved value
+    ReservedAddressMode,
+    /// The frame version isn't 2003 or 2006
+    UnsupportedVersion(u8),
+    /// The frame is secured, which we don't handle
+    SecurityNotSupported,
+    /// A PAN ID is missing, or is there without an address
+    InconsistentAddressing,
+    /// The buffer is too small for the frame
+    BufferTooSmall,
+}
+
+/// What a frame is for
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameType {
+    /// A beacon, from a PAN coordinator
+    Beacon = 0,
+    /// Data
+    Data = 1,
+    /// An acknowledgement
+    Ack = 2,
+    /// A MAC command
+    MacCommand = 3,
+}
+
+/// Which edition of the standard a frame follows
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameVersion {
+    /// IEEE 802.15.4-2003
+    Ieee2003 = 0,
+    /// IEEE 802.15.4-2006
+    Ieee2006 = 1,
+}
+
+/// What kind of address a frame carries, in one direction
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AddressMode {
+    /// No address (and no PAN ID)
+    None = 0,
+    /// A 16-bit short address
+    Short = 2,
+    /// A 64-bit extended address
+    Extended = 3,
+}
+
+impl AddressMode {
+    fn from_bits(bits: u16) -> Result<AddressMode, Error> {
+        match bits {
+            0 => Ok(AddressMode::None),
+            2 => Ok(AddressMode::Short),
+            3 => Ok(AddressMode::Extended),
+            _ => Err(Error::ReservedAddressMode),
+        }
+    }
+
+    /// How many bytes an address of this kind takes
+    pub const fn address_len(self) -> usize {
+        match self {
+            AddressMode::None => 0,
+            AddressMode::Short => 2,
+            AddressMode::Extended => 8,
+        }
+    }
+}
+
+/// The frame control field, at the start of every frame
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FrameControl {
+    /// What the frame is for
+    pub frame_type: FrameType,
+    /// Whether the frame has an auxiliary security header
+    pub security_enabled: bool,
+    /// The sender has more frames for the receiver
+    pub frame_pending: bool,
+    /// The receiver should acknowledge the frame
+    pub ack_request: bool,
+    /// The source PAN ID is left out, because it's the same as the destination's
+    pub pan_id_compression: bool,
+    /// The kind of destination address
+    pub dst_addressing: AddressMode,
+    /// Which edition of the standard the frame follows
+    pub version: FrameVersion,
+    /// The kind of source address
+    pub src_addressing: AddressMode,
+}
+
+impl FrameControl {
+    const SECURITY_ENABLED: u16 = 1 << 3;
+    const FRAME_PENDING: u16 = 1 << 4;
+    const ACK_REQUEST: u16 = 1 << 5;
+    const PAN_ID_COMPRESSION: u16 = 1 << 6;
+    const DST_ADDRESSING_SHIFT: u16 = 10;
+    const VERSION_SHIFT: u16 = 12;
+    const SRC_ADDRESSING_SHIFT: u16 = 14;
+
+    /// Decode the field, as a little-endian number
+    pub fn from_bits(bits: u16) -> Result<FrameControl, Error> {
+        let frame_type = match bits & 0b111 {
+            0 => FrameType::Beacon,
+            1 => FrameType::Data,
+            2 => FrameType::Ack,
+            3 => FrameType::MacCommand,
+            other => return Err(Error::ReservedFrameType(other as u8)),
+        };
+        let version = match (bits >> Self::VERSION_SHIFT) & 0b11 {
+            0 => FrameVersion::Ieee2003,
+            1 => FrameVersion::Ieee2006,
+            other => return Err(Error::UnsupportedVersion(other as u8)),
+        };
+        Ok(FrameControl {
+            frame_type,
+            security_enabled: bits & Self::SECURITY_ENABLED != 0,
+            frame_pending: bits & Self::FRAME_PENDING != 0,
+            ack_request: bits & Self::ACK_REQUEST != 0,
+            pan_id_compression: bits & Self::PAN_ID_COMPRESSION != 0,
+            dst_addressing: AddressMode::from_bits((bits >> Self::DST_ADDRESSING_SHIFT) & 0b11)?,
+            version,
+            src_addressing: AddressMode::from_bits((bits >> Self::SRC_ADDRESSING_SHIFT) & 0b11)?,
+        })
+    }
+
+    /// Encode the field, as a little-endian number
+    pub fn to_bits(self) -> u16 {
+        let flag = |set: bool, bit: u16| if set { bit } else { 0 };
+        self.frame_type as u16
+            | flag(self.security_enabled, Self::SECURITY_ENABLED)
+            | flag(self.frame_pending, Self::FRAME_PENDING)
+            | flag(self.ack_request, Self::ACK_REQUEST)
+            | flag(self.pan_id_compression, Self::PAN_ID_COMPRESSION)
+            | (self.dst_addressing as u16) << Self::DST_ADDRESSING_SHIFT
+            | (self.version as u16) << Self::VERSION_SHIFT
+            | (self.src_addressing as u16) << Self::SRC_ADDRESSING_SHIFT
+    }
+}
+
+/// A device address
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Address {
+    /// A 16-bit short address, given out by the PAN coordinator
+    Short(u16),
+    /// A 64-bit extended address (EUI-64), unique to the device
+    Extended(u64),
+}
+
+impl Address {
+    /// The short address every device listens to
+    pub const BROADCAST: Address = Address::Short(0xffff);
+
+    /// The kind of address this is
+    pub const fn mode(&self) -> AddressMode {
+        match self {
+            Address::Short(_) => AddressMode::Short,
+            Address::Extended(_) => AddressMode::Extended,
+        }
+    }
+}
+
+/// An address, with the PAN it is in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeviceAddress {
+    /// The PAN ID; `0xffff` is every PAN
+    pub pan_id: u16,
+    /// The device
+    pub address: Address,
+}
+
+/// A MAC frame, without the CRC
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Frame<'a> {
+    /// What the frame is for
+    pub frame_type: FrameType,
+    /// The sender has more frames for the receiver
+    pub frame_pending: bool,
+    /// The receiver should acknowledge the frame
+    pub ack_request: bool,
+    /// Which edition of the standard the frame follows
+    pub version: FrameVersion,
+    /// Lets the receiver spot repeats, and match ACKs to frames
+    pub sequence: u8,
+    /// Who the frame is for
+    pub dst: Option<DeviceAddress>,
+    /// Who sent the frame
+    pub src: Option<DeviceAddress>,
+    /// The source PAN ID is left out, because it's the destination's
+    ///
+    /// [`Frame::with_src`] and [`Frame::with_dst`] set this when the two PAN
+    /// IDs are the same. [`Frame::parse`] keeps what the frame said, as a
+    /// frame may carry the same PAN ID twice.
+    pub pan_id_compression: bool,
+    /// What comes after the header
+    pub payload: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    /// A data frame, with no addresses yet
+    pub const fn data(sequence: u8, payload: &'a [u8]) -> Frame<'a> {
+        Frame {
+            frame_type: FrameType::Data,
+            frame_pending: false,
+            ack_request: false,
+            version: FrameVersion::Ieee2006,
+            sequence,
+            dst: None,
+            src: None,
+            pan_id_compression: false,
+            payload,
+        }
+    }
+
+    /// An acknowledgement for the frame with this sequence number
+    pub const fn ack(sequence: u8, frame_pending: bool) -> Frame<'static> {
+        Frame {
+            frame_type: FrameType::Ack,
+            frame_pending,
+            ack_request: false,
+            // ACKs are always sent as 2003 frames
+            version: FrameVersion::Ieee2003,
+            sequence,
+            dst: None,
+            src: None,
+            pan_id_compression: false,
+            payload: &[],
+        }
+    }
+
+    /// The same frame, sent to `address` in PAN `pan_id`
+    pub const fn with_dst(mut self, pan_id: u16, address: Address) -> Frame<'a> {
+        self.dst = Some(DeviceAddress { pan_id, address });
+        self.compress_pan_id()
+    }
+
+    /// The same frame, sent from `address` in PAN `pan_id`
+    pub const fn with_src(mut self, pan_id: u16, address: Address) -> Frame<'a> {
+        self.src = Some(DeviceAddress { pan_id, address });
+        self.compress_pan_id()
+    }
+
+    /// Leave out the source PAN ID if it's the same as the destination's
+    const fn compress_pan_id(mut self) -> Frame<'a> {
+        self.pan_id_compression = match (self.dst, self.src) {
+            (Some(dst), Some(src)) => dst.pan_id == src.pan_id,
+            _ => false,
+        };
+        self
+    }
+
+    /// The same frame, asking to be acknowledged
+    pub const fn with_ack_request(mut self) -> Frame<'a> {
+        self.ack_request = true;
+        self
+    }
+
+    /// The frame control field for this frame
+    pub fn frame_control(&self) -> FrameControl {
+        FrameControl {
+            frame_type: self.frame_type,
+            security_enabled: false,
+            frame_pending: self.frame_pending,
+            ack_request: self.ack_request,
+            pan_id_compression: self.pan_id_compression,
+            dst_addressing: self.dst.map_or(AddressMode::None, |d| d.address.mode()),
+            version: self.version,
+            src_addressing: self.src.map_or(AddressMode::None, |s| s.address.mode()),
+        }
+    }
+
+    /// Decode a frame
+    ///
+    /// The payload borrows from `psdu`.
+    pub fn parse(psdu: &'a [u8]) -> Result<Frame<'a>, Error> {
+        let mut reader = Reader { rest: psdu };
+        let frame_control = FrameControl::from_bits(reader.u16()?)?;
+        if frame_control.security_enabled {
+            return Err(Error::SecurityNotSupported);
+        }
+        let sequence = reader.u8()?;
+
+        let dst = match frame_control.dst_addressing {
+            AddressMode::None => None,
+            mode => {
+                let pan_id = reader.u16()?;
+                Some(DeviceAddress {
+                    pan_id,
+                    address: reader.address(mode)?,
+                })
+            }
+        };
+        let src = match (frame_control.src_addressing, dst) {
+            (AddressMode::None, _) => None,
+            (mode, Some(dst)) if frame_control.pan_id_compression => Some(DeviceAddress {
+                pan_id: dst.pan_id,
+                address: reader.address(mode)?,
+            }),
+            // There's no destination PAN to share
+            (_, None) if frame_control.pan_id_compression => {
+                return Err(Error::InconsistentAddressing)
+            }
+            (mode, _) => {
+                let pan_id = reader.u16()?;
+                Some(DeviceAddress {
+                    pan_id,
+                    address: reader.address(mode)?,
+                })
+            }
+        };
+
+        Ok(Frame {
+            frame_type: frame_control.frame_type,
+            frame_pending: frame_control.frame_pending,
+            ack_request: frame_control.ack_request,
+            version: frame_control.version,
+            sequence,
+            dst,
+            src,
+            pan_id_compression: frame_control.pan_id_compression,
+            payload: reader.rest,
+        })
+    }
+
+    /// How many bytes the frame takes, without the CRC
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        let frame_control = self.frame_control();
+        let dst_pan = if self.dst.is_some() { 2 } else { 0 };
+        let src_pan = if self.src.is_some() && !frame_control.pan_id_compression {
+            2
+        } else {
+            0
+        };
+        3 + dst_pan
+            + frame_control.dst_addressing.address_len()
+            + src_pan
+            + frame_control.src_addressing.address_len()
+            + self.payload.len()
+    }
+
+    /// Encode the frame into `buffer`, returning how many bytes it took
+    ///
+    /// With `pan_id_compression` set, the frame must have a destination and
+    /// a source in the same PAN; otherwise this returns
+    /// `InconsistentAddressing`.
+    pub fn write(&self, buffer: &mut [u8]) -> Result<usize, Error> {
+        if self.pan_id_compression {
+            match (self.dst, self.src) {
+                (Some(dst), Some(src)) if dst.pan_id == src.pan_id => {}
+                _ => return Err(Error::InconsistentAddressing),
+            }
+        }
+        let len = self.len();
+        let buffer = buffer.get_mut(..len).ok_or(Error::BufferTooSmall)?;
+        let frame_control = self.frame_control();
+        let mut writer = Writer { buffer, pos: 0 };
+        writer.put(&frame_control.to_bits().to_le_bytes());
+        writer.put(&[self.sequence]);
+        if let Some(dst) = self.dst {
+            writer.put(&dst.pan_id.to_le_bytes());
+            writer.address(dst.address);
+        }
+        if let Some(src) = self.src {
+            if !frame_control.pan_id_compression {
+                writer.put(&src.pan_id.to_le_bytes());
+            }
+            writer.address(src.address);
+        }
+        writer.put(self.payload);
+        Ok(len)
+    }
+}
+
+/// Sets the Acknowledgment Request bit of the frame in `psdu`, returning its
+/// sequence number
+///
+/// Only the frame control field is checked; the rest of the frame is left
+/// alone.
+pub fn request_ack(psdu: &mut [u8]) -> Result<u8, Error> {
+    let [low, high, sequence, ..] = *psdu else {
+        return Err(Error::Truncated);
+    };
+    let mut frame_control = FrameControl::from_bits(u16::from_le_bytes([low, high]))?;
+    frame_control.ack_request = true;
+    psdu[..2].copy_from_slice(&frame_control.to_bits().to_le_bytes());
+    Ok(sequence)
+}
+
+/// If `psdu` is an ACK for the frame with sequence number `sequence`, its
+/// frame pending bit
+pub fn ack_for(psdu: &[u8], sequence: u8) -> Option<bool> {
+    let frame = Frame::parse(psdu).ok()?;
+    (frame.frame_type == FrameType::Ack && frame.sequence == sequence)
+        .then_some(frame.frame_pending)
+}
+
+/// Takes fields off the front of a frame
+struct Reader<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let (bytes, rest) = self.rest.split_first_chunk().ok_or(Error::Truncated)?;
+        self.rest = rest;
+        Ok(*bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(u8::from_le_bytes(self.take()?))
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn address(&mut self, mode: AddressMode) -> Result<Address, Error> {
+        match mode {
+            AddressMode::Short => Ok(Address::Short(u16::from_le_bytes(self.take()?))),
+            AddressMode::Extended => Ok(Address::Extended(u64::from_le_bytes(self.take()?))),
+            AddressMode::None => Err(Error::InconsistentAddressing),
+        }
+    }
+}
+
+/// Puts fields into a buffer which we know is big enough
+struct Writer<'a> {
+    buffer: &'a mut [u8],
+    pos: usize,
+}
+
+impl Writer<'_> {
+    fn put(&mut self, bytes: &[u8]) {
+        self.buffer[self.pos..][..bytes.len()].copy_from_slice(bytes);
+        self.pos += bytes.len();
+    }
+
+    fn address(&mut self, address: Address) {
+        match address {
+            Address::Short(short) => self.put(&short.to_le_bytes()),
+            Address::Extended(extended) => self.put(&extended.to_le_bytes()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse a frame, and check it writes back the same
+    fn round_trip(psdu: &[u8]) -> Frame<'_> {
+        let frame = Frame::parse(psdu).unwrap();
+        let mut buffer = [0u8; 127];
+        let len = frame.write(&mut buffer).unwrap();
+        assert_eq!(&buffer[..len], psdu);
+        assert_eq!(frame.len(), psdu.len());
+        frame
+    }
+
+    #[test]
+    fn ack() {
+        // An ACK for sequence number 0x2a, with frame pending
+        let frame = round_trip(b"\x12\x00\x2a");
+        assert_eq!(frame, Frame::ack(0x2a, true));
+        assert_eq!(frame.dst, None);
+        assert!(frame.payload.is_empty());
+    }
+
+    #[test]
+    fn acknowledging() {
+        let mut psdu = *b"\x41\x88\x07\xcd\xab\xff\xff\x34\x12hi";
+        assert_eq!(request_ack(&mut psdu), Ok(7));
+        assert!(Frame::parse(&psdu).unwrap().ack_request);
+        assert_eq!(request_ack(&mut [0x41, 0x88]), Err(Error::Truncated));
+        assert_eq!(
+            request_ack(&mut [0x05, 0x00, 0x00]),
+            Err(Error::ReservedFrameType(5))
+        );
+
+        assert_eq!(ack_for(b"\x12\x00\x07", 7), Some(true));
+        assert_eq!(ack_for(b"\x02\x00\x07", 7), Some(false));
+        // someone else's ACK, or not an ACK at all
+        assert_eq!(ack_for(b"\x02\x00\x08", 7), None);
+        assert_eq!(ack_for(&psdu, 7), None);
+    }
+
+    #[test]
+    fn broadcast_data() {
+        // Data, PAN ID compression, short addresses, version 2003
+        let frame = round_trip(b"\x41\x88\x01\xcd\xab\xff\xff\x34\x12hi");
+        assert_eq!(frame.frame_type, FrameType::Data);
+        assert_eq!(frame.version, FrameVersion::Ieee2003);
+        assert!(!frame.ack_request);
+        assert_eq!(frame.sequence, 1);
+        assert_eq!(
+            frame.dst,
+            Some(DeviceAddress {
+                pan_id: 0xabcd,
+                address: Address::BROADCAST
+            })
+        );
+        assert_eq!(
+            frame.src,
+            Some(DeviceAddress {
+                pan_id: 0xabcd,
+                address: Address::Short(0x1234)
+            })
+        );
+        assert_eq!(frame.payload, b"hi");
+    }
+
+    #[test]
+    fn extended_addresses_with_ack_request() {
+        let psdu = b"\x61\xcc\x05\x34\x12\
+            \x08\x07\x06\x05\x04\x03\x02\x01\
+            \x18\x17\x16\x15\x14\x13\x12\x11\
+            payload";
+        let frame = round_trip(psdu);
+        assert!(frame.ack_request);
+        assert_eq!(
+            frame.dst.unwrap().address,
+            Address::Extended(0x0102_0304_0506_0708)
+        );
+        assert_eq!(frame.src.unwrap().pan_id, 0x1234);
+        assert_eq!(
+            frame.src.unwrap().address,
+            Address::Extended(0x1112_1314_1516_1718)
+        );
+    }
+
+    #[test]
+    fn beacon() {
+        // A beacon has only a source: PAN 0xcafe, short address 0x0000,
+        // then the superframe spec, GTS and pending address fields
+        let frame = round_trip(b"\x00\x80\x89\xfe\xca\x00\x00\xff\xcf\x00\x00");
+        assert_eq!(frame.frame_type, FrameType::Beacon);
+        assert_eq!(frame.dst, None);
+        assert_eq!(
+            frame.src,
+            Some(DeviceAddress {
+                pan_id: 0xcafe,
+                address: Address::Short(0)
+            })
+        );
+        assert_eq!(frame.payload, b"\xff\xcf\x00\x00");
+    }
+
+    #[test]
+    fn data_request_command_between_pans() {
+        // MAC command, 2006, ack request, short destination in PAN 0x0001,
+        // extended source in PAN 0x0002, then the Data Request command ID
+        let psdu = b"\x23\xd8\x10\x01\x00\x00\x00\x02\x00\x01\x00\x00\x00\x00\x00\x00\x00\x04";
+        let frame = round_trip(psdu);
+        assert_eq!(frame.frame_type, FrameType::MacCommand);
+        assert_eq!(frame.version, FrameVersion::Ieee2006);
+        assert!(!frame.frame_control().pan_id_compression);
+        assert_eq!(frame.src.unwrap().pan_id, 2);
+        assert_eq!(frame.payload, [0x04]);
+    }
+
+    #[test]
+    fn builder() {
+        let frame = Frame::data(9, b"x")
+            .with_dst(0x1234, Address::Short(1))
+            .with_src(0x1234, Address::Short(2))
+            .with_ack_request();
+        let mut buffer = [0u8; 16];
+        let len = frame.write(&mut buffer).unwrap();
+        // data, ack request, PAN ID compression, short addresses, version 2006
+        assert_eq!(&buffer[..len], b"\x61\x98\x09\x34\x12\x01\x00\x02\x00x");
+        assert_eq!(
+            frame.write(&mut buffer[..len - 1]),
+            Err(Error::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn same_pan_without_compression() {
+        // data, 2006, short destination and source, both in PAN 0x1234, but
+        // the source PAN ID is sent anyway
+        let psdu = b"\x01\x98\x05\x34\x12\x01\x00\x34\x12\x02\x00hi";
+        let frame = round_trip(psdu);
+        assert!(!frame.pan_id_compression);
+        assert_eq!(frame.src.unwrap().pan_id, frame.dst.unwrap().pan_id);
+    }
+
+    #[test]
+    fn compression_needs_the_same_pan() {
+        let mut frame = Frame::data(1, b"")
+            .with_dst(0x1234, Address::Short(1))
+            .with_src(0x1234, Address::Short(2));
+        assert!(frame.pan_id_compression);
+        // the source moves to another PAN, which has to be written out
+        frame.src.as_mut().unwrap().pan_id = 0x4321;
+        let mut buffer = [0u8; 16];
+        assert_eq!(frame.write(&mut buffer), Err(Error::InconsistentAddressing));
+        frame.pan_id_compression = false;
+        assert!(frame.write(&mut buffer).is_ok());
+    }
+
+    #[test]
+    fn bad_frames() {
+        assert_eq!(Frame::parse(b""), Err(Error::Truncated));
+        assert_eq!(Frame::parse(b"\x02\x00"), Err(Error::Truncated));
+        // a destination PAN but no destination address
+        assert_eq!(Frame::parse(b"\x41\x88\x00\xff\xff"), Err(Error::Truncated));
+        assert_eq!(
+            Frame::parse(b"\x05\x00\x00"),
+            Err(Error::ReservedFrameType(5))
+        );
+        assert_eq!(
+            Frame::parse(b"\x01\x04\x00"),
+            Err(Error::ReservedAddressMode)
+        );
+        assert_eq!(
+            Frame::parse(b"\x01\x20\x00"),
+            Err(Error::UnsupportedVersion(2))
+        );
+        assert_eq!(
+            Frame::parse(b"\x09\x00\x00"),
+            Err(Error::SecurityNotSupported)
+        );
+        // PAN ID compression, with no destination PAN to copy
+        assert_eq!(
+            Frame::parse(b"\x41\x80\x00\x34\x12"),
+            Err(Error::InconsistentAddressing)
+        );
+    }
+
+    #[test]
+    fn frame_control_bits() {
+        for bits in [0x0002, 0x8841, 0xcc61, 0x8000, 0xd823, 0x9861] {
+            assert_eq!(FrameControl::from_bits(bits).unwrap().to_bits(), bits);
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! LED brightness patterns, for the PWM sequence hardware to play
+//!
+//! A [`Pattern`] renders into brightness samples (0 is off, 255 is full
+//! brightness), each held for [`Pattern::step_ms`]. `dk::pwm` converts them
+//! to duty cycles with [`duty`] and has the PWM peripheral loop over them,
+//! so the CPU has nothing to do while a pattern plays.
+//!
+//! ```
+//! use dk_core::pattern::{Pattern, MORSE_UNIT_MS};
+//!
+//! let mut samples = [0; 32];
+//! let len = Pattern::Morse("ET").render(&mut samples).unwrap();
+//! // E is a dot, T a dash, then a word gap before it starts again
+//! assert_eq!(
+//!     &samples[..len],
+//!     &[255, 0, 0, 0, 255, 255, 255, 0, 0, 0, 0, 0, 0, 0]
+//! );
+//! assert_eq!(Pattern::Morse("ET").step_ms(), MORSE_UNIT_MS);
+//! ```
+
+/// How long a Morse dot is, in ms (10 words per minute)
+pub const MORSE_UNIT_MS: u32 = 120;
+
+/// How long each blink of a blink code is on, and off, in ms
+pub const BLINK_MS: u32 = 200;
+
+/// How many blink lengths a blink code pauses for before it repeats
+pub const BLINK_PAUSE_STEPS: usize = 5;
+
+/// How many samples a breathing pattern has, whatever its period
+pub const BREATHING_STEPS: usize = 64;
+
+/// Why a pattern couldn't be rendered
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// It needs more samples than there is room for
+    TooLong,
+    /// There's no Morse code for this character
+    NotMorse(char),
+}
+
+/// Something for an LED to do, over and over
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Pattern<'a> {
+    /// Stay at one brightness
+    Steady(u8),
+    /// Fade up and down again, every `period_ms`
+    Breathing {
+        /// How long one breath takes, in ms
+        period_ms: u32,
+    },
+    /// Blink `count` times, then pause: an error code, say
+    BlinkCode {
+        /// How many blinks
+        count: u8,
+    },
+    /// Spell out letters, digits and spaces in Morse code
+    Morse(&'a str),
+}
+
+impl Pattern<'_> {
+    /// How long each sample is held for, in ms
+    pub fn step_ms(&self) -> u32 {
+        match self {
+            Pattern::Steady(_) => 1,
+            Pattern::Breathing { period_ms } => (period_ms / BREATHING_STEPS as u32).max(1),
+            Pattern::BlinkCode { .. } => BLINK_MS,
+            Pattern::Morse(_) => MORSE_UNIT_MS,
+        }
+    }
+
+    /// Writes the samples into `out`, returning how many there are
+    pub fn render(&self, out: &mut [u8]) -> Result<usize, Error> {
+        let mut writer = Writer { out, len: 0 };
+        match *self {
+            Pattern::Steady(brightness) => writer.push(brightness, 1)?,
+            Pattern::Breathing { .. } => {
+                let half = BREATHING_STEPS / 2;
+                for step in 0..BREATHING_STEPS {
+                    let distance = if step < half {
+                        step
+                    } else {
+                        BREATHING_STEPS - step
+                    };
+                    writer.push((distance * 255 / half) as u8, 1)?;
+                }
+            }
+            Pattern::BlinkCode { count } => {
+                for _ in 0..count {
+                    writer.push(255, 1)?;
+                    writer.push(0, 1)?;
+                }
+                writer.push(0, BLINK_PAUSE_STEPS)?;
+            }
+            Pattern::Morse(text) => {
+                let mut gap = 0;
+                for c in text.chars() {
+                    if c == ' ' {
+                        // between words
+                        gap = 7;
+                        continue;
+                    }
+                    let code = morse(c).ok_or(Error::NotMorse(c))?;
+                    writer.push(0, gap)?;
+                    for (i, symbol) in code.bytes().enumerate() {
+                        if i > 0 {
+                            // between the dots and dashes of one character
+                            writer.push(0, 1)?;
+                        }
+                        writer.push(255, if symbol == b'-' { 3 } else { 1 })?;
+                    }
+                    // between characters, unless a space makes it a word gap
+                    gap = 3;
+                }
+                // and before it starts again
+                writer.push(0, 7)?;
+            }
+        }
+        Ok(writer.len)
+    }
+}
+
+struct Writer<'a> {
+    out: &'a mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn push(&mut self, brightness: u8, steps: usize) -> Result<(), Error> {
+        let samples = self
+            .out
+            .get_mut(self.len..self.len + steps)
+            .ok_or(Error::TooLong)?;
+        samples.fill(brightness);
+        self.len += steps;
+        Ok(())
+    }
+}
+
+/// The Morse code for `c`, in dots and dashes
+pub fn morse(c: char) -> Option<&'static str> {
+    const LETTERS: [&str; 26] = [
+        ".-", "-...", "-.-.", "-..", ".", "..-.", "--.", "....", "..", ".---", "-.-", ".-..", "--",
+        "-.", "---", ".--.", "--.-", ".-.", "...", "-", "..-", "...-", ".--", "-..-", "-.--",
+        "--..",
+    ];
+    const DIGITS: [&str; 10] = [
+        "-----", ".----", "..---", "...--", "....-", ".....", "-....", "--...", "---..", "----.",
+    ];
+    match c.to_ascii_uppercase() {
+        c @ 'A'..='Z' => Some(LETTERS[usize::from(c as u8 - b'A')]),
+        c @ '0'..='9' => Some(DIGITS[usize::from(c as u8 - b'0')]),
+        _ => None,
+    }
+}
+
+/// The PWM duty cycle, out of `top`, for `brightness`
+///
+/// Our eyes see brightness on a roughly square-law scale, so we square it:
+/// half brightness is a quarter duty cycle.
+pub const fn duty(brightness: u8, top: u16) -> u16 {
+    let brightness = brightness as u32;
+    let full = 255 * 255;
+    ((brightness * brightness * top as u32 + full / 2) / full) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steady() {
+        let mut out = [0; 4];
+        assert_eq!(Pattern::Steady(42).render(&mut out), Ok(1));
+        assert_eq!(out[0], 42);
+    }
+
+    #[test]
+    fn breathing_goes_up_and_down() {
+        let mut out = [0; BREATHING_STEPS];
+        let pattern = Pattern::Breathing { period_ms: 2_000 };
+        assert_eq!(pattern.render(&mut out), Ok(BREATHING_STEPS));
+        assert_eq!(pattern.step_ms(), 31);
+        assert_eq!(out[0], 0);
+        assert_eq!(out[BREATHING_STEPS / 2], 255);
+        assert!(out[..=BREATHING_STEPS / 2].windows(2).all(|w| w[0] < w[1]));
+        assert!(out[BREATHING_STEPS / 2..].windows(2).all(|w| w[0] > w[1]));
+        // it loops smoothly
+        assert_eq!(out[1], out[BREATHING_STEPS - 1]);
+    }
+
+    #[test]
+    fn blink_code() {
+        let mut out = [0; 16];
+        let len = Pattern::BlinkCode { count: 3 }.render(&mut out).unwrap();
+        assert_eq!(&out[..len], &[255, 0, 255, 0, 255, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn morse_gaps() {
+        let mut out = [0; 64];
+        let len = Pattern::Morse("e e").render(&mut out).unwrap();
+        // dot, word gap, dot, word gap
+        assert_eq!(len, 1 + 7 + 1 + 7);
+        let len = Pattern::Morse("ee").render(&mut out).unwrap();
+        // dot, character gap, dot, word gap
+        assert_eq!(&out[..len], &[255, 0, 0, 0, 255, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            Pattern::Morse("hi!").render(&mut out),
+            Err(Error::NotMorse('!'))
+        );
+        assert_eq!(morse('s'), Some("..."));
+        assert_eq!(morse('0'), Some("-----"));
+    }
+
+    #[test]
+    fn too_long() {
+        let mut out = [0; 8];
+        assert_eq!(Pattern::Morse("sos").render(&mut out), Err(Error::TooLong));
+        assert_eq!(
+            Pattern::Breathing { period_ms: 1_000 }.render(&mut out),
+            Err(Error::TooLong)
+        );
+    }
+
+    #[test]
+    fn gamma() {
+        assert_eq!(duty(0, 1_000), 0);
+        assert_eq!(duty(255, 1_000), 1_000);
+        assert_eq!(duty(128, 1_000), 252);
+        // never dimmer for more brightness
+        assert!((0..255).all(|b| duty(b, 1_000) <= duty(b + 1, 1_000)));
+    }
+}
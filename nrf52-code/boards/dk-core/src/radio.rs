@@ -0,0 +1,606 @@
+//! The RADIO peripheral's state machine, in IEEE 802.15.4 mode
+//!
+//! [`Driver`] moves the RADIO between the states in figure 110 of the
+//! nRF52840 Product Specification, starts and finishes receptions and
+//! transmissions, and does Clear Channel Assessment. It touches the
+//! peripheral only through the [`Registers`] trait: `dk` implements that for
+//! the real RADIO, and [`model::Model`] implements it for a behavioural model
+//! of the RADIO which runs on the host.
+//!
+//! | From                      | Task      | To                 |
+//! |---------------------------|-----------|--------------------|
+//! | `Disabled`                | `RxEn`    | `RxRu` → `RxIdle`  |
+//! | `Disabled`, `RxIdle`      | `TxEn`    | `TxRu` → `TxIdle`  |
+//! | `RxIdle`                  | `Start`   | `Rx` (receive)     |
+//! | `RxIdle`                  | `CcaStart`| `Rx` (CCA)         |
+//! | `RxIdle`                  | `EdStart` | `Rx` (ED)          |
+//! | `TxIdle`                  | `Start`   | `Tx` → `TxIdle`    |
+//! | `Rx` / `Tx`               | `Stop`    | `RxIdle` / `TxIdle`|
+//! | any `Rx*` / `Tx*`         | `Disable` | `RxDisable` / `TxDisable` → `Disabled` |
+//!
+//! `TxIdle` → `RxEn` is missing on purpose: errata 204 (rev 1) says to go
+//! through `Disabled` instead.
+
+use core::sync::atomic::{compiler_fence, Ordering};
+
+pub mod model;
+
+/// The states of the RADIO (the `STATE` register)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum State {
+    /// Off
+    Disabled,
+    /// Ramping up the receiver
+    RxRu,
+    /// Ready to receive
+    RxIdle,
+    /// Receiving, or doing CCA or energy detection
+    Rx,
+    /// Turning the receiver off
+    RxDisable,
+    /// Ramping up the transmitter
+    TxRu,
+    /// Ready to transmit
+    TxIdle,
+    /// Transmitting
+    Tx,
+    /// Turning the transmitter off
+    TxDisable,
+}
+
+/// The RADIO tasks we use
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Task {
+    /// Enable the transmitter
+    TxEn,
+    /// Enable the receiver
+    RxEn,
+    /// Start receiving or transmitting
+    Start,
+    /// Stop receiving or transmitting
+    Stop,
+    /// Turn the RADIO off
+    Disable,
+    /// Start Clear Channel Assessment
+    CcaStart,
+    /// Stop Clear Channel Assessment
+    CcaStop,
+    /// Start energy detection
+    EdStart,
+}
+
+/// The RADIO events we use
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    /// Ramp-up is done
+    Ready,
+    /// The packet has been received or sent, and is in (or out of) RAM
+    End,
+    /// The last bit has gone over the air
+    PhyEnd,
+    /// The RADIO is off
+    Disabled,
+    /// CCA found the channel clear
+    CcaIdle,
+    /// CCA found the channel busy
+    CcaBusy,
+    /// Energy detection is done
+    EdEnd,
+}
+
+/// The RADIO shortcuts we use
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Shorts {
+    /// `READY` (when transmitting) triggers `START`
+    pub txready_start: bool,
+    /// `END` triggers `DISABLE`
+    pub end_disable: bool,
+    /// `CCAIDLE` triggers `TXEN`
+    pub ccaidle_txen: bool,
+    /// `ADDRESS` (the end of the SHR, when receiving) triggers `RSSISTART`
+    pub address_rssistart: bool,
+}
+
+/// What we know about a received packet, besides its contents
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RxInfo {
+    /// The received signal strength, in dBm
+    pub rssi: i8,
+    /// The Link Quality Indicator, as `Packet::lqi` reports it
+    pub lqi: u8,
+    /// The channel it came in on (11 to 26)
+    pub channel: u8,
+    /// When it was received, in `uptime_ticks` (32,768 Hz units)
+    pub timestamp: u64,
+}
+
+/// Converts an `RSSISAMPLE` reading to dBm
+///
+/// The register holds the received signal strength as a positive number;
+/// the signal is that many dBm below 1 mW.
+pub const fn rssi_dbm(sample: u8) -> i8 {
+    if sample > 127 {
+        -127
+    } else {
+        -(sample as i8)
+    }
+}
+
+/// Access to the RADIO registers that the state machine needs
+pub trait Registers {
+    /// Read `STATE`
+    fn state(&mut self) -> State;
+    /// Trigger a task
+    fn trigger(&mut self, task: Task);
+    /// Whether an event has happened
+    fn event(&mut self, event: Event) -> bool;
+    /// Clear an event
+    fn clear_event(&mut self, event: Event);
+    /// Set `SHORTS`
+    fn set_shorts(&mut self, shorts: Shorts);
+    /// Set `PACKETPTR`, where the RADIO reads and writes packets
+    fn set_packet_ptr(&mut self, ptr: *const u8);
+    /// Whether the packet just received had a good CRC
+    fn crc_ok(&mut self) -> bool;
+    /// The CRC of the packet just received
+    fn rx_crc(&mut self) -> u16;
+    /// Set `EDCNT`, how many energy detection samples to take
+    fn set_ed_count(&mut self, count: u32);
+    /// The energy detection result
+    fn ed_sample(&mut self) -> u8;
+    /// `RSSISAMPLE`, the last signal strength measurement
+    fn rssi_sample(&mut self) -> u8;
+}
+
+/// Drives the RADIO through its states
+pub struct Driver<R> {
+    regs: R,
+    /// Settings have changed since the RADIO was enabled; they only take
+    /// effect when it is enabled again
+    needs_enable: bool,
+}
+
+impl<R: Registers> Driver<R> {
+    /// Drive the RADIO through `regs`
+    pub const fn new(regs: R) -> Self {
+        Self {
+            regs,
+            needs_enable: false,
+        }
+    }
+
+    /// The registers
+    pub fn regs(&mut self) -> &mut R {
+        &mut self.regs
+    }
+
+    /// Note that a setting (channel, power, CCA mode) has changed, so the
+    /// RADIO must be enabled again before it is used
+    pub fn settings_changed(&mut self) {
+        self.needs_enable = true;
+    }
+
+    /// Waits until the RADIO is in the given `state`
+    pub fn wait_for_state(&mut self, state: State) {
+        while self.regs.state() != state {}
+    }
+
+    /// Waits out any ramp-up or disabling, and stops any reception or
+    /// transmission, so the RADIO is in `Disabled`, `RxIdle` or `TxIdle`
+    fn settle(&mut self) -> State {
+        loop {
+            match self.regs.state() {
+                state @ (State::Disabled | State::RxIdle | State::TxIdle) => return state,
+                State::RxRu => self.wait_for_state(State::RxIdle),
+                State::TxRu => self.wait_for_state(State::TxIdle),
+                State::RxDisable | State::TxDisable => self.wait_for_state(State::Disabled),
+                State::Rx => {
+                    self.regs.trigger(Task::CcaStop);
+                    self.regs.trigger(Task::Stop);
+                    self.wait_for_state(State::RxIdle);
+                }
+                State::Tx => {
+                    self.regs.trigger(Task::Stop);
+                    self.wait_for_state(State::TxIdle);
+                }
+            }
+        }
+    }
+
+    /// Moves the RADIO from any state to `Disabled`
+    pub fn disable(&mut self) {
+        if self.settle() != State::Disabled {
+            self.regs.trigger(Task::Disable);
+            self.wait_for_state(State::Disabled);
+        }
+    }
+
+    /// Moves the RADIO to `RxIdle`
+    pub fn put_in_rx_mode(&mut self) {
+        let state = self.settle();
+        // NOTE to avoid errata 204 (see rev1 v1.4) we do TXIDLE -> DISABLED -> RXIDLE
+        if state == State::RxIdle && !self.needs_enable {
+            return;
+        }
+        if state != State::Disabled {
+            self.regs.trigger(Task::Disable);
+            self.wait_for_state(State::Disabled);
+        }
+        self.needs_enable = false;
+        self.regs.trigger(Task::RxEn);
+        self.wait_for_state(State::RxIdle);
+    }
+
+    /// Moves the RADIO to `TxIdle`
+    pub fn put_in_tx_mode(&mut self) {
+        let state = self.settle();
+        if state == State::TxIdle && !self.needs_enable {
+            return;
+        }
+        if state != State::Disabled {
+            self.regs.trigger(Task::Disable);
+            self.wait_for_state(State::Disabled);
+        }
+        self.needs_enable = false;
+        self.regs.trigger(Task::TxEn);
+        self.wait_for_state(State::TxIdle);
+    }
+
+    /// Measures the energy on the channel, `sample_cycles` times, and
+    /// returns the largest measurement
+    pub fn energy_detection_scan(&mut self, sample_cycles: u32) -> u8 {
+        self.regs.set_ed_count(sample_cycles);
+        // ensure that the shortcut between READY event and START task is disabled before putting
+        // the radio into recv mode
+        self.regs.set_shorts(Shorts::default());
+        self.put_in_rx_mode();
+        self.regs.clear_event(Event::EdEnd);
+        self.regs.trigger(Task::EdStart);
+        while !self.regs.event(Event::EdEnd) {}
+        self.regs.clear_event(Event::EdEnd);
+        self.regs.ed_sample()
+    }
+
+    /// Starts receiving a packet into `buffer`
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must be valid for writes of a whole packet until
+    /// [`poll_recv`](Self::poll_recv) returns `Some`, or
+    /// [`cancel_recv`](Self::cancel_recv) is called.
+    pub unsafe fn start_recv(&mut self, buffer: *mut u8) {
+        self.regs.clear_event(Event::PhyEnd);
+        self.regs.clear_event(Event::End);
+        self.put_in_rx_mode();
+        // measure the signal strength as the frame starts
+        self.regs.set_shorts(Shorts {
+            address_rssistart: true,
+            ..Shorts::default()
+        });
+        self.regs.set_packet_ptr(buffer);
+        // the DMA transfer will start at some point after the following write operation so
+        // we place the compiler fence here
+        dma_start_fence();
+        self.regs.trigger(Task::Start);
+    }
+
+    /// If a packet has been received: `Ok` with its CRC, or `Err` with its
+    /// (bad) CRC
+    pub fn poll_recv(&mut self) -> Option<Result<u16, u16>> {
+        if !self.regs.event(Event::End) {
+            return None;
+        }
+        self.regs.clear_event(Event::End);
+        dma_end_fence();
+        let crc = self.regs.rx_crc();
+        Some(if self.regs.crc_ok() {
+            Ok(crc)
+        } else {
+            Err(crc)
+        })
+    }
+
+    /// The signal strength of the packet just received, in dBm
+    pub fn rssi(&mut self) -> i8 {
+        rssi_dbm(self.regs.rssi_sample())
+    }
+
+    /// Stops receiving, leaving the RADIO in `RxIdle`
+    pub fn cancel_recv(&mut self) {
+        self.regs.trigger(Task::Stop);
+        self.wait_for_state(State::RxIdle);
+        // DMA transfer may have been in progress so synchronize with its memory operations
+        dma_end_fence();
+    }
+
+    /// Starts CCA, then sending the packet in `buffer` if the channel is clear
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must be valid for reads of a whole packet until
+    /// [`poll_send`](Self::poll_send) returns `Some`, or
+    /// [`cancel_send`](Self::cancel_send) is called.
+    pub unsafe fn start_try_send(&mut self, buffer: *const u8) {
+        self.put_in_rx_mode();
+        self.regs.clear_event(Event::PhyEnd);
+        self.regs.clear_event(Event::End);
+        self.regs.clear_event(Event::CcaBusy);
+        self.regs.set_packet_ptr(buffer);
+        // CCAIDLE -> TXEN, READY -> START, END -> DISABLE
+        self.regs.set_shorts(Shorts {
+            txready_start: true,
+            end_disable: true,
+            ccaidle_txen: true,
+            ..Shorts::default()
+        });
+        dma_start_fence();
+        self.regs.trigger(Task::CcaStart);
+    }
+
+    /// Starts sending the packet in `buffer`, without CCA
+    ///
+    /// # Safety
+    ///
+    /// As for [`start_try_send`](Self::start_try_send)
+    pub unsafe fn start_send_no_cca(&mut self, buffer: *const u8) {
+        self.put_in_tx_mode();
+        self.regs.clear_event(Event::PhyEnd);
+        self.regs.clear_event(Event::End);
+        self.regs.clear_event(Event::CcaBusy);
+        self.regs.set_packet_ptr(buffer);
+        self.regs.set_shorts(Shorts {
+            end_disable: true,
+            ..Shorts::default()
+        });
+        dma_start_fence();
+        self.regs.trigger(Task::Start);
+    }
+
+    /// If the send has finished: `Ok` if the packet was sent, `Err` if CCA
+    /// found the channel busy
+    pub fn poll_send(&mut self) -> Option<Result<(), ()>> {
+        if self.regs.event(Event::PhyEnd) {
+            // transmission completed
+            dma_end_fence();
+            self.regs.clear_event(Event::PhyEnd);
+            self.regs.set_shorts(Shorts::default());
+            Some(Ok(()))
+        } else if self.regs.event(Event::CcaBusy) {
+            // channel is busy
+            self.regs.clear_event(Event::CcaBusy);
+            self.regs.set_shorts(Shorts::default());
+            Some(Err(()))
+        } else {
+            None
+        }
+    }
+
+    /// Abandons a send, turning the RADIO off
+    pub fn cancel_send(&mut self) {
+        self.regs.set_shorts(Shorts::default());
+        self.disable();
+        // DMA transfer may have been in progress so synchronize with its memory operations
+        dma_end_fence();
+    }
+
+    /// Sends the packet in `buffer` if CCA finds the channel clear
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must be valid for reads of a whole packet
+    #[allow(clippy::result_unit_err)]
+    pub unsafe fn try_send(&mut self, buffer: *const u8) -> Result<(), ()> {
+        self.start_try_send(buffer);
+        loop {
+            if let Some(result) = self.poll_send() {
+                return result;
+            }
+        }
+    }
+
+    /// Sends the packet in `buffer`, doing CCA again until the channel is clear
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must be valid for reads of a whole packet
+    pub unsafe fn send(&mut self, buffer: *const u8) {
+        while self.try_send(buffer).is_err() {}
+    }
+
+    /// Sends the packet in `buffer` without CCA
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must be valid for reads of a whole packet
+    pub unsafe fn send_no_cca(&mut self, buffer: *const u8) {
+        self.start_send_no_cca(buffer);
+        while self.poll_send().is_none() {}
+    }
+}
+
+/// NOTE must be followed by a volatile write operation
+fn dma_start_fence() {
+    compiler_fence(Ordering::Release);
+}
+
+/// NOTE must be preceded by a volatile read operation
+fn dma_end_fence() {
+    compiler_fence(Ordering::Acquire);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::model::Model;
+    use super::*;
+
+    const SETTLED: [State; 3] = [State::Disabled, State::RxIdle, State::TxIdle];
+    const ALL: [State; 9] = [
+        State::Disabled,
+        State::RxRu,
+        State::RxIdle,
+        State::Rx,
+        State::RxDisable,
+        State::TxRu,
+        State::TxIdle,
+        State::Tx,
+        State::TxDisable,
+    ];
+    const BUFFER: *const u8 = core::ptr::null();
+
+    #[test]
+    fn disable_from_anywhere() {
+        for state in ALL {
+            let mut driver = Driver::new(Model::in_state(state));
+            driver.disable();
+            assert_eq!(driver.regs().settle(), State::Disabled, "from {state:?}");
+        }
+    }
+
+    #[test]
+    fn rx_mode_from_anywhere() {
+        for state in ALL {
+            let mut driver = Driver::new(Model::in_state(state));
+            driver.put_in_rx_mode();
+            assert_eq!(driver.regs().settle(), State::RxIdle, "from {state:?}");
+        }
+    }
+
+    #[test]
+    fn tx_mode_from_anywhere() {
+        for state in ALL {
+            let mut driver = Driver::new(Model::in_state(state));
+            driver.put_in_tx_mode();
+            assert_eq!(driver.regs().settle(), State::TxIdle, "from {state:?}");
+        }
+    }
+
+    #[test]
+    fn new_settings_need_enable() {
+        for state in SETTLED {
+            let mut driver = Driver::new(Model::in_state(state));
+            driver.put_in_rx_mode();
+            let enables = driver.regs().count(Task::RxEn);
+            // already there
+            driver.put_in_rx_mode();
+            assert_eq!(driver.regs().count(Task::RxEn), enables);
+            // a new channel only takes effect after a fresh RXEN
+            driver.settings_changed();
+            driver.put_in_rx_mode();
+            assert_eq!(driver.regs().count(Task::RxEn), enables + 1);
+            assert_eq!(driver.regs().settle(), State::RxIdle);
+
+            driver.put_in_tx_mode();
+            driver.settings_changed();
+            let enables = driver.regs().count(Task::TxEn);
+            driver.put_in_tx_mode();
+            assert_eq!(driver.regs().count(Task::TxEn), enables + 1);
+        }
+    }
+
+    #[test]
+    fn errata_204_tx_to_rx_goes_through_disabled() {
+        let mut driver = Driver::new(Model::in_state(State::TxIdle));
+        driver.put_in_rx_mode();
+        assert_eq!(driver.regs().count(Task::Disable), 1);
+    }
+
+    #[test]
+    fn receive() {
+        for crc_ok in [true, false] {
+            let mut driver = Driver::new(Model::in_state(State::TxIdle));
+            driver.regs().hear(0x1234, crc_ok);
+            driver.regs().set_signal(60);
+            unsafe { driver.start_recv(core::ptr::null_mut()) };
+            let result = loop {
+                if let Some(result) = driver.poll_recv() {
+                    break result;
+                }
+            };
+            assert_eq!(result, if crc_ok { Ok(0x1234) } else { Err(0x1234) });
+            assert_eq!(driver.rssi(), -60);
+            assert_eq!(driver.regs().settle(), State::RxIdle);
+        }
+    }
+
+    #[test]
+    fn rssi_conversion() {
+        assert_eq!(rssi_dbm(0), 0);
+        assert_eq!(rssi_dbm(42), -42);
+        assert_eq!(rssi_dbm(127), -127);
+        assert_eq!(rssi_dbm(255), -127);
+    }
+
+    #[test]
+    fn cancel_receive() {
+        let mut driver = Driver::new(Model::in_state(State::Disabled));
+        unsafe { driver.start_recv(core::ptr::null_mut()) };
+        assert_eq!(driver.poll_recv(), None);
+        driver.cancel_recv();
+        assert_eq!(driver.regs().settle(), State::RxIdle);
+        // and we can carry on from there
+        driver.put_in_tx_mode();
+        assert_eq!(driver.regs().settle(), State::TxIdle);
+    }
+
+    #[test]
+    fn try_send_on_a_clear_channel() {
+        for state in SETTLED {
+            let mut driver = Driver::new(Model::in_state(state));
+            assert_eq!(unsafe { driver.try_send(BUFFER) }, Ok(()));
+            assert_eq!(driver.regs().transmitted(), 1);
+            // END -> DISABLE turned the radio off
+            assert_eq!(driver.regs().settle(), State::Disabled);
+        }
+    }
+
+    #[test]
+    fn try_send_on_a_busy_channel() {
+        let mut driver = Driver::new(Model::in_state(State::RxIdle));
+        driver.regs().busy_for(1);
+        assert_eq!(unsafe { driver.try_send(BUFFER) }, Err(()));
+        assert_eq!(driver.regs().transmitted(), 0);
+        assert_eq!(driver.regs().settle(), State::RxIdle);
+    }
+
+    #[test]
+    fn send_waits_for_a_clear_channel() {
+        let mut driver = Driver::new(Model::in_state(State::Disabled));
+        driver.regs().busy_for(3);
+        unsafe { driver.send(BUFFER) };
+        assert_eq!(driver.regs().count(Task::CcaStart), 4);
+        assert_eq!(driver.regs().transmitted(), 1);
+    }
+
+    #[test]
+    fn send_no_cca() {
+        for state in SETTLED {
+            let mut driver = Driver::new(Model::in_state(state));
+            unsafe { driver.send_no_cca(BUFFER) };
+            assert_eq!(driver.regs().count(Task::CcaStart), 0);
+            assert_eq!(driver.regs().transmitted(), 1);
+            assert_eq!(driver.regs().settle(), State::Disabled);
+        }
+    }
+
+    #[test]
+    fn cancel_send() {
+        let mut driver = Driver::new(Model::in_state(State::Disabled));
+        unsafe { driver.start_send_no_cca(BUFFER) };
+        driver.cancel_send();
+        assert_eq!(driver.regs().settle(), State::Disabled);
+        assert_eq!(driver.regs().transmitted(), 0);
+    }
+
+    #[test]
+    fn energy_detection() {
+        let mut driver = Driver::new(Model::in_state(State::TxIdle));
+        driver.regs().set_energy(42);
+        assert_eq!(driver.energy_detection_scan(10), 42);
+        assert_eq!(driver.regs().settle(), State::RxIdle);
+    }
+}
@@ -0,0 +1,428 @@
+//! A behavioural model of the RADIO, for testing [`Driver`](super::Driver) on the host
+//!
+//! The [`Model`] follows the state diagram in figure 110 of the nRF52840
+//! Product Specification. Ramp-up, disabling and sending each take a few
+//! register reads to finish, rather than a few microseconds. Triggering a task
+//! which the diagram doesn't allow in the current state panics, and so does
+//! polling a RADIO that will never change state.
+
+use super::{Event, Registers, Shorts, State, Task};
+
+/// How many register reads ramp-up and disabling take
+const RAMP: u8 = 2;
+/// How many register reads a reception, transmission, CCA or energy
+/// detection takes
+const AIRTIME: u8 = 3;
+/// How many register reads, with nothing left to happen, we take as a hang
+const STUCK: u32 = 1_000;
+
+/// What the RADIO is doing in the `Rx` state
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Activity {
+    Receive,
+    Cca,
+    EnergyDetection,
+}
+
+/// A model of the RADIO peripheral
+#[derive(Debug)]
+pub struct Model {
+    state: State,
+    activity: Activity,
+    /// Register reads left until the current step finishes
+    countdown: u8,
+    /// Register reads since anything last happened
+    idle_reads: u32,
+    events: u8,
+    shorts: Shorts,
+    tasks: [u32; 8],
+    /// The next frame in the air: its CRC, and whether that is correct
+    heard: Option<(u16, bool)>,
+    /// How strong received frames are, as `RSSISAMPLE` reads
+    signal: u8,
+    rssi_sample: u8,
+    rx_crc: u16,
+    crc_ok: bool,
+    busy_ccas: u32,
+    energy: u8,
+    transmitted: u32,
+}
+
+impl Model {
+    /// A RADIO that has just been reset
+    pub const fn new() -> Self {
+        Self {
+            state: State::Disabled,
+            activity: Activity::Receive,
+            countdown: 0,
+            idle_reads: 0,
+            events: 0,
+            shorts: Shorts {
+                txready_start: false,
+                end_disable: false,
+                ccaidle_txen: false,
+                address_rssistart: false,
+            },
+            tasks: [0; 8],
+            heard: None,
+            signal: 0,
+            rssi_sample: 0,
+            rx_crc: 0,
+            crc_ok: false,
+            busy_ccas: 0,
+            energy: 0,
+            transmitted: 0,
+        }
+    }
+
+    /// A RADIO that is in `state`, or just entered it
+    ///
+    /// `Rx` is waiting for a packet and `Tx` is part way through sending one.
+    pub const fn in_state(state: State) -> Self {
+        let mut model = Self::new();
+        model.state = state;
+        model.countdown = match state {
+            State::RxRu | State::TxRu | State::RxDisable | State::TxDisable => RAMP,
+            State::Tx => AIRTIME,
+            _ => 0,
+        };
+        model
+    }
+
+    /// The current state, without letting any time pass
+    pub fn current_state(&self) -> State {
+        self.state
+    }
+
+    /// Lets time pass until the RADIO is no longer ramping up, disabling or
+    /// sending, and returns the state it ends up in
+    pub fn settle(&mut self) -> State {
+        while matches!(
+            self.state,
+            State::RxRu | State::TxRu | State::RxDisable | State::TxDisable | State::Tx
+        ) {
+            self.step();
+        }
+        self.state
+    }
+
+    /// The next time the RADIO receives, it hears a frame with this CRC
+    pub fn hear(&mut self, crc: u16, crc_ok: bool) {
+        self.heard = Some((crc, crc_ok));
+    }
+
+    /// Frames arrive with this signal strength, as `RSSISAMPLE` reads it
+    pub fn set_signal(&mut self, sample: u8) {
+        self.signal = sample;
+    }
+
+    /// The next `count` CCAs find the channel busy
+    pub fn busy_for(&mut self, count: u32) {
+        self.busy_ccas = count;
+    }
+
+    /// Energy detection measures `energy`
+    pub fn set_energy(&mut self, energy: u8) {
+        self.energy = energy;
+    }
+
+    /// How many packets the RADIO has sent
+    pub fn transmitted(&self) -> u32 {
+        self.transmitted
+    }
+
+    /// How many times `task` has been triggered
+    pub fn count(&self, task: Task) -> u32 {
+        self.tasks[task as usize]
+    }
+
+    fn raise(&mut self, event: Event) {
+        self.events |= 1 << event as u8;
+    }
+
+    fn begin(&mut self, state: State, countdown: u8) {
+        self.state = state;
+        self.countdown = countdown;
+    }
+
+    fn illegal(&self, task: Task) -> ! {
+        panic!("{task:?} triggered in state {:?}", self.state)
+    }
+
+    /// Lets one register read's worth of time pass
+    fn step(&mut self) {
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            self.idle_reads = 0;
+            return;
+        }
+        match (self.state, self.activity) {
+            (State::RxRu, _) => {
+                self.begin(State::RxIdle, 0);
+                self.raise(Event::Ready);
+            }
+            (State::TxRu, _) => {
+                self.begin(State::TxIdle, 0);
+                self.raise(Event::Ready);
+                if self.shorts.txready_start {
+                    self.trigger(Task::Start);
+                }
+            }
+            (State::RxDisable | State::TxDisable, _) => {
+                self.begin(State::Disabled, 0);
+                self.raise(Event::Disabled);
+            }
+            (State::Tx, _) => {
+                self.transmitted += 1;
+                self.begin(State::TxIdle, 0);
+                self.raise(Event::End);
+                self.raise(Event::PhyEnd);
+                if self.shorts.end_disable {
+                    self.trigger(Task::Disable);
+                }
+            }
+            (State::Rx, Activity::Receive) if self.heard.is_some() => {
+                let (crc, crc_ok) = self.heard.take().unwrap();
+                self.rx_crc = crc;
+                self.crc_ok = crc_ok;
+                // the signal is only measured if something starts the measurement
+                if self.shorts.address_rssistart {
+                    self.rssi_sample = self.signal;
+                }
+                self.begin(State::RxIdle, 0);
+                self.raise(Event::End);
+                self.raise(Event::PhyEnd);
+                if self.shorts.end_disable {
+                    self.trigger(Task::Disable);
+                }
+            }
+            (State::Rx, Activity::Cca) => {
+                self.begin(State::RxIdle, 0);
+                if self.busy_ccas > 0 {
+                    self.busy_ccas -= 1;
+                    self.raise(Event::CcaBusy);
+                } else {
+                    self.raise(Event::CcaIdle);
+                    if self.shorts.ccaidle_txen {
+                        self.trigger(Task::TxEn);
+                    }
+                }
+            }
+            (State::Rx, Activity::EnergyDetection) => {
+                self.begin(State::RxIdle, 0);
+                self.raise(Event::EdEnd);
+            }
+            _ => {
+                self.idle_reads += 1;
+                assert!(
+                    self.idle_reads < STUCK,
+                    "the RADIO is stuck in state {:?}",
+                    self.state
+                );
+            }
+        }
+    }
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Registers for Model {
+    fn state(&mut self) -> State {
+        self.step();
+        self.state
+    }
+
+    fn trigger(&mut self, task: Task) {
+        self.tasks[task as usize] += 1;
+        self.idle_reads = 0;
+        match (task, self.state) {
+            (Task::RxEn, State::Disabled) => self.begin(State::RxRu, RAMP),
+            (Task::RxEn, State::TxIdle) => {
+                panic!("RxEn triggered in state TxIdle; see errata 204")
+            }
+            (Task::TxEn, State::Disabled | State::RxIdle) => self.begin(State::TxRu, RAMP),
+            (Task::Start, State::RxIdle) => {
+                self.activity = Activity::Receive;
+                self.begin(State::Rx, AIRTIME);
+            }
+            (Task::Start, State::TxIdle) => self.begin(State::Tx, AIRTIME),
+            (Task::CcaStart, State::RxIdle) => {
+                self.activity = Activity::Cca;
+                self.begin(State::Rx, AIRTIME);
+            }
+            (Task::EdStart, State::RxIdle) => {
+                self.activity = Activity::EnergyDetection;
+                self.begin(State::Rx, AIRTIME);
+            }
+            (Task::Stop, State::Rx) => self.begin(State::RxIdle, 0),
+            (Task::Stop, State::Tx) => self.begin(State::TxIdle, 0),
+            (Task::Stop, State::RxIdle | State::TxIdle) => {}
+            (Task::CcaStop, State::Rx) if self.activity == Activity::Cca => {
+                self.begin(State::RxIdle, 0)
+            }
+            (Task::CcaStop, State::RxIdle | State::Rx) => {}
+            (Task::Disable, State::RxRu | State::RxIdle | State::Rx) => {
+                self.begin(State::RxDisable, RAMP)
+            }
+            (Task::Disable, State::TxRu | State::TxIdle | State::Tx) => {
+                self.begin(State::TxDisable, RAMP)
+            }
+            (Task::Disable, State::Disabled | State::RxDisable | State::TxDisable) => {}
+            _ => self.illegal(task),
+        }
+    }
+
+    fn event(&mut self, event: Event) -> bool {
+        self.step();
+        self.events & (1 << event as u8) != 0
+    }
+
+    fn clear_event(&mut self, event: Event) {
+        self.events &= !(1 << event as u8);
+    }
+
+    fn set_shorts(&mut self, shorts: Shorts) {
+        self.shorts = shorts;
+    }
+
+    fn set_packet_ptr(&mut self, _ptr: *const u8) {}
+
+    fn crc_ok(&mut self) -> bool {
+        self.crc_ok
+    }
+
+    fn rx_crc(&mut self) -> u16 {
+        self.rx_crc
+    }
+
+    fn set_ed_count(&mut self, _count: u32) {}
+
+    fn ed_sample(&mut self) -> u8 {
+        self.energy
+    }
+
+    fn rssi_sample(&mut self) -> u8 {
+        self.rssi_sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    /// Every transition in figure 110, as (from, task, where it settles)
+    const LEGAL: &[(State, Task, State)] = &[
+        (State::Disabled, Task::RxEn, State::RxIdle),
+        (State::Disabled, Task::TxEn, State::TxIdle),
+        (State::Disabled, Task::Disable, State::Disabled),
+        (State::RxRu, Task::Disable, State::Disabled),
+        (State::RxIdle, Task::Start, State::Rx),
+        (State::RxIdle, Task::CcaStart, State::Rx),
+        (State::RxIdle, Task::EdStart, State::Rx),
+        (State::RxIdle, Task::TxEn, State::TxIdle),
+        (State::RxIdle, Task::Stop, State::RxIdle),
+        (State::RxIdle, Task::Disable, State::Disabled),
+        (State::Rx, Task::Stop, State::RxIdle),
+        (State::Rx, Task::Disable, State::Disabled),
+        (State::RxDisable, Task::Disable, State::Disabled),
+        (State::TxRu, Task::Disable, State::Disabled),
+        (State::TxIdle, Task::Start, State::TxIdle),
+        (State::TxIdle, Task::Stop, State::TxIdle),
+        (State::TxIdle, Task::Disable, State::Disabled),
+        (State::Tx, Task::Stop, State::TxIdle),
+        (State::Tx, Task::Disable, State::Disabled),
+        (State::TxDisable, Task::Disable, State::Disabled),
+    ];
+
+    #[test]
+    fn legal_transitions() {
+        for &(from, task, to) in LEGAL {
+            let mut model = Model::in_state(from);
+            model.trigger(task);
+            assert_eq!(model.settle(), to, "{task:?} from {from:?}");
+        }
+    }
+
+    #[test]
+    fn illegal_tasks_panic() {
+        let all = [
+            State::Disabled,
+            State::RxRu,
+            State::RxIdle,
+            State::Rx,
+            State::RxDisable,
+            State::TxRu,
+            State::TxIdle,
+            State::Tx,
+            State::TxDisable,
+        ];
+        let tasks = [
+            Task::TxEn,
+            Task::RxEn,
+            Task::Start,
+            Task::Stop,
+            Task::Disable,
+            Task::CcaStart,
+            Task::CcaStop,
+            Task::EdStart,
+        ];
+        let mut legal = 0;
+        for from in all {
+            for task in tasks {
+                let result = std::panic::catch_unwind(|| Model::in_state(from).trigger(task));
+                if result.is_ok() {
+                    legal += 1;
+                }
+            }
+        }
+        // the transitions above, plus CcaStop in RxIdle and Rx
+        assert_eq!(legal, LEGAL.len() + 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "errata 204")]
+    fn tx_straight_to_rx() {
+        Model::in_state(State::TxIdle).trigger(Task::RxEn);
+    }
+
+    #[test]
+    fn ramp_up_raises_ready() {
+        let mut model = Model::new();
+        model.trigger(Task::RxEn);
+        assert_eq!(model.state(), State::RxRu);
+        while !model.event(Event::Ready) {}
+        assert_eq!(model.current_state(), State::RxIdle);
+        model.clear_event(Event::Ready);
+        assert!(!model.event(Event::Ready));
+    }
+
+    #[test]
+    fn shorts_chain_a_send() {
+        let mut model = Model::in_state(State::RxIdle);
+        model.set_shorts(Shorts {
+            txready_start: true,
+            end_disable: true,
+            ccaidle_txen: true,
+            address_rssistart: false,
+        });
+        model.trigger(Task::CcaStart);
+        while !model.event(Event::PhyEnd) {}
+        assert!(model.event(Event::CcaIdle));
+        assert_eq!(model.settle(), State::Disabled);
+        assert_eq!(model.transmitted(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "stuck")]
+    fn waiting_forever_panics() {
+        let mut model = Model::in_state(State::TxIdle);
+        // nothing will ever raise END
+        while !model.event(Event::End) {}
+    }
+}
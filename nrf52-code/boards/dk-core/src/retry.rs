@@ -0,0 +1,300 @@
+//! How `send_recv` retries a request that gets no good reply
+//!
+//! `dk::send_recv_with` sends a request and waits for the reply, and tries
+//! again if none comes back. [`SendRecvConfig`] says how often, and how long
+//! to back off in between; [`retry`] runs the attempts, so the policy can be
+//! tested without a radio.
+//!
+//! ```
+//! use dk_core::retry::{retry, Failure, Jitter, SendRecvConfig};
+//!
+//! let config = SendRecvConfig::exponential();
+//! let mut waited = 0;
+//! let result = retry(
+//!     &config,
+//!     &mut Jitter::new(0x1234_5678_9abc),
+//!     &mut waited,
+//!     |waited, us| *waited += us,
+//!     |_, attempt| if attempt < 3 { Err(Failure::Timeout) } else { Ok(()) },
+//! );
+//! assert_eq!(result, Ok(3));
+//! assert!(waited > 0);
+//! ```
+
+/// How `send_recv` retries
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SendRecvConfig {
+    /// How many times to send the request before giving up
+    pub max_attempts: u32,
+    /// How long to wait for each reply, in microseconds
+    pub timeout_us: u32,
+    /// How long to wait before the first send, in microseconds, so a loop of
+    /// requests doesn't hog the channel
+    pub initial_delay_us: u32,
+    /// How long to back off after the first failure, in microseconds. It
+    /// doubles with each failure after that.
+    pub backoff_us: u32,
+    /// The longest we back off for, in microseconds
+    pub max_backoff_us: u32,
+    /// Back off for a random time between half and all of the back-off, so
+    /// two devices which collided once don't keep colliding
+    pub jitter: bool,
+}
+
+impl SendRecvConfig {
+    /// Ten attempts, 10 ms apart, with a 10 ms timeout: what `send_recv` has
+    /// always done
+    pub const fn new() -> Self {
+        Self {
+            max_attempts: 10,
+            timeout_us: 10_000,
+            initial_delay_us: 5_000,
+            backoff_us: 10_000,
+            max_backoff_us: 10_000,
+            jitter: false,
+        }
+    }
+
+    /// Back off for 1 ms, then 2 ms, 4 ms and so on up to 64 ms, with jitter
+    pub const fn exponential() -> Self {
+        Self {
+            backoff_us: 1_000,
+            max_backoff_us: 64_000,
+            jitter: true,
+            ..Self::new()
+        }
+    }
+
+    /// How long to back off after `failures` failed attempts (at least one)
+    pub fn backoff(&self, failures: u32, jitter: &mut Jitter) -> u32 {
+        let doublings = failures.saturating_sub(1).min(31);
+        let backoff = self
+            .backoff_us
+            .saturating_mul(1 << doublings)
+            .min(self.max_backoff_us);
+        if self.jitter {
+            let half = backoff / 2;
+            backoff - half + jitter.below(half + 1)
+        } else {
+            backoff
+        }
+    }
+}
+
+impl Default for SendRecvConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Random numbers for the back-off, seeded from the device ID so that every
+/// device backs off differently
+#[derive(Debug, Clone)]
+pub struct Jitter {
+    state: u32,
+}
+
+impl Jitter {
+    /// Random numbers seeded from `device_id`
+    pub const fn new(device_id: u64) -> Self {
+        let state = (device_id ^ (device_id >> 32)) as u32;
+        // xorshift never leaves zero
+        Self {
+            state: if state == 0 { 0x9e37_79b9 } else { state },
+        }
+    }
+
+    /// A number in `0..bound`, or zero if `bound` is zero
+    pub fn below(&mut self, bound: u32) -> u32 {
+        // xorshift32
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        if bound == 0 {
+            0
+        } else {
+            self.state % bound
+        }
+    }
+}
+
+/// Why an attempt failed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Failure {
+    /// No reply came back in time
+    Timeout,
+    /// The reply had a bad CRC
+    Crc(u16),
+    /// The reply was for somebody else
+    WrongAddress,
+}
+
+/// Every attempt failed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Exhausted {
+    /// How many times we sent the request
+    pub attempts: u32,
+    /// Why the last attempt failed
+    pub last: Failure,
+}
+
+/// A good reply to `send_recv`
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Response<'a> {
+    /// The reply, without our device address
+    pub data: &'a [u8],
+    /// How many times we sent the request (one if it worked first time)
+    pub attempts: u32,
+}
+
+/// Calls `attempt` (with the attempt number, from 1) until it succeeds or
+/// `config.max_attempts` have failed, calling `delay_us` to wait before the
+/// first attempt and between attempts
+///
+/// Both get `context` (usually the timer), which they would otherwise have
+/// to share. Returns how many attempts were made.
+pub fn retry<C>(
+    config: &SendRecvConfig,
+    jitter: &mut Jitter,
+    context: &mut C,
+    mut delay_us: impl FnMut(&mut C, u32),
+    mut attempt: impl FnMut(&mut C, u32) -> Result<(), Failure>,
+) -> Result<u32, Exhausted> {
+    let max_attempts = config.max_attempts.max(1);
+    delay_us(context, config.initial_delay_us);
+    let mut number = 1;
+    loop {
+        match attempt(context, number) {
+            Ok(()) => return Ok(number),
+            Err(last) if number == max_attempts => {
+                return Err(Exhausted {
+                    attempts: number,
+                    last,
+                })
+            }
+            Err(_) => delay_us(context, config.backoff(number, jitter)),
+        }
+        number += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_is_fixed() {
+        let config = SendRecvConfig::default();
+        let mut jitter = Jitter::new(1);
+        for failures in 1..10 {
+            assert_eq!(config.backoff(failures, &mut jitter), 10_000);
+        }
+    }
+
+    #[test]
+    fn exponential_backoff() {
+        let config = SendRecvConfig {
+            jitter: false,
+            ..SendRecvConfig::exponential()
+        };
+        let mut jitter = Jitter::new(1);
+        let backoffs = [1, 2, 3, 7, 8, 100].map(|failures| config.backoff(failures, &mut jitter));
+        assert_eq!(backoffs, [1_000, 2_000, 4_000, 64_000, 64_000, 64_000]);
+    }
+
+    #[test]
+    fn jitter_stays_in_range_and_depends_on_the_device() {
+        let config = SendRecvConfig::exponential();
+        let mut a = Jitter::new(0x0000_1111_2222_3333);
+        let mut b = Jitter::new(0x0000_4444_5555_6666);
+        let mut differed = false;
+        for failures in 1..20 {
+            let full = config
+                .backoff_us
+                .saturating_mul(1 << (failures - 1).min(31));
+            let full = full.min(config.max_backoff_us);
+            let from_a = config.backoff(failures, &mut a);
+            let from_b = config.backoff(failures, &mut b);
+            assert!((full - full / 2..=full).contains(&from_a));
+            differed |= from_a != from_b;
+        }
+        assert!(differed);
+        assert_eq!(Jitter::new(0).below(10), Jitter::new(0).below(10));
+        assert_eq!(Jitter::new(7).below(0), 0);
+    }
+
+    #[test]
+    fn gives_up_with_the_last_failure() {
+        let config = SendRecvConfig::new();
+        // (delays, time waited)
+        let mut waits = (0, 0);
+        let result = retry(
+            &config,
+            &mut Jitter::new(1),
+            &mut waits,
+            |(delays, waited), us| {
+                *delays += 1;
+                *waited += us;
+            },
+            |_, attempt| {
+                Err(if attempt % 2 == 0 {
+                    Failure::Crc(0xbeef)
+                } else {
+                    Failure::WrongAddress
+                })
+            },
+        );
+        assert_eq!(
+            result,
+            Err(Exhausted {
+                attempts: 10,
+                last: Failure::Crc(0xbeef)
+            })
+        );
+        // before the first attempt, then between each
+        assert_eq!(waits, (10, 5_000 + 9 * 10_000));
+    }
+
+    #[test]
+    fn stops_at_the_first_success() {
+        let mut attempts = 0;
+        let result = retry(
+            &SendRecvConfig::new(),
+            &mut Jitter::new(1),
+            &mut attempts,
+            |_, _| {},
+            |attempts, _| {
+                *attempts += 1;
+                Ok(())
+            },
+        );
+        assert_eq!(result, Ok(1));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn always_tries_once() {
+        let config = SendRecvConfig {
+            max_attempts: 0,
+            ..SendRecvConfig::new()
+        };
+        let result = retry(
+            &config,
+            &mut Jitter::new(1),
+            &mut (),
+            |_, _| {},
+            |_, _| Err(Failure::Timeout),
+        );
+        assert_eq!(
+            result,
+            Err(Exhausted {
+                attempts: 1,
+                last: Failure::Timeout
+            })
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Turning the nRF52840's sensor readings into units
+//!
+//! `dk::sensors` reads the registers; these functions work out what the
+//! numbers mean.
+//!
+//! ```
+//! use dk_core::sensor::{self, COIN_CELL};
+//!
+//! // the TEMP peripheral counts in quarter degrees
+//! assert_eq!(sensor::centidegrees(93), 2325);
+//! // a 12-bit SAADC sample of VDD
+//! let millivolts = sensor::vdd_millivolts(3413);
+//! assert_eq!(millivolts, 3000);
+//! assert_eq!(sensor::battery_percent(millivolts, COIN_CELL), 100);
+//! ```
+
+/// The temperature in hundredths of a degree Celsius, from the TEMP
+/// peripheral's `TEMP` register, which counts in quarter degrees
+pub const fn centidegrees(raw: i32) -> i32 {
+    raw * 25
+}
+
+/// What a full-scale SAADC sample means, in millivolts: the 0.6 V internal
+/// reference with a gain of 1/6
+pub const FULL_SCALE_MV: u32 = 3_600;
+
+/// How many steps a 12-bit SAADC sample has
+pub const STEPS: u32 = 1 << 12;
+
+/// VDD in millivolts, from a 12-bit single-ended SAADC sample taken with
+/// the internal reference and a gain of 1/6
+///
+/// Noise can make a single-ended sample of a grounded input slightly
+/// negative; that reads as 0 mV.
+pub const fn vdd_millivolts(sample: i16) -> u16 {
+    if sample < 0 {
+        return 0;
+    }
+    ((sample as u32 * FULL_SCALE_MV + STEPS / 2) / STEPS) as u16
+}
+
+/// How full a battery is at a given voltage: `(millivolts, percent)` pairs,
+/// from full to empty
+pub type DischargeCurve = &'static [(u16, u8)];
+
+/// A CR2032 lithium coin cell, like the one in the DK's battery holder,
+/// under a light load
+pub const COIN_CELL: DischargeCurve = &[
+    (3_000, 100),
+    (2_900, 80),
+    (2_800, 60),
+    (2_700, 40),
+    (2_600, 20),
+    (2_500, 10),
+    (2_000, 0),
+];
+
+/// How full the battery is, in percent, by interpolating along `curve`
+///
+/// Anything above the first point is 100% (or what the first point says),
+/// and anything below the last one is what the last point says.
+pub fn battery_percent(millivolts: u16, curve: DischargeCurve) -> u8 {
+    let Some(&(first_mv, first_percent)) = curve.first() else {
+        return 0;
+    };
+    if millivolts >= first_mv {
+        return first_percent;
+    }
+    for pair in curve.windows(2) {
+        let (high_mv, high_percent) = pair[0];
+        let (low_mv, low_percent) = pair[1];
+        if millivolts >= low_mv {
+            let span_mv = u32::from(high_mv - low_mv);
+            let span_percent = u32::from(high_percent - low_percent);
+            let above = u32::from(millivolts - low_mv);
+            let extra = (above * span_percent + span_mv / 2) / span_mv;
+            return low_percent + extra as u8;
+        }
+    }
+    curve[curve.len() - 1].1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temperature() {
+        assert_eq!(centidegrees(0), 0);
+        assert_eq!(centidegrees(1), 25);
+        assert_eq!(centidegrees(-5), -125);
+        assert_eq!(centidegrees(100), 2_500);
+    }
+
+    #[test]
+    fn vdd() {
+        assert_eq!(vdd_millivolts(0), 0);
+        assert_eq!(vdd_millivolts(-3), 0);
+        assert_eq!(vdd_millivolts(4_095), 3_599);
+        // 3.3 V
+        assert_eq!(vdd_millivolts(3_755), 3_300);
+        // and every step is less than a millivolt
+        assert_eq!(vdd_millivolts(3_754), 3_299);
+    }
+
+    #[test]
+    fn coin_cell() {
+        assert_eq!(battery_percent(3_300, COIN_CELL), 100);
+        assert_eq!(battery_percent(3_000, COIN_CELL), 100);
+        assert_eq!(battery_percent(2_950, COIN_CELL), 90);
+        assert_eq!(battery_percent(2_800, COIN_CELL), 60);
+        assert_eq!(battery_percent(2_250, COIN_CELL), 5);
+        assert_eq!(battery_percent(1_800, COIN_CELL), 0);
+        // it never goes up as the voltage goes down
+        assert!((1_900..3_100)
+            .all(|mv| battery_percent(mv, COIN_CELL) <= battery_percent(mv + 1, COIN_CELL)));
+    }
+
+    #[test]
+    fn other_curves() {
+        const TWO_POINTS: DischargeCurve = &[(4_200, 100), (3_300, 0)];
+        assert_eq!(battery_percent(3_750, TWO_POINTS), 50);
+        assert_eq!(battery_percent(3_000, TWO_POINTS), 0);
+        assert_eq!(battery_percent(3_000, &[]), 0);
+    }
+}
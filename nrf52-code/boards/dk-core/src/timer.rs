@@ -0,0 +1,255 @@
+//! Several timers on one hardware timer
+//!
+//! `dk::timer::TimerService` leaves one hardware TIMER counting
+//! microseconds, and keeps a [`Timers`] table of deadlines against that
+//! count: one-shot timeouts and periodic ticks, each with its own
+//! [`TimerId`]. Nothing interrupts you when a deadline passes; you
+//! [`poll`](Timers::poll) for it, like `Timer::reset_if_finished`.
+//!
+//! ```
+//! use dk_core::timer::Timers;
+//!
+//! let mut timers = Timers::<2>::new();
+//! let blink = timers.alloc().unwrap();
+//! let timeout = timers.alloc().unwrap();
+//! timers.start_periodic(blink, 0, 500_000);
+//! timers.start(timeout, 0, 1_200_000);
+//!
+//! assert!(!timers.poll(blink, 499_999));
+//! assert!(timers.poll(blink, 500_000));
+//! assert!(timers.poll(blink, 1_000_000));
+//! assert!(timers.poll(timeout, 1_200_000));
+//! // one-shot timers fire once
+//! assert!(!timers.poll(timeout, 2_000_000));
+//! assert_eq!(timers.next_deadline(), Some(1_500_000));
+//! ```
+
+/// Which timer, in a [`Timers`] table
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TimerId(u8);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Slot {
+    Free,
+    Stopped,
+    Running {
+        deadline: u64,
+        // zero for a one-shot timer
+        period: u64,
+    },
+}
+
+/// A table of up to `N` timers, against a microsecond clock that the caller
+/// reads (`now`)
+#[derive(Debug, Clone)]
+pub struct Timers<const N: usize> {
+    slots: [Slot; N],
+}
+
+impl<const N: usize> Timers<N> {
+    /// An empty table
+    pub const fn new() -> Self {
+        assert!(N <= u8::MAX as usize, "too many timers");
+        Self {
+            slots: [Slot::Free; N],
+        }
+    }
+
+    /// A new, stopped timer, or `None` if all `N` are in use
+    pub fn alloc(&mut self) -> Option<TimerId> {
+        let index = self.slots.iter().position(|slot| *slot == Slot::Free)?;
+        self.slots[index] = Slot::Stopped;
+        Some(TimerId(index as u8))
+    }
+
+    /// Gives `id` back, for `alloc` to hand out again
+    pub fn free(&mut self, id: TimerId) {
+        self.slots[usize::from(id.0)] = Slot::Free;
+    }
+
+    /// Fire once, `after` microseconds from `now`
+    pub fn start(&mut self, id: TimerId, now: u64, after: u64) {
+        self.slots[usize::from(id.0)] = Slot::Running {
+            deadline: now.saturating_add(after),
+            period: 0,
+        };
+    }
+
+    /// Fire every `period` microseconds, starting `period` from `now`
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `period` is zero
+    pub fn start_periodic(&mut self, id: TimerId, now: u64, period: u64) {
+        assert!(period > 0, "a periodic timer needs a period");
+        self.slots[usize::from(id.0)] = Slot::Running {
+            deadline: now.saturating_add(period),
+            period,
+        };
+    }
+
+    /// Stop `id`, so it doesn't fire
+    pub fn stop(&mut self, id: TimerId) {
+        self.slots[usize::from(id.0)] = Slot::Stopped;
+    }
+
+    /// Whether `id` is going to fire
+    pub fn is_running(&self, id: TimerId) -> bool {
+        matches!(self.slots[usize::from(id.0)], Slot::Running { .. })
+    }
+
+    /// Whether `id` has fired by `now`
+    ///
+    /// A one-shot timer then stops. A periodic one moves on to its next
+    /// deadline after `now`: if you poll too slowly to see every period, the
+    /// ones you missed are skipped, rather than firing back to back.
+    pub fn poll(&mut self, id: TimerId, now: u64) -> bool {
+        let slot = &mut self.slots[usize::from(id.0)];
+        match *slot {
+            Slot::Running { deadline, period } if deadline <= now => {
+                // a one-shot timer has no period
+                *slot = match (now - deadline).checked_div(period) {
+                    None => Slot::Stopped,
+                    Some(missed) => Slot::Running {
+                        deadline: deadline + (missed + 1) * period,
+                        period,
+                    },
+                };
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// How long until `id` fires, in microseconds, or `None` if it's stopped
+    pub fn remaining(&self, id: TimerId, now: u64) -> Option<u64> {
+        match self.slots[usize::from(id.0)] {
+            Slot::Running { deadline, .. } => Some(deadline.saturating_sub(now)),
+            _ => None,
+        }
+    }
+
+    /// The soonest deadline of all the running timers
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.slots
+            .iter()
+            .filter_map(|slot| match slot {
+                Slot::Running { deadline, .. } => Some(*deadline),
+                _ => None,
+            })
+            .min()
+    }
+}
+
+impl<const N: usize> Default for Timers<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Extends a 32-bit counter which wraps around to 64 bits
+///
+/// A 1 MHz TIMER wraps every 71 minutes, so it has to be read at least that
+/// often for `extend` to notice every wrap.
+#[derive(Debug, Clone, Default)]
+pub struct WrappingCounter {
+    last: u32,
+    wraps: u32,
+}
+
+impl WrappingCounter {
+    /// Starting from zero
+    pub const fn new() -> Self {
+        Self { last: 0, wraps: 0 }
+    }
+
+    /// The 64-bit count, given the 32-bit counter reads `low`
+    pub fn extend(&mut self, low: u32) -> u64 {
+        if low < self.last {
+            self.wraps += 1;
+        }
+        self.last = low;
+        u64::from(self.wraps) << 32 | u64::from(low)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alloc_and_free() {
+        let mut timers = Timers::<2>::new();
+        let a = timers.alloc().unwrap();
+        let b = timers.alloc().unwrap();
+        assert_ne!(a, b);
+        assert_eq!(timers.alloc(), None);
+        timers.free(a);
+        assert_eq!(timers.alloc(), Some(a));
+        assert!(!timers.is_running(b));
+    }
+
+    #[test]
+    fn one_shot() {
+        let mut timers = Timers::<1>::new();
+        let id = timers.alloc().unwrap();
+        assert!(!timers.poll(id, u64::MAX));
+        timers.start(id, 100, 50);
+        assert_eq!(timers.remaining(id, 120), Some(30));
+        assert!(!timers.poll(id, 149));
+        assert!(timers.poll(id, 160));
+        assert!(!timers.is_running(id));
+        assert_eq!(timers.remaining(id, 160), None);
+        assert!(!timers.poll(id, 1_000));
+    }
+
+    #[test]
+    fn periodic_skips_missed_periods() {
+        let mut timers = Timers::<1>::new();
+        let id = timers.alloc().unwrap();
+        timers.start_periodic(id, 0, 10);
+        assert!(timers.poll(id, 10));
+        assert!(!timers.poll(id, 19));
+        // we were busy for a while
+        assert!(timers.poll(id, 55));
+        assert_eq!(timers.remaining(id, 55), Some(5));
+        assert!(!timers.poll(id, 59));
+        assert!(timers.poll(id, 60));
+        timers.stop(id);
+        assert!(!timers.poll(id, 100));
+    }
+
+    #[test]
+    #[should_panic(expected = "needs a period")]
+    fn periodic_needs_a_period() {
+        let mut timers = Timers::<1>::new();
+        let id = timers.alloc().unwrap();
+        timers.start_periodic(id, 0, 0);
+    }
+
+    #[test]
+    fn next_deadline() {
+        let mut timers = Timers::<3>::new();
+        assert_eq!(timers.next_deadline(), None);
+        let a = timers.alloc().unwrap();
+        let b = timers.alloc().unwrap();
+        timers.start(a, 0, 300);
+        timers.start_periodic(b, 0, 200);
+        assert_eq!(timers.next_deadline(), Some(200));
+        assert!(timers.poll(b, 250));
+        assert_eq!(timers.next_deadline(), Some(300));
+        timers.stop(a);
+        assert_eq!(timers.next_deadline(), Some(400));
+    }
+
+    #[test]
+    fn counter_wraps() {
+        let mut counter = WrappingCounter::new();
+        assert_eq!(counter.extend(5), 5);
+        assert_eq!(counter.extend(u32::MAX), u64::from(u32::MAX));
+        assert_eq!(counter.extend(3), (1 << 32) + 3);
+        assert_eq!(counter.extend(3), (1 << 32) + 3);
+        assert_eq!(counter.extend(2), (2 << 32) + 2);
+    }
+}
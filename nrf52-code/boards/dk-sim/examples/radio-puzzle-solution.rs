@@ -0,0 +1,77 @@
+//! `radio-app`'s `radio-puzzle-solution.rs`, on a simulated radio
+//!
+//! Start a simulated Dongle first, with `cargo run` in `nrf52-code/dongle-sim`.
+
+use std::collections::HashMap;
+use std::str;
+
+use dk::radio::{Channel, Packet};
+use dk_sim as dk;
+
+const TEN_MS: u32 = 10_000;
+
+fn main() {
+    let board = dk::init().unwrap();
+    let mut radio = board.radio;
+    let mut timer = board.timer;
+
+    // dongle-sim uses channel 20 by default
+    radio.set_channel(Channel::_20); // <- must match the Dongle's listening channel
+
+    /* # Build a dictionary */
+    let mut dict = HashMap::<u8, u8>::new();
+
+    let mut packet = Packet::new();
+    // the printable ASCII range
+    for input in b' '..=b'~' {
+        // send the plaintext
+        println!("> Send plain 0x{:02x} ('{}')", input, input as char);
+        if let Ok(data) = dk::send_recv(&mut packet, &[input], &mut radio, &mut timer, TEN_MS) {
+            // response should be one byte large
+            if data.len() == 1 {
+                // get back the ciphertext, which we use as the key in our map
+                let output = data[0];
+                println!("< Rec cipher 0x{:02x} ('{}')", output, output as char);
+                dict.insert(output, input);
+            } else {
+                println!("response packet was not a single byte");
+                dk::fail()
+            }
+        } else {
+            println!("no response or response packet was corrupted");
+            dk::fail()
+        }
+    }
+
+    /* # Retrieve the secret string */
+    let Ok(secret) = dk::send_recv(&mut packet, &[], &mut radio, &mut timer, TEN_MS) else {
+        println!("no response or response packet was corrupted");
+        dk::fail()
+    };
+
+    println!(
+        "ciphertext: {}",
+        str::from_utf8(secret).expect("packet was not valid UTF-8")
+    );
+
+    /* # Decrypt the string */
+    let buffer: Vec<u8> = secret.iter().map(|c| dict[c]).collect();
+
+    println!(
+        "plaintext: {}",
+        str::from_utf8(&buffer).expect("buffer contains non-UTF-8 data")
+    );
+
+    /* # Verify decrypted text */
+    let Ok(response) = dk::send_recv(&mut packet, &buffer, &mut radio, &mut timer, TEN_MS) else {
+        println!("no response or response packet was corrupted");
+        dk::fail()
+    };
+
+    println!(
+        "Dongle response: {}",
+        str::from_utf8(response).expect("response was not UTF-8")
+    );
+
+    dk::exit()
+}
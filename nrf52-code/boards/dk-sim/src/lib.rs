@@ -0,0 +1,379 @@
+//! The radio parts of the `dk` Board Support Package, on a simulated radio
+//!
+//! Programs written for `dk::radio` can run on your computer instead of a
+//! Development Kit, talking to a simulated Dongle from `dongle-sim`. Add
+//!
+//! ```toml
+//! dk = { package = "dk-sim", path = "../boards/dk-sim" }
+//! ```
+//!
+//! to your `Cargo.toml`, swap `defmt::println!` for `println!`, and your code
+//! should work unchanged. [`init`] joins the UDP multicast medium that
+//! `dongle-sim` uses; [`Board::new`] takes any other
+//! [`Link`](dongle_sim::medium::Link), like one to an in-process
+//! [`Bus`](dongle_sim::medium::Bus) for tests.
+//!
+//! Wrap the link in [`Impaired`](dongle_sim::medium::Impaired) (or set
+//! [`InitOptions::impairments`]) to see how your program copes with lost,
+//! late and corrupted frames.
+//!
+//! The register-level parts of `dk`, like `Radio::regs` and
+//! `Radio::recv_non_blocking`, have no equivalent here, and nor does BLE
+//! (`dk::ble`).
+
+#![deny(missing_docs)]
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use dk_core::retry::{retry, Jitter};
+use dongle_sim::medium::{Impaired, Impairments, Link, UdpLink};
+use embedded_hal::delay::DelayNs;
+
+pub use dk_core::retry::{Exhausted, Failure, Response, SendRecvConfig};
+
+pub mod radio;
+pub mod timer;
+
+/// Components on the board
+pub struct Board {
+    /// LEDs
+    pub leds: Leds,
+    /// Timer
+    pub timer: Timer,
+    /// Radio interface
+    pub radio: radio::Radio,
+}
+
+impl Board {
+    /// A board whose radio is on `link`
+    pub fn new(link: impl Link + 'static) -> Board {
+        START.get_or_init(Instant::now);
+        Board {
+            leds: Leds {
+                _1: Led::new(1),
+                _2: Led::new(2),
+                _3: Led::new(3),
+                _4: Led::new(4),
+            },
+            timer: Timer::new(),
+            radio: radio::Radio::new(link),
+        }
+    }
+}
+
+/// All LEDs on the board
+pub struct Leds {
+    /// LED1
+    pub _1: Led,
+    /// LED2
+    pub _2: Led,
+    /// LED3
+    pub _3: Led,
+    /// LED4
+    pub _4: Led,
+}
+
+/// A single LED
+///
+/// It has nowhere to shine, so it prints a line whenever it changes.
+pub struct Led {
+    number: u8,
+    on: bool,
+}
+
+impl Led {
+    fn new(number: u8) -> Led {
+        Led { number, on: false }
+    }
+
+    /// Turns on the LED
+    pub fn on(&mut self) {
+        self.on = true;
+        println!("LED{} on", self.number);
+    }
+
+    /// Turns off the LED
+    pub fn off(&mut self) {
+        self.on = false;
+        println!("LED{} off", self.number);
+    }
+
+    /// Returns `true` if the LED is in the OFF state
+    pub fn is_off(&self) -> bool {
+        !self.on
+    }
+
+    /// Returns `true` if the LED is in the ON state
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    /// Toggles the state (on/off) of the LED
+    pub fn toggle(&mut self) {
+        if self.is_off() {
+            self.on();
+        } else {
+            self.off()
+        }
+    }
+}
+
+/// A timer for creating blocking delays
+pub struct Timer {
+    deadline: Option<Instant>,
+}
+
+impl DelayNs for Timer {
+    fn delay_ns(&mut self, ns: u32) {
+        std::thread::sleep(Duration::from_nanos(u64::from(ns)));
+    }
+}
+
+impl Timer {
+    /// Create a new timer instance which can be used for blocking delays.
+    pub fn new() -> Self {
+        Self { deadline: None }
+    }
+
+    /// Start the timer with the given microsecond duration.
+    pub fn start(&mut self, microseconds: u32) {
+        self.deadline = Some(Instant::now() + Duration::from_micros(u64::from(microseconds)));
+    }
+
+    /// If the timer has finished, resets it and returns true.
+    ///
+    /// Returns false if the timer is still running.
+    pub fn reset_if_finished(&mut self) -> bool {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                self.deadline = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// How long until the timer finishes
+    pub fn remaining(&self) -> Duration {
+        self.deadline.map_or(Duration::ZERO, |deadline| {
+            deadline.saturating_duration_since(Instant::now())
+        })
+    }
+
+    /// Wait for the specified duration.
+    pub fn wait(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A one-shot timer, which `recv_timeout` and `send_recv` can time out with
+///
+/// [`Timer`] is one; so is a [`timer::VirtualTimer`], which shares its clock
+/// with others.
+pub trait Countdown: DelayNs {
+    /// Start the timer with the given microsecond duration.
+    fn start(&mut self, microseconds: u32);
+
+    /// If the timer has finished, resets it and returns true.
+    ///
+    /// Returns false if the timer is still running.
+    fn reset_if_finished(&mut self) -> bool;
+
+    /// How long until the timer finishes; the simulated radio waits this
+    /// long for a frame, rather than spinning
+    fn remaining(&self) -> Duration;
+}
+
+impl Countdown for Timer {
+    fn start(&mut self, microseconds: u32) {
+        Timer::start(self, microseconds)
+    }
+
+    fn reset_if_finished(&mut self) -> bool {
+        Timer::reset_if_finished(self)
+    }
+
+    fn remaining(&self) -> Duration {
+        Timer::remaining(self)
+    }
+}
+
+const ADDR_LEN: usize = 6;
+
+/// Stands in for the device address in the FICR
+fn get_id() -> [u8; ADDR_LEN] {
+    static ID: OnceLock<[u8; ADDR_LEN]> = OnceLock::new();
+    *ID.get_or_init(rand::random)
+}
+
+/// Send a packet, containing the device address and the given data, and
+/// wait for a response.
+///
+/// If we get a response containing the same device address, it returns a
+/// slice of the remaining payload (i.e. not including the device address).
+///
+/// Replies to other devices are skipped while we wait. If we don't get a
+/// response, or we get a bad one, we try again. A frame with a bad CRC
+/// counts as a bad response, whoever it was for, as we can't trust its
+/// address.
+///
+/// If we try too many times, we give up.
+///
+/// This is [`send_recv_with`] using the default [`SendRecvConfig`], with a
+/// timeout of `microseconds`.
+pub fn send_recv<'packet>(
+    packet: &'packet mut radio::Packet,
+    data_to_send: &[u8],
+    radio: &mut radio::Radio,
+    timer: &mut impl Countdown,
+    microseconds: u32,
+) -> Result<&'packet [u8], radio::Error> {
+    let config = SendRecvConfig {
+        timeout_us: microseconds,
+        ..SendRecvConfig::new()
+    };
+    match send_recv_with(packet, data_to_send, radio, timer, &config) {
+        Ok(response) => Ok(response.data),
+        Err(_) => Err(radio::Error::Timeout),
+    }
+}
+
+/// Like [`send_recv`], but retrying as `config` says
+///
+/// The response says how many attempts it took. If every attempt fails,
+/// the error says how many there were and why the last one failed.
+pub fn send_recv_with<'packet>(
+    packet: &'packet mut radio::Packet,
+    data_to_send: &[u8],
+    radio: &mut radio::Radio,
+    timer: &mut impl Countdown,
+    config: &SendRecvConfig,
+) -> Result<Response<'packet>, Exhausted> {
+    assert!(data_to_send.len() + ADDR_LEN < usize::from(radio::Packet::CAPACITY));
+
+    let id_bytes = get_id();
+    let mut id = [0; 8];
+    id[..ADDR_LEN].copy_from_slice(&id_bytes);
+    // every device backs off differently
+    let mut jitter = Jitter::new(u64::from_be_bytes(id));
+    let attempts = retry(
+        config,
+        &mut jitter,
+        timer,
+        |timer, us| timer.delay_us(us),
+        |timer, i| {
+            packet.set_len(ADDR_LEN as u8 + data_to_send.len() as u8);
+            let source_iter = id_bytes.iter().chain(data_to_send.iter());
+            for (source, dest) in source_iter.zip(packet.iter_mut()) {
+                *dest = *source;
+            }
+            radio.send(packet);
+            // skip replies to everyone else
+            let filter = radio.filter();
+            radio.set_filter(radio::Filter::prefix(&id_bytes));
+            let result = radio.recv_timeout(packet, timer, config.timeout_us);
+            radio.set_filter(filter);
+            match result {
+                Ok(_crc) => {
+                    if packet[0..ADDR_LEN] == id_bytes {
+                        Ok(())
+                    } else {
+                        eprintln!("RX Wrong Address try {i}");
+                        Err(Failure::WrongAddress)
+                    }
+                }
+                Err(radio::Error::Timeout) => {
+                    eprintln!("RX Timeout try {i}");
+                    Err(Failure::Timeout)
+                }
+                Err(radio::Error::Crc(crc)) => {
+                    eprintln!("RX CRC Error try {i}");
+                    Err(Failure::Crc(crc))
+                }
+                Err(radio::Error::NoAck | radio::Error::NotMacFrame) => unreachable!(),
+            }
+        },
+    )?;
+    Ok(Response {
+        data: &packet[ADDR_LEN..],
+        attempts,
+    })
+}
+
+/// The ways that initialisation can fail
+#[derive(Debug, Copy, Clone)]
+pub enum Error {
+    /// You tried to initialise the board twice
+    DoubleInit = 1,
+    /// We could not join the simulated medium
+    Medium,
+}
+
+// Atomic flag to detect double initialization
+static HAL_INIT: AtomicBool = AtomicBool::new(false);
+
+// When the board was initialised
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// Initializes the board, on the same UDP multicast medium as `dongle-sim`
+pub fn init() -> Result<Board, Error> {
+    init_with(Default::default())
+}
+
+/// Options you can pass to `init_with`
+#[derive(Debug, Clone, Default)]
+pub struct InitOptions {
+    /// What goes wrong with the frames we receive
+    pub impairments: Impairments,
+    /// Decides which frames are lost or corrupted
+    pub seed: u64,
+}
+
+/// Initializes the board with the given options
+pub fn init_with(options: InitOptions) -> Result<Board, Error> {
+    if HAL_INIT.swap(true, Ordering::Relaxed) {
+        return Err(Error::DoubleInit);
+    }
+    let link = UdpLink::new().map_err(|e| {
+        eprintln!("could not join the simulated radio medium: {e}");
+        Error::Medium
+    })?;
+    let link = Impaired::new(link, options.impairments, options.seed);
+    Ok(Board::new(link))
+}
+
+/// Exits the application successfully
+pub fn exit() -> ! {
+    println!("`dk::exit()` called; exiting ...");
+    std::process::exit(0)
+}
+
+/// Exits the application with a failure
+pub fn fail() -> ! {
+    println!("`dk::fail()` called; exiting ...");
+    std::process::exit(1)
+}
+
+/// Returns the time elapsed since the board was initialised
+pub fn uptime() -> Duration {
+    START.get().map_or(Duration::ZERO, Instant::elapsed)
+}
+
+/// Returns the time elapsed since the board was initialised, in 32,768 Hz
+/// units (i.e. 32768 = 1 second), like the RTC that `dk` counts with
+pub fn uptime_ticks() -> u64 {
+    (uptime().as_micros() * 32_768 / 1_000_000) as u64
+}
+
+/// Returns the time elapsed since the board was initialised, in microseconds.
+pub fn uptime_us() -> u64 {
+    uptime().as_micros() as u64
+}
@@ -0,0 +1,503 @@
+//! # Simulated IEEE 802.15.4 radio
+//!
+//! The same API as `dk::radio`, for a radio on a [`Link`] to a simulated
+//! medium. There is no real CCA on the medium, so the channel is always clear.
+
+use std::time::Duration;
+
+pub use dk_core::filter::{Filter, Prefix};
+pub use dk_core::mac;
+pub use dk_core::radio::RxInfo;
+use dongle_sim::medium::{Link, Received};
+
+/// Default (IEEE compliant) Start of Frame Delimiter
+pub const DEFAULT_SFD: u8 = 0xA7;
+
+/// Error
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    /// Incorrect CRC
+    Crc(u16),
+    /// Timeout
+    Timeout,
+    /// The frame was sent, but never acknowledged
+    NoAck,
+    /// `send_with_ack` was given a packet which isn't a MAC frame
+    NotMacFrame,
+}
+
+/// How many times `send_with_ack` sends a frame again, if it isn't
+/// acknowledged (`macMaxFrameRetries`)
+pub const MAX_FRAME_RETRIES: u32 = 3;
+
+/// How long `send_with_ack` waits for an ACK, in microseconds
+pub const ACK_WAIT_US: u32 = 2_000;
+
+/// IEEE 802.15.4 channels
+///
+/// NOTE these are NOT the same as WiFi 2.4 GHz channels
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Channel {
+    /// 2_405 MHz
+    _11 = 11,
+    /// 2_410 MHz
+    _12 = 12,
+    /// 2_415 MHz
+    _13 = 13,
+    /// 2_420 MHz
+    _14 = 14,
+    /// 2_425 MHz
+    _15 = 15,
+    /// 2_430 MHz
+    _16 = 16,
+    /// 2_435 MHz
+    _17 = 17,
+    /// 2_440 MHz
+    _18 = 18,
+    /// 2_445 MHz
+    _19 = 19,
+    /// 2_450 MHz
+    _20 = 20,
+    /// 2_455 MHz
+    _21 = 21,
+    /// 2_460 MHz
+    _22 = 22,
+    /// 2_465 MHz
+    _23 = 23,
+    /// 2_470 MHz
+    _24 = 24,
+    /// 2_475 MHz
+    _25 = 25,
+    /// 2_480 MHz
+    _26 = 26,
+}
+
+impl Channel {
+    /// Frequency offset for the given channel.
+    pub const fn frequency_offset(&self) -> u32 {
+        (*self as u32 - 10) * 5
+    }
+}
+
+/// Clear Channel Assessment method
+///
+/// Accepted for compatibility; the simulated channel is always clear.
+pub enum Cca {
+    /// Carrier sense
+    CarrierSense,
+    /// Energy Detection / Energy Above Threshold
+    EnergyDetection {
+        /// Energy measurements above this value mean that the channel is assumed to be busy.
+        ed_threshold: u8,
+    },
+}
+
+/// How long `recv` waits on the medium before checking again
+const RECV_POLL: Duration = Duration::from_secs(1);
+
+/// Simulated IEEE 802.15.4 radio driver.
+pub struct Radio {
+    link: Box<dyn Link>,
+    channel: u8,
+    tx_power: i8,
+    filter: Filter,
+    rx_info: Option<RxInfo>,
+}
+
+impl Radio {
+    /// Create a new radio, on channel 11 like the real one
+    pub fn new(link: impl Link + 'static) -> Self {
+        Self {
+            link: Box::new(link),
+            channel: Channel::_11 as u8,
+            tx_power: 0,
+            filter: Filter::Promiscuous,
+            rx_info: None,
+        }
+    }
+
+    /// Set the channel.
+    pub fn set_channel(&mut self, channel: Channel) {
+        self.channel = channel as u8;
+    }
+
+    /// Changes the radio channel using a raw channel ID.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `channel` is not in the range 11..=26
+    pub fn set_channel_raw(&mut self, channel: u8) {
+        assert!((11..=26).contains(&channel), "channel must be 11..=26");
+        self.channel = channel;
+    }
+
+    /// The channel we are on
+    pub fn channel(&self) -> u8 {
+        self.channel
+    }
+
+    /// Changes the Clear Channel Assessment method
+    pub fn set_cca(&mut self, _cca: Cca) {}
+
+    /// Changes which frames `recv` and `recv_timeout` hand back
+    ///
+    /// The default, `Filter::Promiscuous`, hands back everything.
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+    }
+
+    /// Which frames `recv` and `recv_timeout` hand back
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    /// The signal strength, LQI, channel and time of the last packet that
+    /// `recv` or `recv_timeout` handed back (even with a bad CRC)
+    ///
+    /// `None` until we have received something. The medium only tells us
+    /// the LQI, so the RSSI is worked out from that.
+    pub fn rx_info(&self) -> Option<RxInfo> {
+        self.rx_info
+    }
+
+    /// Changes the Start of Frame Delimiter (SFD)
+    ///
+    /// Every radio on the medium uses the default.
+    pub fn set_sfd(&mut self, _sfd: u8) {}
+
+    /// Changes the radio transmission power, in dBm
+    pub fn set_transmission_power_raw(&mut self, power: i8) {
+        self.tx_power = power;
+    }
+
+    /// The transmission power we were given, in dBm
+    ///
+    /// It makes no difference to who hears us.
+    pub fn transmission_power(&self) -> i8 {
+        self.tx_power
+    }
+
+    /// Sample the received signal power
+    ///
+    /// The simulated medium is always quiet.
+    pub fn energy_detection_scan(&mut self, _sample_cycles: u32) -> u8 {
+        0
+    }
+
+    /// Receives one radio packet and copies its contents into the given `packet` buffer
+    ///
+    /// This methods returns the `Ok` variant if the CRC included the packet was successfully
+    /// validated; otherwise it returns the `Err` variant. In either case, `packet` will be
+    /// updated with the received packet's data
+    ///
+    /// Frames which the filter (see `set_filter`) turns down are skipped.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the medium fails, which a real radio can't.
+    pub fn recv(&mut self, packet: &mut Packet) -> Result<u16, u16> {
+        loop {
+            if let Some(result) = self.recv_for(packet, RECV_POLL) {
+                return result;
+            }
+        }
+    }
+
+    /// Listens for a packet for no longer than the specified amount of microseconds
+    /// and copies its contents into the given `packet` buffer
+    ///
+    /// If no packet is received within the specified time then the `Timeout` error is returned
+    ///
+    /// If a packet is received within the time span then the packet CRC is checked. If the CRC is
+    /// incorrect then the `Crc` error is returned; otherwise the `Ok` variant is returned.
+    /// Note that `packet` will contain the packet in any case, even if the CRC check failed.
+    /// Frames which the filter (see `set_filter`) turns down are skipped.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the medium fails, which a real radio can't.
+    pub fn recv_timeout(
+        &mut self,
+        packet: &mut Packet,
+        timer: &mut impl super::Countdown,
+        microseconds: u32,
+    ) -> Result<u16, Error> {
+        timer.start(microseconds);
+        loop {
+            match self.recv_for(packet, timer.remaining()) {
+                Some(Ok(crc)) => return Ok(crc),
+                Some(Err(crc)) => return Err(Error::Crc(crc)),
+                None if timer.reset_if_finished() => return Err(Error::Timeout),
+                // we skipped a frame; keep listening until the timer runs out
+                None => {}
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for a frame; `None` if none came, or the filter turned it down
+    fn recv_for(&mut self, packet: &mut Packet, timeout: Duration) -> Option<Result<u16, u16>> {
+        let received = self.recv_any_for(packet, timeout)?;
+        if !self.filter.accepts(packet, received.crc_ok) {
+            return None;
+        }
+        self.rx_info = Some(RxInfo {
+            rssi: rssi_from_lqi(received.lqi),
+            lqi: received.lqi,
+            channel: self.channel,
+            timestamp: super::uptime_ticks(),
+        });
+        let crc = crc16(&packet[..]);
+        Some(if received.crc_ok { Ok(crc) } else { Err(crc) })
+    }
+
+    /// Waits up to `timeout` for a frame, whatever the filter
+    fn recv_any_for(&mut self, packet: &mut Packet, timeout: Duration) -> Option<Received> {
+        let received = self
+            .link
+            .receive(self.channel, timeout)
+            .expect("simulated radio failed")?;
+        packet.copy_from_slice(
+            &received.psdu[..received.psdu.len().min(usize::from(Packet::CAPACITY))],
+        );
+        packet.set_lqi(received.lqi);
+        Some(received)
+    }
+
+    /// Sends a MAC frame, asking for it to be acknowledged
+    ///
+    /// This sets the Acknowledgment Request bit in `packet`'s frame control
+    /// field and sends it, up to `MAX_FRAME_RETRIES` more times if no ACK
+    /// with its sequence number comes back within `ACK_WAIT_US`. Other frames
+    /// which arrive meanwhile are ignored.
+    ///
+    /// Returns the frame pending bit of the ACK, or `NoAck`. If `packet`
+    /// isn't a MAC frame, nothing is sent and this returns `NotMacFrame`.
+    pub fn send_with_ack(
+        &mut self,
+        packet: &mut Packet,
+        timer: &mut impl super::Countdown,
+    ) -> Result<bool, Error> {
+        let seq = mac::request_ack(packet).map_err(|_| Error::NotMacFrame)?;
+        let mut reply = Packet::new();
+        for _ in 0..=MAX_FRAME_RETRIES {
+            self.send(packet);
+            // not `recv_timeout`: an ACK has no address for the filter to
+            // look at
+            timer.start(ACK_WAIT_US);
+            while !timer.reset_if_finished() {
+                let Some(received) = self.recv_any_for(&mut reply, timer.remaining()) else {
+                    continue;
+                };
+                if received.crc_ok {
+                    if let Some(frame_pending) = mac::ack_for(&reply, seq) {
+                        return Ok(frame_pending);
+                    }
+                }
+                // someone else's frame, or a broken one: keep listening
+            }
+        }
+        Err(Error::NoAck)
+    }
+
+    /// Tries to send the given `packet`
+    ///
+    /// The simulated channel is always clear, so this always succeeds.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the medium fails, which a real radio can't.
+    #[allow(clippy::result_unit_err)]
+    pub fn try_send(&mut self, packet: &mut Packet) -> Result<(), ()> {
+        self.link
+            .transmit(self.channel, &packet[..])
+            .expect("simulated radio failed");
+        Ok(())
+    }
+
+    /// Sends the given `packet`
+    ///
+    /// This is utility method that *consecutively* calls the `try_send` method until it succeeds.
+    pub fn send(&mut self, packet: &mut Packet) {
+        while self.try_send(packet).is_err() {}
+    }
+
+    /// Sends the specified `packet` without first performing CCA
+    pub fn send_no_cca(&mut self, packet: &mut Packet) {
+        self.send(packet)
+    }
+
+    /// Moves the radio to the DISABLED state
+    pub fn disable(&mut self) {}
+}
+
+/// An IEEE 802.15.4 packet
+///
+/// This `Packet` is laid out like the one in `dk::radio`: the physical header (PHR), then the
+/// PSDU. After a `Radio.recv` operation the byte after the payload holds the LQI.
+pub struct Packet {
+    buffer: [u8; Self::SIZE],
+}
+
+impl Packet {
+    // for indexing purposes
+    const PHY_HDR: usize = 0;
+    const DATA: std::ops::RangeFrom<usize> = 1..;
+
+    /// Maximum amount of usable payload (CRC excluded) a single packet can contain, in bytes
+    pub const CAPACITY: u8 = 125;
+    const CRC: u8 = 2; // size of the CRC, which is *never* copied to / from RAM
+    const MAX_PSDU_LEN: u8 = Self::CAPACITY + Self::CRC;
+    const SIZE: usize = 1 /* PHR */ + Self::MAX_PSDU_LEN as usize;
+
+    /// Returns an empty packet (length = 0)
+    pub fn new() -> Self {
+        let mut packet = Self {
+            buffer: [0; Self::SIZE],
+        };
+        packet.set_len(0);
+        packet
+    }
+
+    /// Fills the packet payload with given `src` data
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `src` is larger than `Self::CAPACITY`
+    pub fn copy_from_slice(&mut self, src: &[u8]) {
+        assert!(src.len() <= Self::CAPACITY as usize);
+        let len = src.len() as u8;
+        self.buffer[Self::DATA][..len as usize].copy_from_slice(src);
+        self.set_len(len);
+    }
+
+    /// Fills the packet payload with a MAC frame
+    pub fn write_frame(&mut self, frame: &mac::Frame) -> Result<(), mac::Error> {
+        let len = frame.len();
+        if len > usize::from(Self::CAPACITY) {
+            return Err(mac::Error::BufferTooSmall);
+        }
+        self.set_len(len as u8);
+        frame.write(self)?;
+        Ok(())
+    }
+
+    /// Parses the packet payload as a MAC frame
+    pub fn frame(&self) -> Result<mac::Frame<'_>, mac::Error> {
+        mac::Frame::parse(self)
+    }
+
+    /// Returns the size of this packet's payload
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u8 {
+        self.buffer[Self::PHY_HDR] - Self::CRC
+    }
+
+    /// Changes the size of the packet's payload
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `len` is larger than `Self::CAPACITY`
+    pub fn set_len(&mut self, len: u8) {
+        assert!(len <= Self::CAPACITY);
+        self.buffer[Self::PHY_HDR] = len + Self::CRC;
+    }
+
+    /// Returns the LQI (Link Quality Indicator) of the received packet
+    ///
+    /// As on the real radio, the value is only valid after a `Radio.recv` operation, and
+    /// operations that modify the `Packet` will overwrite it.
+    pub fn lqi(&self) -> u8 {
+        self.buffer[1 /* PHY_HDR */ + self.len() as usize /* data */]
+    }
+
+    fn set_lqi(&mut self, lqi: u8) {
+        self.buffer[1 + self.len() as usize] = lqi;
+    }
+}
+
+impl Default for Packet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::ops::Deref for Packet {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer[Self::DATA][..self.len() as usize]
+    }
+}
+
+impl std::ops::DerefMut for Packet {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        let len = self.len();
+        &mut self.buffer[Self::DATA][..len as usize]
+    }
+}
+
+/// A made-up signal strength for an LQI: -100 dBm for 0, up to -30 dBm for 255
+fn rssi_from_lqi(lqi: u8) -> i8 {
+    (-100 + i16::from(lqi) * 70 / 255) as i8
+}
+
+/// The 802.15.4 FCS: CRC-16 with polynomial 0x1021, bits reversed, starting at zero
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= u16::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x8408
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packet_len_and_lqi() {
+        let mut packet = Packet::new();
+        assert_eq!(packet.len(), 0);
+        packet.copy_from_slice(b"hello");
+        assert_eq!(&packet[..], b"hello");
+        packet.set_lqi(200);
+        assert_eq!(packet.lqi(), 200);
+        packet.set_len(2);
+        assert_eq!(&packet[..], b"he");
+    }
+
+    #[test]
+    fn packet_mac_frames() {
+        let mut packet = Packet::new();
+        let frame = mac::Frame::data(1, b"hi").with_dst(0x1234, mac::Address::BROADCAST);
+        packet.write_frame(&frame).unwrap();
+        assert_eq!(packet.len() as usize, frame.len());
+        assert_eq!(packet.frame(), Ok(frame));
+
+        let too_big = mac::Frame::data(2, &[0; 123]);
+        assert_eq!(
+            packet.write_frame(&too_big),
+            Err(mac::Error::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn rssi_estimate() {
+        assert_eq!(rssi_from_lqi(0), -100);
+        assert_eq!(rssi_from_lqi(255), -30);
+        assert!(rssi_from_lqi(100) < rssi_from_lqi(200));
+    }
+
+    #[test]
+    fn fcs() {
+        // The check value for CRC-16/KERMIT
+        assert_eq!(crc16(b"123456789"), 0x2189);
+    }
+}
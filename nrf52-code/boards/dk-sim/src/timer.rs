@@ -0,0 +1,338 @@
+//! Several timers on one clock, like `dk::timer`
+//!
+//! [`TimerService`] takes over the board's [`Timer`](crate::Timer) and hands
+//! out up to `N` [`VirtualTimer`]s, each with its own deadline, and any
+//! number of [`Stopwatch`]es. A `VirtualTimer` is a [`Countdown`], so you can
+//! time out a receive with one while another blinks an LED.
+//!
+//! With no `embassy-time` driver on the host, [`VirtualTimer::wait`] and the
+//! `embedded-hal-async` [`DelayNs`](embedded_hal_async::delay::DelayNs) keep
+//! asking to be polled again until their deadline passes.
+
+use std::cell::RefCell;
+use std::task::Poll;
+use std::time::Instant as StdInstant;
+
+use dk_core::timer::{TimerId, Timers};
+use embassy_time::Duration;
+use embedded_hal::delay::DelayNs;
+
+use crate::{Countdown, Timer};
+
+struct Inner<const N: usize> {
+    started: StdInstant,
+    timers: Timers<N>,
+}
+
+impl<const N: usize> Inner<N> {
+    /// Microseconds since the service started
+    fn now(&self) -> u64 {
+        self.started.elapsed().as_micros() as u64
+    }
+}
+
+/// A moment on a [`TimerService`]'s clock, in microseconds since the service
+/// started
+///
+/// This is not an [`embassy_time::Instant`], which counts from an epoch of its
+/// own; the two can't be compared. Subtract one `TimerInstant` from another to get
+/// a [`Duration`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerInstant {
+    micros: u64,
+}
+
+impl TimerInstant {
+    const fn from_micros(micros: u64) -> Self {
+        Self { micros }
+    }
+
+    /// Microseconds since the service started
+    pub const fn as_micros(self) -> u64 {
+        self.micros
+    }
+
+    /// The time from `earlier` to this moment, or zero if `earlier` is later
+    pub fn saturating_duration_since(self, earlier: TimerInstant) -> Duration {
+        Duration::from_micros(self.micros.saturating_sub(earlier.micros))
+    }
+}
+
+impl core::ops::Sub for TimerInstant {
+    type Output = Duration;
+
+    fn sub(self, earlier: TimerInstant) -> Duration {
+        Duration::from_micros(self.micros - earlier.micros)
+    }
+}
+
+impl core::ops::Add<Duration> for TimerInstant {
+    type Output = TimerInstant;
+
+    fn add(self, duration: Duration) -> TimerInstant {
+        TimerInstant::from_micros(self.micros + duration.as_micros())
+    }
+}
+
+/// Up to `N` virtual timers, on one clock
+pub struct TimerService<const N: usize> {
+    inner: RefCell<Inner<N>>,
+}
+
+impl<const N: usize> TimerService<N> {
+    /// Takes over `timer`, which counts from now on
+    pub fn new(_timer: Timer) -> Self {
+        Self {
+            inner: RefCell::new(Inner {
+                started: StdInstant::now(),
+                timers: Timers::new(),
+            }),
+        }
+    }
+
+    /// The time on the service's clock
+    pub fn now(&self) -> TimerInstant {
+        TimerInstant::from_micros(self.inner.borrow().now())
+    }
+
+    /// A new, stopped timer, or `None` if all `N` are in use
+    pub fn timer(&self) -> Option<VirtualTimer<'_, N>> {
+        let id = self.inner.borrow_mut().timers.alloc()?;
+        Some(VirtualTimer { service: self, id })
+    }
+
+    /// The time on the service's clock, `microseconds` from now
+    fn micros_from_now(&self, microseconds: u64) -> u64 {
+        self.inner.borrow().now() + microseconds
+    }
+
+    /// Waits until the service's clock reaches `deadline`
+    async fn sleep_until(&self, deadline: u64) {
+        core::future::poll_fn(|cx| {
+            if self.inner.borrow().now() >= deadline {
+                Poll::Ready(())
+            } else {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// A stopwatch, started now
+    pub fn stopwatch(&self) -> Stopwatch<'_, N> {
+        Stopwatch {
+            service: self,
+            started: self.now(),
+        }
+    }
+}
+
+/// One of a [`TimerService`]'s timers
+///
+/// Dropping it gives it back to the service. Using it as a [`DelayNs`],
+/// blocking or async, waits on the service's clock and leaves the timer's own
+/// deadline alone, so a periodic timer keeps its period.
+pub struct VirtualTimer<'a, const N: usize> {
+    service: &'a TimerService<N>,
+    id: TimerId,
+}
+
+impl<const N: usize> VirtualTimer<'_, N> {
+    /// Fire once, `after` from now
+    pub fn start_after(&mut self, after: Duration) {
+        self.start_us(after.as_micros());
+    }
+
+    fn start_us(&mut self, microseconds: u64) {
+        let mut inner = self.service.inner.borrow_mut();
+        let now = inner.now();
+        inner.timers.start(self.id, now, microseconds);
+    }
+
+    /// Fire every `period`, starting `period` from now
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `period` is zero
+    pub fn start_periodic(&mut self, period: Duration) {
+        let mut inner = self.service.inner.borrow_mut();
+        let now = inner.now();
+        inner
+            .timers
+            .start_periodic(self.id, now, period.as_micros());
+    }
+
+    /// Stop the timer, so it doesn't fire
+    pub fn stop(&mut self) {
+        self.service.inner.borrow_mut().timers.stop(self.id);
+    }
+
+    /// Whether the timer is going to fire
+    pub fn is_running(&self) -> bool {
+        self.service.inner.borrow().timers.is_running(self.id)
+    }
+
+    /// When the timer is going to fire next, if it's running
+    pub fn deadline(&self) -> Option<TimerInstant> {
+        let inner = self.service.inner.borrow();
+        let now = inner.now();
+        let remaining = inner.timers.remaining(self.id, now)?;
+        Some(TimerInstant::from_micros(now + remaining))
+    }
+
+    /// Waits until the timer fires
+    ///
+    /// Like [`reset_if_finished`](Self::reset_if_finished), this stops a
+    /// one-shot timer and moves a periodic one on to its next deadline. If the
+    /// timer isn't running, this never returns.
+    pub async fn wait(&mut self) {
+        core::future::poll_fn(|cx| {
+            if VirtualTimer::reset_if_finished(self) {
+                Poll::Ready(())
+            } else {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// If the timer has fired, returns true: a one-shot timer then stops, and
+    /// a periodic one carries on to its next deadline
+    ///
+    /// Returns false if the timer hasn't fired yet, or isn't running.
+    pub fn reset_if_finished(&mut self) -> bool {
+        let mut inner = self.service.inner.borrow_mut();
+        let now = inner.now();
+        inner.timers.poll(self.id, now)
+    }
+}
+
+impl<const N: usize> Drop for VirtualTimer<'_, N> {
+    fn drop(&mut self) {
+        self.service.inner.borrow_mut().timers.free(self.id);
+    }
+}
+
+impl<const N: usize> Countdown for VirtualTimer<'_, N> {
+    fn start(&mut self, microseconds: u32) {
+        self.start_us(u64::from(microseconds));
+    }
+
+    fn reset_if_finished(&mut self) -> bool {
+        VirtualTimer::reset_if_finished(self)
+    }
+
+    fn remaining(&self) -> std::time::Duration {
+        let inner = self.service.inner.borrow();
+        let remaining = inner.timers.remaining(self.id, inner.now());
+        std::time::Duration::from_micros(remaining.unwrap_or(0))
+    }
+}
+
+impl<const N: usize> DelayNs for VirtualTimer<'_, N> {
+    fn delay_ns(&mut self, ns: u32) {
+        let deadline = self.service.micros_from_now(u64::from(ns.div_ceil(1_000)));
+        loop {
+            let now = self.service.inner.borrow().now();
+            if now >= deadline {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_micros(deadline - now));
+        }
+    }
+}
+
+impl<const N: usize> embedded_hal_async::delay::DelayNs for VirtualTimer<'_, N> {
+    async fn delay_ns(&mut self, ns: u32) {
+        let deadline = self.service.micros_from_now(u64::from(ns.div_ceil(1_000)));
+        self.service.sleep_until(deadline).await;
+    }
+}
+
+/// Measures how long things take, on a [`TimerService`]
+pub struct Stopwatch<'a, const N: usize> {
+    service: &'a TimerService<N>,
+    started: TimerInstant,
+}
+
+impl<const N: usize> Stopwatch<'_, N> {
+    /// The time since the stopwatch started
+    pub fn elapsed(&self) -> Duration {
+        self.service.now() - self.started
+    }
+
+    /// Starts again from zero, returning the time up to now
+    pub fn restart(&mut self) -> Duration {
+        let now = self.service.now();
+        let lap = now - self.started;
+        self.started = now;
+        lap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timers_share_the_clock() {
+        let timers = TimerService::<2>::new(Timer::new());
+        let mut tick = timers.timer().unwrap();
+        let mut timeout = timers.timer().unwrap();
+        assert!(timers.timer().is_none());
+
+        let stopwatch = timers.stopwatch();
+        tick.start_periodic(Duration::from_millis(2));
+        timeout.start_after(Duration::from_millis(9));
+        let deadline = timeout.deadline().unwrap();
+        assert!(deadline - timers.now() <= Duration::from_millis(9));
+        let mut ticks = 0;
+        while !timeout.reset_if_finished() {
+            if tick.reset_if_finished() {
+                ticks += 1;
+            }
+        }
+        // every 2 ms, unless the test thread was held up
+        assert!((1..=4).contains(&ticks));
+        assert!(stopwatch.elapsed() >= Duration::from_millis(9));
+        assert!(tick.is_running());
+        assert!(!timeout.is_running());
+
+        // dropping a timer gives it back
+        drop(timeout);
+        assert!(timers.timer().is_some());
+    }
+    fn block_on<F: core::future::Future>(future: F) -> F::Output {
+        let mut future = core::pin::pin!(future);
+        let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn delays_leave_the_timer_alone() {
+        let timers = TimerService::<1>::new(Timer::new());
+        let mut tick = timers.timer().unwrap();
+        tick.start_periodic(Duration::from_millis(50));
+        let deadline = tick.deadline().unwrap();
+
+        let stopwatch = timers.stopwatch();
+        DelayNs::delay_us(&mut tick, 1_000);
+        block_on(embedded_hal_async::delay::DelayNs::delay_us(
+            &mut tick, 1_000,
+        ));
+        assert!(stopwatch.elapsed() >= Duration::from_millis(2));
+        assert!(tick.is_running());
+        assert_eq!(tick.deadline(), Some(deadline));
+
+        // and `wait` moves it on by a period
+        block_on(tick.wait());
+        assert!(timers.now() >= deadline);
+        assert_eq!(tick.deadline(), Some(deadline + Duration::from_millis(50)));
+    }
+}
@@ -0,0 +1,290 @@
+//! Run a puzzle client, written against the `dk` API, against a simulated
+//! Dongle on a noisy medium
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use dk_sim::radio::{mac, Channel, Filter, Packet};
+use dk_sim::timer::TimerService;
+use dk_sim::{Board, Exhausted, Failure, SendRecvConfig};
+use dongle_sim::medium::{Bus, Impaired, Impairments};
+use dongle_sim::{Dongle, Secret};
+
+const TEN_MS: u32 = 10_000;
+
+/// What `radio-puzzle-solution.rs` does, returning the plaintext and the
+/// Dongle's verdict
+fn solve(board: Board) -> (Vec<u8>, Vec<u8>) {
+    let mut radio = board.radio;
+    let mut timer = board.timer;
+    radio.set_channel(Channel::_20);
+
+    let mut dict = HashMap::new();
+    let mut packet = Packet::new();
+    for input in b' '..=b'~' {
+        let data = dk_sim::send_recv(&mut packet, &[input], &mut radio, &mut timer, TEN_MS)
+            .expect("no response");
+        assert_eq!(data.len(), 1);
+        dict.insert(data[0], input);
+    }
+
+    let secret = dk_sim::send_recv(&mut packet, &[], &mut radio, &mut timer, TEN_MS)
+        .expect("no response")
+        .to_vec();
+    let plaintext: Vec<u8> = secret.iter().map(|c| dict[c]).collect();
+
+    let response = dk_sim::send_recv(&mut packet, &plaintext, &mut radio, &mut timer, TEN_MS)
+        .expect("no response");
+    (plaintext, response.to_vec())
+}
+
+#[test]
+fn solve_on_a_perfect_medium() {
+    let bus = Bus::new();
+    let secret = Secret::new("Hello, world!", 3).unwrap();
+    let dongle = Dongle::new(bus.link(), secret, std::io::sink()).spawn();
+
+    let (plaintext, response) = solve(Board::new(bus.link()));
+    assert_eq!(plaintext, b"Hello, world!");
+    assert_eq!(response, b"correct");
+
+    let dongle = dongle.stop().unwrap();
+    assert_eq!(dongle.counters.err_count, 0);
+}
+
+#[test]
+fn solve_on_a_noisy_medium() {
+    let noisy = Impairments {
+        loss: 0.1,
+        corruption: 0.05,
+        delay: Duration::from_millis(1),
+        lqi: 120,
+    };
+    let bus = Bus::new();
+    let secret = Secret::new("Hello, world!", 3).unwrap();
+    let dongle = Dongle::new(Impaired::new(bus.link(), noisy, 1), secret, std::io::sink()).spawn();
+
+    // `send_recv` tries again when a request or reply goes missing
+    let (plaintext, response) = solve(Board::new(Impaired::new(bus.link(), noisy, 2)));
+    assert_eq!(plaintext, b"Hello, world!");
+    assert_eq!(response, b"correct");
+
+    dongle.stop().unwrap();
+}
+
+#[test]
+fn recv_reports_lqi_and_crc_errors() {
+    let bus = Bus::new();
+    let mut other = Board::new(bus.link());
+    let noisy = Impairments {
+        corruption: 1.0,
+        lqi: 42,
+        ..Impairments::default()
+    };
+    let mut board = Board::new(Impaired::new(bus.link(), noisy, 0));
+    board.radio.set_channel(Channel::_20);
+    other.radio.set_channel(Channel::_20);
+
+    let mut packet = Packet::new();
+    packet.copy_from_slice(b"hello");
+    other.radio.send(&mut packet);
+
+    let mut received = Packet::new();
+    let result = board
+        .radio
+        .recv_timeout(&mut received, &mut board.timer, 100_000);
+    assert!(matches!(result, Err(dk_sim::radio::Error::Crc(_))));
+    assert_eq!(received.len(), 5);
+    assert_eq!(received.lqi(), 42);
+    let info = board.radio.rx_info().unwrap();
+    assert_eq!((info.lqi, info.channel), (42, 20));
+    assert!(info.rssi < -30);
+    assert!(info.timestamp <= dk_sim::uptime_ticks());
+
+    // and nobody answers
+    let result = board
+        .radio
+        .recv_timeout(&mut received, &mut board.timer, 10_000);
+    assert_eq!(result, Err(dk_sim::radio::Error::Timeout));
+}
+
+#[test]
+fn send_recv_with_reports_attempts() {
+    let lossy = Impairments {
+        loss: 0.4,
+        ..Impairments::default()
+    };
+    let bus = Bus::new();
+    let secret = Secret::new("Hello, world!", 3).unwrap();
+    let dongle = Dongle::new(bus.link(), secret, std::io::sink()).spawn();
+
+    let board = Board::new(Impaired::new(bus.link(), lossy, 3));
+    let mut radio = board.radio;
+    let mut timer = board.timer;
+    radio.set_channel(Channel::_20);
+    let config = SendRecvConfig {
+        max_attempts: 20,
+        ..SendRecvConfig::exponential()
+    };
+    let mut packet = Packet::new();
+    let mut retried = false;
+    for input in b'a'..=b'z' {
+        let response =
+            dk_sim::send_recv_with(&mut packet, &[input], &mut radio, &mut timer, &config)
+                .expect("no response");
+        assert_eq!(response.data.len(), 1);
+        retried |= response.attempts > 1;
+    }
+    assert!(retried);
+
+    dongle.stop().unwrap();
+
+    // nobody is listening any more
+    let config = SendRecvConfig {
+        max_attempts: 3,
+        timeout_us: 1_000,
+        ..SendRecvConfig::exponential()
+    };
+    assert_eq!(
+        dk_sim::send_recv_with(&mut packet, b"?", &mut radio, &mut timer, &config),
+        Err(Exhausted {
+            attempts: 3,
+            last: Failure::Timeout
+        })
+    );
+}
+
+#[test]
+fn send_recv_with_reports_crc_errors() {
+    let bus = Bus::new();
+    let secret = Secret::new("Hello, world!", 3).unwrap();
+    let dongle = Dongle::new(bus.link(), secret, std::io::sink()).spawn();
+
+    // every reply is mangled, so we can't tell it was for us
+    let noisy = Impairments {
+        corruption: 1.0,
+        ..Impairments::default()
+    };
+    let board = Board::new(Impaired::new(bus.link(), noisy, 0));
+    let mut radio = board.radio;
+    let mut timer = board.timer;
+    radio.set_channel(Channel::_20);
+    let config = SendRecvConfig {
+        max_attempts: 2,
+        ..SendRecvConfig::exponential()
+    };
+    let mut packet = Packet::new();
+    let result = dk_sim::send_recv_with(&mut packet, b"a", &mut radio, &mut timer, &config);
+    assert!(matches!(
+        result,
+        Err(Exhausted {
+            attempts: 2,
+            last: Failure::Crc(_)
+        })
+    ));
+
+    dongle.stop().unwrap();
+}
+
+#[test]
+fn send_with_ack_hears_acks_through_a_prefix_filter() {
+    let bus = Bus::new();
+    let mut other = Board::new(bus.link());
+    let mut board = Board::new(bus.link());
+    board.radio.set_channel(Channel::_20);
+    other.radio.set_channel(Channel::_20);
+    // an ACK doesn't start with the prefix, but it must still get through
+    board.radio.set_filter(Filter::prefix(b"us:"));
+
+    // someone else's conversation doesn't make us send again
+    let mut ack = Packet::new();
+    ack.write_frame(&mac::Frame::ack(4, false)).unwrap();
+    other.radio.send(&mut ack);
+    ack.write_frame(&mac::Frame::ack(5, true)).unwrap();
+    other.radio.send(&mut ack);
+
+    let mut packet = Packet::new();
+    let frame = mac::Frame::data(5, b"hello").with_dst(0x1234, mac::Address::Short(1));
+    packet.write_frame(&frame).unwrap();
+    assert_eq!(
+        board.radio.send_with_ack(&mut packet, &mut board.timer),
+        Ok(true)
+    );
+    assert!(packet.frame().unwrap().ack_request);
+    let mut received = Packet::new();
+    assert!(other
+        .radio
+        .recv_timeout(&mut received, &mut other.timer, 10_000)
+        .is_ok());
+    assert_eq!(
+        other
+            .radio
+            .recv_timeout(&mut received, &mut other.timer, 10_000),
+        Err(dk_sim::radio::Error::Timeout)
+    );
+
+    // and nobody acknowledges this one
+    assert_eq!(
+        board.radio.send_with_ack(&mut packet, &mut board.timer),
+        Err(dk_sim::radio::Error::NoAck)
+    );
+    // nor can anybody acknowledge something which isn't a MAC frame
+    packet.copy_from_slice(b"us");
+    assert_eq!(
+        board.radio.send_with_ack(&mut packet, &mut board.timer),
+        Err(dk_sim::radio::Error::NotMacFrame)
+    );
+}
+
+#[test]
+fn filters_skip_frames_for_others() {
+    let bus = Bus::new();
+    let mut other = Board::new(bus.link());
+    let mut board = Board::new(bus.link());
+    board.radio.set_channel(Channel::_20);
+    other.radio.set_channel(Channel::_20);
+
+    let mut packet = Packet::new();
+    for frame in [&b"them: hi"[..], b"us: hello"] {
+        packet.copy_from_slice(frame);
+        other.radio.send(&mut packet);
+    }
+
+    let mut received = Packet::new();
+    board.radio.set_filter(Filter::prefix(b"us:"));
+    let result = board
+        .radio
+        .recv_timeout(&mut received, &mut board.timer, 100_000);
+    assert!(result.is_ok());
+    assert_eq!(&received[..], b"us: hello");
+
+    // promiscuous mode hears everything
+    board.radio.set_filter(Filter::Promiscuous);
+    for frame in [&b"them: hi"[..], b"us: hello"] {
+        packet.copy_from_slice(frame);
+        other.radio.send(&mut packet);
+    }
+    for frame in [&b"them: hi"[..], b"us: hello"] {
+        assert!(board.radio.recv(&mut received).is_ok());
+        assert_eq!(&received[..], frame);
+    }
+}
+
+#[test]
+fn recv_times_out_on_a_virtual_timer() {
+    let bus = Bus::new();
+    let board = Board::new(bus.link());
+    let mut radio = board.radio;
+    let timers = TimerService::<2>::new(board.timer);
+    let mut blink = timers.timer().unwrap();
+    let mut timeout = timers.timer().unwrap();
+    blink.start_periodic(embassy_time::Duration::from_millis(1));
+
+    let stopwatch = timers.stopwatch();
+    let mut packet = Packet::new();
+    let result = radio.recv_timeout(&mut packet, &mut timeout, TEN_MS);
+    assert_eq!(result, Err(dk_sim::radio::Error::Timeout));
+    assert!(stopwatch.elapsed() >= embassy_time::Duration::from_micros(TEN_MS.into()));
+    // the other timer kept going meanwhile
+    assert!(blink.reset_if_finished());
+}
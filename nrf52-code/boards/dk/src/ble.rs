@@ -0,0 +1,228 @@
+//! # Bluetooth Low Energy advertising
+//!
+//! The RADIO also speaks the BLE 1 Mbit PHY. [`Ble`] takes it over from the
+//! IEEE 802.15.4 [`Radio`] to send and scan for non-connectable
+//! advertisements, which a phone can see with an app like nRF Connect, and
+//! hands it back with [`Ble::into_ieee802154`].
+//!
+//! The packets themselves are built and parsed by `dk_core::ble`, which is
+//! re-exported here.
+//!
+//! ```rust ignore
+//! let mut ble = dk::ble::Ble::new(board.radio);
+//! let mut data = [0; dk::ble::MAX_DATA_LEN];
+//! let mut builder = dk::ble::AdBuilder::new(&mut data);
+//! builder.flags(dk::ble::flags::BR_EDR_NOT_SUPPORTED).unwrap();
+//! builder.complete_local_name("dk").unwrap();
+//! let advertisement =
+//!     dk::ble::Advertisement::non_connectable(dk::ble::device_address(), builder.as_bytes());
+//! ble.advertise(&advertisement).unwrap();
+//! ```
+
+pub use dk_core::ble::{
+    ad_type, flags, static_random_address, AdBuilder, AdStructure, AdStructures, AdvChannel,
+    Advertisement, Error as PduError, PduType, ACCESS_ADDRESS, CRC_INIT, CRC_POLY, MAX_DATA_LEN,
+    MAX_PDU_LEN, TEST_COMPANY_ID,
+};
+use dk_core::radio::Driver;
+use hal::pac::radio::vals;
+pub use hal::radio::TxPower;
+
+use crate::radio::{Hardware, Radio};
+use crate::{Peri, Timer};
+
+/// Error
+#[derive(Copy, Clone, Debug, PartialEq, defmt::Format)]
+pub enum Error {
+    /// Nothing was heard in time
+    Timeout,
+    /// Something was heard, but its CRC was wrong
+    Crc,
+    /// The advertisement couldn't be written, or what we heard couldn't be parsed
+    Pdu(PduError),
+}
+
+impl From<PduError> for Error {
+    fn from(error: PduError) -> Self {
+        Error::Pdu(error)
+    }
+}
+
+/// An advertisement we heard
+#[derive(Debug, defmt::Format)]
+pub struct Scanned<'a> {
+    /// What it said
+    pub advertisement: Advertisement<'a>,
+    /// How strong the signal was, in dBm
+    pub rssi: i8,
+    /// Which channel we heard it on
+    pub channel: AdvChannel,
+}
+
+/// This board's static random device address, which Nordic programs into
+/// every chip at the factory
+pub fn device_address() -> [u8; 6] {
+    let ficr = hal::pac::FICR;
+    let low = u64::from(ficr.deviceaddr(0).read());
+    let high = u64::from(ficr.deviceaddr(1).read() & 0xffff);
+    static_random_address(high << 32 | low)
+}
+
+/// BLE advertising driver
+pub struct Ble<'d> {
+    _p: Peri<'d, hal::peripherals::RADIO>,
+    driver: Driver<Hardware>,
+    // the RADIO reads (and writes) packets here, so it lives as long as we do
+    buffer: [u8; MAX_PDU_LEN],
+}
+
+impl<'d> Ble<'d> {
+    /// Switch `radio` over to BLE
+    pub fn new(radio: Radio<'d>) -> Self {
+        let p = radio.into_peripheral();
+        let r = hal::pac::RADIO;
+
+        // Disable and enable to reset peripheral
+        r.power().write(|w| w.set_power(false));
+        r.power().write(|w| w.set_power(true));
+
+        r.mode().write(|w| w.set_mode(vals::Mode::BLE_1MBIT));
+        r.pcnf0().write(|w| {
+            // 8-bit on air length
+            w.set_lflen(8);
+            // The first header byte goes in S0
+            w.set_s0len(true);
+            // Zero bytes S1 field length
+            w.set_s1len(0);
+            // Do not include S1 field in RAM if S1 length > 0
+            w.set_s1incl(vals::S1incl::AUTOMATIC);
+            // Zero code Indicator length
+            w.set_cilen(0);
+            // 8-bit preamble
+            w.set_plen(vals::Plen::_8BIT);
+            // The length doesn't include the CRC
+            w.set_crcinc(vals::Crcinc::EXCLUDE);
+        });
+        r.pcnf1().write(|w| {
+            // Longest legacy advertising payload
+            w.set_maxlen((MAX_PDU_LEN - 2) as u8);
+            // Zero static length
+            w.set_statlen(0);
+            // 3 byte base address, plus the 1 byte prefix, makes the access address
+            w.set_balen(3);
+            // Little-endian
+            w.set_endian(vals::Endian::LITTLE);
+            // Enable packet whitening
+            w.set_whiteen(true);
+        });
+
+        // The access address goes out least significant byte first
+        r.base0().write_value(ACCESS_ADDRESS << 8);
+        r.prefix0()
+            .write(|w| w.set_ap(0, (ACCESS_ADDRESS >> 24) as u8));
+        r.txaddress().write(|w| w.set_txaddress(0));
+        r.rxaddresses().write(|w| w.set_addr(0, true));
+
+        // 24-bit CRC over the PDU, not the access address
+        r.crccnf().write(|w| {
+            w.set_len(vals::Len::THREE);
+            w.set_skipaddr(vals::Skipaddr::SKIP);
+        });
+        r.crcpoly().write(|w| w.set_crcpoly(CRC_POLY));
+        r.crcinit().write(|w| w.set_crcinit(CRC_INIT));
+
+        let mut ble = Self {
+            _p: p,
+            driver: Driver::new(Hardware),
+            buffer: [0; MAX_PDU_LEN],
+        };
+        ble.set_transmission_power(TxPower::_0_DBM);
+        ble.set_channel(AdvChannel::_37);
+        ble
+    }
+
+    /// Switch back to IEEE 802.15.4, with the default settings
+    pub fn into_ieee802154(mut self) -> Radio<'d> {
+        self.driver.disable();
+        Radio::new(self._p)
+    }
+
+    /// Changes the radio transmission power
+    pub fn set_transmission_power(&mut self, power: TxPower) {
+        self.driver.settings_changed();
+        hal::pac::RADIO.txpower().write(|w| w.set_txpower(power));
+    }
+
+    fn set_channel(&mut self, channel: AdvChannel) {
+        let r = hal::pac::RADIO;
+        self.driver.settings_changed();
+        r.frequency().write(|w| {
+            w.set_frequency(channel.frequency_offset());
+            w.set_map(vals::Map::DEFAULT);
+        });
+        // the channel index seeds the whitening (the hardware sets bit 6)
+        r.datawhiteiv()
+            .write(|w| w.set_datawhiteiv(channel.index()));
+    }
+
+    /// Sends `advertisement` once on each advertising channel, which is one
+    /// advertising event
+    ///
+    /// Advertisers repeat this every 20 ms to 10 s; leave a few ms of random
+    /// delay in between, so two of you don't keep colliding.
+    pub fn advertise(&mut self, advertisement: &Advertisement) -> Result<(), Error> {
+        for channel in AdvChannel::ALL {
+            self.advertise_on(channel, advertisement)?;
+        }
+        Ok(())
+    }
+
+    /// Sends `advertisement` once, on `channel`
+    pub fn advertise_on(
+        &mut self,
+        channel: AdvChannel,
+        advertisement: &Advertisement,
+    ) -> Result<(), Error> {
+        advertisement.write(&mut self.buffer)?;
+        self.set_channel(channel);
+        // NOTE(unsafe) we wait for the transfer to finish before returning
+        unsafe { self.driver.send_no_cca(self.buffer.as_ptr()) }
+        Ok(())
+    }
+
+    /// Listens on `channel` for up to `microseconds`, returning the first
+    /// advertisement heard
+    ///
+    /// PDUs which carry no advertising data (scan and connection requests)
+    /// come back as `Err(Error::Pdu(_))`; call this again to keep listening.
+    pub fn scan(
+        &mut self,
+        channel: AdvChannel,
+        timer: &mut Timer,
+        microseconds: u32,
+    ) -> Result<Scanned<'_>, Error> {
+        self.set_channel(channel);
+        // NOTE(unsafe) we either wait for the transfer to finish or cancel it before returning
+        unsafe { self.driver.start_recv(self.buffer.as_mut_ptr()) }
+        timer.start(microseconds);
+        let result = loop {
+            if let Some(result) = self.driver.poll_recv() {
+                break result;
+            }
+            if timer.reset_if_finished() {
+                self.driver.cancel_recv();
+                return Err(Error::Timeout);
+            }
+        };
+        let rssi = self.driver.rssi();
+        self.driver.disable();
+        if result.is_err() {
+            return Err(Error::Crc);
+        }
+        Ok(Scanned {
+            advertisement: Advertisement::parse(&self.buffer)?,
+            rssi,
+            channel,
+        })
+    }
+}
@@ -0,0 +1,92 @@
+//! # Buttons 1 to 4, debounced
+//!
+//! Each [`Button`] turns its bouncy pin into [`ButtonEvent`]s, using
+//! `dk_core::button::Debouncer`. Either poll for them from your main loop:
+//!
+//! ```rust ignore
+//! loop {
+//!     if let Some(ButtonEvent::LongPress) = board.buttons._1.poll() {
+//!         defmt::println!("held down");
+//!     }
+//! }
+//! ```
+//!
+//! or wait for them in an async task, which sleeps on the GPIOTE interrupt
+//! until a pin changes:
+//!
+//! ```rust ignore
+//! let event = board.buttons._1.next_event().await;
+//! ```
+//!
+//! Times come from `embassy-time`, which `dk::init` starts.
+
+pub use dk_core::button::{ButtonEvent, DebounceConfig, Debouncer};
+use embassy_time::Instant;
+use hal::gpio::{Input, Pin, Pull};
+use hal::Peri;
+
+/// All Buttons on the board
+pub struct Buttons {
+    /// Button1: pin P0.11
+    pub _1: Button,
+    /// Button2: pin P0.12
+    pub _2: Button,
+    /// Button3: pin P0.24
+    pub _3: Button,
+    /// Button4: pin P0.25
+    pub _4: Button,
+}
+
+/// A single Button
+pub struct Button {
+    inner: Input<'static>,
+    debouncer: Debouncer,
+}
+
+impl Button {
+    pub(crate) fn new(pin: Peri<'static, impl Pin>) -> Self {
+        Self {
+            // NOTE pin goes low when button is pressed
+            inner: Input::new(pin, Pull::Up),
+            debouncer: Debouncer::new(DebounceConfig::new()),
+        }
+    }
+
+    /// Is the button pressed right now (not debounced)
+    pub fn is_pressed(&self) -> bool {
+        self.inner.is_low()
+    }
+
+    /// Changes how long the button takes to settle, and what counts as a
+    /// long press or a double click
+    pub fn set_config(&mut self, config: DebounceConfig) {
+        self.debouncer = Debouncer::new(config);
+    }
+
+    /// What the user did since the last poll, if anything
+    ///
+    /// Call this every few milliseconds; a press shorter than the debounce
+    /// time in between two polls is missed.
+    pub fn poll(&mut self) -> Option<ButtonEvent> {
+        let now = Instant::now().as_micros();
+        self.debouncer.update(now, self.is_pressed())
+    }
+
+    /// Waits for the user to do something
+    pub async fn next_event(&mut self) -> ButtonEvent {
+        loop {
+            if let Some(event) = self.poll() {
+                return event;
+            }
+            let edge = self.inner.wait_for_any_edge();
+            match self.debouncer.next_deadline() {
+                // still settling, or maybe a long press; it's time to look
+                // again if the pin doesn't change before then
+                Some(deadline) => {
+                    let _ = embassy_time::with_deadline(Instant::from_micros(deadline), edge).await;
+                }
+                None => edge.await,
+            }
+        }
+    }
+}
@@ -26,14 +26,28 @@ use hal::{
     Peri,
 };
 
-#[cfg(any(feature = "radio", feature = "advanced"))]
+#[cfg(all(
+    any(feature = "radio", feature = "advanced"),
+    not(any(feature = "log-uart", feature = "log-queue"))
+))]
 use defmt_rtt as _; // global logger
 
+#[cfg(feature = "radio")]
+pub mod ble;
+#[cfg(feature = "buttons")]
+pub mod buttons;
 #[cfg(feature = "advanced")]
 mod errata;
+#[cfg(any(feature = "log-uart", feature = "log-queue"))]
+pub mod log;
 pub mod peripheral;
+#[cfg(feature = "pwm")]
+pub mod pwm;
 #[cfg(feature = "radio")]
 pub mod radio;
+#[cfg(feature = "sensors")]
+pub mod sensors;
+pub mod timer;
 #[cfg(feature = "advanced")]
 pub mod usbd;
 
@@ -43,6 +57,15 @@ pub struct Board {
     pub leds: Leds,
     /// Timer
     pub timer: Timer,
+    /// Buttons
+    #[cfg(feature = "buttons")]
+    pub buttons: buttons::Buttons,
+    /// PWM0, for [`pwm::PwmLeds`] to dim the LEDs with
+    #[cfg(feature = "pwm")]
+    pub pwm: Peri<'static, hal::peripherals::PWM0>,
+    /// Temperature, random numbers and supply voltage
+    #[cfg(feature = "sensors")]
+    pub sensors: sensors::Sensors,
 
     /// Radio interface
     #[cfg(feature = "radio")]
@@ -50,6 +73,10 @@ pub struct Board {
     /// Raw register block for the USBD peripheral.
     #[cfg(any(feature = "advanced", feature = "usbd"))]
     pub usbd: hal::pac::usbd::Usbd,
+    /// USBD, for the USB device that [`log::send_over_acm`] sends the logs
+    /// through
+    #[cfg(feature = "log-queue")]
+    pub usb: Peri<'static, hal::peripherals::USBD>,
     /// POWER (Power Supply) peripheral
     #[cfg(feature = "advanced")]
     pub power: hal::pac::power::Power,
@@ -196,13 +223,38 @@ impl Timer {
     }
 }
 
+/// A one-shot timer, which `recv_timeout` and `send_recv` can time out with
+///
+/// [`Timer`] is one; so is a [`timer::VirtualTimer`], which shares its
+/// hardware timer with others.
+pub trait Countdown: DelayNs {
+    /// Start the timer with the given microsecond duration.
+    fn start(&mut self, microseconds: u32);
+
+    /// If the timer has finished, resets it and returns true.
+    ///
+    /// Returns false if the timer is still running.
+    fn reset_if_finished(&mut self) -> bool;
+}
+
+impl Countdown for Timer {
+    fn start(&mut self, microseconds: u32) {
+        Timer::start(self, microseconds)
+    }
+
+    fn reset_if_finished(&mut self) -> bool {
+        Timer::reset_if_finished(self)
+    }
+}
+
 #[cfg(feature = "radio")]
 mod radio_retry {
+    use dk_core::retry::{retry, Exhausted, Failure, Jitter, Response, SendRecvConfig};
     use embedded_hal::delay::DelayNs as _;
 
     use crate::radio::{self, Packet};
+    use crate::Countdown;
 
-    const RETRY_COUNT: u32 = 10;
     const ADDR_LEN: usize = 6;
 
     fn get_id() -> [u8; ADDR_LEN] {
@@ -229,60 +281,107 @@ mod radio_retry {
     /// If we get a response containing the same device address, it returns a
     /// slice of the remaining payload (i.e. not including the device address).
     ///
-    /// If we don't get a response, or we get a bad response (with the wrong
-    /// address in it), we try again.
+    /// Replies to other devices are skipped while we wait. If we don't get a
+    /// response, or we get a bad one, we try again. A frame with a bad CRC
+    /// counts as a bad response, whoever it was for, as we can't trust its
+    /// address.
     ///
     /// If we try too many times, we give up.
+    ///
+    /// This is `send_recv_with` using the default `SendRecvConfig`, with a
+    /// timeout of `microseconds`.
     pub fn send_recv<'packet>(
         packet: &'packet mut Packet,
         data_to_send: &[u8],
         radio: &mut crate::radio::Radio,
-        timer: &mut crate::Timer,
+        timer: &mut impl Countdown,
         microseconds: u32,
     ) -> Result<&'packet [u8], crate::radio::Error> {
+        let config = SendRecvConfig {
+            timeout_us: microseconds,
+            ..SendRecvConfig::new()
+        };
+        match send_recv_with(packet, data_to_send, radio, timer, &config) {
+            Ok(response) => Ok(response.data),
+            Err(_) => Err(radio::Error::Timeout),
+        }
+    }
+
+    /// Like `send_recv`, but retrying as `config` says
+    ///
+    /// The response says how many attempts it took. If every attempt fails,
+    /// the error says how many there were and why the last one failed.
+    pub fn send_recv_with<'packet>(
+        packet: &'packet mut Packet,
+        data_to_send: &[u8],
+        radio: &mut crate::radio::Radio,
+        timer: &mut impl Countdown,
+        config: &SendRecvConfig,
+    ) -> Result<Response<'packet>, Exhausted> {
         assert!(data_to_send.len() + ADDR_LEN < usize::from(Packet::CAPACITY));
 
         let id_bytes = get_id();
-        // Short delay before sending, so we don't get into a tight loop and steal all the bandwidth
-        timer.delay_us(5000);
-        for i in 0..RETRY_COUNT {
-            packet.set_len(ADDR_LEN as u8 + data_to_send.len() as u8);
-            let source_iter = id_bytes.iter().chain(data_to_send.iter());
-            let dest_iter = packet.iter_mut();
-            for (source, dest) in source_iter.zip(dest_iter) {
-                *dest = *source;
-            }
-            defmt::debug!("TX: {=[u8]:02x}", &packet[..]);
-            radio.send(packet);
-            match radio.recv_timeout(packet, timer, microseconds) {
-                Ok(_crc) => {
-                    defmt::debug!("RX: {=[u8]:02x}", packet[..]);
-                    // packet is long enough
-                    if packet[0..ADDR_LEN] == id_bytes {
-                        // and it has the right bytes at the start
-                        defmt::debug!("OK: {=[u8]:02x}", packet[ADDR_LEN..]);
-                        return Ok(&packet[ADDR_LEN..]);
-                    } else {
-                        defmt::warn!("RX Wrong Address try {}", i);
-                        timer.delay_us(10000);
-                    }
-                }
-                Err(radio::Error::Timeout) => {
-                    defmt::warn!("RX Timeout try {}", i);
-                    timer.delay_us(10000);
+        let mut id = [0; 8];
+        id[..ADDR_LEN].copy_from_slice(&id_bytes);
+        // every device backs off differently
+        let mut jitter = Jitter::new(u64::from_be_bytes(id));
+        let attempts = retry(
+            config,
+            &mut jitter,
+            timer,
+            |timer, us| timer.delay_us(us),
+            |timer, i| {
+                packet.set_len(ADDR_LEN as u8 + data_to_send.len() as u8);
+                let source_iter = id_bytes.iter().chain(data_to_send.iter());
+                let dest_iter = packet.iter_mut();
+                for (source, dest) in source_iter.zip(dest_iter) {
+                    *dest = *source;
                 }
-                Err(radio::Error::Crc(_)) => {
-                    defmt::warn!("RX CRC Error try {}", i);
-                    timer.delay_us(10000);
+                defmt::debug!("TX: {=[u8]:02x}", &packet[..]);
+                radio.send(packet);
+                // skip replies to everyone else
+                let filter = radio.filter();
+                radio.set_filter(radio::Filter::prefix(&id_bytes));
+                let result = radio.recv_timeout(packet, timer, config.timeout_us);
+                radio.set_filter(filter);
+                match result {
+                    Ok(_crc) => {
+                        defmt::debug!("RX: {=[u8]:02x}", packet[..]);
+                        // packet is long enough
+                        if packet[0..ADDR_LEN] == id_bytes {
+                            // and it has the right bytes at the start
+                            defmt::debug!("OK: {=[u8]:02x}", packet[ADDR_LEN..]);
+                            Ok(())
+                        } else {
+                            defmt::warn!("RX Wrong Address try {}", i);
+                            Err(Failure::WrongAddress)
+                        }
+                    }
+                    Err(radio::Error::Timeout) => {
+                        defmt::warn!("RX Timeout try {}", i);
+                        Err(Failure::Timeout)
+                    }
+                    Err(radio::Error::Crc(crc)) => {
+                        defmt::warn!("RX CRC Error try {}", i);
+                        Err(Failure::Crc(crc))
+                    }
+                    Err(radio::Error::NoAck | radio::Error::NotMacFrame) => {
+                        defmt::unreachable!()
+                    }
                 }
-            }
-        }
-        Err(radio::Error::Timeout)
+            },
+        )?;
+        Ok(Response {
+            data: &packet[ADDR_LEN..],
+            attempts,
+        })
     }
 }
 
 #[cfg(feature = "radio")]
-pub use radio_retry::send_recv;
+pub use dk_core::retry::{Exhausted, Failure, Response, SendRecvConfig};
+#[cfg(feature = "radio")]
+pub use radio_retry::{send_recv, send_recv_with};
 
 /// The ways that initialisation can fail
 #[derive(Debug, Copy, Clone, defmt::Format)]
@@ -337,11 +436,18 @@ pub fn init_with(options: InitOptions) -> Result<Board, Error>{
     // for waiting for probe-rs to connect.
     //
     // do this *after* clock set-up to avoid start-up issues
+    #[cfg(not(any(feature = "log-uart", feature = "log-queue")))]
     if options.wait_for_rtt {
         while !defmt_rtt::in_blocking_mode() {
             core::hint::spin_loop();
         }
     }
+    // the logs aren't going over RTT, so there's nothing to wait for
+    #[cfg(any(feature = "log-uart", feature = "log-queue"))]
+    let _ = options.wait_for_rtt;
+
+    #[cfg(feature = "log-uart")]
+    log::init_uart(periph.UARTE0, periph.P0_06);
 
     // NOTE: this branch runs at most once
 
@@ -386,8 +492,19 @@ pub fn init_with(options: InitOptions) -> Result<Board, Error>{
 
     defmt::debug!("I/O pins have been configured for digital output");
 
+    #[cfg(feature = "buttons")]
+    let buttons = buttons::Buttons {
+        _1: buttons::Button::new(periph.P0_11),
+        _2: buttons::Button::new(periph.P0_12),
+        _3: buttons::Button::new(periph.P0_24),
+        _4: buttons::Button::new(periph.P0_25),
+    };
+
     let timer = Timer::new(periph.TIMER0);
 
+    #[cfg(feature = "sensors")]
+    let sensors = sensors::Sensors::new(periph.TEMP, periph.RNG, periph.SAADC);
+
     #[cfg(feature = "radio")]
     let radio = {
         use hal::radio::TxPower;
@@ -410,10 +527,18 @@ pub fn init_with(options: InitOptions) -> Result<Board, Error>{
         #[cfg(feature = "radio")]
         radio,
         timer,
+        #[cfg(feature = "buttons")]
+        buttons,
+        #[cfg(feature = "pwm")]
+        pwm: periph.PWM0,
+        #[cfg(feature = "sensors")]
+        sensors,
         #[cfg(feature = "advanced")]
         ep0in: unsafe { usbd::Ep0In::new(&EP0IN_BUF) },
         #[cfg(any(feature = "advanced", feature = "usbd"))]
         usbd: hal::pac::USBD,
+        #[cfg(feature = "log-queue")]
+        usb: periph.USBD,
         #[cfg(feature = "advanced")]
         power: hal::pac::POWER,
     })
@@ -0,0 +1,274 @@
+//! # defmt logs without a debug probe
+//!
+//! Normally `dk` sends its defmt logs over RTT, which needs a debug probe
+//! attached and `probe-rs` reading them. With one of these features, `dk`
+//! provides a different global logger, which puts each log frame in a queue
+//! instead:
+//!
+//! * `log-uart` sends the queue out of UARTE0 on P0.06, at 115200 baud. On
+//!   the DK that's wired to the J-Link's virtual COM port, so the logs show
+//!   up on a serial port on your computer, even when the DK runs off its
+//!   battery and the probe is only used as a USB-serial adapter.
+//! * `log-queue` sends the queue over a USB CDC ACM class (a USB serial port)
+//!   on the DK's nRF USB connector. You set up the USB device, from
+//!   `Board::usb`, and [`send_over_acm`] keeps the class fed:
+//!
+//!   ```rust ignore
+//!   hal::bind_interrupts!(struct Irqs {
+//!       USBD => hal::usb::InterruptHandler<hal::peripherals::USBD>;
+//!       CLOCK_POWER => hal::usb::vbus_detect::InterruptHandler;
+//!   });
+//!
+//!   let board = dk::init()?;
+//!   let driver = hal::usb::Driver::new(board.usb, Irqs, HardwareVbusDetect::new(Irqs));
+//!   let mut builder = embassy_usb::Builder::new(driver, config, /* buffers */);
+//!   let mut class = CdcAcmClass::new(&mut builder, &mut state, 64);
+//!   let mut usb = builder.build();
+//!   join(usb.run(), dk::log::send_over_acm(&mut class)).await;
+//!   ```
+//!
+//!   Or call [`read`] and send the bytes however you like.
+//!
+//! Either way the bytes are defmt frames, not text. Decode them on your
+//! computer with the firmware's ELF file, which has the format strings in it:
+//!
+//! ```console
+//! $ cargo xtask defmt-log target/thumbv7em-none-eabihf/debug/blinky /dev/ttyACM0
+//! ```
+//!
+//! Logs from before `dk::init` wait in the queue. If the queue is full, new
+//! frames are dropped whole; [`dropped_frames`] counts them.
+//!
+//! Don't also link `defmt-rtt` (with `use defmt_rtt as _;`) when you turn
+//! one of these on; there can only be one global logger.
+
+use core::cell::{RefCell, UnsafeCell};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub use dk_core::log::FrameQueue;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+
+#[cfg(all(feature = "log-uart", feature = "log-queue"))]
+compile_error!("pick one of the `log-uart` and `log-queue` features");
+
+/// How many bytes of log frames can wait to be sent
+pub const QUEUE_LEN: usize = 4096;
+
+static QUEUE: Mutex<CriticalSectionRawMutex, RefCell<FrameQueue<QUEUE_LEN>>> =
+    Mutex::new(RefCell::new(FrameQueue::new()));
+
+/// Set between `acquire` and `release`
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+struct LoggerState {
+    encoder: defmt::Encoder,
+    restore: critical_section::RestoreState,
+}
+
+struct SharedState(UnsafeCell<LoggerState>);
+
+// SAFETY: only touched between `acquire` and `release`, which hold a critical
+// section, and `TAKEN` stops them nesting
+unsafe impl Sync for SharedState {}
+
+static STATE: SharedState = SharedState(UnsafeCell::new(LoggerState {
+    encoder: defmt::Encoder::new(),
+    restore: critical_section::RestoreState::invalid(),
+}));
+
+#[defmt::global_logger]
+struct Logger;
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {
+        // SAFETY: `release` releases it
+        let restore = unsafe { critical_section::acquire() };
+        if TAKEN.swap(true, Ordering::Relaxed) {
+            panic!("defmt logger taken reentrantly")
+        }
+        // SAFETY: we hold the critical section, and `TAKEN`
+        let state = unsafe { &mut *STATE.0.get() };
+        state.restore = restore;
+        QUEUE.lock(|queue| queue.borrow_mut().start_frame());
+        state.encoder.start_frame(write_bytes);
+    }
+
+    unsafe fn flush() {
+        #[cfg(feature = "log-uart")]
+        uart::flush();
+    }
+
+    unsafe fn release() {
+        let state = &mut *STATE.0.get();
+        state.encoder.end_frame(write_bytes);
+        QUEUE.lock(|queue| queue.borrow_mut().end_frame());
+        #[cfg(feature = "log-uart")]
+        uart::kick();
+        let restore = state.restore;
+        TAKEN.store(false, Ordering::Relaxed);
+        critical_section::release(restore);
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        (*STATE.0.get()).encoder.write(bytes, write_bytes);
+    }
+}
+
+fn write_bytes(bytes: &[u8]) {
+    QUEUE.lock(|queue| queue.borrow_mut().write(bytes));
+}
+
+/// Moves as many bytes of finished log frames as fit into `buf`, for you to
+/// send to the host, and returns how many
+#[cfg(feature = "log-queue")]
+pub fn read(buf: &mut [u8]) -> usize {
+    QUEUE.lock(|queue| queue.borrow_mut().read(buf))
+}
+
+/// How often [`send_over_acm`] looks for new log frames, when it has sent
+/// everything
+#[cfg(feature = "log-queue")]
+const SEND_INTERVAL_MS: u64 = 10;
+
+/// Sends the log queue to the host over `class`, forever
+///
+/// Frames logged while the host isn't connected wait in the queue, and
+/// whatever doesn't fit is dropped, as usual. Don't also call [`read`].
+#[cfg(feature = "log-queue")]
+pub async fn send_over_acm<'d, D>(class: &mut embassy_usb::class::cdc_acm::CdcAcmClass<'d, D>) -> !
+where
+    D: embassy_usb::driver::Driver<'d>,
+{
+    // a full-speed bulk packet is at most 64 bytes
+    let mut packet = [0u8; 64];
+    let max_len = usize::from(class.max_packet_size()).min(packet.len());
+    loop {
+        class.wait_connection().await;
+        // The host takes a full-size packet to mean there's more to come, so
+        // the last one before a pause must be shorter, even if it's empty
+        let mut ended = true;
+        loop {
+            let len = QUEUE.lock(|queue| {
+                let queue = queue.borrow();
+                let chunk = queue.peek();
+                let len = chunk.len().min(max_len);
+                packet[..len].copy_from_slice(&chunk[..len]);
+                len
+            });
+            if len == 0 && ended {
+                embassy_time::Timer::after_millis(SEND_INTERVAL_MS).await;
+                continue;
+            }
+            if class.write_packet(&packet[..len]).await.is_err() {
+                // the host went away; the packet is still in the queue
+                break;
+            }
+            QUEUE.lock(|queue| queue.borrow_mut().consume(len));
+            ended = len < max_len;
+        }
+    }
+}
+
+/// How many log frames were dropped because the queue was full
+pub fn dropped_frames() -> u32 {
+    QUEUE.lock(|queue| queue.borrow().dropped_frames())
+}
+
+#[cfg(feature = "log-uart")]
+pub(crate) use uart::init as init_uart;
+
+#[cfg(feature = "log-uart")]
+mod uart {
+    use core::sync::atomic::{compiler_fence, AtomicBool, AtomicUsize, Ordering};
+
+    use hal::gpio::{Level, Output, OutputDrive};
+    use hal::pac::uarte::vals;
+    use hal::pac::{interrupt, Interrupt};
+    use hal::Peri;
+
+    use super::QUEUE;
+
+    static STARTED: AtomicBool = AtomicBool::new(false);
+
+    /// How many bytes EasyDMA is sending; 0 when the UARTE is idle
+    static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+    /// Starts sending the queue out of P0.06
+    pub(crate) fn init(
+        _uarte: Peri<'static, hal::peripherals::UARTE0>,
+        txd: Peri<'static, hal::peripherals::P0_06>,
+    ) {
+        // a UART line idles high; the UARTE drives the pin from now on, and
+        // we never give it back
+        core::mem::forget(Output::new(txd, Level::High, OutputDrive::Standard));
+
+        let r = hal::pac::UARTE0;
+        r.psel().txd().write_value(6);
+        r.baudrate()
+            .write(|w| w.set_baudrate(vals::Baudrate::BAUD115200));
+        r.enable().write(|w| w.set_enable(vals::Enable::ENABLED));
+        r.intenset().write(|w| w.set_endtx(true));
+        STARTED.store(true, Ordering::Relaxed);
+        // NOTE like RTC0, this interrupt's handler is ours, and it only
+        // touches the queue in a critical section
+        unsafe { cortex_m::peripheral::NVIC::unmask(Interrupt::UARTE0) };
+
+        // send whatever was logged before now
+        critical_section::with(|_| kick());
+    }
+
+    /// Starts sending the next chunk of the queue, if the UARTE is idle
+    pub(super) fn kick() {
+        if !STARTED.load(Ordering::Relaxed) || IN_FLIGHT.load(Ordering::Relaxed) != 0 {
+            return;
+        }
+        QUEUE.lock(|queue| {
+            let queue = queue.borrow();
+            let chunk = queue.peek();
+            if chunk.is_empty() {
+                return;
+            }
+            // MAXCNT is 16 bits
+            let len = chunk.len().min(usize::from(u16::MAX));
+            let r = hal::pac::UARTE0;
+            r.txd().ptr().write_value(chunk.as_ptr() as u32);
+            r.txd().maxcnt().write(|w| w.set_maxcnt(len as u16));
+            r.events_endtx().write_value(0);
+            IN_FLIGHT.store(len, Ordering::Relaxed);
+            // the frames must be in RAM before EasyDMA reads them
+            compiler_fence(Ordering::SeqCst);
+            r.tasks_starttx().write_value(1);
+        });
+    }
+
+    /// If the UARTE has finished a chunk, drops it from the queue and starts
+    /// on the next
+    ///
+    /// Only call this in a critical section; the UARTE0 interrupt calls it
+    /// too.
+    fn service() {
+        let r = hal::pac::UARTE0;
+        if r.events_endtx().read() == 0 {
+            return;
+        }
+        r.events_endtx().write_value(0);
+        let sent = IN_FLIGHT.swap(0, Ordering::Relaxed);
+        QUEUE.lock(|queue| queue.borrow_mut().consume(sent));
+        kick();
+    }
+
+    /// Waits for everything in the queue to be sent
+    pub(super) fn flush() {
+        // each step in its own critical section, so the interrupt can't
+        // service the same chunk twice
+        while critical_section::with(|_| {
+            service();
+            IN_FLIGHT.load(Ordering::Relaxed) != 0
+        }) {}
+    }
+
+    #[interrupt]
+    fn UARTE0() {
+        critical_section::with(|_| service());
+    }
+}
@@ -0,0 +1,214 @@
+//! # LED brightness and patterns, on the PWM peripheral
+//!
+//! [`PwmLeds`] takes over the four LEDs and drives them from PWM0, so each
+//! one can be dimmed, and one of them can play a [`Pattern`]:
+//!
+//! ```rust ignore
+//! let mut leds = dk::pwm::PwmLeds::new(board.leds, board.pwm);
+//! leds.set_brightness(LedId::_1, 32);
+//! leds.play(LedId::_4, &Pattern::Morse("solved"))?;
+//! // carry on; the PWM peripheral loops over the pattern by itself
+//! ```
+//!
+//! The pattern is rendered by `dk_core::pattern` into a sequence of duty
+//! cycles, which the PWM peripheral reads from RAM with EasyDMA, holding each
+//! one for as many PWM periods as the pattern asks for. Once it has started,
+//! the CPU isn't involved at all.
+
+use core::sync::atomic::{compiler_fence, Ordering};
+
+pub use dk_core::pattern::{duty, morse, Error, Pattern};
+use grounded::uninit::GroundedArrayCell;
+use hal::pac::pwm::vals;
+use hal::Peri;
+
+use crate::Leds;
+
+/// How many samples a pattern can have
+pub const CAPACITY: usize = 512;
+
+/// The PWM counter runs at 1 MHz and counts up to this, so one PWM period is
+/// 1 ms
+const TOP: u16 = 1_000;
+
+/// Bit 15 of a duty cycle picks the polarity. Clear, the pin is low for the
+/// first `duty` counts of each period, which is when our LEDs are on.
+const ACTIVE_LOW: u16 = 0;
+
+/// The duty cycles, four per sample (one per LED), for EasyDMA to read
+static SEQUENCE: GroundedArrayCell<u16, { 4 * CAPACITY }> = GroundedArrayCell::const_init();
+
+/// Which LED
+#[derive(Debug, Copy, Clone, PartialEq, Eq, defmt::Format)]
+pub enum LedId {
+    /// LED1: pin P0.13
+    _1,
+    /// LED2: pin P0.14
+    _2,
+    /// LED3: pin P0.15
+    _3,
+    /// LED4: pin P0.16
+    _4,
+}
+
+impl LedId {
+    fn channel(self) -> usize {
+        self as usize
+    }
+}
+
+/// The four LEDs, on PWM0
+///
+/// Dropping it leaves the LEDs doing whatever they were doing.
+pub struct PwmLeds {
+    leds: Leds,
+    _pwm: Peri<'static, hal::peripherals::PWM0>,
+    words: &'static mut [u16],
+    brightness: [u8; 4],
+    /// The pattern's samples, and which LED plays them, how many there are
+    /// and how long each one lasts, in ms
+    samples: [u8; CAPACITY],
+    pattern: Option<(LedId, usize, u32)>,
+    running: bool,
+}
+
+impl PwmLeds {
+    /// Takes over the LEDs, all off
+    pub fn new(leds: Leds, pwm: Peri<'static, hal::peripherals::PWM0>) -> Self {
+        // SAFETY: only whoever owns PWM0 touches the sequence, and there's
+        // only one PWM0, so this is the only reference to it
+        let words = unsafe {
+            let (ptr, len) = SEQUENCE.initialize_all_copied(0);
+            core::slice::from_raw_parts_mut(ptr, len)
+        };
+
+        let r = hal::pac::PWM0;
+        // the LED pins stay outputs, as the PWM peripheral needs; it just
+        // takes over driving them
+        for (n, led) in [&leds._1, &leds._2, &leds._3, &leds._4]
+            .into_iter()
+            .enumerate()
+        {
+            r.psel().out(n).write_value(u32::from(led.pin));
+        }
+        r.mode().write(|w| w.set_updown(vals::Updown::UP));
+        r.prescaler()
+            .write(|w| w.set_prescaler(vals::Prescaler::DIV_16));
+        r.countertop().write(|w| w.set_countertop(TOP));
+        r.decoder().write(|w| {
+            w.set_load(vals::Load::INDIVIDUAL);
+            w.set_mode(vals::Mode::REFRESH_COUNT);
+        });
+        // play sequence 0 then sequence 1 (the same samples) once, then
+        // start over, forever
+        r.loop_().write(|w| w.set_cnt(vals::LoopCnt(1)));
+        r.shorts().write(|w| w.set_loopsdone_seqstart0(true));
+        r.enable().write(|w| w.set_enable(true));
+
+        defmt::debug!("LEDs switched over to PWM0");
+
+        Self {
+            leds,
+            _pwm: pwm,
+            words,
+            brightness: [0; 4],
+            samples: [0; CAPACITY],
+            pattern: None,
+            running: false,
+        }
+    }
+
+    /// Sets an LED to `brightness`, from 0 (off) to 255 (fully on)
+    ///
+    /// If the LED was playing a pattern, it stops; a pattern on another LED
+    /// carries on.
+    pub fn set_brightness(&mut self, led: LedId, brightness: u8) {
+        self.brightness[led.channel()] = brightness;
+        if matches!(self.pattern, Some((playing, ..)) if playing == led) {
+            self.pattern = None;
+        }
+        self.start();
+    }
+
+    /// Plays `pattern` on one LED, over and over, while the others stay at
+    /// their brightness
+    ///
+    /// This replaces any pattern already playing, on any LED. It returns as
+    /// soon as the pattern has started.
+    pub fn play(&mut self, led: LedId, pattern: &Pattern) -> Result<(), Error> {
+        let len = pattern.render(&mut self.samples)?;
+        self.pattern = Some((led, len, pattern.step_ms()));
+        self.start();
+        defmt::debug!("playing {} on LED {}", pattern, led);
+        Ok(())
+    }
+
+    /// Stops any pattern; every LED goes back to its brightness
+    pub fn stop_pattern(&mut self) {
+        self.pattern = None;
+        self.start();
+    }
+
+    /// Switches the LEDs off and hands them back, for on/off use
+    pub fn release(mut self) -> (Leds, Peri<'static, hal::peripherals::PWM0>) {
+        self.halt();
+        let r = hal::pac::PWM0;
+        r.enable().write(|w| w.set_enable(false));
+        for n in 0..4 {
+            // disconnected
+            r.psel().out(n).write_value(1 << 31);
+        }
+        let mut leds = self.leds;
+        for led in [&mut leds._1, &mut leds._2, &mut leds._3, &mut leds._4] {
+            led.off();
+        }
+        (leds, self._pwm)
+    }
+
+    /// Fills in the sequence and starts playing it
+    fn start(&mut self) {
+        self.halt();
+
+        // with no pattern, one sample held for one period
+        let (len, step_ms) = self
+            .pattern
+            .map_or((1, 1), |(_, len, step_ms)| (len, step_ms));
+        for (i, frame) in self.words.chunks_exact_mut(4).take(len).enumerate() {
+            for (channel, word) in frame.iter_mut().enumerate() {
+                let brightness = match self.pattern {
+                    Some((led, ..)) if led.channel() == channel => self.samples[i],
+                    _ => self.brightness[channel],
+                };
+                *word = duty(brightness, TOP) | ACTIVE_LOW;
+            }
+        }
+
+        let r = hal::pac::PWM0;
+        for seq in 0..2 {
+            let s = r.seq(seq);
+            s.ptr().write_value(self.words.as_ptr() as u32);
+            s.cnt().write(|w| w.set_cnt(4 * len as u16));
+            // each sample plays for `refresh + 1` periods of 1 ms
+            s.refresh().write(|w| w.set_cnt(step_ms.saturating_sub(1)));
+            s.enddelay().write(|w| w.set_cnt(0));
+        }
+        // make sure the samples are in RAM before EasyDMA reads them
+        compiler_fence(Ordering::SeqCst);
+        r.tasks_seqstart(0).write_value(1);
+        self.running = true;
+    }
+
+    /// Stops the PWM peripheral, so we can change the sequence under it
+    fn halt(&mut self) {
+        if !self.running {
+            return;
+        }
+        let r = hal::pac::PWM0;
+        r.events_stopped().write_value(0);
+        r.tasks_stop().write_value(1);
+        while r.events_stopped().read() == 0 {
+            core::hint::spin_loop();
+        }
+        self.running = false;
+    }
+}
@@ -1,6 +1,8 @@
 //! # IEEE 802.15.4 radio driver
 //!
 //! This driver is a synchronous/blocking one written for a radio training.
+//! [`AsyncRadio`] wraps it to wait on the RADIO interrupt instead, for use
+//! from async tasks (like RTIC 2 software tasks).
 //! A lot of its implementation is based on the
 //! [nrf52840 HAL](https://github.com/nrf-rs/nrf-hal)
 //!
@@ -28,10 +30,16 @@
 //! IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 //! DEALINGS IN THE SOFTWARE.
 
-use core::sync::atomic::{compiler_fence, Ordering};
+use core::future::poll_fn;
+use core::task::Poll;
 
-use crate::Peri;
-use hal::pac::radio::regs::Shorts;
+use crate::{Countdown, Peri};
+pub use dk_core::filter::{Filter, Prefix};
+pub use dk_core::mac;
+pub use dk_core::radio::RxInfo;
+use dk_core::radio::{Driver, Event, Registers, Shorts, State, Task};
+use embassy_sync::waitqueue::AtomicWaker;
+use hal::interrupt::typelevel::{Binding, Handler, Interrupt as _, RADIO};
 use hal::pac::radio::vals::{self, Crcstatus, State as RadioState};
 pub use hal::radio::TxPower;
 
@@ -45,6 +53,56 @@ pub enum Error {
     Crc(u16),
     /// Timeout
     Timeout,
+    /// The frame was sent, but never acknowledged
+    NoAck,
+    /// `send_with_ack` was given a packet which isn't a MAC frame
+    NotMacFrame,
+}
+
+/// How many times `send_with_ack` sends a frame again, if it isn't
+/// acknowledged (`macMaxFrameRetries`)
+pub const MAX_FRAME_RETRIES: u32 = 3;
+
+/// How long `send_with_ack` waits for an ACK, in microseconds
+///
+/// The standard's `macAckWaitDuration` is 864 µs, but the other end may well
+/// be acknowledging in software, like this driver does.
+pub const ACK_WAIT_US: u32 = 2_000;
+
+/// The addresses we acknowledge frames for
+#[derive(Debug, Copy, Clone, PartialEq, Eq, defmt::Format)]
+pub struct AckAddress {
+    /// Our PAN ID
+    pub pan_id: u16,
+    /// Our short address
+    pub short: u16,
+    /// Our extended (EUI-64) address
+    pub extended: u64,
+}
+
+/// If `psdu` asks us for an ACK, its sequence number
+fn ack_requested(psdu: &[u8], us: &AckAddress) -> Option<u8> {
+    let frame = mac::Frame::parse(psdu).ok()?;
+    let wants_ack = frame.ack_request
+        && matches!(
+            frame.frame_type,
+            mac::FrameType::Data | mac::FrameType::MacCommand
+        );
+    // Frames with no destination are for the PAN coordinator, which we aren't
+    let dst = frame.dst?;
+    let ours = dst.pan_id == us.pan_id
+        && match dst.address {
+            mac::Address::Short(short) => {
+                short == us.short && dst.address != mac::Address::BROADCAST
+            }
+            mac::Address::Extended(extended) => extended == us.extended,
+        };
+    (wants_ack && ours).then_some(frame.sequence)
+}
+
+/// Sets the Acknowledgment Request bit in a MAC frame, returning its sequence number
+fn request_ack(packet: &mut Packet) -> Result<u8, Error> {
+    mac::request_ack(packet).map_err(|_| Error::NotMacFrame)
 }
 
 /// IEEE 802.15.4 channels
@@ -93,20 +151,95 @@ impl Channel {
     }
 }
 
-/// Driver state
-///
-/// After, or at the start of, any method call the RADIO will be in one of these states
-// This is a subset of the STATE_A enum
-#[derive(Copy, Clone, PartialEq, Eq, defmt::Format)]
-enum State {
-    Disabled,
-    RxIdle,
-    TxIdle,
+/// The RADIO registers, for the state machine in `dk_core::radio`
+pub(crate) struct Hardware;
+
+impl Registers for Hardware {
+    fn state(&mut self) -> State {
+        match hal::pac::RADIO.state().read().state() {
+            RadioState::DISABLED => State::Disabled,
+            RadioState::RX_RU => State::RxRu,
+            RadioState::RX_IDLE => State::RxIdle,
+            RadioState::RX => State::Rx,
+            RadioState::RX_DISABLE => State::RxDisable,
+            RadioState::TX_RU => State::TxRu,
+            RadioState::TX_IDLE => State::TxIdle,
+            RadioState::TX => State::Tx,
+            RadioState::TX_DISABLE => State::TxDisable,
+            _ => unreachable!(),
+        }
+    }
+
+    fn trigger(&mut self, task: Task) {
+        let r = hal::pac::RADIO;
+        let task = match task {
+            Task::TxEn => r.tasks_txen(),
+            Task::RxEn => r.tasks_rxen(),
+            Task::Start => r.tasks_start(),
+            Task::Stop => r.tasks_stop(),
+            Task::Disable => r.tasks_disable(),
+            Task::CcaStart => r.tasks_ccastart(),
+            Task::CcaStop => r.tasks_ccastop(),
+            Task::EdStart => r.tasks_edstart(),
+        };
+        task.write_value(1);
+    }
+
+    fn event(&mut self, event: Event) -> bool {
+        event_register(event).read() == 1
+    }
+
+    fn clear_event(&mut self, event: Event) {
+        event_register(event).write_value(0);
+    }
+
+    fn set_shorts(&mut self, shorts: Shorts) {
+        hal::pac::RADIO.shorts().write(|w| {
+            w.set_txready_start(shorts.txready_start);
+            w.set_end_disable(shorts.end_disable);
+            w.set_ccaidle_txen(shorts.ccaidle_txen);
+            w.set_address_rssistart(shorts.address_rssistart);
+        });
+    }
+
+    fn set_packet_ptr(&mut self, ptr: *const u8) {
+        hal::pac::RADIO.packetptr().write_value(ptr as u32);
+    }
+
+    fn crc_ok(&mut self) -> bool {
+        hal::pac::RADIO.crcstatus().read().crcstatus() == Crcstatus::CRCOK
+    }
+
+    fn rx_crc(&mut self) -> u16 {
+        hal::pac::RADIO.rxcrc().read().rxcrc() as u16
+    }
+
+    fn set_ed_count(&mut self, count: u32) {
+        hal::pac::RADIO.edcnt().write(|w| w.set_edcnt(count));
+    }
+
+    fn ed_sample(&mut self) -> u8 {
+        // note that if EDCNT has been increased, the EDSAMPLE register contains the maximum
+        // recorded value, not the average
+        hal::pac::RADIO.edsample().read().edlvl()
+    }
+
+    fn rssi_sample(&mut self) -> u8 {
+        hal::pac::RADIO.rssisample().read().rssisample()
+    }
 }
 
-#[derive(Debug, defmt::Format)]
-enum Event {
-    PhyEnd,
+fn event_register(event: Event) -> hal::pac::common::Reg<u32, hal::pac::common::RW> {
+    let r = hal::pac::RADIO;
+    match event {
+        Event::Ready => r.events_ready(),
+        Event::End => r.events_end(),
+        Event::PhyEnd => r.events_phyend(),
+        Event::Disabled => r.events_disabled(),
+        Event::CcaIdle => r.events_ccaidle(),
+        Event::CcaBusy => r.events_ccabusy(),
+        Event::EdEnd => r.events_edend(),
+    }
 }
 
 /// Non-blocking receive
@@ -126,21 +259,11 @@ impl<'a, 'c> Recv<'a, 'c> {
     /// `Err(nb::Error::WouldBlock)` if a packet hasn't been received
     /// yet, and `Err(nb::Error::Other)` if the CRC check failed.
     pub fn is_done(&self) -> nb::Result<u16, u16> {
-        let regs = self.radio.regs();
-        if regs.events_end().read() == 1 {
-            regs.events_end().write_value(0);
-
-            dma_end_fence();
-
-            let crc = regs.rxcrc().read().rxcrc() as u16;
-
-            if regs.crcstatus().read().crcstatus() == Crcstatus::CRCOK {
-                Ok(crc)
-            } else {
-                Err(nb::Error::Other(crc))
-            }
-        } else {
-            Err(nb::Error::WouldBlock)
+        // polling only reads the registers, so it needs none of our driver's state
+        match Driver::new(Hardware).poll_recv() {
+            Some(Ok(crc)) => Ok(crc),
+            Some(Err(crc)) => Err(nb::Error::Other(crc)),
+            None => Err(nb::Error::WouldBlock),
         }
     }
 }
@@ -170,7 +293,12 @@ pub enum Cca {
 /// IEEE 802.15.4 radio driver.
 pub struct Radio<'d> {
     _p: Peri<'d, hal::peripherals::RADIO>,
-    needs_enable: bool,
+    driver: Driver<Hardware>,
+    ack_address: Option<AckAddress>,
+    frame_pending: bool,
+    filter: Filter,
+    channel: u8,
+    rx_info: Option<RxInfo>,
 }
 
 impl<'d> Radio<'d> {
@@ -222,13 +350,16 @@ impl<'d> Radio<'d> {
             w.set_whiteen(false);
         });
 
-        // Enable NVIC interrupt
-        //T::Interrupt::unpend();
-        //unsafe { T::Interrupt::enable() };
+        // The NVIC interrupt is only enabled by `AsyncRadio`
 
         let mut radio = Self {
             _p: radio,
-            needs_enable: false,
+            driver: Driver::new(Hardware),
+            ack_address: None,
+            frame_pending: false,
+            filter: Filter::Promiscuous,
+            channel: 11,
+            rx_info: None,
         };
 
         radio.set_sfd(DEFAULT_SFD);
@@ -257,17 +388,23 @@ impl<'d> Radio<'d> {
             panic!("Bad 802.15.4 channel");
         }
         let frequency_offset = (channel - 10) * 5;
-        self.needs_enable = true;
+        self.channel = channel;
+        self.driver.settings_changed();
         r.frequency().write(|w| {
             w.set_frequency(frequency_offset);
             w.set_map(vals::Map::DEFAULT);
         });
     }
 
+    /// The channel we are on
+    pub fn channel(&self) -> u8 {
+        self.channel
+    }
+
     /// Changes the Clear Channel Assessment method
     pub fn set_cca(&mut self, cca: Cca) {
         let r = self.regs();
-        self.needs_enable = true;
+        self.driver.settings_changed();
         match cca {
             Cca::CarrierSense => r
                 .ccactrl()
@@ -283,6 +420,54 @@ impl<'d> Radio<'d> {
         }
     }
 
+    /// Turns automatic acknowledgement on (with our addresses) or off
+    ///
+    /// When it is on, `recv` and `recv_timeout` reply with an ACK to every
+    /// data or command frame which asks for one and which is addressed to
+    /// us. Frames sent to the broadcast address are never acknowledged.
+    pub fn set_auto_ack(&mut self, address: Option<AckAddress>) {
+        self.ack_address = address;
+    }
+
+    /// Sets the frame pending bit in the ACKs we send
+    ///
+    /// This tells the other end that we have more data for it.
+    pub fn set_frame_pending(&mut self, pending: bool) {
+        self.frame_pending = pending;
+    }
+
+    /// Changes which frames `recv` and `recv_timeout` hand back
+    ///
+    /// The RADIO has no address matching in 802.15.4 mode, so the driver
+    /// drops the frames that `filter` turns down and listens again. The
+    /// default, `Filter::Promiscuous`, hands back everything.
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+    }
+
+    /// Which frames `recv` and `recv_timeout` hand back
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    /// The signal strength, LQI, channel and time of the last packet that
+    /// `recv` or `recv_timeout` handed back (even with a bad CRC)
+    ///
+    /// `None` until we have received something.
+    pub fn rx_info(&self) -> Option<RxInfo> {
+        self.rx_info
+    }
+
+    /// Remember what we know about `packet`, which we just received
+    fn record_rx_info(&mut self, packet: &Packet) {
+        self.rx_info = Some(RxInfo {
+            rssi: self.driver.rssi(),
+            lqi: packet.lqi(),
+            channel: self.channel,
+            timestamp: crate::uptime_ticks(),
+        });
+    }
+
     /// Changes the Start of Frame Delimiter (SFD)
     pub fn set_sfd(&mut self, sfd: u8) {
         let r = self.regs();
@@ -297,8 +482,6 @@ impl<'d> Radio<'d> {
 
     /// Changes the radio transmission power
     pub fn set_transmission_power_raw(&mut self, power: i8) {
-        self.needs_enable = true;
-
         let tx_power: TxPower = match power {
             8 => TxPower::POS8_DBM,
             7 => TxPower::POS7_DBM,
@@ -324,7 +507,7 @@ impl<'d> Radio<'d> {
     /// Changes the radio transmission power
     pub fn set_transmission_power(&mut self, power: TxPower) {
         let r = self.regs();
-        self.needs_enable = true;
+        self.driver.settings_changed();
         r.txpower().write(|w| w.set_txpower(power));
     }
 
@@ -333,28 +516,6 @@ impl<'d> Radio<'d> {
         while self.regs().state().read().state() != state {}
     }
 
-    /// Get the current radio state
-    fn state(&self) -> State {
-        match self.regs().state().read().state() {
-            // final states
-            RadioState::DISABLED => State::Disabled,
-            RadioState::TX_IDLE => State::TxIdle,
-            RadioState::RX_IDLE => State::RxIdle,
-
-            // transitory states
-            RadioState::TX_DISABLE => {
-                self.wait_for_state_a(RadioState::DISABLED);
-                State::Disabled
-            }
-
-            _ => unreachable!(),
-        }
-    }
-
-    fn set_buffer(&mut self, buffer: &[u8]) {
-        self.regs().packetptr().write_value(buffer.as_ptr() as u32);
-    }
-
     /// Sample the received signal power (i.e. the presence of possibly interfering signals)
     /// within the bandwidth of the currently used channel for `sample_cycles` iterations.
     /// Note that one iteration has a sample time of 128μs, and that each iteration produces the
@@ -366,33 +527,7 @@ impl<'d> Radio<'d> {
     /// For details, see Section 6.20.12.3 Energy detection (ED) of the PS.
     /// RSSI samples are averaged over a measurement time of 8 symbol periods (128 μs).
     pub fn energy_detection_scan(&mut self, sample_cycles: u32) -> u8 {
-        let regs = self.regs();
-        // Increase the time spent listening
-        regs.edcnt().write(|w| w.set_edcnt(sample_cycles));
-
-        // ensure that the shortcut between READY event and START task is disabled before putting
-        // the radio into recv mode
-        regs.shorts()
-            .write_value(hal::pac::radio::regs::Shorts::default());
-        self.put_in_rx_mode();
-
-        // clear related events
-        regs.events_edend().write_value(0);
-
-        // start energy detection sampling
-        regs.tasks_edstart().write_value(1);
-
-        loop {
-            if regs.events_edend().read() == 1 {
-                // sampling period is over; collect value
-                regs.events_edend().write_value(0);
-
-                // note that since we have increased EDCNT, the EDSAMPLE register contains the
-                // maximum recorded value, not the average
-                let read_lvl = regs.edsample().read().edlvl();
-                return read_lvl;
-            }
-        }
+        self.driver.energy_detection_scan(sample_cycles)
     }
 
     /// Receives one radio packet and copies its contents into the given `packet` buffer
@@ -400,17 +535,29 @@ impl<'d> Radio<'d> {
     /// This methods returns the `Ok` variant if the CRC included the packet was successfully
     /// validated by the hardware; otherwise it returns the `Err` variant. In either case, `packet`
     /// will be updated with the received packet's data
+    ///
+    /// Frames which the filter (see `set_filter`) turns down are skipped.
     pub fn recv(&mut self, packet: &mut Packet) -> Result<u16, u16> {
-        // Start non-blocking receive
-        self.recv_non_blocking(packet, |recv| {
-            // Block until receive is done
-            nb::block!(recv.is_done())
-        })
+        loop {
+            // Start non-blocking receive
+            let result = self.recv_non_blocking(packet, |recv| {
+                // Block until receive is done
+                nb::block!(recv.is_done())
+            });
+            if self.filter.accepts(packet, result.is_ok()) {
+                self.record_rx_info(packet);
+                if result.is_ok() {
+                    self.acknowledge(packet);
+                }
+                return result;
+            }
+        }
     }
 
     /// Receives one radio packet and copies its contents into the given `packet` buffer
     ///
-    /// This method is non-blocking
+    /// This method is non-blocking. It never sends an ACK, even when
+    /// `set_auto_ack` is on, and it hands back every frame, whatever the filter.
     pub fn recv_non_blocking<'a, R>(
         &'a mut self,
         packet: &'a mut Packet,
@@ -436,6 +583,7 @@ impl<'d> Radio<'d> {
     /// If a packet is received within the time span then the packet CRC is checked. If the CRC is
     /// incorrect then the `Crc` error is returned; otherwise the `Ok` variant is returned.
     /// Note that `packet` will contain the packet in any case, even if the CRC check failed.
+    /// Frames which the filter (see `set_filter`) turns down are skipped.
     ///
     /// Note that the time it takes to switch the radio to RX mode is included in the timeout count.
     /// This transition may take up to a hundred of microseconds; see the section 6.20.15.8 in the
@@ -443,13 +591,41 @@ impl<'d> Radio<'d> {
     pub fn recv_timeout(
         &mut self,
         packet: &mut Packet,
-        timer: &mut super::Timer,
+        timer: &mut impl Countdown,
         microseconds: u32,
     ) -> Result<u16, Error> {
         // Start the timeout timer
         timer.start(microseconds);
 
-        // Start non-blocking receive
+        loop {
+            let result = self.recv_until(packet, timer);
+            match result {
+                Err(Error::Timeout) => return result,
+                _ if !self.filter.accepts(packet, result.is_ok()) => {
+                    // not for us; keep listening until the timer runs out
+                }
+                Ok(_) => {
+                    self.record_rx_info(packet);
+                    self.acknowledge(packet);
+                    return result;
+                }
+                Err(_) => {
+                    self.record_rx_info(packet);
+                    return result;
+                }
+            }
+        }
+    }
+
+    /// Receives one packet, or times out when `timer` finishes
+    ///
+    /// Like `recv_non_blocking`, this hands back every frame, whatever the
+    /// filter, and never sends an ACK.
+    fn recv_until(
+        &mut self,
+        packet: &mut Packet,
+        timer: &mut impl Countdown,
+    ) -> Result<u16, Error> {
         self.recv_non_blocking(packet, |recv| {
             // Check if either receive is done or timeout occurred
             loop {
@@ -474,32 +650,64 @@ impl<'d> Radio<'d> {
         })
     }
 
+    /// Send an ACK for `packet`, if it wants one from us
+    fn acknowledge(&mut self, packet: &Packet) {
+        let Some(address) = self.ack_address else {
+            return;
+        };
+        if let Some(seq) = ack_requested(packet, &address) {
+            let mut reply = Packet::new();
+            // an ACK always fits
+            let _ = reply.write_frame(&mac::Frame::ack(seq, self.frame_pending));
+            self.send_no_cca(&mut reply);
+        }
+    }
+
+    /// Sends a MAC frame, asking for it to be acknowledged
+    ///
+    /// This sets the Acknowledgment Request bit in `packet`'s frame control
+    /// field and sends it, up to `MAX_FRAME_RETRIES` more times if no ACK
+    /// with its sequence number comes back within `ACK_WAIT_US`. Other frames
+    /// which arrive meanwhile are ignored, and not acknowledged.
+    ///
+    /// Returns the frame pending bit of the ACK, or `NoAck`. If `packet`
+    /// isn't a MAC frame, nothing is sent and this returns `NotMacFrame`.
+    pub fn send_with_ack(
+        &mut self,
+        packet: &mut Packet,
+        timer: &mut impl Countdown,
+    ) -> Result<bool, Error> {
+        let seq = request_ack(packet)?;
+        let mut reply = Packet::new();
+        for _ in 0..=MAX_FRAME_RETRIES {
+            self.send(packet);
+            // not `recv_timeout`: an ACK has no address for the filter to
+            // look at, and we mustn't acknowledge other frames meanwhile
+            timer.start(ACK_WAIT_US);
+            loop {
+                match self.recv_until(&mut reply, timer) {
+                    Err(Error::Timeout) => break,
+                    Ok(_) => {
+                        if let Some(frame_pending) = mac::ack_for(&reply, seq) {
+                            return Ok(frame_pending);
+                        }
+                    }
+                    // someone else's frame, or a broken one: keep listening
+                    Err(_) => {}
+                }
+            }
+        }
+        Err(Error::NoAck)
+    }
+
     unsafe fn start_recv(&mut self, packet: &mut Packet) {
-        let regs = self.regs();
         // NOTE we do NOT check the address of `packet` because the mutable reference ensures it's
         // allocated in RAM
-
-        // clear related events
-        regs.events_phyend().write_value(0);
-        regs.events_end().write_value(0);
-
-        self.put_in_rx_mode();
-
-        // NOTE(unsafe) DMA transfer has not yet started
-        // set up RX buffer
-        self.set_buffer(packet.buffer.as_mut());
-
-        // start transfer
-        dma_start_fence();
-        regs.tasks_start().write_value(1);
+        self.driver.start_recv(packet.buffer.as_mut_ptr());
     }
 
     fn cancel_recv(&mut self) {
-        let regs = self.regs();
-        regs.tasks_stop().write_value(1);
-        self.wait_for_state_a(RadioState::RX_IDLE);
-        // DMA transfer may have been in progress so synchronize with its memory operations
-        dma_end_fence();
+        self.driver.cancel_recv();
     }
 
     /// Tries to send the given `packet`
@@ -514,45 +722,8 @@ impl<'d> Radio<'d> {
     // allocated in RAM
     #[allow(clippy::result_unit_err)]
     pub fn try_send(&mut self, packet: &mut Packet) -> Result<(), ()> {
-        let regs = self.regs();
-        // enable radio to perform cca
-        self.put_in_rx_mode();
-
-        // clear related events
-        regs.events_phyend().write_value(0);
-        regs.events_end().write_value(0);
-
-        // NOTE(unsafe) DMA transfer has not yet started
-        regs.packetptr().write_value(packet.buffer.as_ptr() as u32);
-
-        // configure radio to immediately start transmission if the channel is idle
-        regs.shorts().modify(|w| {
-            w.set_ccaidle_txen(true);
-            w.set_end_disable(true);
-        });
-
-        // the DMA transfer will start at some point after the following write operation so
-        // we place the compiler fence here
-        dma_start_fence();
-        // start CCA. In case the channel is clear, the data at packetptr will be sent automatically
-        regs.tasks_ccastart().write_value(1);
-
-        loop {
-            if regs.events_phyend().read() == 1 {
-                // transmission completed
-                dma_end_fence();
-                regs.events_phyend().write_value(0);
-                regs.shorts().write_value(Shorts::default());
-                return Ok(());
-            }
-
-            if regs.events_ccabusy().read() == 1 {
-                // channel is busy
-                regs.events_ccabusy().write_value(0);
-                regs.shorts().write_value(Shorts::default());
-                return Err(());
-            }
-        }
+        // NOTE(unsafe) we wait for the transfer to finish before returning
+        unsafe { self.driver.try_send(packet.buffer.as_ptr()) }
     }
 
     /// Sends the given `packet`
@@ -566,49 +737,8 @@ impl<'d> Radio<'d> {
     // NOTE we do NOT check the address of `packet` because the mutable reference ensures it's
     // allocated in RAM
     pub fn send(&mut self, packet: &mut Packet) {
-        let regs = self.regs();
-
-        // enable radio to perform cca
-        self.put_in_rx_mode();
-
-        // clear related events
-        regs.events_phyend().write_value(0);
-        regs.events_end().write_value(0);
-
-        // immediately start transmission if the channel is idle
-        regs.shorts().modify(|w| {
-            w.set_ccaidle_txen(true);
-            w.set_txready_start(true);
-            w.set_end_disable(true);
-        });
-
-        // the DMA transfer will start at some point after the following write operation so
-        // we place the compiler fence here
-        dma_start_fence();
-        // NOTE(unsafe) DMA transfer has not yet started
-        regs.packetptr().write_value(packet.buffer.as_ptr() as u32);
-
-        'cca: loop {
-            // start CCA (+ sending if channel is clear)
-            regs.tasks_ccastart().write_value(1);
-
-            loop {
-                if regs.events_phyend().read() == 1 {
-                    dma_end_fence();
-                    // transmission is complete
-                    regs.events_phyend().write_value(0);
-                    break 'cca;
-                }
-
-                if regs.events_ccabusy().read() == 1 {
-                    // channel is busy; try another CCA
-                    regs.events_ccabusy().write_value(0);
-                    continue 'cca;
-                }
-            }
-        }
-
-        regs.shorts().write_value(Shorts::default());
+        // NOTE(unsafe) we wait for the transfer to finish before returning
+        unsafe { self.driver.send(packet.buffer.as_ptr()) }
     }
 
     /// Sends the specified `packet` without first performing CCA
@@ -620,109 +750,273 @@ impl<'d> Radio<'d> {
     // NOTE we do NOT check the address of `packet` because the mutable reference ensures it's
     // allocated in RAM
     pub fn send_no_cca(&mut self, packet: &mut Packet) {
-        let regs = self.regs();
-        self.put_in_tx_mode();
+        // NOTE(unsafe) we wait for the transfer to finish before returning
+        unsafe { self.driver.send_no_cca(packet.buffer.as_ptr()) }
+    }
 
-        // clear related events
-        regs.events_phyend().write_value(0);
-        regs.events_end().write_value(0);
+    /// Moves the radio from any state to the DISABLED state
+    pub fn disable(&mut self) {
+        // See figure 110 in nRF52840-PS
+        self.driver.disable();
+    }
 
-        // NOTE(unsafe) DMA transfer has not yet started
-        regs.packetptr().write_value(packet.buffer.as_ptr() as u32);
+    /// Stops the radio and gives back the peripheral, for `ble::Ble` to use
+    pub(crate) fn into_peripheral(mut self) -> Peri<'d, hal::peripherals::RADIO> {
+        self.disable();
+        self._p
+    }
+}
 
-        // configure radio to disable transmitter once packet is sent
-        regs.shorts().modify(|w| w.set_end_disable(true));
+/// Wakes the task waiting for the RADIO
+static WAKER: AtomicWaker = AtomicWaker::new();
 
-        // start DMA transfer
-        dma_start_fence();
-        regs.tasks_start().write_value(1);
+/// The RADIO interrupt handler for [`AsyncRadio`]
+///
+/// Bind it to the interrupt with:
+///
+/// ```rust ignore
+/// dk::hal::bind_interrupts!(struct Irqs {
+///     RADIO => dk::radio::InterruptHandler;
+/// });
+/// ```
+pub struct InterruptHandler {
+    _private: (),
+}
 
-        self.wait_for_event(Event::PhyEnd);
-        regs.shorts().write_value(Shorts::default());
+impl Handler<RADIO> for InterruptHandler {
+    unsafe fn on_interrupt() {
+        // Leave the events for the waiting task to look at; just stop them
+        // interrupting us again
+        hal::pac::RADIO.intenclr().write(|w| w.0 = 0xffff_ffff);
+        WAKER.wake();
     }
+}
 
-    /// Moves the radio from any state to the DISABLED state
-    pub fn disable(&mut self) {
-        let regs = self.regs();
-        // See figure 110 in nRF52840-PS
+/// Interrupt-driven IEEE 802.15.4 radio driver
+///
+/// The same operations as [`Radio`], but they wait for the RADIO interrupt
+/// rather than spinning, so other tasks can run in the meantime. Timeouts
+/// use `embassy-time`.
+///
+/// Dropping one of its futures part way through stops the RADIO, so the
+/// `packet` buffer is safe to reuse afterwards. Leaking one (with
+/// `core::mem::forget`) is not allowed.
+pub struct AsyncRadio<'d> {
+    radio: Radio<'d>,
+}
+
+impl<'d> AsyncRadio<'d> {
+    /// Use `radio` asynchronously, with interrupts bound by `bind_interrupts!`
+    pub fn new(mut radio: Radio<'d>, _irq: impl Binding<RADIO, InterruptHandler> + 'd) -> Self {
+        radio.clear_all_interrupts();
+        RADIO::unpend();
+        // SAFETY: our handler shares nothing with the code it interrupts
+        // except `WAKER`, which is made for this
+        unsafe { RADIO::enable() };
+        Self { radio }
+    }
+
+    /// Go back to the blocking driver
+    pub fn into_blocking(self) -> Radio<'d> {
+        RADIO::disable();
+        self.radio
+    }
+
+    /// The blocking driver underneath, for changing its settings
+    pub fn blocking(&mut self) -> &mut Radio<'d> {
+        &mut self.radio
+    }
+
+    /// Set the channel.
+    pub fn set_channel(&mut self, channel: Channel) {
+        self.radio.set_channel(channel);
+    }
+
+    /// See [`Radio::rx_info`]
+    pub fn rx_info(&self) -> Option<RxInfo> {
+        self.radio.rx_info()
+    }
+
+    /// Receives one radio packet and copies its contents into the given `packet` buffer
+    ///
+    /// This methods returns the `Ok` variant if the CRC included the packet was successfully
+    /// validated by the hardware; otherwise it returns the `Err` variant. In either case, `packet`
+    /// will be updated with the received packet's data
+    ///
+    /// Like [`Radio::recv`], it skips frames which the filter turns down, and sends an ACK if
+    /// `set_auto_ack` is on and one is wanted.
+    pub async fn recv(&mut self, packet: &mut Packet) -> Result<u16, u16> {
         loop {
-            match regs.state().read().state() {
-                RadioState::DISABLED => return,
-                RadioState::RX_RU
-                | RadioState::RX_IDLE
-                | RadioState::TX_RU
-                | RadioState::TX_IDLE => {
-                    regs.tasks_disable().write_value(1);
-
-                    self.wait_for_state_a(RadioState::DISABLED);
-                    return;
-                }
-                RadioState::RX_DISABLE | RadioState::TX_DISABLE => {
-                    self.wait_for_state_a(RadioState::DISABLED);
-                    return;
-                }
-                RadioState::RX => {
-                    regs.tasks_ccastop().write_value(1);
-                    regs.tasks_stop().write_value(1);
-                    self.wait_for_state_a(RadioState::RX_IDLE);
-                }
-                RadioState::TX => {
-                    regs.tasks_stop().write_value(1);
-                    self.wait_for_state_a(RadioState::TX_IDLE);
+            let result = self.recv_any(packet).await;
+            if self.radio.filter.accepts(packet, result.is_ok()) {
+                self.radio.record_rx_info(packet);
+                if result.is_ok() {
+                    // An ACK is short enough to send without waiting for the interrupt
+                    self.radio.acknowledge(packet);
                 }
-                _ => (),
+                return result;
             }
         }
     }
 
-    /// Moves the radio to the RXIDLE state
-    fn put_in_rx_mode(&mut self) {
-        let regs = self.regs();
-        let state = self.state();
-
-        let (disable, enable) = match state {
-            State::Disabled => (false, true),
-            State::RxIdle => (false, self.needs_enable),
-            // NOTE to avoid errata 204 (see rev1 v1.4) we do TXIDLE -> DISABLED -> RXIDLE
-            State::TxIdle => (true, true),
-        };
-
-        if disable {
-            regs.tasks_disable().write_value(1);
-            self.wait_for_state_a(RadioState::DISABLED);
+    /// Receives one packet, whatever the filter, without sending an ACK
+    async fn recv_any(&mut self, packet: &mut Packet) -> Result<u16, u16> {
+        // NOTE(unsafe) `recv` stops the transfer when it is dropped, which
+        // happens before our borrow of `packet` ends, even if we are cancelled
+        unsafe {
+            self.radio.start_recv(packet);
         }
+        let recv = Recv::new(&mut self.radio);
+        wait_for(
+            || match recv.is_done() {
+                Ok(crc) => Some(Ok(crc)),
+                Err(nb::Error::Other(crc)) => Some(Err(crc)),
+                Err(nb::Error::WouldBlock) => None,
+            },
+            |r| r.intenset().write(|w| w.set_end(true)),
+        )
+        .await
+    }
 
-        if enable {
-            self.needs_enable = false;
-            regs.tasks_rxen().write_value(1);
-            self.wait_for_state_a(RadioState::RX_IDLE);
+    /// Listens for a packet for no longer than `timeout` and copies its contents into the given
+    /// `packet` buffer
+    ///
+    /// If no packet is received in time then the `Timeout` error is returned. If the CRC is
+    /// incorrect then the `Crc` error is returned; otherwise the `Ok` variant is returned.
+    /// Note that `packet` will contain the packet in any case, even if the CRC check failed.
+    pub async fn recv_timeout(
+        &mut self,
+        packet: &mut Packet,
+        timeout: embassy_time::Duration,
+    ) -> Result<u16, Error> {
+        match embassy_time::with_timeout(timeout, self.recv(packet)).await {
+            Ok(Ok(crc)) => Ok(crc),
+            Ok(Err(crc)) => Err(Error::Crc(crc)),
+            Err(embassy_time::TimeoutError) => Err(Error::Timeout),
         }
     }
 
-    /// Moves the radio to the TXIDLE state
-    fn put_in_tx_mode(&mut self) {
-        let state = self.state();
+    /// Tries to send the given `packet`
+    ///
+    /// This method performs Clear Channel Assessment (CCA) first and sends the `packet` only if the
+    /// channel is observed to be *clear* (no transmission is currently ongoing), otherwise no
+    /// packet is transmitted and the `Err` variant is returned
+    #[allow(clippy::result_unit_err)]
+    pub async fn try_send(&mut self, packet: &mut Packet) -> Result<(), ()> {
+        // NOTE(unsafe) `guard` stops the transfer if we are cancelled, before our borrow of
+        // `packet` ends
+        unsafe { self.radio.driver.start_try_send(packet.buffer.as_ptr()) };
+        let mut guard = StopOnDrop::new(&mut self.radio);
+        let result = wait_for(
+            || guard.radio.driver.poll_send(),
+            |r| {
+                r.intenset().write(|w| {
+                    w.set_phyend(true);
+                    w.set_ccabusy(true);
+                })
+            },
+        )
+        .await;
+        guard.finished = true;
+        result
+    }
 
-        if state != State::TxIdle || self.needs_enable {
-            self.needs_enable = false;
-            self.regs().tasks_rxen().write_value(1);
-            self.wait_for_state_a(RadioState::TX_IDLE);
+    /// Sends a MAC frame, asking for it to be acknowledged
+    ///
+    /// See [`Radio::send_with_ack`].
+    pub async fn send_with_ack(&mut self, packet: &mut Packet) -> Result<bool, Error> {
+        let seq = request_ack(packet)?;
+        let mut reply = Packet::new();
+        for _ in 0..=MAX_FRAME_RETRIES {
+            self.send(packet).await;
+            let wait = embassy_time::Duration::from_micros(ACK_WAIT_US.into());
+            // not `recv_timeout`: an ACK has no address for the filter to
+            // look at, and we mustn't acknowledge other frames meanwhile
+            let ack = embassy_time::with_timeout(wait, async {
+                loop {
+                    if self.recv_any(&mut reply).await.is_ok() {
+                        if let Some(frame_pending) = mac::ack_for(&reply, seq) {
+                            return frame_pending;
+                        }
+                    }
+                    // someone else's frame, or a broken one: keep listening
+                }
+            });
+            if let Ok(frame_pending) = ack.await {
+                return Ok(frame_pending);
+            }
         }
+        Err(Error::NoAck)
     }
 
-    fn wait_for_event(&self, event: Event) {
-        match event {
-            Event::PhyEnd => {
-                while self.regs().events_phyend().read() == 0 {}
-                self.regs().events_phyend().write_value(0);
+    /// Sends the given `packet`, calling `try_send` until the channel is clear
+    ///
+    /// Note that this approach is *not* IEEE spec compliant -- there must be delay between failed
+    /// CCA attempts to be spec compliant
+    pub async fn send(&mut self, packet: &mut Packet) {
+        while self.try_send(packet).await.is_err() {}
+    }
+
+    /// Sends the specified `packet` without first performing CCA
+    ///
+    /// Acknowledgment packets must be sent using this method
+    pub async fn send_no_cca(&mut self, packet: &mut Packet) {
+        // NOTE(unsafe) as in `try_send`
+        unsafe { self.radio.driver.start_send_no_cca(packet.buffer.as_ptr()) };
+        let mut guard = StopOnDrop::new(&mut self.radio);
+        // without CCA, the only way to finish is to send
+        let _ = wait_for(
+            || guard.radio.driver.poll_send(),
+            |r| r.intenset().write(|w| w.set_phyend(true)),
+        )
+        .await;
+        guard.finished = true;
+    }
+}
+
+/// Wait until `check` returns `Some`, enabling the interrupts that might
+/// change its mind (with `enable`) while we sleep
+async fn wait_for<T>(
+    mut check: impl FnMut() -> Option<T>,
+    enable: impl Fn(hal::pac::radio::Radio),
+) -> T {
+    poll_fn(|cx| {
+        WAKER.register(cx.waker());
+        match check() {
+            Some(value) => Poll::Ready(value),
+            None => {
+                // An event which is already set interrupts us straight away,
+                // so nothing is missed between `check` and here
+                enable(hal::pac::RADIO);
+                Poll::Pending
             }
         }
+    })
+    .await
+}
+
+/// Turns off the RADIO interrupts after a transmission, and stops the RADIO
+/// if the transmission was cancelled
+struct StopOnDrop<'a, 'd> {
+    radio: &'a mut Radio<'d>,
+    finished: bool,
+}
+
+impl<'a, 'd> StopOnDrop<'a, 'd> {
+    fn new(radio: &'a mut Radio<'d>) -> Self {
+        Self {
+            radio,
+            finished: false,
+        }
     }
+}
 
-    //use hal::pac::radio::vals::State
-    /// Waits until the radio state matches the given `state`
-    fn wait_for_state_a(&self, state: RadioState) {
-        while self.regs().state().read().state() != state {}
+impl Drop for StopOnDrop<'_, '_> {
+    fn drop(&mut self) {
+        self.radio.regs().intenclr().write(|w| w.0 = 0xffff_ffff);
+        if !self.finished {
+            self.radio.driver.cancel_send();
+        }
     }
 }
 
@@ -774,6 +1068,22 @@ impl Packet {
         self.set_len(len);
     }
 
+    /// Fills the packet payload with a MAC frame
+    pub fn write_frame(&mut self, frame: &mac::Frame) -> Result<(), mac::Error> {
+        let len = frame.len();
+        if len > usize::from(Self::CAPACITY) {
+            return Err(mac::Error::BufferTooSmall);
+        }
+        self.set_len(len as u8);
+        frame.write(self)?;
+        Ok(())
+    }
+
+    /// Parses the packet payload as a MAC frame
+    pub fn frame(&self) -> Result<mac::Frame<'_>, mac::Error> {
+        mac::Frame::parse(self)
+    }
+
     /// Returns the size of this packet's payload
     #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> u8 {
@@ -824,13 +1134,3 @@ impl core::ops::DerefMut for Packet {
         &mut self.buffer[Self::DATA][..len as usize]
     }
 }
-
-/// NOTE must be followed by a volatile write operation
-fn dma_start_fence() {
-    compiler_fence(Ordering::Release);
-}
-
-/// NOTE must be preceded by a volatile read operation
-fn dma_end_fence() {
-    compiler_fence(Ordering::Acquire);
-}
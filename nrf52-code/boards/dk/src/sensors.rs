@@ -0,0 +1,231 @@
+//! # The nRF52840's own sensors
+//!
+//! * [`Temp`] reads the die temperature, which is close enough to the room
+//!   temperature when the chip isn't working hard.
+//! * [`Rng`] reads the hardware random number generator, and works with the
+//!   `rand` crates through `rand_core`.
+//! * [`Vdd`] measures the supply voltage with the SAADC, which tells you how
+//!   flat the battery is when the DK runs off its coin cell.
+//!
+//! ```rust ignore
+//! let mut sensors = board.sensors;
+//! let temperature = sensors.temp.read();
+//! let millivolts = sensors.vdd.millivolts();
+//! let nonce = sensors.rng.next_u32();
+//! ```
+//!
+//! The conversions are in `dk_core::sensor`, where they're tested.
+
+use core::convert::Infallible;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+pub use dk_core::sensor::{battery_percent, DischargeCurve, COIN_CELL};
+use hal::pac::saadc::vals;
+use hal::Peri;
+
+/// The sensors
+pub struct Sensors {
+    /// Temperature
+    pub temp: Temp,
+    /// Random numbers
+    pub rng: Rng,
+    /// Supply voltage
+    pub vdd: Vdd,
+}
+
+impl Sensors {
+    pub(crate) fn new(
+        temp: Peri<'static, hal::peripherals::TEMP>,
+        rng: Peri<'static, hal::peripherals::RNG>,
+        saadc: Peri<'static, hal::peripherals::SAADC>,
+    ) -> Self {
+        Self {
+            temp: Temp { _p: temp },
+            rng: Rng::new(rng),
+            vdd: Vdd::new(saadc),
+        }
+    }
+}
+
+/// The TEMP peripheral
+pub struct Temp {
+    _p: Peri<'static, hal::peripherals::TEMP>,
+}
+
+impl Temp {
+    /// The temperature in hundredths of a degree Celsius
+    ///
+    /// A measurement takes about 36 µs, which this waits for.
+    pub fn read(&mut self) -> i32 {
+        dk_core::sensor::centidegrees(self.read_raw())
+    }
+
+    /// The temperature in quarter degrees Celsius, as the TEMP peripheral
+    /// measures it
+    pub fn read_raw(&mut self) -> i32 {
+        let r = hal::pac::TEMP;
+        r.events_datardy().write_value(0);
+        r.tasks_start().write_value(1);
+        while r.events_datardy().read() == 0 {
+            core::hint::spin_loop();
+        }
+        r.events_datardy().write_value(0);
+        let raw = r.temp().read() as i32;
+        r.tasks_stop().write_value(1);
+        defmt::trace!("TEMP read {} quarter degrees", raw);
+        raw
+    }
+}
+
+/// The RNG peripheral
+///
+/// With bias correction on, each byte takes around 120 µs. That's fine for
+/// seeds, nonces and random back-offs; seed a software generator from it if
+/// you need lots of random numbers.
+pub struct Rng {
+    _p: Peri<'static, hal::peripherals::RNG>,
+}
+
+impl Rng {
+    fn new(p: Peri<'static, hal::peripherals::RNG>) -> Self {
+        // without bias correction there are slightly more of one bit
+        // value than the other
+        hal::pac::RNG.config().write(|w| w.set_dercen(true));
+        Self { _p: p }
+    }
+
+    /// One random byte
+    pub fn next_byte(&mut self) -> u8 {
+        let r = hal::pac::RNG;
+        r.events_valrdy().write_value(0);
+        r.tasks_start().write_value(1);
+        while r.events_valrdy().read() == 0 {
+            core::hint::spin_loop();
+        }
+        let value = r.value().read().value();
+        r.tasks_stop().write_value(1);
+        value
+    }
+
+    /// One random `u32`
+    pub fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0; 4];
+        self.fill(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    /// Fills `buf` with random bytes
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        let r = hal::pac::RNG;
+        r.events_valrdy().write_value(0);
+        r.tasks_start().write_value(1);
+        for byte in buf {
+            while r.events_valrdy().read() == 0 {
+                core::hint::spin_loop();
+            }
+            r.events_valrdy().write_value(0);
+            *byte = r.value().read().value();
+        }
+        r.tasks_stop().write_value(1);
+    }
+}
+
+impl rand_core::TryRng for Rng {
+    type Error = Infallible;
+
+    fn try_next_u32(&mut self) -> Result<u32, Infallible> {
+        Ok(Rng::next_u32(self))
+    }
+
+    fn try_next_u64(&mut self) -> Result<u64, Infallible> {
+        let mut bytes = [0; 8];
+        self.fill(&mut bytes);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn try_fill_bytes(&mut self, dst: &mut [u8]) -> Result<(), Infallible> {
+        self.fill(dst);
+        Ok(())
+    }
+}
+
+impl rand_core::TryCryptoRng for Rng {}
+
+/// The SAADC, measuring the supply voltage
+pub struct Vdd {
+    _p: Peri<'static, hal::peripherals::SAADC>,
+}
+
+impl Vdd {
+    fn new(p: Peri<'static, hal::peripherals::SAADC>) -> Self {
+        let r = hal::pac::SAADC;
+        r.enable().write(|w| w.set_enable(true));
+        r.resolution().write(|w| w.set_val(vals::Val::_12BIT));
+        r.ch(0).config().write(|w| {
+            // 0.6 V reference, so up to 3.6 V
+            w.set_refsel(vals::Refsel::INTERNAL);
+            w.set_gain(vals::Gain::GAIN1_6);
+            w.set_tacq(vals::Tacq::_10US);
+            w.set_mode(vals::ConfigMode::SE);
+        });
+        r.ch(0).pselp().write(|w| w.set_pselp(vals::Psel::VDD));
+
+        // measure the offset error, once
+        r.events_calibratedone().write_value(0);
+        r.tasks_calibrateoffset().write_value(1);
+        while r.events_calibratedone().read() == 0 {
+            core::hint::spin_loop();
+        }
+        r.events_calibratedone().write_value(0);
+
+        Self { _p: p }
+    }
+
+    /// The supply voltage, in millivolts
+    pub fn millivolts(&mut self) -> u16 {
+        let millivolts = dk_core::sensor::vdd_millivolts(self.sample());
+        defmt::trace!("VDD is {} mV", millivolts);
+        millivolts
+    }
+
+    /// How full the battery is, in percent, if it follows `curve`
+    pub fn battery_percent(&mut self, curve: DischargeCurve) -> u8 {
+        battery_percent(self.millivolts(), curve)
+    }
+
+    /// One raw 12-bit sample
+    pub fn sample(&mut self) -> i16 {
+        let r = hal::pac::SAADC;
+        let mut result: i16 = 0;
+        let ptr = core::ptr::addr_of_mut!(result);
+        r.result().ptr().write_value(ptr as u32);
+        r.result().maxcnt().write(|w| w.set_maxcnt(1));
+
+        r.events_started().write_value(0);
+        r.events_end().write_value(0);
+        compiler_fence(Ordering::SeqCst);
+        r.tasks_start().write_value(1);
+        // the result pointer is only latched once it has started
+        while r.events_started().read() == 0 {
+            core::hint::spin_loop();
+        }
+        r.events_started().write_value(0);
+        r.tasks_sample().write_value(1);
+        while r.events_end().read() == 0 {
+            core::hint::spin_loop();
+        }
+        r.events_end().write_value(0);
+        // EasyDMA has written `result` behind the compiler's back
+        compiler_fence(Ordering::SeqCst);
+
+        r.events_stopped().write_value(0);
+        r.tasks_stop().write_value(1);
+        while r.events_stopped().read() == 0 {
+            core::hint::spin_loop();
+        }
+        r.events_stopped().write_value(0);
+
+        // SAFETY: EasyDMA is done with it
+        unsafe { core::ptr::read_volatile(ptr) }
+    }
+}
@@ -0,0 +1,297 @@
+//! # Several timers on one TIMER
+//!
+//! [`Timer`](crate::Timer) has one deadline, and `recv_timeout` uses it up.
+//! [`TimerService`] leaves the TIMER counting microseconds instead, and hands
+//! out up to `N` [`VirtualTimer`]s, each with its own deadline, and any
+//! number of [`Stopwatch`]es. A `VirtualTimer` is a [`Countdown`], so you can
+//! time out a receive with one while another blinks an LED:
+//!
+//! ```rust ignore
+//! let timers = dk::timer::TimerService::<2>::new(board.timer);
+//! let mut blink = timers.timer().unwrap();
+//! let mut timeout = timers.timer().unwrap();
+//! blink.start_periodic(Duration::from_millis(250));
+//! loop {
+//!     if blink.reset_if_finished() {
+//!         led.toggle();
+//!     }
+//!     match radio.recv_timeout(&mut packet, &mut timeout, 1_000) { /* .. */ }
+//! }
+//! ```
+//!
+//! Nothing interrupts you when a deadline passes, so poll your timers at
+//! least every 71 minutes, or the 32-bit TIMER wraps around unnoticed. In
+//! async code, [`VirtualTimer::wait`] and the `embedded-hal-async`
+//! [`DelayNs`](embedded_hal_async::delay::DelayNs) sleep on `embassy-time` and
+//! do that polling for you.
+
+use core::cell::RefCell;
+
+use dk_core::timer::{TimerId, Timers, WrappingCounter};
+use embassy_time::Duration;
+use embedded_hal::delay::DelayNs;
+
+use crate::{Countdown, Timer};
+
+struct Inner<const N: usize> {
+    timer: hal::timer::Timer<'static>,
+    counter: WrappingCounter,
+    timers: Timers<N>,
+}
+
+impl<const N: usize> Inner<N> {
+    /// Microseconds since the service started
+    fn now(&mut self) -> u64 {
+        // capture the count into CC[1], as the count itself can't be read
+        let low = self.timer.cc(1).capture();
+        self.counter.extend(low)
+    }
+}
+
+/// The longest we sleep without looking at the TIMER, well inside the 71
+/// minutes it takes to wrap around
+const MAX_SLEEP_US: u64 = 60 * 60 * 1_000_000;
+
+/// A moment on a [`TimerService`]'s clock, in microseconds since the service
+/// started
+///
+/// This is not an [`embassy_time::Instant`], which counts from boot, in RTC ticks;
+/// the two can't be compared. Subtract one `TimerInstant` from another to get
+/// a [`Duration`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, defmt::Format)]
+pub struct TimerInstant {
+    micros: u64,
+}
+
+impl TimerInstant {
+    const fn from_micros(micros: u64) -> Self {
+        Self { micros }
+    }
+
+    /// Microseconds since the service started
+    pub const fn as_micros(self) -> u64 {
+        self.micros
+    }
+
+    /// The time from `earlier` to this moment, or zero if `earlier` is later
+    pub fn saturating_duration_since(self, earlier: TimerInstant) -> Duration {
+        Duration::from_micros(self.micros.saturating_sub(earlier.micros))
+    }
+}
+
+impl core::ops::Sub for TimerInstant {
+    type Output = Duration;
+
+    fn sub(self, earlier: TimerInstant) -> Duration {
+        Duration::from_micros(self.micros - earlier.micros)
+    }
+}
+
+impl core::ops::Add<Duration> for TimerInstant {
+    type Output = TimerInstant;
+
+    fn add(self, duration: Duration) -> TimerInstant {
+        TimerInstant::from_micros(self.micros + duration.as_micros())
+    }
+}
+
+/// Up to `N` virtual timers, on one hardware TIMER
+pub struct TimerService<const N: usize> {
+    inner: RefCell<Inner<N>>,
+}
+
+impl<const N: usize> TimerService<N> {
+    /// Takes over `timer`, which counts from now on
+    pub fn new(timer: Timer) -> Self {
+        let timer = timer.0;
+        timer.stop();
+        timer.clear();
+        // count freely, rather than stopping at the `Timer::start` deadline
+        timer.cc(0).unshort_compare_clear();
+        timer.cc(0).unshort_compare_stop();
+        timer.start();
+        Self {
+            inner: RefCell::new(Inner {
+                timer,
+                counter: WrappingCounter::new(),
+                timers: Timers::new(),
+            }),
+        }
+    }
+
+    /// The time on the service's clock
+    pub fn now(&self) -> TimerInstant {
+        TimerInstant::from_micros(self.inner.borrow_mut().now())
+    }
+
+    /// A new, stopped timer, or `None` if all `N` are in use
+    pub fn timer(&self) -> Option<VirtualTimer<'_, N>> {
+        let id = self.inner.borrow_mut().timers.alloc()?;
+        Some(VirtualTimer { service: self, id })
+    }
+
+    /// A stopwatch, started now
+    pub fn stopwatch(&self) -> Stopwatch<'_, N> {
+        Stopwatch {
+            service: self,
+            started: self.now(),
+        }
+    }
+
+    /// The time on the service's clock, `microseconds` from now
+    fn micros_from_now(&self, microseconds: u64) -> u64 {
+        self.inner.borrow_mut().now() + microseconds
+    }
+
+    /// Sleeps on `embassy-time` until the service's clock reaches `deadline`
+    async fn sleep_until(&self, deadline: u64) {
+        loop {
+            let now = self.inner.borrow_mut().now();
+            if now >= deadline {
+                return;
+            }
+            embassy_time::Timer::after_micros((deadline - now).min(MAX_SLEEP_US)).await;
+        }
+    }
+}
+
+/// One of a [`TimerService`]'s timers
+///
+/// Dropping it gives it back to the service. Using it as a [`DelayNs`],
+/// blocking or async, waits on the service's clock and leaves the timer's own
+/// deadline alone, so a periodic timer keeps its period.
+pub struct VirtualTimer<'a, const N: usize> {
+    service: &'a TimerService<N>,
+    id: TimerId,
+}
+
+impl<const N: usize> VirtualTimer<'_, N> {
+    /// Fire once, `after` from now
+    pub fn start_after(&mut self, after: Duration) {
+        self.start_us(after.as_micros());
+    }
+
+    // `Duration` counts in RTC ticks, which are coarser than the TIMER's microseconds
+    fn start_us(&mut self, microseconds: u64) {
+        let mut inner = self.service.inner.borrow_mut();
+        let now = inner.now();
+        inner.timers.start(self.id, now, microseconds);
+    }
+
+    /// Fire every `period`, starting `period` from now
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `period` is zero
+    pub fn start_periodic(&mut self, period: Duration) {
+        let mut inner = self.service.inner.borrow_mut();
+        let now = inner.now();
+        inner
+            .timers
+            .start_periodic(self.id, now, period.as_micros());
+    }
+
+    /// Stop the timer, so it doesn't fire
+    pub fn stop(&mut self) {
+        self.service.inner.borrow_mut().timers.stop(self.id);
+    }
+
+    /// Whether the timer is going to fire
+    pub fn is_running(&self) -> bool {
+        self.service.inner.borrow().timers.is_running(self.id)
+    }
+
+    /// When the timer is going to fire next, if it's running
+    pub fn deadline(&self) -> Option<TimerInstant> {
+        let mut inner = self.service.inner.borrow_mut();
+        let now = inner.now();
+        let remaining = inner.timers.remaining(self.id, now)?;
+        Some(TimerInstant::from_micros(now + remaining))
+    }
+
+    /// Waits until the timer fires, sleeping on `embassy-time` in between
+    ///
+    /// Like [`reset_if_finished`](Self::reset_if_finished), this stops a
+    /// one-shot timer and moves a periodic one on to its next deadline. If the
+    /// timer isn't running, this never returns.
+    pub async fn wait(&mut self) {
+        while !VirtualTimer::reset_if_finished(self) {
+            let remaining = {
+                let mut inner = self.service.inner.borrow_mut();
+                let now = inner.now();
+                inner.timers.remaining(self.id, now)
+            };
+            match remaining {
+                Some(microseconds) => {
+                    embassy_time::Timer::after_micros(microseconds.min(MAX_SLEEP_US)).await
+                }
+                None => core::future::pending().await,
+            }
+        }
+    }
+
+    /// If the timer has fired, returns true: a one-shot timer then stops, and
+    /// a periodic one carries on to its next deadline
+    ///
+    /// Returns false if the timer hasn't fired yet, or isn't running.
+    pub fn reset_if_finished(&mut self) -> bool {
+        let mut inner = self.service.inner.borrow_mut();
+        let now = inner.now();
+        inner.timers.poll(self.id, now)
+    }
+}
+
+impl<const N: usize> Drop for VirtualTimer<'_, N> {
+    fn drop(&mut self) {
+        self.service.inner.borrow_mut().timers.free(self.id);
+    }
+}
+
+impl<const N: usize> Countdown for VirtualTimer<'_, N> {
+    fn start(&mut self, microseconds: u32) {
+        self.start_us(u64::from(microseconds));
+    }
+
+    fn reset_if_finished(&mut self) -> bool {
+        VirtualTimer::reset_if_finished(self)
+    }
+}
+
+impl<const N: usize> DelayNs for VirtualTimer<'_, N> {
+    fn delay_ns(&mut self, ns: u32) {
+        // to the next whole microsecond
+        let deadline = self.service.micros_from_now(u64::from(ns.div_ceil(1_000)));
+        while self.service.inner.borrow_mut().now() < deadline {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+impl<const N: usize> embedded_hal_async::delay::DelayNs for VirtualTimer<'_, N> {
+    async fn delay_ns(&mut self, ns: u32) {
+        // to the next whole microsecond
+        let deadline = self.service.micros_from_now(u64::from(ns.div_ceil(1_000)));
+        self.service.sleep_until(deadline).await;
+    }
+}
+
+/// Measures how long things take, on a [`TimerService`]
+pub struct Stopwatch<'a, const N: usize> {
+    service: &'a TimerService<N>,
+    started: TimerInstant,
+}
+
+impl<const N: usize> Stopwatch<'_, N> {
+    /// The time since the stopwatch started
+    pub fn elapsed(&self) -> Duration {
+        self.service.now() - self.started
+    }
+
+    /// Starts again from zero, returning the time up to now
+    pub fn restart(&mut self) -> Duration {
+        let now = self.service.now();
+        let lap = now - self.started;
+        self.started = now;
+        lap
+    }
+}
@@ -0,0 +1,927 @@
+//! The USB HID report protocol spoken by `dongle-fw`
+//!
+//! The host sends 64 byte OUT reports, and `dongle-fw` answers every valid
+//! request with a 64 byte IN report.
+//!
+//! OUT report (host to Dongle):
+//!
+//! | Byte  | Meaning                                  |
+//! |-------|------------------------------------------|
+//! | 0     | [`MAGIC`]                                |
+//! | 1     | [`PROTOCOL_VERSION`]                     |
+//! | 2     | [`Command`]                              |
+//! | 3     | Sequence number, chosen by the host      |
+//! | 4     | Payload length                           |
+//! | 5..   | Payload                                  |
+//!
+//! IN report (Dongle to host):
+//!
+//! | Byte  | Meaning                                  |
+//! |-------|------------------------------------------|
+//! | 0     | [`MAGIC`]                                |
+//! | 1     | [`PROTOCOL_VERSION`]                     |
+//! | 2     | [`Command`] being answered               |
+//! | 3     | Sequence number of the request           |
+//! | 4     | [`Status`]                               |
+//! | 5     | Payload length                           |
+//! | 6..   | Payload                                  |
+//!
+//! All multi-byte values are little-endian.
+//!
+//! Older versions of `cargo xtask change-channel` sent a report whose first
+//! byte was the new channel number. [`MAGIC`] is not a valid channel, so such
+//! reports are still understood and decoded as [`OutReport::LegacyChangeChannel`].
+
+/// Size of both the IN and the OUT report, in bytes
+pub const REPORT_LEN: usize = 64;
+
+/// The first byte of every report in this protocol
+pub const MAGIC: u8 = 0xFD;
+
+/// The version of the protocol described by this module
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// The valid IEEE 802.15.4 channels
+pub const CHANNELS: core::ops::RangeInclusive<u8> = 11..=26;
+
+const REQUEST_HEADER_LEN: usize = 5;
+const RESPONSE_HEADER_LEN: usize = 6;
+
+/// Maximum payload carried by an OUT report
+pub const MAX_REQUEST_PAYLOAD: usize = REPORT_LEN - REQUEST_HEADER_LEN;
+
+/// Maximum payload carried by an IN report
+pub const MAX_RESPONSE_PAYLOAD: usize = REPORT_LEN - RESPONSE_HEADER_LEN;
+
+/// The commands the host can send
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Command {
+    /// Ask for an [`Info`] reply
+    GetInfo = 0x01,
+    /// Change the radio channel; the payload is the channel number
+    SetChannel = 0x02,
+    /// Change the application mode; the payload is a [`Mode`]
+    SetMode = 0x03,
+    /// Ask for a [`Stats`] reply
+    GetStats = 0x04,
+    /// Ask for a [`Version`] reply
+    GetVersion = 0x05,
+    /// Ask for a [`BuildInfo`] reply
+    GetBuildInfo = 0x06,
+    /// Change how much is logged over USB serial; the payload is a [`Verbosity`]
+    SetVerbosity = 0x07,
+    /// Configure [`Mode::Traffic`]; the payload is a [`TrafficConfig`]
+    SetTraffic = 0x08,
+}
+
+impl TryFrom<u8> for Command {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(Command::GetInfo),
+            0x02 => Ok(Command::SetChannel),
+            0x03 => Ok(Command::SetMode),
+            0x04 => Ok(Command::GetStats),
+            0x05 => Ok(Command::GetVersion),
+            0x06 => Ok(Command::GetBuildInfo),
+            0x07 => Ok(Command::SetVerbosity),
+            0x08 => Ok(Command::SetTraffic),
+            other => Err(other),
+        }
+    }
+}
+
+/// The outcome of a request, as reported in the IN report
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Status {
+    /// The request was carried out
+    Ok = 0x00,
+    /// The command byte was not recognised
+    UnknownCommand = 0x01,
+    /// The payload was missing, too long, or out of range
+    InvalidArgument = 0x02,
+    /// The report used a protocol version we do not speak
+    UnsupportedVersion = 0x03,
+    /// The firmware was too busy to handle the request
+    Busy = 0x04,
+}
+
+impl TryFrom<u8> for Status {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(Status::Ok),
+            0x01 => Ok(Status::UnknownCommand),
+            0x02 => Ok(Status::InvalidArgument),
+            0x03 => Ok(Status::UnsupportedVersion),
+            0x04 => Ok(Status::Busy),
+            other => Err(other),
+        }
+    }
+}
+
+/// The application modes of `dongle-fw`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Mode {
+    /// Every packet received is reversed and sent back
+    Loopback = 0x00,
+    /// The radio puzzle game
+    Puzzle = 0x01,
+    /// Frames are relayed between USB serial and the radio, see [`crate::relay`]
+    Relay = 0x02,
+    /// A stream of frames is transmitted, see [`TrafficConfig`]
+    Traffic = 0x03,
+}
+
+impl TryFrom<u8> for Mode {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(Mode::Loopback),
+            0x01 => Ok(Mode::Puzzle),
+            0x02 => Ok(Mode::Relay),
+            0x03 => Ok(Mode::Traffic),
+            other => Err(other),
+        }
+    }
+}
+
+/// How much `dongle-fw` logs over USB serial
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Verbosity {
+    /// Only errors, and answers to things the user asked for
+    Quiet = 0x00,
+    /// A line for every packet handled (the default)
+    Normal = 0x01,
+    /// Also the contents of every packet received
+    Verbose = 0x02,
+}
+
+impl TryFrom<u8> for Verbosity {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(Verbosity::Quiet),
+            0x01 => Ok(Verbosity::Normal),
+            0x02 => Ok(Verbosity::Verbose),
+            other => Err(other),
+        }
+    }
+}
+
+/// A request from the host
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Request {
+    /// See [`Command::GetInfo`]
+    GetInfo,
+    /// See [`Command::SetChannel`]
+    SetChannel(u8),
+    /// See [`Command::SetMode`]
+    SetMode(Mode),
+    /// See [`Command::GetStats`]
+    GetStats,
+    /// See [`Command::GetVersion`]
+    GetVersion,
+    /// See [`Command::GetBuildInfo`]
+    GetBuildInfo,
+    /// See [`Command::SetVerbosity`]
+    SetVerbosity(Verbosity),
+    /// See [`Command::SetTraffic`]
+    SetTraffic(TrafficConfig),
+}
+
+impl Request {
+    /// Which command byte this request is sent with
+    pub fn command(&self) -> Command {
+        match self {
+            Request::GetInfo => Command::GetInfo,
+            Request::SetChannel(_) => Command::SetChannel,
+            Request::SetMode(_) => Command::SetMode,
+            Request::GetStats => Command::GetStats,
+            Request::GetVersion => Command::GetVersion,
+            Request::GetBuildInfo => Command::GetBuildInfo,
+            Request::SetVerbosity(_) => Command::SetVerbosity,
+            Request::SetTraffic(_) => Command::SetTraffic,
+        }
+    }
+
+    /// Encode this request as an OUT report
+    pub fn to_report(&self, seq: u8) -> [u8; REPORT_LEN] {
+        let mut report = [0u8; REPORT_LEN];
+        report[0] = MAGIC;
+        report[1] = PROTOCOL_VERSION;
+        report[2] = self.command() as u8;
+        report[3] = seq;
+        let payload_len = match self {
+            Request::GetInfo | Request::GetStats | Request::GetVersion | Request::GetBuildInfo => 0,
+            Request::SetChannel(channel) => {
+                report[REQUEST_HEADER_LEN] = *channel;
+                1
+            }
+            Request::SetMode(mode) => {
+                report[REQUEST_HEADER_LEN] = *mode as u8;
+                1
+            }
+            Request::SetVerbosity(verbosity) => {
+                report[REQUEST_HEADER_LEN] = *verbosity as u8;
+                1
+            }
+            Request::SetTraffic(config) => {
+                report[REQUEST_HEADER_LEN..][..TrafficConfig::LEN]
+                    .copy_from_slice(&config.to_bytes());
+                TrafficConfig::LEN as u8
+            }
+        };
+        report[4] = payload_len;
+        report
+    }
+}
+
+/// A decoded OUT report
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OutReport {
+    /// A report from a host tool which predates this protocol
+    LegacyChangeChannel(u8),
+    /// A request which should be answered with a [`Response`]
+    Request {
+        /// Sequence number, to be copied into the [`Response`]
+        seq: u8,
+        /// What the host asked for
+        request: Request,
+    },
+}
+
+/// The ways decoding a report can fail
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The report was empty
+    Empty,
+    /// The report was shorter than its header says
+    Truncated,
+    /// The report does not start with [`MAGIC`]
+    BadMagic,
+    /// An IN report carried a status byte we don't know
+    UnknownStatus(u8),
+    /// A well-formed request we could not act on
+    ///
+    /// The host should be told about these; see [`Error::response`].
+    Rejected {
+        /// Command byte, as sent
+        command: u8,
+        /// Sequence number, as sent
+        seq: u8,
+        /// Why it was rejected
+        status: Status,
+    },
+}
+
+impl Error {
+    /// The reply the host should get for this error, if any
+    pub fn response(&self) -> Option<Response> {
+        match *self {
+            Error::Rejected {
+                command,
+                seq,
+                status,
+            } => Some(Response::new(command, seq, status)),
+            _ => None,
+        }
+    }
+}
+
+impl OutReport {
+    /// Decode an OUT report
+    ///
+    /// Linux hands us just the bytes the host wrote, whilst Windows always
+    /// gives us the full report padded with zeroes.
+    pub fn parse(data: &[u8]) -> Result<OutReport, Error> {
+        let Some(&first) = data.first() else {
+            return Err(Error::Empty);
+        };
+        if first != MAGIC {
+            if CHANNELS.contains(&first) {
+                return Ok(OutReport::LegacyChangeChannel(first));
+            }
+            return Err(Error::BadMagic);
+        }
+        if data.len() < REQUEST_HEADER_LEN {
+            return Err(Error::Truncated);
+        }
+        let version = data[1];
+        let command = data[2];
+        let seq = data[3];
+        let payload_len = usize::from(data[4]);
+        let reject = |status| Error::Rejected {
+            command,
+            seq,
+            status,
+        };
+        if version != PROTOCOL_VERSION {
+            return Err(reject(Status::UnsupportedVersion));
+        }
+        if payload_len > MAX_REQUEST_PAYLOAD {
+            return Err(reject(Status::InvalidArgument));
+        }
+        let Some(payload) = data.get(REQUEST_HEADER_LEN..REQUEST_HEADER_LEN + payload_len) else {
+            return Err(Error::Truncated);
+        };
+        let command = Command::try_from(command).map_err(|_| reject(Status::UnknownCommand))?;
+        let request = match (command, payload) {
+            (Command::GetInfo, []) => Request::GetInfo,
+            (Command::GetStats, []) => Request::GetStats,
+            (Command::GetVersion, []) => Request::GetVersion,
+            (Command::GetBuildInfo, []) => Request::GetBuildInfo,
+            (Command::SetChannel, [channel]) if CHANNELS.contains(channel) => {
+                Request::SetChannel(*channel)
+            }
+            (Command::SetMode, [mode]) => Request::SetMode(
+                Mode::try_from(*mode).map_err(|_| reject(Status::InvalidArgument))?,
+            ),
+            (Command::SetVerbosity, [verbosity]) => Request::SetVerbosity(
+                Verbosity::try_from(*verbosity).map_err(|_| reject(Status::InvalidArgument))?,
+            ),
+            (Command::SetTraffic, config) if config.len() == TrafficConfig::LEN => {
+                Request::SetTraffic(
+                    TrafficConfig::from_bytes(config).ok_or(reject(Status::InvalidArgument))?,
+                )
+            }
+            _ => return Err(reject(Status::InvalidArgument)),
+        };
+        Ok(OutReport::Request { seq, request })
+    }
+}
+
+/// A reply from the Dongle
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Response {
+    /// The command byte of the request being answered
+    pub command: u8,
+    /// The sequence number of the request being answered
+    pub seq: u8,
+    /// Whether the request was carried out
+    pub status: Status,
+    payload: [u8; MAX_RESPONSE_PAYLOAD],
+    payload_len: u8,
+}
+
+impl Response {
+    /// Create a response with an empty payload
+    pub fn new(command: u8, seq: u8, status: Status) -> Response {
+        Response {
+            command,
+            seq,
+            status,
+            payload: [0u8; MAX_RESPONSE_PAYLOAD],
+            payload_len: 0,
+        }
+    }
+
+    /// Create a successful response carrying the given payload
+    ///
+    /// # Panics
+    ///
+    /// Panics if `payload` is longer than [`MAX_RESPONSE_PAYLOAD`]
+    pub fn ok(command: Command, seq: u8, payload: &[u8]) -> Response {
+        let mut response = Response::new(command as u8, seq, Status::Ok);
+        response.payload[..payload.len()].copy_from_slice(payload);
+        response.payload_len = payload.len() as u8;
+        response
+    }
+
+    /// The payload of this response
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..usize::from(self.payload_len)]
+    }
+
+    /// Encode this response as an IN report
+    pub fn to_report(&self) -> [u8; REPORT_LEN] {
+        let mut report = [0u8; REPORT_LEN];
+        report[0] = MAGIC;
+        report[1] = PROTOCOL_VERSION;
+        report[2] = self.command;
+        report[3] = self.seq;
+        report[4] = self.status as u8;
+        report[5] = self.payload_len;
+        report[RESPONSE_HEADER_LEN..][..self.payload().len()].copy_from_slice(self.payload());
+        report
+    }
+
+    /// Decode an IN report
+    pub fn parse(data: &[u8]) -> Result<Response, Error> {
+        if data.is_empty() {
+            return Err(Error::Empty);
+        }
+        if data[0] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        if data.len() < RESPONSE_HEADER_LEN {
+            return Err(Error::Truncated);
+        }
+        let command = data[2];
+        let seq = data[3];
+        if data[1] != PROTOCOL_VERSION {
+            return Err(Error::Rejected {
+                command,
+                seq,
+                status: Status::UnsupportedVersion,
+            });
+        }
+        let status = Status::try_from(data[4]).map_err(Error::UnknownStatus)?;
+        let payload_len = usize::from(data[5]);
+        if payload_len > MAX_RESPONSE_PAYLOAD {
+            return Err(Error::Truncated);
+        }
+        let Some(payload) = data.get(RESPONSE_HEADER_LEN..RESPONSE_HEADER_LEN + payload_len) else {
+            return Err(Error::Truncated);
+        };
+        let mut response = Response::new(command, seq, status);
+        response.payload[..payload_len].copy_from_slice(payload);
+        response.payload_len = payload_len as u8;
+        Ok(response)
+    }
+}
+
+/// Payload of a successful [`Command::GetInfo`] reply
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Info {
+    /// The 64-bit unique device identifier, from the FICR
+    pub device_id: u64,
+    /// The radio channel we are listening on
+    pub channel: u8,
+    /// The current application mode
+    pub mode: Mode,
+}
+
+impl Info {
+    /// Size of the encoded payload, in bytes
+    pub const LEN: usize = 10;
+
+    /// Encode for use as a [`Response`] payload
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0u8; Self::LEN];
+        bytes[0..8].copy_from_slice(&self.device_id.to_le_bytes());
+        bytes[8] = self.channel;
+        bytes[9] = self.mode as u8;
+        bytes
+    }
+
+    /// Decode from a [`Response`] payload
+    pub fn from_bytes(bytes: &[u8]) -> Option<Info> {
+        let bytes: &[u8; Self::LEN] = bytes.get(..Self::LEN)?.try_into().ok()?;
+        Some(Info {
+            device_id: u64::from_le_bytes(bytes[0..8].try_into().ok()?),
+            channel: bytes[8],
+            mode: Mode::try_from(bytes[9]).ok()?,
+        })
+    }
+}
+
+/// Payload of a successful [`Command::GetStats`] reply
+///
+/// Fields have been added over time. Firmware which predates a field sends a
+/// shorter payload, and the missing fields decode as zero.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Stats {
+    /// How many packets have been received OK
+    pub rx_count: u32,
+    /// How many packets have been received with errors
+    pub err_count: u32,
+    /// How many bytes of USB serial log output were dropped
+    pub log_bytes_dropped: u32,
+    /// How many lines of USB serial log output were cut short
+    pub log_lines_truncated: u32,
+    /// How many frames [`Mode::Traffic`] has sent
+    pub traffic_sent: u32,
+    /// How many frames [`Mode::Traffic`] didn't send, because the channel was busy
+    pub traffic_cca_busy: u32,
+    /// Average time taken to answer a puzzle request, in microseconds
+    pub latency_avg_us: u32,
+    /// Longest time taken to answer a puzzle request, in microseconds
+    pub latency_max_us: u32,
+}
+
+impl Stats {
+    /// Size of the encoded payload, in bytes
+    pub const LEN: usize = 32;
+
+    /// Size of the shortest payload we accept
+    const MIN_LEN: usize = 8;
+
+    /// Encode for use as a [`Response`] payload
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0u8; Self::LEN];
+        bytes[0..4].copy_from_slice(&self.rx_count.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.err_count.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.log_bytes_dropped.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.log_lines_truncated.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.traffic_sent.to_le_bytes());
+        bytes[20..24].copy_from_slice(&self.traffic_cca_busy.to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.latency_avg_us.to_le_bytes());
+        bytes[28..32].copy_from_slice(&self.latency_max_us.to_le_bytes());
+        bytes
+    }
+
+    /// Decode from a [`Response`] payload
+    pub fn from_bytes(bytes: &[u8]) -> Option<Stats> {
+        if bytes.len() < Self::MIN_LEN {
+            return None;
+        }
+        let mut padded = [0u8; Self::LEN];
+        let len = bytes.len().min(Self::LEN);
+        padded[..len].copy_from_slice(&bytes[..len]);
+        Some(Stats {
+            rx_count: u32::from_le_bytes(padded[0..4].try_into().ok()?),
+            err_count: u32::from_le_bytes(padded[4..8].try_into().ok()?),
+            log_bytes_dropped: u32::from_le_bytes(padded[8..12].try_into().ok()?),
+            log_lines_truncated: u32::from_le_bytes(padded[12..16].try_into().ok()?),
+            traffic_sent: u32::from_le_bytes(padded[16..20].try_into().ok()?),
+            traffic_cca_busy: u32::from_le_bytes(padded[20..24].try_into().ok()?),
+            latency_avg_us: u32::from_le_bytes(padded[24..28].try_into().ok()?),
+            latency_max_us: u32::from_le_bytes(padded[28..32].try_into().ok()?),
+        })
+    }
+}
+
+/// Payload of a [`Command::SetTraffic`] request
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TrafficConfig {
+    /// Length of each frame, not counting the CRC (1 to 125)
+    pub len: u8,
+    /// Time between frames, in milliseconds
+    pub interval_ms: u16,
+    /// Fill frames with random bytes, rather than a counter
+    pub random_payload: bool,
+    /// Do a Clear Channel Assessment before each frame, and skip it if the
+    /// channel is busy
+    pub cca: bool,
+}
+
+impl TrafficConfig {
+    /// Size of the encoded payload, in bytes
+    pub const LEN: usize = 4;
+
+    /// The frame lengths we can send
+    pub const FRAME_LENS: core::ops::RangeInclusive<u8> = 1..=125;
+
+    const FLAG_RANDOM: u8 = 1 << 0;
+    const FLAG_CCA: u8 = 1 << 1;
+
+    /// Encode for use as a [`Request`] payload
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let interval = self.interval_ms.to_le_bytes();
+        let mut flags = 0;
+        if self.random_payload {
+            flags |= Self::FLAG_RANDOM;
+        }
+        if self.cca {
+            flags |= Self::FLAG_CCA;
+        }
+        [self.len, interval[0], interval[1], flags]
+    }
+
+    /// Decode from a [`Request`] payload
+    pub fn from_bytes(bytes: &[u8]) -> Option<TrafficConfig> {
+        let bytes = bytes.get(..Self::LEN)?;
+        if !Self::FRAME_LENS.contains(&bytes[0]) {
+            return None;
+        }
+        Some(TrafficConfig {
+            len: bytes[0],
+            interval_ms: u16::from_le_bytes([bytes[1], bytes[2]]),
+            random_payload: bytes[3] & Self::FLAG_RANDOM != 0,
+            cca: bytes[3] & Self::FLAG_CCA != 0,
+        })
+    }
+}
+
+impl Default for TrafficConfig {
+    fn default() -> Self {
+        TrafficConfig {
+            len: 16,
+            interval_ms: 10,
+            random_payload: true,
+            cca: true,
+        }
+    }
+}
+
+/// Payload of a successful [`Command::GetVersion`] reply
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Version {
+    /// The highest protocol version the firmware speaks
+    pub protocol: u8,
+    /// The `major.minor.patch` version of the firmware
+    pub firmware: [u8; 3],
+}
+
+impl Version {
+    /// Size of the encoded payload, in bytes
+    pub const LEN: usize = 4;
+
+    /// Encode for use as a [`Response`] payload
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        [
+            self.protocol,
+            self.firmware[0],
+            self.firmware[1],
+            self.firmware[2],
+        ]
+    }
+
+    /// Decode from a [`Response`] payload
+    pub fn from_bytes(bytes: &[u8]) -> Option<Version> {
+        let bytes = bytes.get(..Self::LEN)?;
+        Some(Version {
+            protocol: bytes[0],
+            firmware: [bytes[1], bytes[2], bytes[3]],
+        })
+    }
+}
+
+/// Payload of a successful [`Command::GetBuildInfo`] reply
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BuildInfo {
+    /// Abbreviated git commit hash, in ASCII, padded with zero bytes
+    pub git_rev: [u8; 12],
+    /// When the firmware was built, in seconds since the Unix epoch
+    pub timestamp: u64,
+    /// A combination of the `FLAG_*` constants
+    pub flags: u8,
+    /// 32-bit FNV-1a hash of the hidden message
+    pub secret_hash: u32,
+}
+
+impl BuildInfo {
+    /// Size of the encoded payload, in bytes
+    pub const LEN: usize = 25;
+
+    /// Built with the `dk` feature, to run on a Development Kit
+    pub const FLAG_DK: u8 = 1 << 0;
+
+    /// Built from a git checkout with uncommitted changes
+    pub const FLAG_DIRTY: u8 = 1 << 1;
+
+    /// The git revision, as a string
+    pub fn git_rev(&self) -> &str {
+        let len = self
+            .git_rev
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(self.git_rev.len());
+        core::str::from_utf8(&self.git_rev[..len]).unwrap_or("?")
+    }
+
+    /// Encode for use as a [`Response`] payload
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0u8; Self::LEN];
+        bytes[0..12].copy_from_slice(&self.git_rev);
+        bytes[12..20].copy_from_slice(&self.timestamp.to_le_bytes());
+        bytes[20] = self.flags;
+        bytes[21..25].copy_from_slice(&self.secret_hash.to_le_bytes());
+        bytes
+    }
+
+    /// Decode from a [`Response`] payload
+    pub fn from_bytes(bytes: &[u8]) -> Option<BuildInfo> {
+        let bytes = bytes.get(..Self::LEN)?;
+        Some(BuildInfo {
+            git_rev: bytes[0..12].try_into().ok()?,
+            timestamp: u64::from_le_bytes(bytes[12..20].try_into().ok()?),
+            flags: bytes[20],
+            secret_hash: u32::from_le_bytes(bytes[21..25].try_into().ok()?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_reports() {
+        // Linux sends 1 byte
+        assert_eq!(
+            OutReport::parse(&[20]),
+            Ok(OutReport::LegacyChangeChannel(20))
+        );
+
+        // Windows sends 64 (with 63 zero bytes)
+        let mut report = [0u8; REPORT_LEN];
+        report[0] = 11;
+        assert_eq!(
+            OutReport::parse(&report),
+            Ok(OutReport::LegacyChangeChannel(11))
+        );
+
+        // not a channel
+        assert_eq!(OutReport::parse(&[27]), Err(Error::BadMagic));
+        assert_eq!(OutReport::parse(&[]), Err(Error::Empty));
+    }
+
+    #[test]
+    fn request_round_trip() {
+        for request in [
+            Request::GetInfo,
+            Request::SetChannel(26),
+            Request::SetMode(Mode::Puzzle),
+            Request::GetStats,
+            Request::GetVersion,
+            Request::GetBuildInfo,
+            Request::SetVerbosity(Verbosity::Quiet),
+            Request::SetTraffic(TrafficConfig {
+                len: 125,
+                interval_ms: 1000,
+                random_payload: false,
+                cca: true,
+            }),
+        ] {
+            let report = request.to_report(42);
+            assert_eq!(
+                OutReport::parse(&report),
+                Ok(OutReport::Request { seq: 42, request })
+            );
+            // the host may also send a short report
+            let short = &report[..REQUEST_HEADER_LEN + usize::from(report[4])];
+            assert_eq!(
+                OutReport::parse(short),
+                Ok(OutReport::Request { seq: 42, request })
+            );
+        }
+    }
+
+    #[test]
+    fn rejected_requests() {
+        // bad channel
+        let mut report = Request::SetChannel(11).to_report(7);
+        report[REQUEST_HEADER_LEN] = 27;
+        let error = OutReport::parse(&report).unwrap_err();
+        assert_eq!(
+            error,
+            Error::Rejected {
+                command: Command::SetChannel as u8,
+                seq: 7,
+                status: Status::InvalidArgument
+            }
+        );
+        let response = error.response().unwrap();
+        assert_eq!(response.seq, 7);
+        assert_eq!(response.status, Status::InvalidArgument);
+
+        // bad mode
+        let mut report = Request::SetMode(Mode::Loopback).to_report(1);
+        report[REQUEST_HEADER_LEN] = 0xAA;
+        assert!(matches!(
+            OutReport::parse(&report),
+            Err(Error::Rejected {
+                status: Status::InvalidArgument,
+                ..
+            })
+        ));
+
+        // frame too long to send
+        let mut report = Request::SetTraffic(TrafficConfig::default()).to_report(1);
+        report[REQUEST_HEADER_LEN] = 126;
+        assert!(matches!(
+            OutReport::parse(&report),
+            Err(Error::Rejected {
+                status: Status::InvalidArgument,
+                ..
+            })
+        ));
+
+        // bad verbosity
+        let mut report = Request::SetVerbosity(Verbosity::Normal).to_report(1);
+        report[REQUEST_HEADER_LEN] = 3;
+        assert!(matches!(
+            OutReport::parse(&report),
+            Err(Error::Rejected {
+                status: Status::InvalidArgument,
+                ..
+            })
+        ));
+
+        // missing argument
+        let mut report = Request::SetChannel(11).to_report(1);
+        report[4] = 0;
+        assert!(matches!(
+            OutReport::parse(&report),
+            Err(Error::Rejected {
+                status: Status::InvalidArgument,
+                ..
+            })
+        ));
+
+        // unknown command
+        let mut report = Request::GetInfo.to_report(3);
+        report[2] = 0x7F;
+        assert_eq!(
+            OutReport::parse(&report),
+            Err(Error::Rejected {
+                command: 0x7F,
+                seq: 3,
+                status: Status::UnknownCommand
+            })
+        );
+
+        // from the future
+        let mut report = Request::GetInfo.to_report(3);
+        report[1] = PROTOCOL_VERSION + 1;
+        assert!(matches!(
+            OutReport::parse(&report),
+            Err(Error::Rejected {
+                status: Status::UnsupportedVersion,
+                ..
+            })
+        ));
+
+        // payload length points past the end of the data
+        let report = Request::SetChannel(11).to_report(1);
+        assert_eq!(OutReport::parse(&report[..5]), Err(Error::Truncated));
+    }
+
+    #[test]
+    fn response_round_trip() {
+        let info = Info {
+            device_id: 0x0123_4567_89AB_CDEF,
+            channel: 20,
+            mode: Mode::Loopback,
+        };
+        let response = Response::ok(Command::GetInfo, 9, &info.to_bytes());
+        let decoded = Response::parse(&response.to_report()).unwrap();
+        assert_eq!(decoded, response);
+        assert_eq!(decoded.command, Command::GetInfo as u8);
+        assert_eq!(decoded.status, Status::Ok);
+        assert_eq!(Info::from_bytes(decoded.payload()), Some(info));
+
+        // from newer firmware, with a status we've never heard of
+        let mut report = response.to_report();
+        report[4] = 0x7E;
+        assert_eq!(Response::parse(&report), Err(Error::UnknownStatus(0x7E)));
+
+        let stats = Stats {
+            rx_count: 100_000,
+            err_count: 3,
+            log_bytes_dropped: 512,
+            log_lines_truncated: 2,
+            traffic_sent: 7,
+            traffic_cca_busy: 1,
+            latency_avg_us: 40,
+            latency_max_us: 95,
+        };
+        assert_eq!(Stats::from_bytes(&stats.to_bytes()), Some(stats));
+        // from firmware which only counted packets
+        assert_eq!(
+            Stats::from_bytes(&stats.to_bytes()[..8]),
+            Some(Stats {
+                log_bytes_dropped: 0,
+                log_lines_truncated: 0,
+                traffic_sent: 0,
+                traffic_cca_busy: 0,
+                latency_avg_us: 0,
+                latency_max_us: 0,
+                ..stats
+            })
+        );
+
+        let version = Version {
+            protocol: PROTOCOL_VERSION,
+            firmware: [1, 2, 3],
+        };
+        assert_eq!(Version::from_bytes(&version.to_bytes()), Some(version));
+
+        let build_info = BuildInfo {
+            git_rev: *b"4cbdf99e8e\0\0",
+            timestamp: 1_760_000_000,
+            flags: BuildInfo::FLAG_DIRTY,
+            secret_hash: 0xd58b_3fa7,
+        };
+        let decoded = BuildInfo::from_bytes(&build_info.to_bytes()).unwrap();
+        assert_eq!(decoded, build_info);
+        assert_eq!(decoded.git_rev(), "4cbdf99e8e");
+        const { assert!(BuildInfo::LEN <= MAX_RESPONSE_PAYLOAD) };
+
+        // too short
+        assert_eq!(Info::from_bytes(&[0; 4]), None);
+    }
+}
@@ -1,5 +1,9 @@
 #![no_std]
 
+pub mod hid;
+pub mod puzzle;
+pub mod relay;
+
 /// A USB VID we randomly picked for the demo code on the dongle
 pub const USB_VID_DEMO: u16 = 0x1209;
 
@@ -0,0 +1,132 @@
+//! The radio puzzle served by `dongle-fw`
+//!
+//! Every request starts with [`ADDR_BYTES`] address bytes, which the reply
+//! copies. What follows (the payload) says what the client wants:
+//!
+//! | Payload                                  | Reply                                  |
+//! |------------------------------------------|----------------------------------------|
+//! | empty                                    | the encoded secret, or its first fragment if it doesn't fit in one packet |
+//! | one byte of plaintext                    | the matching byte of ciphertext        |
+//! | [`FRAGMENT_REQUEST`], index              | index, fragment count, that fragment of the encoded secret |
+//! | [`FRAGMENT_REQUEST`], index, plaintext   | `correct` or `incorrect`, for that fragment |
+//! | anything else                            | `correct` or `incorrect`, for the whole secret |
+//!
+//! [`FRAGMENT_REQUEST`] is not a valid plaintext byte, so it can't be confused
+//! with a guess.
+
+/// The characters a secret may contain
+///
+/// The cipher maps each of these to another one of these. We avoid \ to
+/// prevent escaping issues.
+pub const PLAIN_LETTERS: &str = r##"0123456789 abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ!"#$%&'()*+,-./:;<=>?@[\]^_`{|}~"##;
+
+/// How many address bytes start each request, and are reflected back
+pub const ADDR_BYTES: usize = 6;
+
+/// The most a packet can carry, not counting the CRC
+pub const PACKET_CAPACITY: usize = 125;
+
+/// The largest payload, after the address bytes
+pub const MAX_PAYLOAD: usize = PACKET_CAPACITY - ADDR_BYTES;
+
+/// The first payload byte of a request about one fragment of the secret
+pub const FRAGMENT_REQUEST: u8 = 0x00;
+
+/// Bytes at the start of a fragment reply: the index and the fragment count
+pub const FRAGMENT_HEADER_LEN: usize = 2;
+
+/// How much of the encoded secret each fragment carries (the last may be shorter)
+pub const FRAGMENT_LEN: usize = MAX_PAYLOAD - FRAGMENT_HEADER_LEN;
+
+/// The most fragments a secret may be split into
+pub const MAX_FRAGMENTS: usize = 16;
+
+/// The longest secret which can be sent in one reply
+pub const MAX_SINGLE_PACKET_MESSAGE_LEN: usize = MAX_PAYLOAD;
+
+/// The longest secret the puzzle supports
+pub const MAX_MESSAGE_LEN: usize = FRAGMENT_LEN * MAX_FRAGMENTS;
+
+/// How many fragments a secret of `len` bytes is split into
+pub const fn fragment_count(len: usize) -> usize {
+    len.div_ceil(FRAGMENT_LEN)
+}
+
+/// The seed for a secret's cipher, when none is given
+///
+/// This is a 64-bit FNV-1a hash of `puzzle-seed:` and the secret. It's salted
+/// so that it differs from the secret's 32-bit hash, which anyone can ask the
+/// Dongle for; otherwise the seed (and so the cipher) would be public.
+pub const fn default_seed(plaintext: &str) -> u64 {
+    const fn fnv1a_64(mut hash: u64, data: &[u8]) -> u64 {
+        let mut i = 0;
+        while i < data.len() {
+            hash ^= data[i] as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            i += 1;
+        }
+        hash
+    }
+    let hash = fnv1a_64(0xcbf2_9ce4_8422_2325, b"puzzle-seed:");
+    fnv1a_64(hash, plaintext.as_bytes())
+}
+
+/// The cipher for `seed`: [`PLAIN_LETTERS`], shuffled
+///
+/// Each byte of [`PLAIN_LETTERS`] encodes to the byte at the same position in
+/// here. `dongle-fw`'s build script and `dongle-sim` both use this, so the same
+/// seed gives the same cipher in each.
+#[cfg(feature = "cipher")]
+pub fn cipher_letters(seed: u64) -> [u8; PLAIN_LETTERS.len()] {
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    let mut rng = rand::rngs::Xoshiro256PlusPlus::seed_from_u64(seed);
+    let mut letters = [0; PLAIN_LETTERS.len()];
+    letters.copy_from_slice(PLAIN_LETTERS.as_bytes());
+    letters.shuffle(&mut rng);
+    letters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeds() {
+        assert_eq!(
+            default_seed("This is an example message"),
+            0x8669_06d3_7fab_093b
+        );
+        assert_ne!(default_seed("a"), default_seed("b"));
+    }
+
+    /// Changing these would change the cipher of every secret out there
+    #[cfg(feature = "cipher")]
+    #[test]
+    fn ciphers() {
+        assert_eq!(
+            &cipher_letters(0),
+            br##"u05~*e%D[oY`=-k]b6URQI^H|$?>WC<lO,pN&mj4@{Ew7ScM/hf9qxa(1)tvgs":.rLd;FGAZ!2TX }_zyJ#KnBP3'V8\i+"##
+        );
+        // every letter is still there, once
+        let mut letters = cipher_letters(default_seed("This is an example message"));
+        letters.sort_unstable();
+        let mut sorted = [0; PLAIN_LETTERS.len()];
+        sorted.copy_from_slice(PLAIN_LETTERS.as_bytes());
+        sorted.sort_unstable();
+        assert_eq!(letters, sorted);
+    }
+
+    #[test]
+    fn fragments() {
+        assert_eq!(fragment_count(1), 1);
+        assert_eq!(fragment_count(FRAGMENT_LEN), 1);
+        assert_eq!(fragment_count(FRAGMENT_LEN + 1), 2);
+        assert_eq!(fragment_count(MAX_MESSAGE_LEN), MAX_FRAGMENTS);
+        // the index and count must fit in a byte each
+        const { assert!(MAX_FRAGMENTS <= u8::MAX as usize) };
+        // a fragment request with a full fragment of plaintext fits
+        const { assert!(FRAGMENT_HEADER_LEN + FRAGMENT_LEN == MAX_PAYLOAD) };
+    }
+}
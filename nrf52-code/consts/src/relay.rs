@@ -0,0 +1,267 @@
+//! The framing used over USB serial when `dongle-fw` is in relay mode
+//!
+//! In relay mode the Dongle is a radio modem: the host gives it 802.15.4
+//! frames to transmit, and it hands every frame it receives to the host. The
+//! USB serial port then carries binary frames instead of log text.
+//!
+//! Each frame is SLIP encoded (RFC 1055): it is sent between two [`END`]
+//! bytes, with any [`END`] or [`ESC`] bytes inside it escaped. The first byte
+//! of a frame says what kind of frame it is:
+//!
+//! | Kind   | Direction     | Rest of the frame                               |
+//! |--------|---------------|-------------------------------------------------|
+//! | `0x01` | host → Dongle | The frame to transmit, without the CRC          |
+//! | `0x02` | Dongle → host | The LQI, then the frame received, without CRC   |
+//! | `0x03` | Dongle → host | `0x00` if the frame was sent, `0x01` if the channel was busy |
+//!
+//! Frames which are empty, too long or of an unknown kind should be ignored,
+//! so that stray log text doesn't do any harm.
+
+/// Marks the start and the end of a frame
+pub const END: u8 = 0xC0;
+/// Starts an escape sequence
+pub const ESC: u8 = 0xDB;
+/// [`ESC`] followed by this means [`END`]
+pub const ESC_END: u8 = 0xDC;
+/// [`ESC`] followed by this means [`ESC`]
+pub const ESC_ESC: u8 = 0xDD;
+
+/// The largest 802.15.4 frame we can carry, not counting the CRC
+pub const MAX_PSDU_LEN: usize = 125;
+
+/// The largest frame, before SLIP encoding
+pub const MAX_FRAME_LEN: usize = 2 + MAX_PSDU_LEN;
+
+/// The largest frame, after SLIP encoding
+pub const MAX_ENCODED_LEN: usize = 2 + 2 * MAX_FRAME_LEN;
+
+const KIND_TRANSMIT: u8 = 0x01;
+const KIND_RECEIVED: u8 = 0x02;
+const KIND_SENT: u8 = 0x03;
+
+/// A relay mode frame
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Frame<'a> {
+    /// Please transmit this
+    Transmit(&'a [u8]),
+    /// We received this
+    Received {
+        /// Link Quality Indicator, as measured by the radio
+        lqi: u8,
+        /// The frame, without the CRC
+        psdu: &'a [u8],
+    },
+    /// The outcome of the last [`Frame::Transmit`]
+    Sent {
+        /// `false` if the channel was busy, so nothing was sent
+        ok: bool,
+    },
+}
+
+impl<'a> Frame<'a> {
+    /// Decode a frame, as produced by a [`Decoder`]
+    pub fn parse(data: &'a [u8]) -> Option<Frame<'a>> {
+        match data {
+            [KIND_TRANSMIT, psdu @ ..] if psdu.len() <= MAX_PSDU_LEN => Some(Frame::Transmit(psdu)),
+            [KIND_RECEIVED, lqi, psdu @ ..] if psdu.len() <= MAX_PSDU_LEN => {
+                Some(Frame::Received { lqi: *lqi, psdu })
+            }
+            [KIND_SENT, 0x00] => Some(Frame::Sent { ok: true }),
+            [KIND_SENT, 0x01] => Some(Frame::Sent { ok: false }),
+            _ => None,
+        }
+    }
+
+    /// SLIP encode this frame into `out`
+    ///
+    /// Returns how many bytes were used, or `None` if `out` is too small.
+    /// [`MAX_ENCODED_LEN`] bytes is always enough.
+    pub fn encode(&self, out: &mut [u8]) -> Option<usize> {
+        let sent_status = |ok| if ok { 0x00 } else { 0x01 };
+        let (header, body): (&[u8], &[u8]) = match *self {
+            Frame::Transmit(psdu) => (&[KIND_TRANSMIT], psdu),
+            Frame::Received { lqi, psdu } => (&[KIND_RECEIVED, lqi], psdu),
+            Frame::Sent { ok } => (&[KIND_SENT, sent_status(ok)], &[]),
+        };
+        encode(header.iter().chain(body), out)
+    }
+}
+
+/// SLIP encode some bytes into `out`
+fn encode<'a>(data: impl Iterator<Item = &'a u8>, out: &mut [u8]) -> Option<usize> {
+    let mut len = 0;
+    let mut push = |byte| {
+        *out.get_mut(len)? = byte;
+        len += 1;
+        Some(())
+    };
+    push(END)?;
+    for &byte in data {
+        match byte {
+            END => {
+                push(ESC)?;
+                push(ESC_END)?;
+            }
+            ESC => {
+                push(ESC)?;
+                push(ESC_ESC)?;
+            }
+            other => push(other)?,
+        }
+    }
+    push(END)?;
+    Some(len)
+}
+
+/// Pulls SLIP encoded frames out of a stream of bytes
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    buffer: [u8; MAX_FRAME_LEN],
+    len: usize,
+    escaped: bool,
+    /// The current frame is too long or badly escaped, so will be ignored
+    discard: bool,
+}
+
+impl Decoder {
+    /// Create a decoder, waiting for the start of a frame
+    pub const fn new() -> Decoder {
+        Decoder {
+            buffer: [0u8; MAX_FRAME_LEN],
+            len: 0,
+            escaped: false,
+            discard: false,
+        }
+    }
+
+    /// Feed one byte into the decoder
+    ///
+    /// Returns the frame, still SLIP decoded but not parsed, if this byte
+    /// completes one. See [`Frame::parse`].
+    pub fn push(&mut self, byte: u8) -> Option<&[u8]> {
+        if byte == END {
+            let len = core::mem::take(&mut self.len);
+            let discard = core::mem::take(&mut self.discard);
+            self.escaped = false;
+            return if len > 0 && !discard {
+                Some(&self.buffer[..len])
+            } else {
+                None
+            };
+        }
+        let byte = if core::mem::take(&mut self.escaped) {
+            match byte {
+                ESC_END => END,
+                ESC_ESC => ESC,
+                _ => {
+                    self.discard = true;
+                    return None;
+                }
+            }
+        } else if byte == ESC {
+            self.escaped = true;
+            return None;
+        } else {
+            byte
+        };
+        if let Some(slot) = self.buffer.get_mut(self.len) {
+            *slot = byte;
+            self.len += 1;
+        } else {
+            self.discard = true;
+        }
+        None
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern crate std;
+    use std::{vec, vec::Vec};
+
+    /// Run some bytes through a fresh decoder and collect the frames
+    fn decode_all(data: &[u8]) -> Vec<Vec<u8>> {
+        let mut decoder = Decoder::new();
+        data.iter()
+            .filter_map(|b| decoder.push(*b).map(|f| f.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let awkward = [END, ESC, 0x00, ESC_END, ESC_ESC, 0xFF];
+        for frame in [
+            Frame::Transmit(&awkward),
+            Frame::Transmit(&[]),
+            Frame::Received {
+                lqi: END,
+                psdu: &awkward,
+            },
+            Frame::Sent { ok: true },
+            Frame::Sent { ok: false },
+        ] {
+            let mut encoded = [0u8; MAX_ENCODED_LEN];
+            let len = frame.encode(&mut encoded).unwrap();
+            let encoded = &encoded[..len];
+            assert_eq!(encoded.first(), Some(&END));
+            assert_eq!(encoded.last(), Some(&END));
+            assert!(!encoded[1..len - 1].contains(&END));
+
+            let decoded = decode_all(encoded);
+            assert_eq!(decoded.len(), 1);
+            assert_eq!(Frame::parse(&decoded[0]), Some(frame));
+        }
+    }
+
+    #[test]
+    fn largest_frame_fits() {
+        let psdu = [END; MAX_PSDU_LEN];
+        let frame = Frame::Received {
+            lqi: ESC,
+            psdu: &psdu,
+        };
+        let mut encoded = [0u8; MAX_ENCODED_LEN];
+        let len = frame.encode(&mut encoded).unwrap();
+        assert!(len <= MAX_ENCODED_LEN);
+        assert_eq!(frame.encode(&mut encoded[..len - 1]), None);
+    }
+
+    #[test]
+    fn junk_is_ignored() {
+        let mut encoded = [0u8; MAX_ENCODED_LEN];
+        let len = Frame::Transmit(b"hello").encode(&mut encoded).unwrap();
+
+        // log text before the frame, and back-to-back END bytes
+        let mut stream = b"\nReceived 5 bytes (LQI=200)\n".to_vec();
+        stream.extend_from_slice(&[END, END]);
+        stream.extend_from_slice(&encoded[..len]);
+        let decoded = decode_all(&stream);
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(Frame::parse(&decoded[0]), None);
+        assert_eq!(Frame::parse(&decoded[1]), Some(Frame::Transmit(b"hello")));
+
+        // bad escape
+        let decoded = decode_all(&[END, KIND_TRANSMIT, ESC, 0x00, 0x01, END]);
+        assert!(decoded.is_empty());
+
+        // too long
+        let mut stream = vec![END, KIND_TRANSMIT];
+        stream.extend_from_slice(&[0x55; MAX_FRAME_LEN]);
+        stream.push(END);
+        assert!(decode_all(&stream).is_empty());
+
+        // unknown kind, or no content
+        assert_eq!(Frame::parse(&[0x7F, 1, 2, 3]), None);
+        assert_eq!(Frame::parse(&[KIND_SENT]), None);
+        assert_eq!(Frame::parse(&[KIND_RECEIVED]), None);
+    }
+}
@@ -0,0 +1,414 @@
+//! The application logic of `dongle-fw`, independent of the hardware
+//!
+//! The firmware's `radio` task receives a frame and hands it to
+//! [`handle_frame`], which counts it and, depending on the [`AppMode`], passes
+//! it to [`handle_loopback`], to [`puzzle::Puzzle::reply`] and then
+//! [`send_reply`], or to the host. Requests over USB HID go to
+//! [`handle_hid_request`]. These talk to the outside world only through the
+//! [`Radio`], [`Acm`], [`Leds`] and [`Device`] traits, so the same code runs on
+//! the Dongle and in the `dongle-sim` host simulator.
+
+#![no_std]
+#![deny(missing_docs)]
+
+use consts::{hid, relay};
+use embedded_hal_async::delay::DelayNs;
+
+pub mod puzzle;
+
+/// How long we wait before replying in loopback mode
+///
+/// The client waits for 10ms, and we want to ensure they are definitely in
+/// receive mode by the time we send our reply.
+pub const LOOPBACK_TURNAROUND_US: u32 = 5_000;
+
+/// Sends frames, on the channel we listen on
+#[allow(async_fn_in_trait)]
+pub trait Radio {
+    /// Why a frame could not be sent
+    type Error: core::fmt::Debug;
+
+    /// Transmit a frame. The radio adds the CRC.
+    async fn transmit(&mut self, frame: &Frame) -> Result<(), Self::Error>;
+
+    /// The channel we are on
+    fn channel(&self) -> u8;
+
+    /// Move to another channel, one of [`hid::CHANNELS`]
+    fn set_channel(&mut self, channel: u8);
+}
+
+/// The USB serial port, where we log what we are doing
+///
+/// Text is buffered by [`core::fmt::Write`] until [`Acm::flush`] is called.
+#[allow(async_fn_in_trait)]
+pub trait Acm: core::fmt::Write {
+    /// Send everything written so far to the host
+    async fn flush(&mut self);
+
+    /// Send binary data, like a relay frame, without going through the buffer
+    async fn write_bytes(&mut self, data: &[u8]);
+
+    /// Send binary data straight away if there's room for it, or drop it
+    fn try_write_bytes(&mut self, data: &[u8]);
+}
+
+/// The RGB LED, which shows the mode
+pub trait Leds {
+    /// Turn each colour on or off
+    fn set_rgb(&mut self, red: bool, green: bool, blue: bool);
+}
+
+/// The rest of the Dongle: what it knows about itself, and what USB HID
+/// requests can change
+pub trait Device {
+    /// The chip's unique ID
+    fn device_id(&self) -> u64;
+
+    /// Which mode we are in
+    fn mode(&self) -> AppMode;
+
+    /// Change mode, and show it on the RGB LED
+    fn set_mode(&mut self, mode: AppMode);
+
+    /// How much we log over USB ACM
+    fn verbosity(&self) -> hid::Verbosity;
+
+    /// Change how much we log
+    fn set_verbosity(&mut self, verbosity: hid::Verbosity);
+
+    /// How many frames we have received
+    fn counters(&mut self) -> &mut Counters;
+
+    /// What a [`hid::Request::GetStats`] reports
+    fn stats(&self) -> hid::Stats;
+
+    /// What a [`hid::Request::GetVersion`] reports
+    fn version(&self) -> hid::Version;
+
+    /// What a [`hid::Request::GetBuildInfo`] reports
+    fn build_info(&self) -> hid::BuildInfo;
+
+    /// Change the frames we send in [`AppMode::Traffic`]
+    fn set_traffic(&mut self, config: hid::TrafficConfig);
+
+    /// A puzzle reply is ready to send
+    ///
+    /// Returns how long, in microseconds, since the request arrived, if we
+    /// measure that.
+    fn reply_ready(&mut self) -> Option<u32> {
+        None
+    }
+}
+
+/// How many frames we have received
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Counters {
+    /// Frames received with a good CRC
+    pub rx_count: u32,
+    /// Frames received with a bad CRC
+    pub err_count: u32,
+}
+
+/// One 802.15.4 frame, without the CRC
+#[derive(Clone)]
+pub struct Frame {
+    data: [u8; Frame::CAPACITY],
+    len: u8,
+    lqi: u8,
+}
+
+impl Frame {
+    /// The largest frame, not counting the CRC
+    pub const CAPACITY: usize = 125;
+
+    /// An empty frame
+    pub const fn new() -> Frame {
+        Frame {
+            data: [0; Frame::CAPACITY],
+            len: 0,
+            lqi: 0,
+        }
+    }
+
+    /// A copy of a received frame, or `None` if `psdu` is too long
+    pub fn from_received(psdu: &[u8], lqi: u8) -> Option<Frame> {
+        let mut frame = Frame::new();
+        frame.data.get_mut(..psdu.len())?.copy_from_slice(psdu);
+        frame.len = psdu.len() as u8;
+        frame.lqi = lqi;
+        Some(frame)
+    }
+
+    /// Change the length of the frame
+    ///
+    /// # Panics
+    ///
+    /// If `len` is more than [`Frame::CAPACITY`].
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len <= Frame::CAPACITY, "frame too long");
+        self.len = len as u8;
+    }
+
+    /// Link Quality Indicator, as measured when the frame was received
+    pub fn lqi(&self) -> u8 {
+        self.lqi
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Frame::new()
+    }
+}
+
+impl core::ops::Deref for Frame {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data[..usize::from(self.len)]
+    }
+}
+
+impl core::ops::DerefMut for Frame {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.data[..usize::from(self.len)]
+    }
+}
+
+impl core::fmt::Debug for Frame {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Frame({:02x?}, lqi={})", &self[..], self.lqi)
+    }
+}
+
+/// The modes of the application.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AppMode {
+    /// Loopback mode.
+    Loopback,
+    /// Puzzle mode.
+    Puzzle,
+    /// Relay mode: frames go between USB ACM and the radio.
+    Relay,
+    /// Traffic mode: we transmit a stream of frames, as interference.
+    Traffic,
+}
+
+impl AppMode {
+    /// Toggle between the app modes.
+    pub fn toggle(&mut self) {
+        *self = match self {
+            AppMode::Loopback => AppMode::Puzzle,
+            AppMode::Puzzle | AppMode::Relay | AppMode::Traffic => AppMode::Loopback,
+        };
+    }
+
+    /// Show the mode on the RGB LED.
+    pub fn show(&self, leds: &mut impl Leds) {
+        match self {
+            AppMode::Loopback => leds.set_rgb(false, true, false),
+            AppMode::Puzzle => leds.set_rgb(false, false, true),
+            AppMode::Relay => leds.set_rgb(false, true, true),
+            AppMode::Traffic => leds.set_rgb(true, false, true),
+        }
+    }
+}
+
+impl From<hid::Mode> for AppMode {
+    fn from(mode: hid::Mode) -> AppMode {
+        match mode {
+            hid::Mode::Loopback => AppMode::Loopback,
+            hid::Mode::Puzzle => AppMode::Puzzle,
+            hid::Mode::Relay => AppMode::Relay,
+            hid::Mode::Traffic => AppMode::Traffic,
+        }
+    }
+}
+
+impl From<AppMode> for hid::Mode {
+    fn from(mode: AppMode) -> hid::Mode {
+        match mode {
+            AppMode::Loopback => hid::Mode::Loopback,
+            AppMode::Puzzle => hid::Mode::Puzzle,
+            AppMode::Relay => hid::Mode::Relay,
+            AppMode::Traffic => hid::Mode::Traffic,
+        }
+    }
+}
+
+/// Deal with a frame the radio has received, according to the mode we are in
+///
+/// `frame` is `None` if it arrived with a bad CRC.
+pub async fn handle_frame(
+    frame: Option<&mut Frame>,
+    puzzle: &puzzle::Puzzle<'_>,
+    device: &mut impl Device,
+    radio: &mut impl Radio,
+    acm: &mut impl Acm,
+    delay: &mut impl DelayNs,
+) {
+    let mode = device.mode();
+    let verbosity = device.verbosity();
+    let Some(frame) = frame else {
+        device.counters().err_count += 1;
+        // In relay mode the host is reading SLIP frames, and a stray byte
+        // would garble the next one. Otherwise, mark the bad frame without
+        // holding up the radio.
+        if mode != AppMode::Relay && verbosity >= hid::Verbosity::Normal {
+            acm.try_write_bytes(b"!");
+        }
+        return;
+    };
+    device.counters().rx_count += 1;
+    match mode {
+        AppMode::Loopback => handle_loopback(frame, radio, acm, delay, verbosity).await,
+        AppMode::Puzzle => {
+            if puzzle.reply(frame, acm, verbosity) {
+                if let Some(latency_us) = device.reply_ready() {
+                    if verbosity >= hid::Verbosity::Verbose {
+                        let _ = writeln!(acm, "Reply ready in {} us", latency_us);
+                    }
+                }
+                send_reply(frame, puzzle::TURNAROUND_US, radio, acm, delay).await;
+            }
+            acm.flush().await;
+        }
+        AppMode::Relay => {
+            // Pass it on to the host
+            let received = relay::Frame::Received {
+                lqi: frame.lqi(),
+                psdu: frame,
+            };
+            let mut encoded = [0u8; relay::MAX_ENCODED_LEN];
+            if let Some(len) = received.encode(&mut encoded) {
+                acm.write_bytes(&encoded[..len]).await;
+            }
+        }
+        AppMode::Traffic => {
+            // We're the noise; just count what we hear
+        }
+    }
+}
+
+/// Carry out a request which arrived over USB HID, and say how it went
+pub async fn handle_hid_request(
+    seq: u8,
+    request: hid::Request,
+    device: &mut impl Device,
+    radio: &mut impl Radio,
+    acm: &mut impl Acm,
+) -> hid::Response {
+    let command = request.command();
+    match request {
+        hid::Request::GetInfo => {
+            let info = hid::Info {
+                device_id: device.device_id(),
+                channel: radio.channel(),
+                mode: device.mode().into(),
+            };
+            hid::Response::ok(command, seq, &info.to_bytes())
+        }
+        hid::Request::SetChannel(n) => {
+            let _ = writeln!(acm, "\nChanging Channel to {}", n);
+            acm.flush().await;
+            // `OutReport::parse` has checked the range for us
+            radio.set_channel(n);
+            hid::Response::ok(command, seq, &[n])
+        }
+        hid::Request::SetMode(mode) => {
+            let mode = AppMode::from(mode);
+            device.set_mode(mode);
+            let _ = writeln!(acm, "\nChanged app mode to {:?}", mode);
+            acm.flush().await;
+            hid::Response::ok(command, seq, &[hid::Mode::from(mode) as u8])
+        }
+        hid::Request::GetStats => hid::Response::ok(command, seq, &device.stats().to_bytes()),
+        hid::Request::GetVersion => hid::Response::ok(command, seq, &device.version().to_bytes()),
+        hid::Request::GetBuildInfo => {
+            hid::Response::ok(command, seq, &device.build_info().to_bytes())
+        }
+        hid::Request::SetVerbosity(verbosity) => {
+            device.set_verbosity(verbosity);
+            let _ = writeln!(acm, "\nChanged verbosity to {:?}", verbosity);
+            acm.flush().await;
+            hid::Response::ok(command, seq, &[verbosity as u8])
+        }
+        hid::Request::SetTraffic(config) => {
+            device.set_traffic(config);
+            let _ = writeln!(
+                acm,
+                "\nTraffic is now {} byte frames every {} ms (random={}, cca={})",
+                config.len, config.interval_ms, config.random_payload, config.cca
+            );
+            acm.flush().await;
+            hid::Response::ok(command, seq, &config.to_bytes())
+        }
+    }
+}
+
+/// Reply to a frame received in loopback mode
+///
+/// We reverse the bytes, so olleh -> hello.
+pub async fn handle_loopback(
+    frame: &mut Frame,
+    radio: &mut impl Radio,
+    acm: &mut impl Acm,
+    delay: &mut impl DelayNs,
+    verbosity: hid::Verbosity,
+) {
+    frame.reverse();
+    send_reply(frame, LOOPBACK_TURNAROUND_US, radio, acm, delay).await;
+    if verbosity >= hid::Verbosity::Normal {
+        let _ = writeln!(
+            acm,
+            "\nReceived {} bytes (LQI={})",
+            frame.len(),
+            frame.lqi()
+        );
+    }
+    acm.flush().await;
+}
+
+/// Send a reply, once the client has had `turnaround_us` to start listening
+pub async fn send_reply(
+    frame: &Frame,
+    turnaround_us: u32,
+    radio: &mut impl Radio,
+    acm: &mut impl Acm,
+    delay: &mut impl DelayNs,
+) {
+    delay.delay_us(turnaround_us).await;
+    if let Err(e) = radio.transmit(frame).await {
+        let _ = writeln!(acm, "\nWriting reply packet failed with error {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_len() {
+        let mut frame = Frame::from_received(b"hello", 200).unwrap();
+        assert_eq!(&frame[..], b"hello");
+        assert_eq!(frame.lqi(), 200);
+        frame.set_len(Frame::CAPACITY);
+        assert_eq!(frame.len(), Frame::CAPACITY);
+        assert!(Frame::from_received(&[0; Frame::CAPACITY + 1], 0).is_none());
+    }
+
+    #[test]
+    fn modes_round_trip() {
+        for mode in [
+            AppMode::Loopback,
+            AppMode::Puzzle,
+            AppMode::Relay,
+            AppMode::Traffic,
+        ] {
+            assert_eq!(AppMode::from(hid::Mode::from(mode)), mode);
+        }
+    }
+}
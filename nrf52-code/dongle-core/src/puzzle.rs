@@ -0,0 +1,284 @@
+//! The radio puzzle
+//!
+//! The protocol is described in [`consts::puzzle`].
+
+use core::fmt::Write;
+
+use consts::hid;
+use consts::puzzle::{self as protocol, ADDR_BYTES};
+
+use crate::Frame;
+
+/// How long we wait before replying to a puzzle request
+///
+/// The client waits for 10ms, and we want to ensure they are definitely in
+/// receive mode by the time we send our reply.
+pub const TURNAROUND_US: u32 = 500;
+
+/// A request was too short to hold an address
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InvalidPacketError;
+
+/// Commands we can receive over the radio
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Send the whole encoded secret
+    SendSecret,
+    /// Send one fragment of the encoded secret
+    SendFragment(u8),
+    /// Send the ciphertext for a plaintext byte: `(plain, cipher)`
+    MapChar(u8, u8),
+    /// Tell them their guess was correct
+    Correct,
+    /// Tell them their guess was wrong
+    Wrong,
+}
+
+/// A secret, and the substitution cipher that encoded it
+#[derive(Debug, Copy, Clone)]
+pub struct Puzzle<'a> {
+    encoded: &'a [u8],
+    cipher_table: &'a [u8; 256],
+    decipher_table: &'a [u8; 256],
+}
+
+impl<'a> Puzzle<'a> {
+    /// Set up a puzzle
+    ///
+    /// `cipher_table` maps each plaintext byte to its ciphertext, and
+    /// `decipher_table` maps it back. `encoded` must be no longer than
+    /// [`protocol::MAX_MESSAGE_LEN`].
+    pub const fn new(
+        encoded: &'a [u8],
+        cipher_table: &'a [u8; 256],
+        decipher_table: &'a [u8; 256],
+    ) -> Puzzle<'a> {
+        Puzzle {
+            encoded,
+            cipher_table,
+            decipher_table,
+        }
+    }
+
+    /// The encoded secret
+    pub fn encoded(&self) -> &'a [u8] {
+        self.encoded
+    }
+
+    /// Work out what a request is asking for
+    ///
+    /// `request` is the whole frame, including the address.
+    pub fn command(&self, request: &[u8]) -> Result<Command, InvalidPacketError> {
+        let Some(payload) = request.get(ADDR_BYTES..) else {
+            return Err(InvalidPacketError);
+        };
+        if payload.is_empty() {
+            if self.encoded.len() <= protocol::MAX_SINGLE_PACKET_MESSAGE_LEN {
+                Ok(Command::SendSecret)
+            } else {
+                // Too long for one packet, so start them off with the first fragment
+                Ok(Command::SendFragment(0))
+            }
+        } else if payload.len() == 1 {
+            // They give us plaintext, we give them ciphertext
+            let plain = payload[0];
+            let cipher = self.cipher_table[usize::from(plain)];
+            Ok(Command::MapChar(plain, cipher))
+        } else if let [protocol::FRAGMENT_REQUEST, index] = payload {
+            Ok(Command::SendFragment(*index))
+        } else {
+            // They give us plaintext, we tell them if it is correct - either
+            // for one fragment, or for the whole secret
+            let (plaintext, secret) = match payload {
+                [protocol::FRAGMENT_REQUEST, index, plaintext @ ..] => {
+                    (plaintext, self.fragment(*index))
+                }
+                _ => (payload, self.encoded),
+            };
+            // Decrypt the secret one byte at a time, and compare
+            let correct = plaintext.len() == secret.len()
+                && plaintext
+                    .iter()
+                    .zip(secret)
+                    .all(|(&plain, &cipher)| self.decipher_table[usize::from(cipher)] == plain);
+            if correct {
+                Ok(Command::Correct)
+            } else {
+                Ok(Command::Wrong)
+            }
+        }
+    }
+
+    /// How many fragments the secret is sent in, when asked for fragments
+    pub fn fragment_count(&self) -> usize {
+        protocol::fragment_count(self.encoded.len())
+    }
+
+    /// One fragment of the encoded secret (empty if there's no such fragment)
+    pub fn fragment(&self, index: u8) -> &'a [u8] {
+        self.encoded
+            .chunks(protocol::FRAGMENT_LEN)
+            .nth(usize::from(index))
+            .unwrap_or(&[])
+    }
+
+    /// Turn a request into the reply to a command, keeping the address
+    pub fn write_reply(&self, command: Command, frame: &mut Frame) {
+        let payload: &[u8] = match command {
+            Command::SendSecret => self.encoded,
+            Command::SendFragment(index) => {
+                let fragment = self.fragment(index);
+                let start = ADDR_BYTES + protocol::FRAGMENT_HEADER_LEN;
+                frame.set_len(start + fragment.len());
+                frame[ADDR_BYTES] = index;
+                frame[ADDR_BYTES + 1] = self.fragment_count() as u8;
+                frame[start..].copy_from_slice(fragment);
+                return;
+            }
+            Command::MapChar(_plain, cipher) => &[cipher],
+            Command::Correct => b"correct",
+            Command::Wrong => b"incorrect",
+        };
+        frame.set_len(ADDR_BYTES + payload.len());
+        frame[ADDR_BYTES..].copy_from_slice(payload);
+    }
+
+    /// Turn a request into its reply, logging what we did
+    ///
+    /// Returns `false` if there should be no reply. Send the reply with
+    /// [`crate::send_reply`], after [`TURNAROUND_US`].
+    pub fn reply(
+        &self,
+        frame: &mut Frame,
+        log: &mut impl Write,
+        verbosity: hid::Verbosity,
+    ) -> bool {
+        if verbosity >= hid::Verbosity::Verbose {
+            let _ = writeln!(log, "RX {:02x?}", &frame[..]);
+        }
+        let command = match self.command(frame) {
+            Ok(command) => command,
+            Err(InvalidPacketError) => {
+                let _ = writeln!(log, "RX invalid packet");
+                // not enough bytes - send nothing back
+                return false;
+            }
+        };
+        self.write_reply(command, frame);
+        if verbosity >= hid::Verbosity::Normal {
+            let _ = match command {
+                Command::SendSecret => writeln!(log, "TX Secret"),
+                Command::SendFragment(index) => {
+                    writeln!(log, "TX Fragment {index} of {}", self.fragment_count())
+                }
+                Command::MapChar(plain, cipher) => writeln!(log, "TX Map({plain}) => {cipher}"),
+                Command::Correct => writeln!(log, "TX Correct"),
+                Command::Wrong => writeln!(log, "TX Incorrect"),
+            };
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Caesar cipher over the printable ASCII, for testing
+    fn tables() -> ([u8; 256], [u8; 256]) {
+        let mut cipher = [0u8; 256];
+        let mut decipher = [0u8; 256];
+        for plain in b' '..=b'~' {
+            let c = b' ' + (plain - b' ' + 3) % 95;
+            cipher[usize::from(plain)] = c;
+            decipher[usize::from(c)] = plain;
+        }
+        (cipher, decipher)
+    }
+
+    fn encode(cipher: &[u8; 256], plaintext: &[u8], out: &mut [u8]) {
+        for (o, p) in out.iter_mut().zip(plaintext) {
+            *o = cipher[usize::from(*p)];
+        }
+    }
+
+    fn request(payload: &[u8]) -> Frame {
+        let mut frame = Frame::new();
+        frame.set_len(ADDR_BYTES + payload.len());
+        frame[..ADDR_BYTES].copy_from_slice(b"\x01\x02\x03\x04\x05\x06");
+        frame[ADDR_BYTES..].copy_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn short_secret() {
+        let (cipher, decipher) = tables();
+        let mut encoded = [0u8; 5];
+        encode(&cipher, b"hello", &mut encoded);
+        let puzzle = Puzzle::new(&encoded, &cipher, &decipher);
+
+        assert_eq!(puzzle.command(&[0; 3]), Err(InvalidPacketError));
+        assert_eq!(puzzle.command(&request(b"")), Ok(Command::SendSecret));
+        assert_eq!(
+            puzzle.command(&request(b"a")),
+            Ok(Command::MapChar(b'a', b'd'))
+        );
+        assert_eq!(puzzle.command(&request(b"hello")), Ok(Command::Correct));
+        assert_eq!(puzzle.command(&request(b"hellO")), Ok(Command::Wrong));
+        assert_eq!(puzzle.command(&request(b"hell")), Ok(Command::Wrong));
+
+        let mut frame = request(b"");
+        assert!(puzzle.reply(&mut frame, &mut NoLog, hid::Verbosity::Quiet));
+        assert_eq!(&frame[..ADDR_BYTES], b"\x01\x02\x03\x04\x05\x06");
+        assert_eq!(&frame[ADDR_BYTES..], &encoded);
+
+        let mut frame = request(b"hello");
+        assert!(puzzle.reply(&mut frame, &mut NoLog, hid::Verbosity::Quiet));
+        assert_eq!(&frame[ADDR_BYTES..], b"correct");
+    }
+
+    #[test]
+    fn fragmented_secret() {
+        let (cipher, decipher) = tables();
+        let plaintext = [b'x'; protocol::FRAGMENT_LEN * 2 + 1];
+        let mut encoded = [0u8; protocol::FRAGMENT_LEN * 2 + 1];
+        encode(&cipher, &plaintext, &mut encoded);
+        let puzzle = Puzzle::new(&encoded, &cipher, &decipher);
+        assert_eq!(puzzle.fragment_count(), 3);
+
+        // An empty request gets the first fragment
+        let mut frame = request(b"");
+        assert!(puzzle.reply(&mut frame, &mut NoLog, hid::Verbosity::Quiet));
+        assert_eq!(frame.len(), protocol::PACKET_CAPACITY);
+        assert_eq!(frame[ADDR_BYTES..ADDR_BYTES + 2], [0, 3]);
+
+        // The last fragment is short
+        let mut frame = request(&[protocol::FRAGMENT_REQUEST, 2]);
+        assert!(puzzle.reply(&mut frame, &mut NoLog, hid::Verbosity::Quiet));
+        assert_eq!(&frame[ADDR_BYTES..], &[2, 3, encoded[encoded.len() - 1]]);
+
+        // Past the end, they just get the count
+        let mut frame = request(&[protocol::FRAGMENT_REQUEST, 7]);
+        assert!(puzzle.reply(&mut frame, &mut NoLog, hid::Verbosity::Quiet));
+        assert_eq!(&frame[ADDR_BYTES..], &[7, 3]);
+
+        // Guesses are checked one fragment at a time
+        let mut guess = [b'x'; protocol::FRAGMENT_LEN + 2];
+        guess[0] = protocol::FRAGMENT_REQUEST;
+        guess[1] = 1;
+        assert_eq!(puzzle.command(&request(&guess)), Ok(Command::Correct));
+        assert_eq!(puzzle.command(&request(&guess[..4])), Ok(Command::Wrong));
+        assert_eq!(
+            puzzle.command(&request(&[protocol::FRAGMENT_REQUEST, 2, b'x'])),
+            Ok(Command::Correct)
+        );
+    }
+
+    struct NoLog;
+
+    impl Write for NoLog {
+        fn write_str(&mut self, _s: &str) -> core::fmt::Result {
+            Ok(())
+        }
+    }
+}
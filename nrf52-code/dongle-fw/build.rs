@@ -1,11 +1,21 @@
 //! Configure the puzzle firmware
+//!
+//! Environment variables:
+//!
+//! * `HIDDEN_MESSAGE` - the secret (required when `CI_BUILD=1`). Secrets
+//!   which don't fit in one packet are sent in fragments; see
+//!   [`consts::puzzle`].
+//! * `PUZZLE_SEED` - seeds the cipher, as a decimal or `0x` hex number. If not
+//!   set, we use a hash of `HIDDEN_MESSAGE` (see
+//!   [`consts::puzzle::default_seed`]), so the same message always gets the
+//!   same cipher.
+//! * `PUZZLE_MANIFEST` - if set, where to put a copy of `puzzle_manifest.json`
+//!   (which is always written to `OUT_DIR`). Relative paths are relative to
+//!   this directory.
 
-use rand::prelude::*;
+use consts::puzzle::{self, PLAIN_LETTERS};
 
 fn main() {
-    // We avoid \ to prevent escaping issues
-    const PLAIN_LETTERS: &str = r##"0123456789 abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ!"#$%&'()*+,-./:;<=>?@[\]^_`{|}~"##;
-
     let maybe_msg = std::env::var("HIDDEN_MESSAGE");
     if std::env::var("CI_BUILD").is_ok_and(|val| val == "1") {
         if maybe_msg.is_err() {
@@ -16,10 +26,29 @@ fn main() {
     }
 
     let plaintext = maybe_msg.as_deref().unwrap_or("This is an example message");
+    if let Some(bad) = plaintext.chars().find(|c| !PLAIN_LETTERS.contains(*c)) {
+        panic!("HIDDEN_MESSAGE contains {bad:?}, which is not one of {PLAIN_LETTERS:?}");
+    }
+    if plaintext.is_empty() {
+        panic!("HIDDEN_MESSAGE is empty");
+    } else if plaintext.len() > puzzle::MAX_MESSAGE_LEN {
+        panic!(
+            "HIDDEN_MESSAGE is {} bytes, but can be at most {} bytes ({} fragments of {} bytes)",
+            plaintext.len(),
+            puzzle::MAX_MESSAGE_LEN,
+            puzzle::MAX_FRAGMENTS,
+            puzzle::FRAGMENT_LEN
+        );
+    } else if plaintext.len() > puzzle::MAX_SINGLE_PACKET_MESSAGE_LEN {
+        println!(
+            "cargo:warning=HIDDEN_MESSAGE is {} bytes, so will be sent in {} fragments",
+            plaintext.len(),
+            puzzle::fragment_count(plaintext.len())
+        );
+    }
 
-    let mut rng = rand::rng();
-    let mut cipher_letters: Vec<u8> = PLAIN_LETTERS.bytes().collect();
-    cipher_letters.shuffle(&mut rng);
+    let seed = puzzle_seed(plaintext);
+    let cipher_letters = puzzle::cipher_letters(seed.value);
     let cipher_letters_str = std::str::from_utf8(&cipher_letters).unwrap();
 
     let mut dict = std::collections::HashMap::new();
@@ -27,6 +56,7 @@ fn main() {
         dict.insert(from, to);
     }
 
+    println!("seed: {:#018x} ({})", seed.value, seed.source);
     println!("from: {:?}", PLAIN_LETTERS);
     println!("to: {:?}", cipher_letters_str);
     println!("plaintext: {:?}", plaintext);
@@ -36,10 +66,176 @@ fn main() {
     println!("secret: {:?}", encoded_str);
 
     output_data("ENCODED_MESSAGE.txt", encoded_str);
-    output_data("PLAIN_LETTERS.txt", PLAIN_LETTERS);
-    output_data("CIPHER_LETTERS.txt", cipher_letters_str);
+    output_data(
+        "cipher_tables.rs",
+        &cipher_tables(PLAIN_LETTERS.as_bytes(), &cipher_letters),
+    );
+
+    // For instructors, and any tools which check puzzle solutions
+    let manifest = format!(
+        r#"{{
+  "seed": "{seed:#018x}",
+  "seed_source": "{source}",
+  "plain_letters": {plain_letters},
+  "cipher_letters": {cipher_letters},
+  "encoded_message": {encoded},
+  "message_len": {message_len},
+  "fragments": {fragments},
+  "secret_hash": "{secret_hash:08x}"
+}}
+"#,
+        seed = seed.value,
+        source = seed.source,
+        plain_letters = json_string(PLAIN_LETTERS),
+        cipher_letters = json_string(cipher_letters_str),
+        encoded = json_string(encoded_str),
+        message_len = plaintext.len(),
+        fragments = puzzle::fragment_count(plaintext.len()),
+        secret_hash = fnv1a(plaintext.as_bytes()),
+    );
+    output_data("puzzle_manifest.json", &manifest);
+    if let Some(path) = std::env::var_os("PUZZLE_MANIFEST") {
+        std::fs::write(&path, &manifest).expect("could not write PUZZLE_MANIFEST");
+    }
 
     println!("cargo:rerun-if-env-changed=HIDDEN_MESSAGE");
+    println!("cargo:rerun-if-env-changed=PUZZLE_SEED");
+    println!("cargo:rerun-if-env-changed=PUZZLE_MANIFEST");
+
+    emit_build_info(plaintext);
+}
+
+/// Where the cipher comes from
+struct PuzzleSeed {
+    value: u64,
+    source: &'static str,
+}
+
+/// Work out the seed for the cipher, from `PUZZLE_SEED` or the message
+fn puzzle_seed(plaintext: &str) -> PuzzleSeed {
+    match std::env::var("PUZZLE_SEED") {
+        Ok(seed) => {
+            let value = match seed.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => seed.parse(),
+            };
+            PuzzleSeed {
+                value: value.expect("PUZZLE_SEED is not a number"),
+                source: "PUZZLE_SEED",
+            }
+        }
+        Err(_) => PuzzleSeed {
+            value: puzzle::default_seed(plaintext),
+            source: "HIDDEN_MESSAGE",
+        },
+    }
+}
+
+/// A string, quoted and escaped for JSON
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Rust source for lookup tables going from plaintext to ciphertext, and back
+///
+/// Bytes which aren't in the alphabet map to zero.
+fn cipher_tables(plain_letters: &[u8], cipher_letters: &[u8]) -> String {
+    let mut forward = [0u8; 256];
+    let mut inverse = [0u8; 256];
+    for (&plain, &cipher) in plain_letters.iter().zip(cipher_letters) {
+        forward[usize::from(plain)] = cipher;
+        inverse[usize::from(cipher)] = plain;
+    }
+    for &plain in plain_letters {
+        assert_eq!(inverse[usize::from(forward[usize::from(plain)])], plain);
+    }
+
+    format!(
+        "/// Maps a plaintext byte to its ciphertext (or zero)\n\
+         static CIPHER_TABLE: [u8; 256] = {forward:?};\n\
+         \n\
+         /// Maps a ciphertext byte back to its plaintext (or zero)\n\
+         static DECIPHER_TABLE: [u8; 256] = {inverse:?};\n"
+    )
+}
+
+/// Tell the firmware how, and from what, it was built
+///
+/// These end up in the `DONGLE_FW_*` environment variables at compile time.
+fn emit_build_info(plaintext: &str) {
+    let git_rev = git(&["rev-parse", "--short=10", "HEAD"]).unwrap_or_else(|| "unknown".into());
+    let dirty = git(&["status", "--porcelain", "--untracked-files=no"])
+        .is_some_and(|status| !status.is_empty());
+    // Re-run when someone commits, checks out something else, stages
+    // something or edits the firmware, so the dirty flag stays honest
+    if let Some(git_dir) = git(&["rev-parse", "--absolute-git-dir"]) {
+        println!("cargo:rerun-if-changed={git_dir}/HEAD");
+        println!("cargo:rerun-if-changed={git_dir}/logs/HEAD");
+        println!("cargo:rerun-if-changed={git_dir}/index");
+    }
+    // Asking for any of the above switches off Cargo's default of re-running
+    // whenever anything in the package changes, so ask for that explicitly
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=Cargo.toml");
+
+    // Honour https://reproducible-builds.org/specs/source-date-epoch/
+    let timestamp = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch.parse().expect("SOURCE_DATE_EPOCH is not a number"),
+        Err(_) => std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    };
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let features = if std::env::var_os("CARGO_FEATURE_DK").is_some() {
+        "dk"
+    } else {
+        "dongle"
+    };
+
+    // Lets an instructor check which secret a Dongle has, without revealing it
+    let secret_hash = fnv1a(plaintext.as_bytes());
+
+    println!("build: rev={git_rev} dirty={dirty} timestamp={timestamp} features={features} secret={secret_hash:08x}");
+    println!("cargo:rustc-env=DONGLE_FW_GIT_REV={git_rev}");
+    println!("cargo:rustc-env=DONGLE_FW_GIT_DIRTY={dirty}");
+    println!("cargo:rustc-env=DONGLE_FW_BUILD_TIMESTAMP={timestamp}");
+    println!("cargo:rustc-env=DONGLE_FW_FEATURES={features}");
+    println!("cargo:rustc-env=DONGLE_FW_SECRET_HASH={secret_hash}");
+}
+
+/// Run git, returning its trimmed output if it worked
+///
+/// The release zip file is not a git checkout, so this can fail.
+fn git(args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    Some(stdout.trim().to_owned())
+}
+
+/// 32-bit FNV-1a, which is small enough to re-implement anywhere
+fn fnv1a(data: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for byte in data {
+        hash ^= u32::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
 }
 
 fn output_data(filename: &str, value: &str) {
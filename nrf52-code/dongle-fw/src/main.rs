@@ -2,11 +2,17 @@
 //!
 //! Sets up a USB Serial port and listens for radio packets.
 //!
-//! This application has two queues:
+//! This application has three queues:
 //!
 //! * USB HID from host computer -> `usb_hid` task -> `HidTransferHandler` -> `MSG_CHANNEL` -> `radio` task
 //! * USB ACM from host computer -> `usb_acm` task -> `MSG_CHANNEL` -> `radio` task
 //! * various tasks -> `ACM_PIPE` - `usb_acm` task -> USB ACM to host computer
+//! * `radio` task -> `HID_RESPONSE_CHANNEL` -> `usb_hid_response` task -> USB HID to host computer
+//!
+//! The HID reports are described in [`consts::hid`].
+//!
+//! What we do with the frames we receive, and with USB HID requests, lives in
+//! `dongle-core`, so that `dongle-sim` can run it on a host computer.
 
 #![no_main]
 #![no_std]
@@ -15,47 +21,66 @@
 #[cfg(not(feature = "dk"))]
 use bsp::RgbLed;
 
+mod panic_record;
+
 #[rtic::app(device = bsp, peripherals = false, dispatchers = [QSPI, CRYPTOCELL])]
 mod app {
     use bsp::hal::gpiote::InputChannel;
     use bsp::hal::{self, usb::vbus_detect::HardwareVbusDetect};
+    use consts::hid;
+    use consts::relay;
     use core::fmt::Write as _;
+    use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
     use defmt_rtt as _;
+    use dongle_core::{puzzle::Puzzle, AppMode, Counters, Frame};
+    use embassy_futures::select::{select, Either};
     use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
     use embassy_time::Delay;
     use embassy_time::Duration;
     use embassy_usb::class::cdc_acm;
-    use embassy_usb::class::hid;
+    use embassy_usb::class::hid as usb_hid_class;
     use embedded_hal_async::delay::DelayNs as _;
     use embedded_io_async::Write as _;
     use static_cell::StaticCell;
 
     const MSG_CHANNEL_LEN: usize = 8;
+    const HID_RESPONSE_CHANNEL_LEN: usize = 4;
     const ACM_PIPE_LEN: usize = 256;
     const MAX_ACM_PACKET_SIZE: usize = 64;
 
-    /// App mode.
-    #[derive(Debug, defmt::Format, Copy, Clone, PartialEq, Eq)]
-    pub enum AppMode {
-        /// Loopback mode.
-        Loopback,
-        /// Puzzle mode.
-        Puzzle,
-    }
-
-    impl AppMode {
-        /// Toggle between the app modes.
-        pub fn toggle(&mut self) {
-            *self = match self {
-                AppMode::Loopback => AppMode::Puzzle,
-                AppMode::Puzzle => AppMode::Loopback,
-            };
-        }
+    /// How many USB ACM packets we dropped because they didn't fit our buffer
+    static USB_RX_OVERFLOWS: AtomicU32 = AtomicU32::new(0);
+    /// How many USB ACM packets we dropped because the host wouldn't take them
+    static USB_TX_OVERFLOWS: AtomicU32 = AtomicU32::new(0);
+
+    /// How long we wait for room in the ACM_PIPE before dropping log output
+    const ACM_PIPE_TIMEOUT: Duration = Duration::from_millis(50);
+    /// How many bytes of log output didn't fit in the ACM_PIPE in time
+    static ACM_BYTES_DROPPED: AtomicU32 = AtomicU32::new(0);
+    /// How many log lines didn't fit in a [`WriteAsyncPipeAdapter`] buffer
+    static ACM_LINES_TRUNCATED: AtomicU32 = AtomicU32::new(0);
+    /// Set when log output was lost, cleared once the host has been told
+    static ACM_LOSS_PENDING: AtomicBool = AtomicBool::new(false);
+
+    /// How much we log over USB ACM, as a [`hid::Verbosity`]
+    static VERBOSITY: AtomicU8 = AtomicU8::new(hid::Verbosity::Normal as u8);
+
+    /// How much we are logging, right now
+    fn current_verbosity() -> hid::Verbosity {
+        hid::Verbosity::try_from(VERBOSITY.load(Ordering::Relaxed))
+            .unwrap_or(hid::Verbosity::Normal)
     }
 
-    /// Invalid radio packet received, too short to contain an address.
-    #[derive(Debug, defmt::Format)]
-    pub struct InvalidPacketError;
+    /// Shows the [`AppMode`] on the RGB LED
+    struct ModeLeds<'a>(&'a mut bsp::RgbLed);
+
+    impl dongle_core::Leds for ModeLeds<'_> {
+        fn set_rgb(&mut self, red: bool, green: bool, blue: bool) {
+            self.0.red.set(red);
+            self.0.green.set(green);
+            self.0.blue.set(blue);
+        }
+    }
 
     /// Handles commands from host, to application
     type MessageChannel =
@@ -74,20 +99,104 @@ mod app {
     /// The writing end of an [`AcmPipe`]
     type AcmPipeWriter = embassy_sync::pipe::Writer<'static, CriticalSectionRawMutex, ACM_PIPE_LEN>;
 
-    /// The secret message, but encoded.
+    /// Handles HID replies from application, to host
+    type HidResponseChannel = embassy_sync::channel::Channel<
+        CriticalSectionRawMutex,
+        hid::Response,
+        HID_RESPONSE_CHANNEL_LEN,
+    >;
+    /// The receiving end of a [`HidResponseChannel`]
+    type HidResponseReceiver = embassy_sync::channel::Receiver<
+        'static,
+        CriticalSectionRawMutex,
+        hid::Response,
+        HID_RESPONSE_CHANNEL_LEN,
+    >;
+    /// The sending end of a [`HidResponseChannel`]
+    type HidResponseSender = embassy_sync::channel::Sender<
+        'static,
+        CriticalSectionRawMutex,
+        hid::Response,
+        HID_RESPONSE_CHANNEL_LEN,
+    >;
+
+    // The substitution cipher, as `CIPHER_TABLE` and `DECIPHER_TABLE`. Looking
+    // up a byte takes the same (short) time whatever the byte is.
+    include!(concat!(env!("OUT_DIR"), "/cipher_tables.rs"));
+
+    /// The secret message, but encoded, and the cipher.
     ///
-    /// We do this rather than the plaintext -- otherwise `strings $elf` will reveal the answer
-    static ENCODED_MESSAGE: &[u8] =
-        include_bytes!(concat!(env!("OUT_DIR"), "/ENCODED_MESSAGE.txt"));
-
-    /// The plaintext side of the map
-    static PLAIN_LETTERS: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/PLAIN_LETTERS.txt"));
-
-    /// The ciphertext side of the map
-    static CIPHER_LETTERS: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/CIPHER_LETTERS.txt"));
+    /// We keep the encoded message rather than the plaintext -- otherwise
+    /// `strings $elf` will reveal the answer
+    static PUZZLE: Puzzle<'static> = Puzzle::new(
+        include_bytes!(concat!(env!("OUT_DIR"), "/ENCODED_MESSAGE.txt")),
+        &CIPHER_TABLE,
+        &DECIPHER_TABLE,
+    );
+
+    /// Our version, as reported over USB HID
+    const FIRMWARE_VERSION: [u8; 3] = [
+        parse_version_field(env!("CARGO_PKG_VERSION_MAJOR")),
+        parse_version_field(env!("CARGO_PKG_VERSION_MINOR")),
+        parse_version_field(env!("CARGO_PKG_VERSION_PATCH")),
+    ];
+
+    const fn parse_version_field(field: &str) -> u8 {
+        match u8::from_str_radix(field, 10) {
+            Ok(value) => value,
+            Err(_) => panic!("version field does not fit in a u8"),
+        }
+    }
 
-    /// How many address bytes we reflect back
-    const ADDR_BYTES: usize = 6;
+    /// Our version, as USB `bcdDevice` (`0xJJMN` for version `JJ.M.N`)
+    ///
+    /// BCD only has room for 99.9.9, so bigger fields are shown as 99 or 9;
+    /// the HID `GetVersion` request has the exact version.
+    const DEVICE_RELEASE: u16 = {
+        let [major, minor, patch] = FIRMWARE_VERSION;
+        let major = if major > 99 { 99 } else { major };
+        let minor = if minor > 9 { 9 } else { minor };
+        let patch = if patch > 9 { 9 } else { patch };
+        ((major / 10) as u16) << 12
+            | ((major % 10) as u16) << 8
+            | (minor as u16) << 4
+            | patch as u16
+    };
+
+    /// How we were built, as worked out by `build.rs`
+    const BUILD_INFO: hid::BuildInfo = hid::BuildInfo {
+        git_rev: git_rev_bytes(env!("DONGLE_FW_GIT_REV")),
+        timestamp: match u64::from_str_radix(env!("DONGLE_FW_BUILD_TIMESTAMP"), 10) {
+            Ok(value) => value,
+            Err(_) => panic!("bad DONGLE_FW_BUILD_TIMESTAMP"),
+        },
+        flags: {
+            let mut flags = 0;
+            if cfg!(feature = "dk") {
+                flags |= hid::BuildInfo::FLAG_DK;
+            }
+            if let b"true" = env!("DONGLE_FW_GIT_DIRTY").as_bytes() {
+                flags |= hid::BuildInfo::FLAG_DIRTY;
+            }
+            flags
+        },
+        secret_hash: match u32::from_str_radix(env!("DONGLE_FW_SECRET_HASH"), 10) {
+            Ok(value) => value,
+            Err(_) => panic!("bad DONGLE_FW_SECRET_HASH"),
+        },
+    };
+
+    /// Copies as much of the git revision as will fit, padding with zeroes
+    const fn git_rev_bytes(rev: &str) -> [u8; 12] {
+        let rev = rev.as_bytes();
+        let mut bytes = [0u8; 12];
+        let mut i = 0;
+        while i < rev.len() && i < bytes.len() {
+            bytes[i] = rev[i];
+            i += 1;
+        }
+        bytes
+    }
 
     hal::bind_interrupts!(struct Irqs {
         USBD => hal::usb::InterruptHandler<hal::peripherals::USBD>;
@@ -99,49 +208,333 @@ mod app {
     /// An adapter that simplifies asynchronously writing to the USB ACM by buffering writes.
     ///
     /// All writes are buffered until `flush` is called, which performs the async write.
+    ///
+    /// Nothing here waits for the host for long. Text that doesn't fit in the
+    /// buffer is cut off, and text the ACM_PIPE won't take within
+    /// [`ACM_PIPE_TIMEOUT`] is dropped. Both are counted, and the `usb_acm`
+    /// task tells the host about it once it has caught up.
     #[derive(Debug, Clone)]
     struct WriteAsyncPipeAdapter {
         // Intermediate buffer which is required because we can not used async code
         // in the [core::fmt::Write] implementation.
         buffer: heapless::String<256>,
+        /// Some text didn't fit in `buffer` since the last flush
+        truncated: bool,
         acm_pipe_writer: AcmPipeWriter,
     }
 
     impl core::fmt::Write for WriteAsyncPipeAdapter {
         fn write_str(&mut self, s: &str) -> core::fmt::Result {
-            write!(self.buffer, "{}", s)
+            if self.buffer.push_str(s).is_ok() {
+                return Ok(());
+            }
+            // Keep as much as fits, without splitting a character
+            let mut len = self.buffer.capacity() - self.buffer.len();
+            while !s.is_char_boundary(len) {
+                len -= 1;
+            }
+            let _ = self.buffer.push_str(&s[..len]);
+            self.truncated = true;
+            Err(core::fmt::Error)
         }
     }
 
     impl WriteAsyncPipeAdapter {
         /// Flush the buffer to the underlying writer.
         async fn flush(&mut self) {
-            self.acm_pipe_writer.write_all(self.buffer.as_bytes()).await;
+            if core::mem::take(&mut self.truncated) {
+                ACM_LINES_TRUNCATED.fetch_add(1, Ordering::Relaxed);
+                ACM_LOSS_PENDING.store(true, Ordering::Relaxed);
+            }
+            write_to_pipe(&self.acm_pipe_writer, self.buffer.as_bytes()).await;
             self.buffer.clear();
         }
+
+        /// Write binary data, bypassing the buffer.
+        async fn write_bytes(&mut self, data: &[u8]) {
+            write_to_pipe(&self.acm_pipe_writer, data).await;
+        }
     }
 
-    /// Messages we can get over USB HID which the radio task needs to handle
-    #[derive(Debug, defmt::Format, Copy, Clone, PartialEq, Eq)]
+    impl dongle_core::Acm for WriteAsyncPipeAdapter {
+        async fn flush(&mut self) {
+            WriteAsyncPipeAdapter::flush(self).await
+        }
+
+        async fn write_bytes(&mut self, data: &[u8]) {
+            WriteAsyncPipeAdapter::write_bytes(self, data).await
+        }
+
+        fn try_write_bytes(&mut self, data: &[u8]) {
+            let written = self.acm_pipe_writer.try_write(data).unwrap_or(0);
+            if written < data.len() {
+                ACM_BYTES_DROPPED.fetch_add((data.len() - written) as u32, Ordering::Relaxed);
+                ACM_LOSS_PENDING.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Sends `dongle_core`'s frames, using our packet buffer
+    struct RadioTx<'a> {
+        radio: &'a mut hal::radio::ieee802154::Radio<'static>,
+        packet: &'a mut hal::radio::ieee802154::Packet,
+        channel: &'a mut u8,
+    }
+
+    impl dongle_core::Radio for RadioTx<'_> {
+        type Error = hal::radio::Error;
+
+        async fn transmit(&mut self, frame: &Frame) -> Result<(), Self::Error> {
+            self.packet.copy_from_slice(frame);
+            self.radio.try_send(self.packet).await
+        }
+
+        fn channel(&self) -> u8 {
+            *self.channel
+        }
+
+        fn set_channel(&mut self, channel: u8) {
+            self.radio.set_channel(channel);
+            *self.channel = channel;
+        }
+    }
+
+    /// The radio task's state, for `dongle_core`
+    ///
+    /// Other tasks share the mode, so a change is only noted here, for the
+    /// radio task to make once `dongle_core` is done.
+    struct RadioState<'a> {
+        mode: AppMode,
+        new_mode: Option<AppMode>,
+        counters: &'a mut Counters,
+        traffic: &'a mut TrafficGenerator,
+        latency: &'a mut LatencyStats,
+        /// When the frame we are dealing with arrived
+        received_at: embassy_time::Instant,
+    }
+
+    impl dongle_core::Device for RadioState<'_> {
+        fn device_id(&self) -> u64 {
+            u64::from(bsp::deviceid1()) << 32 | u64::from(bsp::deviceid0())
+        }
+
+        fn mode(&self) -> AppMode {
+            self.mode
+        }
+
+        fn set_mode(&mut self, mode: AppMode) {
+            if mode == AppMode::Traffic {
+                self.traffic.restart();
+            }
+            self.mode = mode;
+            self.new_mode = Some(mode);
+        }
+
+        fn verbosity(&self) -> hid::Verbosity {
+            current_verbosity()
+        }
+
+        fn set_verbosity(&mut self, verbosity: hid::Verbosity) {
+            VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+        }
+
+        fn counters(&mut self) -> &mut Counters {
+            self.counters
+        }
+
+        fn stats(&self) -> hid::Stats {
+            hid::Stats {
+                rx_count: self.counters.rx_count,
+                err_count: self.counters.err_count,
+                log_bytes_dropped: ACM_BYTES_DROPPED.load(Ordering::Relaxed),
+                log_lines_truncated: ACM_LINES_TRUNCATED.load(Ordering::Relaxed),
+                traffic_sent: self.traffic.sent,
+                traffic_cca_busy: self.traffic.cca_busy,
+                latency_avg_us: self.latency.average_us(),
+                latency_max_us: self.latency.max_us,
+            }
+        }
+
+        fn version(&self) -> hid::Version {
+            hid::Version {
+                protocol: hid::PROTOCOL_VERSION,
+                firmware: FIRMWARE_VERSION,
+            }
+        }
+
+        fn build_info(&self) -> hid::BuildInfo {
+            BUILD_INFO
+        }
+
+        fn set_traffic(&mut self, config: hid::TrafficConfig) {
+            self.traffic.config = config;
+            self.traffic.restart();
+        }
+
+        fn reply_ready(&mut self) -> Option<u32> {
+            let latency = self.received_at.elapsed();
+            self.latency.record(latency);
+            Some(u32::try_from(latency.as_micros()).unwrap_or(u32::MAX))
+        }
+    }
+
+    /// Write to the ACM_PIPE, dropping whatever doesn't fit in time
+    async fn write_to_pipe(acm_pipe_writer: &AcmPipeWriter, mut data: &[u8]) {
+        while !data.is_empty() {
+            match embassy_time::with_timeout(ACM_PIPE_TIMEOUT, acm_pipe_writer.write(data)).await {
+                Ok(n) => data = &data[n..],
+                Err(_) => {
+                    ACM_BYTES_DROPPED.fetch_add(data.len() as u32, Ordering::Relaxed);
+                    ACM_LOSS_PENDING.store(true, Ordering::Relaxed);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// How long puzzle requests take to answer
+    ///
+    /// Measured from the end of the request to the reply being ready to go
+    /// (before the deliberate delay which gives the client time to listen).
+    #[derive(Default)]
+    struct LatencyStats {
+        count: u32,
+        total_us: u64,
+        max_us: u32,
+    }
+
+    impl LatencyStats {
+        fn record(&mut self, latency: Duration) {
+            let us = u32::try_from(latency.as_micros()).unwrap_or(u32::MAX);
+            self.count = self.count.wrapping_add(1);
+            self.total_us += u64::from(us);
+            self.max_us = self.max_us.max(us);
+        }
+
+        fn average_us(&self) -> u32 {
+            match self.count {
+                0 => 0,
+                n => (self.total_us / u64::from(n)) as u32,
+            }
+        }
+    }
+
+    /// Makes the frames we send in [`AppMode::Traffic`]
+    struct TrafficGenerator {
+        config: hid::TrafficConfig,
+        /// xorshift32 state, never zero
+        rng: u32,
+        /// Fills the frames when they aren't random
+        counter: u8,
+        /// When the next frame is due
+        next_tx: embassy_time::Instant,
+        /// How many frames we have sent
+        sent: u32,
+        /// How many frames we didn't send, because the channel was busy
+        cca_busy: u32,
+    }
+
+    impl TrafficGenerator {
+        fn new(seed: u32) -> TrafficGenerator {
+            TrafficGenerator {
+                config: hid::TrafficConfig::default(),
+                rng: seed | 1,
+                counter: 0,
+                next_tx: embassy_time::Instant::now(),
+                sent: 0,
+                cca_busy: 0,
+            }
+        }
+
+        /// Send the next frame straight away
+        fn restart(&mut self) {
+            self.next_tx = embassy_time::Instant::now();
+        }
+
+        /// How long until the next frame is due
+        fn time_to_next(&self) -> Duration {
+            self.next_tx
+                .saturating_duration_since(embassy_time::Instant::now())
+        }
+
+        /// Put the next frame in `packet`, and work out when the one after is due
+        fn fill(&mut self, packet: &mut hal::radio::ieee802154::Packet) {
+            packet.set_len(self.config.len);
+            for byte in packet.iter_mut() {
+                *byte = if self.config.random_payload {
+                    self.rng ^= self.rng << 13;
+                    self.rng ^= self.rng >> 17;
+                    self.rng ^= self.rng << 5;
+                    self.rng as u8
+                } else {
+                    self.counter
+                };
+            }
+            self.counter = self.counter.wrapping_add(1);
+
+            // If we fell behind, don't try to catch up with a burst
+            let now = embassy_time::Instant::now();
+            self.next_tx += Duration::from_millis(u64::from(self.config.interval_ms));
+            if self.next_tx < now {
+                self.next_tx = now;
+            }
+        }
+    }
+
+    /// Messages we can get over USB which the radio task needs to handle
+    #[derive(Debug, defmt::Format, Clone, PartialEq, Eq)]
     enum Message {
         ChangeChannel(u8),
         WantInfo,
+        HidRequest {
+            seq: u8,
+            request: hid::Request,
+        },
+        /// A frame to transmit, in relay mode
+        RelayTx(heapless::Vec<u8, { relay::MAX_PSDU_LEN }>),
     }
 
     /// A helper for dealing with incoming USB HID events
-    struct HidTransferHandler(MessageChannelSender);
+    struct HidTransferHandler {
+        msg_channel_sender: MessageChannelSender,
+        hid_response_sender: HidResponseSender,
+    }
 
-    impl hid::RequestHandler for HidTransferHandler {
-        // HID requests are used to switch the channel.
+    impl usb_hid_class::RequestHandler for HidTransferHandler {
+        // HID requests carry commands for the radio task, see `consts::hid`.
         fn set_report(
             &mut self,
-            report_id: hid::ReportId,
+            report_id: usb_hid_class::ReportId,
             data: &[u8],
         ) -> embassy_usb::control::OutResponse {
             defmt::debug!("HID report id {} with data {}", report_id, data);
-            // Linux sends 1 byte, Windows sends 64 (with 63 zero bytes)
-            if data.len() == 1 || data.len() == 64 {
-                let _ = self.0.try_send(Message::ChangeChannel(data[0]));
+            let sent = match hid::OutReport::parse(data) {
+                Ok(hid::OutReport::LegacyChangeChannel(channel)) => self
+                    .msg_channel_sender
+                    .try_send(Message::ChangeChannel(channel))
+                    .is_ok(),
+                Ok(hid::OutReport::Request { seq, request }) => {
+                    let queued = self
+                        .msg_channel_sender
+                        .try_send(Message::HidRequest { seq, request })
+                        .is_ok();
+                    if !queued {
+                        let response =
+                            hid::Response::new(request.command() as u8, seq, hid::Status::Busy);
+                        let _ = self.hid_response_sender.try_send(response);
+                    }
+                    queued
+                }
+                Err(e) => {
+                    defmt::warn!("Bad HID report: {}", e);
+                    if let Some(response) = e.response() {
+                        let _ = self.hid_response_sender.try_send(response);
+                    }
+                    false
+                }
+            };
+            if !sent {
+                defmt::warn!("HID report was not handled");
             }
             embassy_usb::control::OutResponse::Accepted
         }
@@ -158,18 +551,22 @@ mod app {
         packet: bsp::hal::radio::ieee802154::Packet,
         /// Used to measure elapsed time
         timer: bsp::Timer,
-        /// How many packets have been received OK?
-        rx_count: u32,
-        /// How many packets have been received with errors?
-        err_count: u32,
+        /// How many packets have been received, OK and with errors
+        counters: Counters,
+        /// Where we panicked before the last reset, if we did
+        last_panic: Option<crate::panic_record::PanicRecord>,
+        /// Makes frames in traffic mode
+        traffic: TrafficGenerator,
+        /// How quickly we answer puzzle requests
+        latency: LatencyStats,
         /// A place to read from the message channel
         msg_channel_receiver: MessageChannelReceiver,
         /// A place to write to the message channel
         msg_channel_sender_acm: MessageChannelSender,
+        /// A place to write replies to HID requests
+        hid_response_sender: HidResponseSender,
         /// The green LED on the board.
         green_led: bsp::Led,
-        /// The RGB LED on the board
-        rgb_led: bsp::RgbLed,
         /// Our raw USB device
         usb_dev: embassy_usb::UsbDevice<'static, hal::usb::Driver<'static, HardwareVbusDetect>>,
         /// Handles doing async writeln! to the USB ACM interface from the radio task.
@@ -188,6 +585,8 @@ mod app {
     #[shared]
     struct MySharedResources {
         mode: AppMode,
+        /// The RGB LED on the board, which shows the mode
+        rgb_led: bsp::RgbLed,
     }
 
     #[init]
@@ -195,6 +594,11 @@ mod app {
         let board = bsp::init().unwrap();
         defmt::println!("-- Radio Puzzle firmware --");
 
+        let last_panic = crate::panic_record::take();
+        if let Some(record) = last_panic {
+            defmt::warn!("Reset after a panic at {}", record);
+        }
+
         #[cfg(feature = "dk")]
         let driver = hal::usb::Driver::new(
             unsafe { hal::Peripherals::steal().USBD },
@@ -210,6 +614,19 @@ mod app {
             embassy_usb::Config::new(consts::USB_VID_DEMO, consts::USB_PID_DONGLE_UNIFIED);
         config.manufacturer = Some("Ferrous Systems");
         config.product = Some("Dongle Loopback");
+        config.device_release = DEVICE_RELEASE;
+
+        // Use the unique device ID as the serial number, so the host can tell
+        // Dongles apart (and give them stable names, like /dev/serial/by-id/...)
+        static SERIAL_NUMBER: StaticCell<heapless::String<16>> = StaticCell::new();
+        let serial_number = SERIAL_NUMBER.init(heapless::String::new());
+        let _ = write!(
+            serial_number,
+            "{:08x}{:08x}",
+            bsp::deviceid1(),
+            bsp::deviceid0()
+        );
+        config.serial_number = Some(serial_number.as_str());
         config.max_packet_size_0 = MAX_ACM_PACKET_SIZE as u8;
         config.composite_with_iads = true;
 
@@ -268,14 +685,19 @@ mod app {
             report_descriptor: desc,
             request_handler: None,
             poll_ms: 100,
-            max_packet_size: 64,
-            hid_subclass: hid::HidSubclass::No,
-            hid_boot_protocol: hid::HidBootProtocol::None,
+            max_packet_size: hid::REPORT_LEN as u16,
+            hid_subclass: usb_hid_class::HidSubclass::No,
+            hid_boot_protocol: usb_hid_class::HidBootProtocol::None,
         };
 
-        let hid_rw = hid::HidReaderWriter::<_, 64, 0>::new(&mut builder, state_hid, config);
-        // We are only interested in reading from the HID interface.
-        let (hid_reader, _) = hid_rw.split();
+        let hid_rw =
+            usb_hid_class::HidReaderWriter::<_, { hid::REPORT_LEN }, { hid::REPORT_LEN }>::new(
+                &mut builder,
+                state_hid,
+                config,
+            );
+        // Requests come in on the reader, replies go out on the writer.
+        let (hid_reader, hid_writer) = hid_rw.split();
 
         // Build the builder.
         let usb_dev = builder.build();
@@ -306,15 +728,22 @@ mod app {
             static_cell::ConstStaticCell::new(embassy_sync::channel::Channel::new());
         static ACM_PIPE: static_cell::ConstStaticCell<AcmPipe> =
             static_cell::ConstStaticCell::new(embassy_sync::pipe::Pipe::new());
+        static HID_RESPONSE_CHANNEL: static_cell::ConstStaticCell<HidResponseChannel> =
+            static_cell::ConstStaticCell::new(embassy_sync::channel::Channel::new());
 
         let msg_channel = MSG_CHANNEL.take();
         let msg_channel_receiver = msg_channel.receiver();
         let msg_channel_sender_acm = msg_channel.sender();
         let msg_channel_sender_hid = msg_channel.sender();
         let (acm_pipe_reader, acm_pipe_writer) = ACM_PIPE.take().split();
+        let hid_response_channel = HID_RESPONSE_CHANNEL.take();
+        let hid_response_receiver = hid_response_channel.receiver();
+        let hid_response_sender = hid_response_channel.sender();
+        let hid_response_sender_hid = hid_response_channel.sender();
 
         let usb_acm_pipe_adapter = WriteAsyncPipeAdapter {
             buffer: heapless::String::new(),
+            truncated: false,
             acm_pipe_writer,
         };
 
@@ -327,24 +756,25 @@ mod app {
 
         let (green_led, mut rgb_led) = board.leds.split();
         // We start with loopback mode, which is green.
-        rgb_led.green_only();
+        let mode = AppMode::Loopback;
+        mode.show(&mut ModeLeds(&mut rgb_led));
 
         defmt::debug!("Building structures...");
-        let shared = MySharedResources {
-            mode: AppMode::Loopback,
-        };
+        let shared = MySharedResources { mode, rgb_led };
         let local = MyLocalResources {
             radio,
             current_channel,
             user_button,
             packet: bsp::hal::radio::ieee802154::Packet::new(),
             timer: board.timer,
-            rx_count: 0,
-            err_count: 0,
+            counters: Counters::default(),
+            last_panic,
+            traffic: TrafficGenerator::new(bsp::deviceid0() ^ bsp::deviceid1()),
+            latency: LatencyStats::default(),
             msg_channel_receiver,
             msg_channel_sender_acm,
+            hid_response_sender,
             green_led,
-            rgb_led,
             usb_dev,
             usb_acm,
             usb_acm_pipe_adapter_radio: usb_acm_pipe_adapter.clone(),
@@ -354,7 +784,14 @@ mod app {
 
         usb_dev::spawn().unwrap();
         usb_acm::spawn().unwrap();
-        let _ = usb_hid::spawn(hid_reader, msg_channel_sender_hid);
+        let _ = usb_hid::spawn(
+            hid_reader,
+            HidTransferHandler {
+                msg_channel_sender: msg_channel_sender_hid,
+                hid_response_sender: hid_response_sender_hid,
+            },
+        );
+        let _ = usb_hid_response::spawn(hid_writer, hid_response_receiver);
         radio::spawn().unwrap();
         button_task::spawn().unwrap();
 
@@ -383,7 +820,7 @@ mod app {
         ctx.local.usb_dev.run().await;
     }
 
-    #[task(local = [user_button, usb_acm_pipe_adapter_button, rgb_led], shared = [mode], priority = 1)]
+    #[task(local = [user_button, usb_acm_pipe_adapter_button], shared = [mode, rgb_led], priority = 1)]
     async fn button_task(mut ctx: button_task::Context) {
         // Wait for the button default state first.
         ctx.local.user_button.wait_for_high().await;
@@ -400,14 +837,9 @@ mod app {
                     mode.toggle();
                     *mode
                 });
-                match mode {
-                    AppMode::Loopback => {
-                        ctx.local.rgb_led.green_only();
-                    }
-                    AppMode::Puzzle => {
-                        ctx.local.rgb_led.blue_only();
-                    }
-                }
+                ctx.shared
+                    .rgb_led
+                    .lock(|rgb_led| mode.show(&mut ModeLeds(rgb_led)));
                 let _ = writeln!(
                     &mut ctx.local.usb_acm_pipe_adapter_button,
                     "Toggled app mode to {:?}",
@@ -427,13 +859,39 @@ mod app {
     async fn usb_hid(
         _ctx: usb_hid::Context,
         // Need to send this by value, because it is consumed by the run method.
-        usb_hid_reader: hid::HidReader<'static, hal::usb::Driver<'static, HardwareVbusDetect>, 64>,
-        msg_channel_tx_hid: MessageChannelSender,
+        usb_hid_reader: usb_hid_class::HidReader<
+            'static,
+            hal::usb::Driver<'static, HardwareVbusDetect>,
+            { hid::REPORT_LEN },
+        >,
+        mut req_handler: HidTransferHandler,
     ) {
-        let mut req_handler = HidTransferHandler(msg_channel_tx_hid);
         usb_hid_reader.run(false, &mut req_handler).await;
     }
 
+    /// Sends replies to USB HID requests back to the host
+    ///
+    /// Takes replies from the HID_RESPONSE_CHANNEL (via `hid_response_receiver`)
+    #[task(priority = 1)]
+    async fn usb_hid_response(
+        _ctx: usb_hid_response::Context,
+        mut usb_hid_writer: usb_hid_class::HidWriter<
+            'static,
+            hal::usb::Driver<'static, HardwareVbusDetect>,
+            { hid::REPORT_LEN },
+        >,
+        hid_response_receiver: HidResponseReceiver,
+    ) {
+        loop {
+            let response = hid_response_receiver.receive().await;
+            defmt::debug!("HID response {}", response);
+            if let Err(e) = usb_hid_writer.write(&response.to_report()).await {
+                // Probably no-one is listening. Drop it.
+                defmt::warn!("Failed to send HID response: {}", e);
+            }
+        }
+    }
+
     /// This task handles the USB ACM interface
     ///
     /// * Puts messages into the MSG_CHANNEL (via `msg_channel_sender_acm`) when
@@ -443,7 +901,7 @@ mod app {
     /// * Deals with being disconnected from the host
     ///
     /// Defers to [`connected_usb_acm`] for most of the work
-    #[task(local = [usb_acm, msg_channel_sender_acm, acm_pipe_reader], priority = 1)]
+    #[task(local = [usb_acm, msg_channel_sender_acm, acm_pipe_reader], shared = [mode], priority = 1)]
     async fn usb_acm(mut ctx: usb_acm::Context) {
         loop {
             // Wait for up to 200 ms for a connection, discard ACM data otherwise.
@@ -476,6 +934,7 @@ mod app {
     /// Called by [`usb_acm`] when we are actually connected
     async fn connected_usb_acm(ctx: &mut usb_acm::Context<'_>) {
         let mut buffer = [0u8; MAX_ACM_PACKET_SIZE];
+        let mut decoder = relay::Decoder::new();
         loop {
             // Poll for a frame for up to 50 milliseconds.
             if let Ok(result) = embassy_time::with_timeout(
@@ -486,7 +945,25 @@ mod app {
             {
                 match result {
                     Ok(n) => {
-                        if n > 0 {
+                        if ctx.shared.mode.lock(|mode| *mode == AppMode::Relay) {
+                            // The host is sending us frames to transmit
+                            for b in &buffer[0..n] {
+                                let Some(frame) = decoder.push(*b) else {
+                                    continue;
+                                };
+                                let msg = match relay::Frame::parse(frame) {
+                                    Some(relay::Frame::Transmit(psdu)) if !psdu.is_empty() => {
+                                        heapless::Vec::from_slice(psdu).ok().map(Message::RelayTx)
+                                    }
+                                    _ => None,
+                                };
+                                if let Some(msg) = msg {
+                                    ctx.local.msg_channel_sender_acm.send(msg).await;
+                                } else {
+                                    defmt::warn!("Ignoring bad relay frame");
+                                }
+                            }
+                        } else if n > 0 {
                             for b in &buffer[0..n] {
                                 if *b == b'?' {
                                     // User pressed "?" in the terminal
@@ -501,7 +978,12 @@ mod app {
                     }
                     Err(e) => match e {
                         embassy_usb::driver::EndpointError::BufferOverflow => {
-                            panic!("unexpected buffer overflow")
+                            // The host sent more than fits in a packet. Drop
+                            // it, and go back to waiting for a connection so
+                            // we start again with a clean endpoint.
+                            USB_RX_OVERFLOWS.fetch_add(1, Ordering::Relaxed);
+                            defmt::warn!("USB ACM read overflowed, dropping packet");
+                            break;
                         }
                         embassy_usb::driver::EndpointError::Disabled => break,
                     },
@@ -522,7 +1004,9 @@ mod app {
                         if let Err(e) = result {
                             match e {
                                 embassy_usb::driver::EndpointError::BufferOverflow => {
-                                    panic!("unexpected buffer overflow")
+                                    // Drop this chunk of log output and carry on
+                                    USB_TX_OVERFLOWS.fetch_add(1, Ordering::Relaxed);
+                                    defmt::warn!("USB ACM write overflowed, dropping packet");
                                 }
                                 embassy_usb::driver::EndpointError::Disabled => break,
                             }
@@ -533,35 +1017,49 @@ mod app {
                     }
                 }
             }
+            // The host has caught up; tell it if it missed anything. In relay
+            // mode it's reading SLIP frames, which a line of text would
+            // garble, so that waits until it leaves relay mode.
+            let relay = ctx.shared.mode.lock(|mode| *mode == AppMode::Relay);
+            if !relay && ACM_LOSS_PENDING.swap(false, Ordering::Relaxed) {
+                let mut line: heapless::String<MAX_ACM_PACKET_SIZE> = heapless::String::new();
+                let _ = write!(
+                    line,
+                    "\n[log lost: {} bytes dropped, {} lines cut short]\n",
+                    ACM_BYTES_DROPPED.load(Ordering::Relaxed),
+                    ACM_LINES_TRUNCATED.load(Ordering::Relaxed)
+                );
+                let _ = embassy_time::with_timeout(
+                    Duration::from_millis(50),
+                    ctx.local.usb_acm.write_packet(line.as_bytes()),
+                )
+                .await;
+            }
         }
     }
 
-    /// Commands we can receive over the radio
-    enum Command {
-        SendSecret,
-        MapChar(u8, u8),
-        Correct,
-        Wrong,
-    }
-
     /// Handles the radio interface
     ///
     /// * Listens for incoming data
     /// * Works out what command it is
     /// * Sends the appropriate response
     /// * Handles messages on the MSG_CHANNEL (via `msg_channel_receiver`)
+    /// * Replies to HID requests on the HID_RESPONSE_CHANNEL (via `hid_response_sender`)
     /// * Sends logs to the ACM_PIPE (via `usb_acm_pipe_adapter`)
     #[task(local = [
         radio,
         current_channel,
         packet,
         timer,
-        rx_count,
-        err_count,
+        counters,
+        last_panic,
+        traffic,
+        latency,
         msg_channel_receiver,
+        hid_response_sender,
         green_led,
         usb_acm_pipe_adapter_radio,
-    ], shared = [mode], priority = 2)]
+    ], shared = [mode, rgb_led], priority = 2)]
     async fn radio(mut ctx: radio::Context) {
         defmt::info!(
             "deviceid={=u32:08x}{=u32:08x} channel={=u8} TxPower=+8dBm app=dongle-fw",
@@ -569,26 +1067,75 @@ mod app {
             bsp::deviceid0(),
             ctx.local.current_channel
         );
-
-        let mut dict: heapless::LinearMap<u8, u8, 128> = heapless::LinearMap::new();
-        for (&plain, &cipher) in PLAIN_LETTERS.iter().zip(CIPHER_LETTERS.iter()) {
-            let _ = dict.insert(plain, cipher);
-        }
+        defmt::info!(
+            "version={=str} rev={=str} dirty={=str} built={=u64} features={=str} secret={=u32:08x}",
+            env!("CARGO_PKG_VERSION"),
+            env!("DONGLE_FW_GIT_REV"),
+            env!("DONGLE_FW_GIT_DIRTY"),
+            BUILD_INFO.timestamp,
+            env!("DONGLE_FW_FEATURES"),
+            BUILD_INFO.secret_hash,
+        );
 
         loop {
             while let Ok(msg) = ctx.local.msg_channel_receiver.try_receive() {
                 match msg {
                     Message::WantInfo => {
+                        let usb_rx_overflows = USB_RX_OVERFLOWS.load(Ordering::Relaxed);
+                        let usb_tx_overflows = USB_TX_OVERFLOWS.load(Ordering::Relaxed);
+                        let log_bytes_dropped = ACM_BYTES_DROPPED.load(Ordering::Relaxed);
+                        let log_lines_truncated = ACM_LINES_TRUNCATED.load(Ordering::Relaxed);
                         defmt::info!(
-                            "rx={=u32}, err={=u32}, ch={=u8}, app=dongle-fw",
-                            ctx.local.rx_count,
-                            ctx.local.err_count,
-                            ctx.local.current_channel
+                            "rx={=u32}, err={=u32}, ch={=u8}, usb_overflows={=u32}/{=u32}, app=dongle-fw",
+                            ctx.local.counters.rx_count,
+                            ctx.local.counters.err_count,
+                            ctx.local.current_channel,
+                            usb_rx_overflows,
+                            usb_tx_overflows
                         );
                         let _ = writeln!(
                             &mut ctx.local.usb_acm_pipe_adapter_radio,
-                            "\nrx={}, err={}, ch={}, app=dongle-fw",
-                            ctx.local.rx_count, ctx.local.err_count, ctx.local.current_channel
+                            "\nrx={}, err={}, ch={}, usb_overflows={}/{}, app=dongle-fw",
+                            ctx.local.counters.rx_count,
+                            ctx.local.counters.err_count,
+                            ctx.local.current_channel,
+                            usb_rx_overflows,
+                            usb_tx_overflows
+                        );
+                        let _ = writeln!(
+                            &mut ctx.local.usb_acm_pipe_adapter_radio,
+                            "log_dropped={}, log_truncated={}, verbosity={:?}",
+                            log_bytes_dropped,
+                            log_lines_truncated,
+                            current_verbosity()
+                        );
+                        let _ = writeln!(
+                            &mut ctx.local.usb_acm_pipe_adapter_radio,
+                            "traffic_sent={}, traffic_cca_busy={}",
+                            ctx.local.traffic.sent, ctx.local.traffic.cca_busy
+                        );
+                        let _ = writeln!(
+                            &mut ctx.local.usb_acm_pipe_adapter_radio,
+                            "latency_avg={}us, latency_max={}us",
+                            ctx.local.latency.average_us(),
+                            ctx.local.latency.max_us
+                        );
+                        if let Some(record) = ctx.local.last_panic {
+                            let _ = writeln!(
+                                &mut ctx.local.usb_acm_pipe_adapter_radio,
+                                "reset after panic at {}:{}: {}",
+                                record.file(),
+                                record.line(),
+                                record.message()
+                            );
+                        }
+                        let _ = writeln!(
+                            &mut ctx.local.usb_acm_pipe_adapter_radio,
+                            "version={}, rev={}, features={}, secret={:08x}",
+                            env!("CARGO_PKG_VERSION"),
+                            BUILD_INFO.git_rev(),
+                            env!("DONGLE_FW_FEATURES"),
+                            BUILD_INFO.secret_hash
                         );
                         ctx.local.usb_acm_pipe_adapter_radio.flush().await;
                     }
@@ -601,26 +1148,91 @@ mod app {
                         );
                         ctx.local.usb_acm_pipe_adapter_radio.flush().await;
 
-                        if !(11..=26).contains(&n) {
+                        if !hid::CHANNELS.contains(&n) {
                             defmt::info!("Bad Channel {}!", n);
                         } else {
                             ctx.local.radio.set_channel(n);
                             *ctx.local.current_channel = n;
                         }
                     }
+                    Message::HidRequest { seq, request } => {
+                        defmt::info!("HID request {} (seq={=u8})", request, seq);
+                        let mut state = RadioState {
+                            mode: ctx.shared.mode.lock(|mode| *mode),
+                            new_mode: None,
+                            counters: ctx.local.counters,
+                            traffic: ctx.local.traffic,
+                            latency: ctx.local.latency,
+                            received_at: embassy_time::Instant::now(),
+                        };
+                        let mut radio = RadioTx {
+                            radio: ctx.local.radio,
+                            packet: ctx.local.packet,
+                            channel: ctx.local.current_channel,
+                        };
+                        let response = dongle_core::handle_hid_request(
+                            seq,
+                            request,
+                            &mut state,
+                            &mut radio,
+                            ctx.local.usb_acm_pipe_adapter_radio,
+                        )
+                        .await;
+                        if let Some(mode) = state.new_mode {
+                            ctx.shared.mode.lock(|m| *m = mode);
+                            ctx.shared
+                                .rgb_led
+                                .lock(|rgb_led| mode.show(&mut ModeLeds(rgb_led)));
+                        }
+                        // If the host is not reading replies, don't let that stall the radio
+                        if ctx.local.hid_response_sender.try_send(response).is_err() {
+                            defmt::warn!("HID response dropped");
+                        }
+                    }
+                    Message::RelayTx(psdu) => {
+                        relay_transmit(&mut ctx, &psdu).await;
+                    }
                 }
             }
 
+            let mode = ctx.shared.mode.lock(|mode| *mode);
+            if mode == AppMode::Traffic && ctx.local.traffic.time_to_next().as_ticks() == 0 {
+                send_traffic(&mut ctx).await;
+            }
+
             defmt::debug!("Waiting for packet..");
 
-            // Poll for a frame for up to 200 milliseconds.
-            if let Ok(result) = embassy_time::with_timeout(
-                Duration::from_millis(200),
-                ctx.local.radio.receive(ctx.local.packet),
-            )
-            .await
-            {
-                match result {
+            let receive = ctx.local.radio.receive(ctx.local.packet);
+            let result = match mode {
+                // Listen until a frame arrives or the host has one for us to
+                // send. A timeout short enough to keep transmissions prompt
+                // would cut long frames off half way through.
+                AppMode::Relay => {
+                    let host_frame = ctx.local.msg_channel_receiver.ready_to_receive();
+                    match select(receive, host_frame).await {
+                        Either::First(result) => Some(result),
+                        Either::Second(()) => None,
+                    }
+                }
+                // Poll for a frame for up to 200 milliseconds, or less if we
+                // have frames to send.
+                AppMode::Traffic => {
+                    let rx_timeout = ctx
+                        .local
+                        .traffic
+                        .time_to_next()
+                        .min(Duration::from_millis(200));
+                    embassy_time::with_timeout(rx_timeout, receive).await.ok()
+                }
+                AppMode::Loopback | AppMode::Puzzle => {
+                    embassy_time::with_timeout(Duration::from_millis(200), receive)
+                        .await
+                        .ok()
+                }
+            };
+            if let Some(result) = result {
+                let received_at = embassy_time::Instant::now();
+                let mut frame = match result {
                     Ok(_) => {
                         #[cfg(not(feature = "dk"))]
                         ctx.local.green_led.toggle();
@@ -629,161 +1241,85 @@ mod app {
                             ctx.local.packet.len(),
                             ctx.local.packet.lqi(),
                         );
-                        *ctx.local.rx_count += 1;
-
-                        let mode = ctx.shared.mode.lock(|mode| *mode);
-                        match mode {
-                            AppMode::Loopback => {
-                                handle_packet_loopback_mode(&mut ctx).await;
-                            }
-                            AppMode::Puzzle => {
-                                handle_packet_puzzle_mode(&mut ctx, &dict).await;
-                            }
-                        }
+                        Some(received_frame(ctx.local.packet))
                     }
                     Err(_e) => {
                         defmt::debug!("RX fail!");
-                        let _ = ctx
-                            .local
-                            .usb_acm_pipe_adapter_radio
-                            .acm_pipe_writer
-                            .write("!".as_bytes())
-                            .await;
-                        *ctx.local.err_count += 1;
+                        None
                     }
-                }
+                };
+                let mut state = RadioState {
+                    mode: ctx.shared.mode.lock(|mode| *mode),
+                    new_mode: None,
+                    counters: ctx.local.counters,
+                    traffic: ctx.local.traffic,
+                    latency: ctx.local.latency,
+                    received_at,
+                };
+                let mut radio = RadioTx {
+                    radio: ctx.local.radio,
+                    packet: ctx.local.packet,
+                    channel: ctx.local.current_channel,
+                };
+                dongle_core::handle_frame(
+                    frame.as_mut(),
+                    &PUZZLE,
+                    &mut state,
+                    &mut radio,
+                    ctx.local.usb_acm_pipe_adapter_radio,
+                    &mut Delay,
+                )
+                .await;
             }
         }
     }
 
-    async fn handle_packet_loopback_mode(ctx: &mut radio::Context<'_>) {
-        // reverse the bytes, so olleh -> hello
-        ctx.local.packet.reverse();
-        // send packet after 5ms (we know the client waits for 10ms and
-        // we want to ensure they are definitely in receive mode by the
-        // time we send this reply)
-        Delay.delay_ms(5).await;
-        if let Err(e) = ctx.local.radio.try_send(ctx.local.packet).await {
-            let _ = writeln!(
-                &mut ctx.local.usb_acm_pipe_adapter_radio,
-                "\nWriting reply packet failed with error {:?}",
-                e
-            );
+    /// Send the next frame in traffic mode
+    async fn send_traffic(ctx: &mut radio::Context<'_>) {
+        use hal::radio::ieee802154::Cca;
+
+        let traffic = &mut *ctx.local.traffic;
+        traffic.fill(ctx.local.packet);
+        if !traffic.config.cca {
+            // The energy level can't go above the highest threshold, so the
+            // channel always looks clear
+            ctx.local.radio.set_cca(Cca::EnergyDetection {
+                ed_threshold: u8::MAX,
+            });
+        }
+        let result = ctx.local.radio.try_send(ctx.local.packet).await;
+        if !traffic.config.cca {
+            ctx.local.radio.set_cca(Cca::CarrierSense);
+        }
+        match result {
+            Ok(()) => traffic.sent += 1,
+            Err(_) => traffic.cca_busy += 1,
         }
-
-        let _ = writeln!(
-            &mut ctx.local.usb_acm_pipe_adapter_radio,
-            "\nReceived {} bytes (LQI={})",
-            ctx.local.packet.len(),
-            ctx.local.packet.lqi(),
-        );
-        ctx.local.usb_acm_pipe_adapter_radio.flush().await;
     }
 
-    async fn handle_packet_puzzle_mode(
-        ctx: &mut radio::Context<'_>,
-        dict: &heapless::LinearMap<u8, u8, 128>,
-    ) {
-        let mut reply = true;
-        match handle_packet(ctx.local.packet, &dict) {
-            Err(InvalidPacketError) => {
-                let _ = writeln!(
-                    &mut ctx.local.usb_acm_pipe_adapter_radio,
-                    "RX invalid packet"
-                );
-                // not enough bytes - send nothing back
-                reply = false;
-            }
-            Ok(Command::SendSecret) => {
-                ctx.local
-                    .packet
-                    .set_len(ENCODED_MESSAGE.len() as u8 + ADDR_BYTES as u8);
-                for (src, dest) in ENCODED_MESSAGE
-                    .iter()
-                    .zip(&mut ctx.local.packet[ADDR_BYTES..])
-                {
-                    *dest = *src;
-                }
-                let _ = writeln!(&mut ctx.local.usb_acm_pipe_adapter_radio, "TX Secret");
-            }
-            Ok(Command::MapChar(plain, cipher)) => {
-                ctx.local.packet.set_len(1 + ADDR_BYTES as u8);
-                ctx.local.packet[ADDR_BYTES] = cipher;
-                let _ = writeln!(
-                    &mut ctx.local.usb_acm_pipe_adapter_radio,
-                    "TX Map({plain}) => {cipher}"
-                );
-            }
-            Ok(Command::Correct) => {
-                let message = b"correct";
-                ctx.local
-                    .packet
-                    .set_len(message.len() as u8 + ADDR_BYTES as u8);
-                for (src, dest) in message.iter().zip(&mut ctx.local.packet[ADDR_BYTES..]) {
-                    *dest = *src;
-                }
-                let _ = writeln!(&mut ctx.local.usb_acm_pipe_adapter_radio, "TX Correct");
-            }
-            Ok(Command::Wrong) => {
-                let message = b"incorrect";
-                ctx.local
-                    .packet
-                    .set_len(message.len() as u8 + ADDR_BYTES as u8);
-                for (src, dest) in message.iter().zip(&mut ctx.local.packet[ADDR_BYTES..]) {
-                    *dest = *src;
-                }
-                let _ = writeln!(&mut ctx.local.usb_acm_pipe_adapter_radio, "TX Incorrect");
+    /// Transmit a frame from the host, and tell it how that went
+    async fn relay_transmit(ctx: &mut radio::Context<'_>, psdu: &[u8]) {
+        ctx.local.packet.copy_from_slice(psdu);
+        let ok = match ctx.local.radio.try_send(ctx.local.packet).await {
+            Ok(()) => true,
+            Err(_) => {
+                defmt::warn!("Relay transmit failed, channel busy");
+                false
             }
+        };
+        let mut encoded = [0u8; relay::MAX_ENCODED_LEN];
+        if let Some(len) = (relay::Frame::Sent { ok }).encode(&mut encoded) {
+            ctx.local
+                .usb_acm_pipe_adapter_radio
+                .write_bytes(&encoded[..len])
+                .await;
         }
+    }
 
-        // send packet after 500 us (we know the client waits for 10ms and
-        // we want to ensure they are definitely in receive mode by the
-        // time we send this reply)
-        if reply {
-            Delay.delay_us(500).await;
-            if let Err(e) = ctx.local.radio.try_send(ctx.local.packet).await {
-                let _ = writeln!(
-                    &mut ctx.local.usb_acm_pipe_adapter_radio,
-                    "\nWriting reply packet failed with error {:?}",
-                    e
-                );
-            }
-        }
-        ctx.local.usb_acm_pipe_adapter_radio.flush().await;
-    }
-
-    fn handle_packet(
-        packet: &mut hal::radio::ieee802154::Packet,
-        dict: &heapless::LinearMap<u8, u8, 128>,
-    ) -> Result<Command, InvalidPacketError> {
-        if (packet.len() as usize) < ADDR_BYTES {
-            return Err(InvalidPacketError);
-        }
-        // Unwrap is okay, we did a length check above.
-        let payload = packet.get_mut(ADDR_BYTES..).unwrap();
-        if payload.is_empty() {
-            Ok(Command::SendSecret)
-        } else if payload.len() == 1 {
-            // They give us plaintext, we give them ciphertext
-            let plain = payload[0];
-            let cipher = *dict.get(&plain).unwrap_or(&0);
-            Ok(Command::MapChar(plain, cipher))
-        } else {
-            // They give us plaintext, we tell them if it is correct
-            // Encrypt every byte of plaintext they give us
-            for slot in payload.iter_mut() {
-                if let Some(c) = dict.get(slot) {
-                    *slot = *c;
-                } else {
-                    *slot = 0;
-                }
-            }
-            if &payload[..] == ENCODED_MESSAGE {
-                Ok(Command::Correct)
-            } else {
-                Ok(Command::Wrong)
-            }
-        }
+    /// Copy the packet we just received, for `dongle_core`
+    fn received_frame(packet: &hal::radio::ieee802154::Packet) -> Frame {
+        // A packet never holds more than a frame
+        Frame::from_received(packet, packet.lqi()).unwrap_or_default()
     }
 }
 
@@ -801,7 +1337,13 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
     } else {
         defmt::error!("Panic at unknown location");
     }
-    loop {
-        core::hint::spin_loop();
-    }
+    panic_record::store(info);
+
+    // Leave the red LED on long enough to be seen, then start again. We'll
+    // report where and why we panicked when we come back up.
+    cortex_m::asm::delay(PANIC_RESET_DELAY_CYCLES);
+    cortex_m::peripheral::SCB::sys_reset()
 }
+
+/// About two seconds at 64 MHz
+const PANIC_RESET_DELAY_CYCLES: u32 = 128_000_000;
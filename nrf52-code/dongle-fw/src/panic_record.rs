@@ -0,0 +1,146 @@
+//! Remembers where and why we panicked, across a reset
+//!
+//! The record lives in the `.uninit` section, which the start-up code does not
+//! zero, so it survives a soft reset (but not a power cycle). The panic handler
+//! fills it in and resets the chip; `init` takes it out again on the next boot.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+
+/// Marks the record as holding a real panic location, rather than random RAM
+const MAGIC: u32 = 0x5041_4E43;
+
+/// How much of the file name we keep
+const MAX_FILE_LEN: usize = 48;
+
+/// How much of the panic message we keep
+const MAX_MESSAGE_LEN: usize = 80;
+
+/// Where we panicked, and why
+#[derive(Copy, Clone)]
+pub struct PanicRecord {
+    magic: u32,
+    line: u32,
+    file_len: u32,
+    file: [u8; MAX_FILE_LEN],
+    message_len: u32,
+    message: [u8; MAX_MESSAGE_LEN],
+}
+
+impl PanicRecord {
+    /// The source file that panicked, or at least the end of its path
+    pub fn file(&self) -> &str {
+        let len = (self.file_len as usize).min(MAX_FILE_LEN);
+        core::str::from_utf8(&self.file[..len]).unwrap_or("?")
+    }
+
+    /// The line that panicked
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// What the panic said, or at least the start of it
+    pub fn message(&self) -> &str {
+        let len = (self.message_len as usize).min(MAX_MESSAGE_LEN);
+        core::str::from_utf8(&self.message[..len]).unwrap_or("?")
+    }
+}
+
+impl defmt::Format for PanicRecord {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(
+            fmt,
+            "{=str}:{=u32}: {=str}",
+            self.file(),
+            self.line,
+            self.message()
+        )
+    }
+}
+
+/// Keeps as many whole characters as fit, and quietly drops the rest
+struct Truncating<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    full: bool,
+}
+
+impl core::fmt::Write for Truncating<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            let end = self.len + c.len_utf8();
+            if self.full || end > self.buf.len() {
+                self.full = true;
+                break;
+            }
+            c.encode_utf8(&mut self.buf[self.len..end]);
+            self.len = end;
+        }
+        Ok(())
+    }
+}
+
+/// Holds the record in RAM that is not initialised on boot
+struct Slot(UnsafeCell<MaybeUninit<PanicRecord>>);
+
+// Safety: we are single core, and the slot is only touched by `take` (from
+// `init`, with interrupts off) and by `store` (from the panic handler, with
+// interrupts off).
+unsafe impl Sync for Slot {}
+
+#[link_section = ".uninit.PANIC_RECORD"]
+static PANIC_RECORD: Slot = Slot(UnsafeCell::new(MaybeUninit::uninit()));
+
+/// Record where and why we panicked, ready for the next boot
+///
+/// Only call this with interrupts disabled.
+pub fn store(info: &core::panic::PanicInfo) {
+    let (file, line) = info
+        .location()
+        .map_or(("unknown", 0), |l| (l.file(), l.line()));
+    // Keep the end of the path - it's the interesting part - without
+    // splitting a character in two
+    let mut start = file.len().saturating_sub(MAX_FILE_LEN);
+    while !file.is_char_boundary(start) {
+        start += 1;
+    }
+    let file = file[start..].as_bytes();
+    let mut record = PanicRecord {
+        magic: MAGIC,
+        line,
+        file_len: file.len() as u32,
+        file: [0; MAX_FILE_LEN],
+        message_len: 0,
+        message: [0; MAX_MESSAGE_LEN],
+    };
+    record.file[..file.len()].copy_from_slice(file);
+    let mut message = Truncating {
+        buf: &mut record.message,
+        len: 0,
+        full: false,
+    };
+    let _ = core::fmt::write(&mut message, format_args!("{}", info.message()));
+    record.message_len = message.len as u32;
+    // Safety: see `Slot`. Volatile so the write isn't optimised away before
+    // we reset.
+    unsafe {
+        core::ptr::write_volatile(PANIC_RECORD.0.get(), MaybeUninit::new(record));
+    }
+}
+
+/// Take the record left by the last panic, if there was one
+///
+/// Only call this with interrupts disabled.
+pub fn take() -> Option<PanicRecord> {
+    // Safety: see `Slot`. We only read the `magic` field until we know the
+    // record was written by `store`, and every bit pattern is a valid `u32`.
+    unsafe {
+        let slot = PANIC_RECORD.0.get() as *mut PanicRecord;
+        let magic = core::ptr::addr_of_mut!((*slot).magic);
+        if core::ptr::read_volatile(magic) != MAGIC {
+            return None;
+        }
+        core::ptr::write_volatile(magic, 0);
+        Some(core::ptr::read_volatile(slot))
+    }
+}
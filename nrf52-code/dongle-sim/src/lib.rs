@@ -0,0 +1,459 @@
+//! Runs the `dongle-fw` application logic on a host computer
+//!
+//! What the Dongle does with the frames it receives, and with USB HID
+//! requests, lives in `dongle-core`. Here we give that code a simulated radio
+//! (see [`medium`]) and send its log output somewhere other than a USB serial
+//! port, so the puzzle protocol can be exercised end to end without any
+//! hardware. The simulator doesn't send frames of its own in traffic mode.
+//!
+//! ```no_run
+//! use dongle_sim::{medium::Bus, Dongle, Secret};
+//!
+//! let bus = Bus::new();
+//! let secret = Secret::new("hello", 1).unwrap();
+//! let dongle = Dongle::new(bus.link(), secret, std::io::sink()).spawn();
+//! // ... talk to it with `bus.link()` ...
+//! dongle.stop().unwrap();
+//! ```
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use consts::hid;
+use consts::puzzle::{self, PLAIN_LETTERS};
+use dongle_core::{puzzle::Puzzle, AppMode, Counters, Device, Frame};
+
+pub mod medium;
+
+use medium::Link;
+
+/// The channel the Dongle starts on
+pub const DEFAULT_CHANNEL: u8 = 20;
+
+/// How long we wait for a frame, before checking whether we should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The device ID we report, having no chip to ask: "SIMULATE" in ASCII
+pub const DEVICE_ID: u64 = u64::from_be_bytes(*b"SIMULATE");
+
+/// The secret, encoded, and the cipher that encoded it
+#[derive(Clone)]
+pub struct Secret {
+    encoded: Vec<u8>,
+    /// 32-bit FNV-1a hash of the plaintext, as `dongle-fw` reports it
+    hash: u32,
+    cipher_table: [u8; 256],
+    decipher_table: [u8; 256],
+}
+
+impl Secret {
+    /// Encode a secret, shuffling the cipher with `seed`
+    ///
+    /// This is the same cipher as `dongle-fw` gets when built with
+    /// `HIDDEN_MESSAGE=<plaintext> PUZZLE_SEED=<seed>`, or with just
+    /// `HIDDEN_MESSAGE` when `seed` is [`puzzle::default_seed`] of it.
+    pub fn new(plaintext: &str, seed: u64) -> Result<Secret, String> {
+        if let Some(bad) = plaintext.chars().find(|c| !PLAIN_LETTERS.contains(*c)) {
+            return Err(format!("{bad:?} is not one of {PLAIN_LETTERS:?}"));
+        }
+        if plaintext.is_empty() || plaintext.len() > puzzle::MAX_MESSAGE_LEN {
+            return Err(format!(
+                "the secret must be 1 to {} bytes long",
+                puzzle::MAX_MESSAGE_LEN
+            ));
+        }
+        let cipher_letters = puzzle::cipher_letters(seed);
+
+        let mut cipher_table = [0u8; 256];
+        let mut decipher_table = [0u8; 256];
+        for (plain, &cipher) in PLAIN_LETTERS.bytes().zip(&cipher_letters) {
+            cipher_table[usize::from(plain)] = cipher;
+            decipher_table[usize::from(cipher)] = plain;
+        }
+        let encoded = plaintext
+            .bytes()
+            .map(|b| cipher_table[usize::from(b)])
+            .collect();
+        Ok(Secret {
+            encoded,
+            hash: fnv1a(plaintext.as_bytes()),
+            cipher_table,
+            decipher_table,
+        })
+    }
+
+    /// The encoded secret
+    pub fn encoded(&self) -> &[u8] {
+        &self.encoded
+    }
+
+    fn puzzle(&self) -> Puzzle<'_> {
+        Puzzle::new(&self.encoded, &self.cipher_table, &self.decipher_table)
+    }
+}
+
+/// A simulated Dongle
+pub struct Dongle<L, W> {
+    link: L,
+    secret: Secret,
+    acm: SimAcm<W>,
+    leds: SimLeds,
+    /// Which mode we are in
+    pub mode: AppMode,
+    /// Which channel we listen on
+    pub channel: u8,
+    /// How much we log
+    pub verbosity: hid::Verbosity,
+    /// How many frames we have received
+    pub counters: Counters,
+}
+
+impl<L, W> Dongle<L, W>
+where
+    L: Link,
+    W: io::Write + Send,
+{
+    /// Create a Dongle in puzzle mode, logging to `acm`
+    ///
+    /// The real Dongle starts in loopback mode, but this is almost always
+    /// what you want from the simulator.
+    pub fn new(link: L, secret: Secret, acm: W) -> Dongle<L, W> {
+        let mut dongle = Dongle {
+            link,
+            secret,
+            acm: SimAcm {
+                buffer: String::new(),
+                writer: acm,
+            },
+            leds: SimLeds::default(),
+            mode: AppMode::Puzzle,
+            channel: DEFAULT_CHANNEL,
+            verbosity: hid::Verbosity::Normal,
+            counters: Counters::default(),
+        };
+        dongle.set_mode(AppMode::Puzzle);
+        dongle
+    }
+
+    /// Change mode, as the button or a HID request would
+    pub fn set_mode(&mut self, mode: AppMode) {
+        self.parts().device.set_mode(mode);
+    }
+
+    /// What the RGB LED is showing: red, green and blue
+    pub fn rgb_led(&self) -> (bool, bool, bool) {
+        self.leds.rgb
+    }
+
+    /// Carry out a USB HID request, as the firmware would
+    pub fn hid_request(&mut self, seq: u8, request: hid::Request) -> hid::Response {
+        let Parts {
+            mut device,
+            mut radio,
+            acm,
+            ..
+        } = self.parts();
+        block_on(dongle_core::handle_hid_request(
+            seq,
+            request,
+            &mut device,
+            &mut radio,
+            acm,
+        ))
+    }
+
+    /// Wait up to `timeout` for a frame, and deal with it
+    ///
+    /// Returns `false` if nothing arrived.
+    pub fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        let Some(received) = self.link.receive(self.channel, timeout)? else {
+            return Ok(false);
+        };
+        // The real radio never receives anything longer, so ignore it
+        let Some(mut frame) = Frame::from_received(&received.psdu, received.lqi) else {
+            return Ok(true);
+        };
+        let Parts {
+            mut device,
+            mut radio,
+            acm,
+            secret,
+        } = self.parts();
+        block_on(dongle_core::handle_frame(
+            received.crc_ok.then_some(&mut frame),
+            &secret.puzzle(),
+            &mut device,
+            &mut radio,
+            acm,
+            &mut StdDelay,
+        ));
+        Ok(true)
+    }
+
+    /// Borrow the pieces of the Dongle that `dongle_core` works with
+    fn parts(&mut self) -> Parts<'_, L, W> {
+        Parts {
+            device: SimDevice {
+                mode: &mut self.mode,
+                verbosity: &mut self.verbosity,
+                counters: &mut self.counters,
+                leds: &mut self.leds,
+                secret_hash: self.secret.hash,
+            },
+            radio: SimRadio {
+                link: &mut self.link,
+                channel: &mut self.channel,
+            },
+            acm: &mut self.acm,
+            secret: &self.secret,
+        }
+    }
+
+    /// Handle frames until `stop` is set
+    pub fn run(&mut self, stop: &AtomicBool) -> io::Result<()> {
+        while !stop.load(Ordering::Relaxed) {
+            self.poll(POLL_INTERVAL)?;
+        }
+        Ok(())
+    }
+}
+
+impl<L, W> Dongle<L, W>
+where
+    L: Link + 'static,
+    W: io::Write + Send + 'static,
+{
+    /// Run the Dongle on its own thread
+    pub fn spawn(mut self) -> Running<L, W> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = std::thread::spawn({
+            let stop = stop.clone();
+            move || {
+                self.run(&stop)?;
+                Ok(self)
+            }
+        });
+        Running { stop, thread }
+    }
+}
+
+/// A Dongle running on its own thread
+pub struct Running<L, W> {
+    stop: Arc<AtomicBool>,
+    thread: std::thread::JoinHandle<io::Result<Dongle<L, W>>>,
+}
+
+impl<L, W> Running<L, W> {
+    /// Stop the Dongle, and get it back
+    pub fn stop(self) -> io::Result<Dongle<L, W>> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.join().expect("simulated Dongle panicked")
+    }
+}
+
+/// A [`Dongle`], borrowed as the pieces `dongle_core` works with
+struct Parts<'a, L, W> {
+    device: SimDevice<'a>,
+    radio: SimRadio<'a, L>,
+    acm: &'a mut SimAcm<W>,
+    secret: &'a Secret,
+}
+
+/// The state of a [`Dongle`], for `dongle_core`
+struct SimDevice<'a> {
+    mode: &'a mut AppMode,
+    verbosity: &'a mut hid::Verbosity,
+    counters: &'a mut Counters,
+    leds: &'a mut SimLeds,
+    secret_hash: u32,
+}
+
+impl Device for SimDevice<'_> {
+    fn device_id(&self) -> u64 {
+        DEVICE_ID
+    }
+
+    fn mode(&self) -> AppMode {
+        *self.mode
+    }
+
+    fn set_mode(&mut self, mode: AppMode) {
+        *self.mode = mode;
+        mode.show(self.leds);
+    }
+
+    fn verbosity(&self) -> hid::Verbosity {
+        *self.verbosity
+    }
+
+    fn set_verbosity(&mut self, verbosity: hid::Verbosity) {
+        *self.verbosity = verbosity;
+    }
+
+    fn counters(&mut self) -> &mut Counters {
+        self.counters
+    }
+
+    fn stats(&self) -> hid::Stats {
+        // We never drop log output, send traffic or time our replies
+        hid::Stats {
+            rx_count: self.counters.rx_count,
+            err_count: self.counters.err_count,
+            log_bytes_dropped: 0,
+            log_lines_truncated: 0,
+            traffic_sent: 0,
+            traffic_cca_busy: 0,
+            latency_avg_us: 0,
+            latency_max_us: 0,
+        }
+    }
+
+    fn version(&self) -> hid::Version {
+        let field = |value: &str| value.parse().unwrap_or(u8::MAX);
+        hid::Version {
+            protocol: hid::PROTOCOL_VERSION,
+            firmware: [
+                field(env!("CARGO_PKG_VERSION_MAJOR")),
+                field(env!("CARGO_PKG_VERSION_MINOR")),
+                field(env!("CARGO_PKG_VERSION_PATCH")),
+            ],
+        }
+    }
+
+    fn build_info(&self) -> hid::BuildInfo {
+        // There's no firmware build to describe, beyond the secret
+        hid::BuildInfo {
+            git_rev: [0; 12],
+            timestamp: 0,
+            flags: 0,
+            secret_hash: self.secret_hash,
+        }
+    }
+
+    fn set_traffic(&mut self, _config: hid::TrafficConfig) {
+        // We don't send traffic, so there's nothing to configure
+    }
+}
+
+/// Sends frames for `dongle_core`
+struct SimRadio<'a, L> {
+    link: &'a mut L,
+    channel: &'a mut u8,
+}
+
+impl<L: Link> dongle_core::Radio for SimRadio<'_, L> {
+    type Error = io::Error;
+
+    async fn transmit(&mut self, frame: &Frame) -> Result<(), io::Error> {
+        self.link.transmit(*self.channel, frame)
+    }
+
+    fn channel(&self) -> u8 {
+        *self.channel
+    }
+
+    fn set_channel(&mut self, channel: u8) {
+        *self.channel = channel;
+    }
+}
+
+/// Buffers log text until it is flushed, like the firmware's ACM pipe
+struct SimAcm<W> {
+    buffer: String,
+    writer: W,
+}
+
+impl<W> core::fmt::Write for SimAcm<W> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.buffer.push_str(s);
+        Ok(())
+    }
+}
+
+impl<W: io::Write> dongle_core::Acm for SimAcm<W> {
+    async fn flush(&mut self) {
+        // Like the real thing, we drop logs that can't be written
+        let _ = self.writer.write_all(self.buffer.as_bytes());
+        let _ = self.writer.flush();
+        self.buffer.clear();
+    }
+
+    async fn write_bytes(&mut self, data: &[u8]) {
+        self.try_write_bytes(data);
+    }
+
+    fn try_write_bytes(&mut self, data: &[u8]) {
+        let _ = self.writer.write_all(data);
+        let _ = self.writer.flush();
+    }
+}
+
+/// Remembers what the RGB LED is showing
+#[derive(Default)]
+struct SimLeds {
+    rgb: (bool, bool, bool),
+}
+
+impl dongle_core::Leds for SimLeds {
+    fn set_rgb(&mut self, red: bool, green: bool, blue: bool) {
+        self.rgb = (red, green, blue);
+    }
+}
+
+/// Delays by sleeping the thread
+struct StdDelay;
+
+impl embedded_hal_async::delay::DelayNs for StdDelay {
+    async fn delay_ns(&mut self, ns: u32) {
+        std::thread::sleep(Duration::from_nanos(u64::from(ns)));
+    }
+}
+
+/// 32-bit FNV-1a, as `dongle-fw`'s build script hashes the secret
+fn fnv1a(data: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for byte in data {
+        hash ^= u32::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+/// Run a future which never waits for anything
+///
+/// Everything we give `dongle_core` blocks instead of returning `Pending`, so
+/// this never has to wait either.
+fn block_on<F: core::future::Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let mut context = core::task::Context::from_waker(core::task::Waker::noop());
+    loop {
+        if let core::task::Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        std::thread::yield_now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_is_checked() {
+        assert!(Secret::new("hello", 0).is_ok());
+        assert!(Secret::new("", 0).is_err());
+        assert!(Secret::new("tab\tstop", 0).is_err());
+        assert!(Secret::new(&"a".repeat(puzzle::MAX_MESSAGE_LEN + 1), 0).is_err());
+    }
+
+    #[test]
+    fn same_seed_same_cipher() {
+        let a = Secret::new("hello world", 42).unwrap();
+        let b = Secret::new("hello world", 42).unwrap();
+        let c = Secret::new("hello world", 43).unwrap();
+        assert_eq!(a.encoded(), b.encoded());
+        assert_ne!(a.encoded(), c.encoded());
+    }
+}
@@ -0,0 +1,131 @@
+//! A simulated Dongle, on the UDP multicast radio medium
+//!
+//! The log output that would go to the Dongle's USB serial port goes to a
+//! pseudo-terminal instead, which you can open with any serial terminal.
+
+use std::io::Write;
+use std::sync::atomic::AtomicBool;
+
+use consts::hid;
+use dongle_core::AppMode;
+use dongle_sim::{medium::UdpLink, Dongle, Secret};
+
+const USAGE: &str = "dongle-sim
+Runs the Dongle's loopback and puzzle modes on this computer
+
+USAGE:
+    dongle-sim [OPTIONS]
+
+OPTIONS:
+    --message TEXT      the secret (default: the same as dongle-fw)
+    --seed NUMBER       seeds the cipher, like PUZZLE_SEED for dongle-fw (default: a hash
+                        of the message, as for dongle-fw)
+    --channel NUMBER    the radio channel, 11..=26 (default: 20)
+    --mode MODE         `loopback` or `puzzle` (default: puzzle)
+    --verbose           log every frame in full
+    --stdout            log to stdout, instead of a pseudo-terminal
+";
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {e}\n\n{USAGE}");
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), String> {
+    let mut message = String::from("This is an example message");
+    let mut seed = None;
+    let mut channel = dongle_sim::DEFAULT_CHANNEL;
+    let mut mode = AppMode::Puzzle;
+    let mut verbosity = hid::Verbosity::Normal;
+    let mut stdout = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--message" => message = value()?,
+            "--seed" => seed = Some(value()?.parse().map_err(|_| "bad seed")?),
+            "--channel" => {
+                channel = value()?.parse().map_err(|_| "bad channel")?;
+                if !hid::CHANNELS.contains(&channel) {
+                    return Err(format!("channel {channel} is not in 11..=26"));
+                }
+            }
+            "--mode" => {
+                mode = match value()?.as_str() {
+                    "loopback" => AppMode::Loopback,
+                    "puzzle" => AppMode::Puzzle,
+                    other => return Err(format!("unknown mode {other:?}")),
+                }
+            }
+            "--verbose" => verbosity = hid::Verbosity::Verbose,
+            "--stdout" => stdout = true,
+            "--help" | "-h" => {
+                print!("{USAGE}");
+                return Ok(());
+            }
+            other => return Err(format!("unknown option {other:?}")),
+        }
+    }
+
+    let seed = seed.unwrap_or_else(|| consts::puzzle::default_seed(&message));
+    let secret = Secret::new(&message, seed)?;
+    let link = UdpLink::new().map_err(|e| format!("could not join the radio medium: {e}"))?;
+    let acm: Box<dyn Write + Send> = if stdout {
+        Box::new(std::io::stdout())
+    } else {
+        let (path, pty) = open_pty().map_err(|e| format!("could not open a pty: {e}"))?;
+        println!("Serial port: {path}");
+        Box::new(pty)
+    };
+
+    println!("Listening on channel {channel}, in {mode:?} mode");
+    let mut dongle = Dongle::new(link, secret, acm);
+    dongle.set_mode(mode);
+    dongle.channel = channel;
+    dongle.verbosity = verbosity;
+    dongle
+        .run(&AtomicBool::new(false))
+        .map_err(|e| format!("radio failed: {e}"))
+}
+
+/// Open a pseudo-terminal, returning the path of the terminal end and the
+/// controlling end, which we write to
+#[cfg(unix)]
+fn open_pty() -> std::io::Result<(String, std::fs::File)> {
+    use std::os::fd::FromRawFd;
+
+    let mut controller = 0;
+    let mut terminal = 0;
+    let mut name = [0 as libc::c_char; 128];
+    // Safety: `name` is long enough for any pty name, and we check the result
+    // before using the file descriptors.
+    let result = unsafe {
+        libc::openpty(
+            &mut controller,
+            &mut terminal,
+            name.as_mut_ptr(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // Safety: `openpty` wrote a nul-terminated string into `name`
+    let path = unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) };
+    // We never close `terminal`, so the pty stays usable between clients
+    let _ = terminal;
+    // Safety: `controller` is a file descriptor we own
+    let controller = unsafe { std::fs::File::from_raw_fd(controller) };
+    Ok((path.to_string_lossy().into_owned(), controller))
+}
+
+#[cfg(not(unix))]
+fn open_pty() -> std::io::Result<(String, std::fs::File)> {
+    Err(std::io::Error::other(
+        "pseudo-terminals need a Unix; use --stdout",
+    ))
+}
@@ -0,0 +1,433 @@
+//! Simulated radio media
+//!
+//! A [`Link`] is one radio's connection to the medium. Every frame sent by
+//! one link is heard by all the others which are listening on the same
+//! channel, but not by the sender.
+//!
+//! Wrap a link in [`Impaired`] to make it lose frames, delay them or corrupt
+//! them, like a real radio in a busy room.
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::prelude::*;
+use rand::rngs::Xoshiro256PlusPlus;
+
+/// The LQI we report for every frame
+///
+/// Nothing is ever far away in a simulation.
+pub const LQI: u8 = 255;
+
+/// A frame, as received
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Received {
+    /// The frame, without the CRC
+    pub psdu: Vec<u8>,
+    /// Link Quality Indicator
+    pub lqi: u8,
+    /// Whether the CRC was correct
+    pub crc_ok: bool,
+}
+
+/// One radio's connection to a simulated medium
+pub trait Link: Send {
+    /// Send a frame on a channel
+    fn transmit(&mut self, channel: u8, psdu: &[u8]) -> io::Result<()>;
+
+    /// Wait up to `timeout` for a frame on a channel
+    fn receive(&mut self, channel: u8, timeout: Duration) -> io::Result<Option<Received>>;
+}
+
+/// How many frames wait for a [`BusLink`] that isn't listening right now
+const QUEUE_LEN: usize = 8;
+
+/// A medium shared by the radios in one process
+///
+/// Clone it to share it. Frames sent while a link isn't receiving wait in a
+/// short queue, so a reply isn't lost just because the thread that asked for
+/// it hasn't started waiting yet.
+#[derive(Clone, Default)]
+pub struct Bus {
+    shared: Arc<(Mutex<Vec<LinkQueue>>, Condvar)>,
+}
+
+/// Frames waiting for one link
+#[derive(Default)]
+struct LinkQueue {
+    frames: VecDeque<(u8, Vec<u8>)>,
+}
+
+impl Bus {
+    /// Create an empty medium
+    pub fn new() -> Bus {
+        Bus::default()
+    }
+
+    /// Connect another radio
+    pub fn link(&self) -> BusLink {
+        let (queues, _) = &*self.shared;
+        let mut queues = queues.lock().unwrap();
+        queues.push(LinkQueue::default());
+        BusLink {
+            bus: self.clone(),
+            id: queues.len() - 1,
+        }
+    }
+}
+
+/// A radio on a [`Bus`]
+pub struct BusLink {
+    bus: Bus,
+    id: usize,
+}
+
+impl Link for BusLink {
+    fn transmit(&mut self, channel: u8, psdu: &[u8]) -> io::Result<()> {
+        let (queues, frame_sent) = &*self.bus.shared;
+        let mut queues = queues.lock().unwrap();
+        for (id, queue) in queues.iter_mut().enumerate() {
+            if id == self.id {
+                continue;
+            }
+            if queue.frames.len() == QUEUE_LEN {
+                queue.frames.pop_front();
+            }
+            queue.frames.push_back((channel, psdu.to_vec()));
+        }
+        frame_sent.notify_all();
+        Ok(())
+    }
+
+    fn receive(&mut self, channel: u8, timeout: Duration) -> io::Result<Option<Received>> {
+        let deadline = Instant::now() + timeout;
+        let (queues, frame_sent) = &*self.bus.shared;
+        let mut queues = queues.lock().unwrap();
+        loop {
+            // Frames on other channels are never heard
+            while let Some((frame_channel, psdu)) = queues[self.id].frames.pop_front() {
+                if frame_channel == channel {
+                    return Ok(Some(Received {
+                        psdu,
+                        lqi: LQI,
+                        crc_ok: true,
+                    }));
+                }
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            queues = frame_sent.wait_timeout(queues, deadline - now).unwrap().0;
+        }
+    }
+}
+
+/// The multicast group for [`UdpLink`]
+pub const UDP_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 21, 54);
+
+/// The port for [`UdpLink`]
+pub const UDP_PORT: u16 = 15440;
+
+/// Starts every datagram, so we ignore anything else on the port
+const UDP_MAGIC: [u8; 2] = *b"RS";
+
+/// Bytes before the frame in each datagram: magic, channel, sender
+const UDP_HEADER_LEN: usize = 7;
+
+/// A medium shared by the radios on one computer (or one network)
+///
+/// Each frame is a UDP multicast datagram, so simulated radios in different
+/// processes can hear each other.
+pub struct UdpLink {
+    socket: UdpSocket,
+    /// Picked at random, so we can ignore our own frames
+    id: [u8; 4],
+}
+
+impl UdpLink {
+    /// Join the medium
+    pub fn new() -> io::Result<UdpLink> {
+        use socket2::{Domain, Protocol, Socket, Type};
+
+        // Every radio on this computer listens on the same port
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, UDP_PORT).into())?;
+        let socket = UdpSocket::from(socket);
+        socket.join_multicast_v4(&UDP_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_multicast_loop_v4(true)?;
+        Ok(UdpLink {
+            socket,
+            id: rand::random(),
+        })
+    }
+}
+
+impl Link for UdpLink {
+    fn transmit(&mut self, channel: u8, psdu: &[u8]) -> io::Result<()> {
+        let mut datagram = Vec::with_capacity(UDP_HEADER_LEN + psdu.len());
+        datagram.extend_from_slice(&UDP_MAGIC);
+        datagram.push(channel);
+        datagram.extend_from_slice(&self.id);
+        datagram.extend_from_slice(psdu);
+        self.socket
+            .send_to(&datagram, SocketAddrV4::new(UDP_GROUP, UDP_PORT))?;
+        Ok(())
+    }
+
+    fn receive(&mut self, channel: u8, timeout: Duration) -> io::Result<Option<Received>> {
+        let deadline = Instant::now() + timeout;
+        let mut buffer = [0u8; 512];
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            self.socket.set_read_timeout(Some(deadline - now))?;
+            let len = match self.socket.recv(&mut buffer) {
+                Ok(len) => len,
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
+            };
+            let Some((header, psdu)) = buffer[..len].split_at_checked(UDP_HEADER_LEN) else {
+                continue;
+            };
+            let ours = header[..2] == UDP_MAGIC && header[2] == channel && header[3..] != self.id;
+            if ours && psdu.len() <= dongle_core::Frame::CAPACITY {
+                return Ok(Some(Received {
+                    psdu: psdu.to_vec(),
+                    lqi: LQI,
+                    crc_ok: true,
+                }));
+            }
+        }
+    }
+}
+
+/// What goes wrong with the frames an [`Impaired`] link receives
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Impairments {
+    /// The chance, from 0.0 to 1.0, that a frame is never heard
+    ///
+    /// Anything above 1.0 counts as 1.0, and anything below 0.0 (or NaN) as
+    /// 0.0.
+    pub loss: f64,
+    /// The chance that a frame arrives with a bit flipped, and a bad CRC,
+    /// clamped like `loss`
+    pub corruption: f64,
+    /// How long each frame takes to arrive
+    pub delay: Duration,
+    /// The LQI reported for every frame
+    pub lqi: u8,
+}
+
+impl Default for Impairments {
+    /// A perfect medium
+    fn default() -> Impairments {
+        Impairments {
+            loss: 0.0,
+            corruption: 0.0,
+            delay: Duration::ZERO,
+            lqi: LQI,
+        }
+    }
+}
+
+/// A link which loses, delays and corrupts the frames it receives
+///
+/// Impair both ends of a conversation to affect frames going both ways.
+pub struct Impaired<L> {
+    inner: L,
+    impairments: Impairments,
+    rng: Xoshiro256PlusPlus,
+    /// A frame which is still on its way: when it arrives, and its channel
+    pending: Option<(Instant, u8, Received)>,
+}
+
+impl<L: Link> Impaired<L> {
+    /// Wrap a link
+    ///
+    /// Which frames are lost or corrupted depends only on `seed`, and the
+    /// order the frames arrive in.
+    pub fn new(inner: L, impairments: Impairments, seed: u64) -> Impaired<L> {
+        Impaired {
+            inner,
+            impairments,
+            rng: Xoshiro256PlusPlus::seed_from_u64(seed),
+            pending: None,
+        }
+    }
+
+    /// Pass on, or lose, or corrupt a frame from the inner link
+    fn impair(&mut self, mut received: Received) -> Option<Received> {
+        if self.rng.random_bool(chance(self.impairments.loss)) {
+            return None;
+        }
+        if self.rng.random_bool(chance(self.impairments.corruption)) && !received.psdu.is_empty() {
+            let bit = self.rng.random_range(0..received.psdu.len() * 8);
+            received.psdu[bit / 8] ^= 1 << (bit % 8);
+            received.crc_ok = false;
+        }
+        received.lqi = self.impairments.lqi;
+        Some(received)
+    }
+}
+
+/// A probability `random_bool` won't panic on
+fn chance(p: f64) -> f64 {
+    if p.is_nan() {
+        0.0
+    } else {
+        p.clamp(0.0, 1.0)
+    }
+}
+
+impl<L: Link> Link for Impaired<L> {
+    fn transmit(&mut self, channel: u8, psdu: &[u8]) -> io::Result<()> {
+        self.inner.transmit(channel, psdu)
+    }
+
+    fn receive(&mut self, channel: u8, timeout: Duration) -> io::Result<Option<Received>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some((arrival, frame_channel, _)) = &self.pending {
+                if *frame_channel != channel {
+                    // We changed channel while it was on its way
+                    self.pending = None;
+                } else if *arrival <= deadline {
+                    std::thread::sleep(arrival.saturating_duration_since(Instant::now()));
+                    return Ok(self.pending.take().map(|(_, _, received)| received));
+                } else {
+                    std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                    return Ok(None);
+                }
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let Some(received) = self.inner.receive(channel, remaining)? else {
+                return Ok(None);
+            };
+            if let Some(received) = self.impair(received) {
+                let arrival = Instant::now() + self.impairments.delay;
+                self.pending = Some((arrival, channel, received));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bus_delivers_to_others_on_the_channel() {
+        let bus = Bus::new();
+        let mut a = bus.link();
+        let mut b = bus.link();
+        let mut c = bus.link();
+        let short = Duration::from_millis(10);
+
+        a.transmit(20, b"hello").unwrap();
+        assert_eq!(b.receive(20, short).unwrap().unwrap().psdu, b"hello");
+        assert_eq!(a.receive(20, short).unwrap(), None);
+        // c was listening on another channel
+        assert_eq!(c.receive(11, short).unwrap(), None);
+        assert_eq!(c.receive(20, short).unwrap(), None);
+    }
+
+    #[test]
+    fn bus_wakes_receivers() {
+        let bus = Bus::new();
+        let mut a = bus.link();
+        let mut b = bus.link();
+        let sender = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            a.transmit(20, b"late").unwrap();
+        });
+        let received = b.receive(20, Duration::from_secs(5)).unwrap();
+        assert_eq!(received.unwrap().psdu, b"late");
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn impaired_loses_and_corrupts() {
+        let bus = Bus::new();
+        let mut a = bus.link();
+        let lossy = Impairments {
+            loss: 1.0,
+            ..Impairments::default()
+        };
+        let mut b = Impaired::new(bus.link(), lossy, 0);
+        let noisy = Impairments {
+            corruption: 1.0,
+            lqi: 80,
+            ..Impairments::default()
+        };
+        let mut c = Impaired::new(bus.link(), noisy, 0);
+        let short = Duration::from_millis(10);
+
+        a.transmit(20, b"hello").unwrap();
+        assert_eq!(b.receive(20, short).unwrap(), None);
+        let received = c.receive(20, short).unwrap().unwrap();
+        assert!(!received.crc_ok);
+        assert_eq!(received.lqi, 80);
+        // exactly one bit is wrong
+        let flipped: u32 = received
+            .psdu
+            .iter()
+            .zip(b"hello")
+            .map(|(x, y)| (x ^ y).count_ones())
+            .sum();
+        assert_eq!(flipped, 1);
+    }
+
+    #[test]
+    fn impairments_out_of_range() {
+        let bus = Bus::new();
+        let mut a = bus.link();
+        let silly = Impairments {
+            loss: f64::NAN,
+            corruption: 2.0,
+            ..Impairments::default()
+        };
+        let mut b = Impaired::new(bus.link(), silly, 0);
+        let never = Impairments {
+            loss: -1.0,
+            corruption: f64::NAN,
+            ..Impairments::default()
+        };
+        let mut c = Impaired::new(bus.link(), never, 0);
+        let short = Duration::from_millis(10);
+
+        a.transmit(20, b"hello").unwrap();
+        assert!(!b.receive(20, short).unwrap().unwrap().crc_ok);
+        assert!(c.receive(20, short).unwrap().unwrap().crc_ok);
+    }
+
+    #[test]
+    fn impaired_delays() {
+        let bus = Bus::new();
+        let mut a = bus.link();
+        let slow = Impairments {
+            delay: Duration::from_millis(50),
+            ..Impairments::default()
+        };
+        let mut b = Impaired::new(bus.link(), slow, 0);
+
+        a.transmit(20, b"hello").unwrap();
+        // still on its way
+        assert_eq!(b.receive(20, Duration::from_millis(10)).unwrap(), None);
+        let received = b.receive(20, Duration::from_secs(5)).unwrap().unwrap();
+        assert_eq!(received.psdu, b"hello");
+        assert!(received.crc_ok);
+    }
+}
@@ -0,0 +1,219 @@
+//! Solve the puzzle against a simulated Dongle, the way a student's DK would
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use consts::puzzle::{ADDR_BYTES, FRAGMENT_REQUEST, PLAIN_LETTERS};
+use consts::{hid, relay};
+use dongle_core::{AppMode, Counters};
+use dongle_sim::medium::{Bus, BusLink, Impaired, Impairments, Link};
+use dongle_sim::{Dongle, Secret, DEFAULT_CHANNEL, DEVICE_ID};
+
+const ADDR: [u8; ADDR_BYTES] = *b"\x11\x22\x33\x44\x55\x66";
+
+/// Collects the Dongle's log output
+#[derive(Clone, Default)]
+struct Log(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Log {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Log {
+    fn text(&self) -> String {
+        String::from_utf8(self.bytes()).unwrap()
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Send a request, and return the payload of the reply
+fn send_recv(link: &mut BusLink, payload: &[u8]) -> Vec<u8> {
+    let mut request = ADDR.to_vec();
+    request.extend_from_slice(payload);
+    link.transmit(DEFAULT_CHANNEL, &request).unwrap();
+    let reply = link
+        .receive(DEFAULT_CHANNEL, Duration::from_secs(5))
+        .unwrap()
+        .expect("no reply");
+    assert_eq!(reply.psdu[..ADDR_BYTES], ADDR);
+    reply.psdu[ADDR_BYTES..].to_vec()
+}
+
+/// Ask the Dongle how to decode each letter
+fn decipher_table(link: &mut BusLink) -> HashMap<u8, u8> {
+    PLAIN_LETTERS
+        .bytes()
+        .map(|plain| {
+            let cipher = send_recv(link, &[plain]);
+            (cipher[0], plain)
+        })
+        .collect()
+}
+
+#[test]
+fn solve_puzzle() {
+    let bus = Bus::new();
+    let log = Log::default();
+    let secret = Secret::new("Hello, world!", 7).unwrap();
+    let dongle = Dongle::new(bus.link(), secret, log.clone()).spawn();
+    let mut client = bus.link();
+
+    let encoded = send_recv(&mut client, &[]);
+    let decipher = decipher_table(&mut client);
+    let plaintext: Vec<u8> = encoded.iter().map(|c| decipher[c]).collect();
+    assert_eq!(plaintext, b"Hello, world!");
+
+    assert_eq!(send_recv(&mut client, b"Hello, world?"), b"incorrect");
+    assert_eq!(send_recv(&mut client, &plaintext), b"correct");
+
+    let dongle = dongle.stop().unwrap();
+    assert_eq!(dongle.counters.rx_count, 3 + PLAIN_LETTERS.len() as u32);
+    assert!(log.text().contains("TX Correct"));
+}
+
+#[test]
+fn solve_fragmented_puzzle() {
+    let message = "The quick brown fox jumps over the lazy dog. ".repeat(6);
+    let bus = Bus::new();
+    let secret = Secret::new(&message, 1).unwrap();
+    let dongle = Dongle::new(bus.link(), secret, std::io::sink()).spawn();
+    let mut client = bus.link();
+
+    let first = send_recv(&mut client, &[]);
+    let (index, count) = (first[0], first[1]);
+    assert_eq!((index, count), (0, 3));
+
+    let decipher = decipher_table(&mut client);
+    let mut plaintext = Vec::new();
+    for index in 0..count {
+        let fragment = send_recv(&mut client, &[FRAGMENT_REQUEST, index]);
+        assert_eq!(fragment[..2], [index, count]);
+        let decoded: Vec<u8> = fragment[2..].iter().map(|c| decipher[c]).collect();
+
+        let mut guess = vec![FRAGMENT_REQUEST, index];
+        guess.extend_from_slice(&decoded);
+        assert_eq!(send_recv(&mut client, &guess), b"correct");
+        plaintext.extend(decoded);
+    }
+    assert_eq!(plaintext, message.as_bytes());
+
+    dongle.stop().unwrap();
+}
+
+#[test]
+fn loopback() {
+    let bus = Bus::new();
+    let secret = Secret::new("unused", 0).unwrap();
+    let mut dongle = Dongle::new(bus.link(), secret, std::io::sink());
+    dongle.set_mode(AppMode::Loopback);
+    assert_eq!(dongle.rgb_led(), (false, true, false));
+    let dongle = dongle.spawn();
+
+    let mut client = bus.link();
+    client.transmit(DEFAULT_CHANNEL, b"olleh").unwrap();
+    let reply = client
+        .receive(DEFAULT_CHANNEL, Duration::from_secs(5))
+        .unwrap()
+        .expect("no reply");
+    assert_eq!(reply.psdu, b"hello");
+
+    dongle.stop().unwrap();
+}
+
+#[test]
+fn hid_requests() {
+    let bus = Bus::new();
+    let secret = Secret::new("unused", 0).unwrap();
+    let mut dongle = Dongle::new(bus.link(), secret, std::io::sink());
+
+    let response = dongle.hid_request(1, hid::Request::SetChannel(25));
+    assert_eq!(response.status, hid::Status::Ok);
+    assert_eq!(response.payload(), [25]);
+    let response = dongle.hid_request(2, hid::Request::SetMode(hid::Mode::Loopback));
+    assert_eq!(response.seq, 2);
+    assert_eq!(dongle.rgb_led(), (false, true, false));
+    let response = dongle.hid_request(3, hid::Request::GetInfo);
+    let info = hid::Info::from_bytes(response.payload()).unwrap();
+    assert_eq!(info.device_id, DEVICE_ID);
+    assert_eq!((info.channel, info.mode), (25, hid::Mode::Loopback));
+
+    let dongle = dongle.spawn();
+    let mut client = bus.link();
+    client.transmit(25, b"olleh").unwrap();
+    let reply = client
+        .receive(25, Duration::from_secs(5))
+        .unwrap()
+        .expect("no reply on the new channel");
+    assert_eq!(reply.psdu, b"hello");
+
+    let mut dongle = dongle.stop().unwrap();
+    let response = dongle.hid_request(4, hid::Request::GetStats);
+    let stats = hid::Stats::from_bytes(response.payload()).unwrap();
+    assert_eq!((stats.rx_count, stats.err_count), (1, 0));
+}
+
+#[test]
+fn relay_passes_frames_to_the_host() {
+    let bus = Bus::new();
+    let log = Log::default();
+    let secret = Secret::new("unused", 0).unwrap();
+    let mut dongle = Dongle::new(bus.link(), secret, log.clone());
+    dongle.set_mode(AppMode::Relay);
+
+    let mut client = bus.link();
+    client.transmit(DEFAULT_CHANNEL, b"hello").unwrap();
+    assert!(dongle.poll(Duration::from_secs(5)).unwrap());
+
+    let mut decoder = relay::Decoder::new();
+    let frames: Vec<Vec<u8>> = log
+        .bytes()
+        .into_iter()
+        .filter_map(|byte| decoder.push(byte).map(<[u8]>::to_vec))
+        .collect();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(
+        relay::Frame::parse(&frames[0]),
+        Some(relay::Frame::Received {
+            lqi: dongle_sim::medium::LQI,
+            psdu: b"hello"
+        })
+    );
+}
+
+#[test]
+fn crc_errors_are_marked_except_in_relay_mode() {
+    let bus = Bus::new();
+    let log = Log::default();
+    let secret = Secret::new("unused", 0).unwrap();
+    let corrupt = Impairments {
+        corruption: 1.0,
+        ..Impairments::default()
+    };
+    let mut dongle = Dongle::new(Impaired::new(bus.link(), corrupt, 0), secret, log.clone());
+
+    let mut client = bus.link();
+    for mode in [AppMode::Puzzle, AppMode::Relay, AppMode::Traffic] {
+        dongle.set_mode(mode);
+        client.transmit(DEFAULT_CHANNEL, b"hello").unwrap();
+        assert!(dongle.poll(Duration::from_secs(5)).unwrap());
+    }
+    assert_eq!(log.text(), "!!");
+    assert_eq!(
+        dongle.counters,
+        Counters {
+            rx_count: 0,
+            err_count: 3
+        }
+    );
+}
@@ -0,0 +1,39 @@
+#![deny(unused_must_use)]
+#![no_main]
+#![no_std]
+
+use core::time::Duration;
+
+use cortex_m_rt::entry;
+use dk::ble::{flags, AdBuilder, Advertisement, Ble, TEST_COMPANY_ID};
+// this imports `src/lib.rs`to retrieve our global logger + panicking-behavior
+use radio_app as _;
+
+#[entry]
+fn main() -> ! {
+    let board = dk::init().unwrap();
+    let mut timer = board.timer;
+    let mut ble = Ble::new(board.radio);
+
+    let address = dk::ble::device_address();
+    defmt::println!("advertising as {=[u8]:02x}", address);
+
+    // what we tell the world: our name, and how far through the puzzle we are
+    let mut data = [0; dk::ble::MAX_DATA_LEN];
+    let mut builder = AdBuilder::new(&mut data);
+    builder
+        .flags(flags::BR_EDR_NOT_SUPPORTED)
+        .unwrap()
+        .complete_local_name("dk")
+        .unwrap()
+        .manufacturer_specific_data(TEST_COMPANY_ID, b"puzzle 0/5")
+        .unwrap();
+    let advertisement = Advertisement::non_connectable(address, builder.as_bytes());
+
+    for _ in 0..100 {
+        ble.advertise(&advertisement).unwrap();
+        timer.wait(Duration::from_millis(100));
+    }
+
+    dk::exit()
+}
@@ -48,6 +48,8 @@ fn main() -> ! {
         }
         Err(Error::Crc(crc)) => defmt::error!("invalid CRC: {:X}", crc),
         Err(Error::Timeout) => defmt::error!("no response within {} ms", TEN_MS / 1_000),
+        // only `send_with_ack` waits for an ACK, or needs a MAC frame
+        Err(Error::NoAck | Error::NotMacFrame) => defmt::unreachable!(),
     }
 
     dk::exit()
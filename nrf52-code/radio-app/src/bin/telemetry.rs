@@ -0,0 +1,50 @@
+#![deny(unused_must_use)]
+#![no_main]
+#![no_std]
+
+use core::fmt::Write;
+use core::time::Duration;
+
+use cortex_m_rt::entry;
+use dk::radio::{Channel, Packet};
+use dk::sensors::COIN_CELL;
+// this imports `src/lib.rs`to retrieve our global logger + panicking-behavior
+use radio_app as _;
+
+#[entry]
+fn main() -> ! {
+    let board = dk::init().unwrap();
+    let mut radio = board.radio;
+    let mut timer = board.timer;
+    let mut sensors = board.sensors;
+
+    // NOTE if you ran `change-channel` then you may need to update the channel here
+    radio.set_channel(Channel::_20); // <- must match the Dongle's listening channel
+
+    let mut packet = Packet::new();
+    for _ in 0..10 {
+        let centidegrees = sensors.temp.read();
+        let millivolts = sensors.vdd.millivolts();
+        let percent = dk::sensors::battery_percent(millivolts, COIN_CELL);
+
+        let mut msg = heapless::String::<64>::new();
+        write!(
+            msg,
+            "{}{}.{:02} C, {} mV ({}%)",
+            if centidegrees < 0 { "-" } else { "" },
+            centidegrees.abs() / 100,
+            centidegrees.abs() % 100,
+            millivolts,
+            percent
+        )
+        .expect("message too long");
+        defmt::println!("sending: {}", msg.as_str());
+
+        packet.copy_from_slice(msg.as_bytes());
+        radio.send(&mut packet);
+
+        timer.wait(Duration::from_secs(1));
+    }
+
+    dk::exit();
+}
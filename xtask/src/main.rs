@@ -9,11 +9,27 @@ fn main() -> color_eyre::Result<()> {
 
     // first arg is the name of the executable; skip it
     let args = env::args().skip(1).collect::<Vec<_>>();
-    let args = args.iter().map(|arg| &arg[..]).collect::<Vec<_>>();
+    let mut args = args.iter().map(|arg| &arg[..]).collect::<Vec<_>>();
+
+    // `--serial SERIAL` picks one Dongle, when more than one is connected
+    let mut serial = None;
+    if let Some(idx) = args.iter().position(|arg| *arg == "--serial") {
+        if idx + 1 < args.len() {
+            serial = Some(args.remove(idx + 1));
+            args.remove(idx);
+        }
+    }
 
     match &args[..] {
-        ["change-channel", channel] => tasks::change_channel(channel),
-        ["serial-term"] => tasks::serial_term(),
+        ["change-channel", channel] => tasks::change_channel(channel, serial),
+        ["defmt-log", elf] => tasks::defmt_log(elf, None, serial),
+        ["defmt-log", elf, port] => tasks::defmt_log(elf, Some(port), serial),
+        ["dongle-info"] => tasks::dongle_info(serial),
+        ["relay"] => tasks::relay(serial),
+        ["serial-term"] => tasks::serial_term(serial),
+        ["set-mode", mode] => tasks::set_mode(mode, serial),
+        ["set-verbosity", verbosity] => tasks::set_verbosity(verbosity, serial),
+        ["traffic", args @ ..] => tasks::traffic(args, serial),
         ["usb-descriptors"] => tasks::usb_descriptors(),
         ["usb-list"] => tasks::usb_list(),
         _ => {
@@ -22,13 +38,28 @@ fn main() -> color_eyre::Result<()> {
 Workshop-specific tools
 
 USAGE:
-    cargo xtask [COMMAND]
+    cargo xtask [COMMAND] [--serial SERIAL]
 
 COMMANDS:
     change-channel [NUMBER]  change the nRF Dongle to a different radio channel (NUMBER is 11..=26)
+    defmt-log [ELF] [PORT]   decode the defmt logs that firmware built from ELF sends to a serial
+                             port, with the `log-uart` or `log-queue` feature of `dk` (PORT is
+                             the J-Link's virtual COM port, say; without it, we wait for a
+                             workshop USB device)
+    dongle-info              print the firmware version, settings and counters of the Dongle
+    relay                    use the Dongle as a radio modem: send and receive raw frames
     serial-term              displays the log output of the Dongle
+    set-mode [MODE]          switch the Dongle to `loopback`, `puzzle`, `relay` or `traffic` mode
+    set-verbosity [LEVEL]    how much the Dongle logs: `quiet`, `normal` or `verbose`
+    traffic [LEN] [MS]       make the Dongle send LEN byte frames every MS milliseconds
+                             (add `--no-cca` to skip Clear Channel Assessment, or
+                             `--counter` to send a counter instead of random bytes)
     usb-descriptors          print the USB descriptors for VID {vid:04x} PID {pid:04x}
     usb-list                 list all connected USB devices; highlights workshop devices
+
+OPTIONS:
+    --serial SERIAL          which Dongle to talk to, if more than one is connected
+                             (see `usb-list` for the serial numbers)
     
 ",
                 vid = consts::USB_VID_DEMO,
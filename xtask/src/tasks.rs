@@ -1,59 +1,206 @@
 use std::{
     io::{self, Write as _},
-    sync::atomic::{AtomicBool, Ordering},
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
     time::Duration,
 };
 
 use color_eyre::eyre::{anyhow, bail};
-use hidapi::{HidApi, HidError};
-use serialport::SerialPortType;
+use consts::{hid, relay};
+use hidapi::{HidApi, HidDevice, HidError};
+use serialport::{SerialPortInfo, SerialPortType};
 
-pub fn change_channel(channel: &str) -> color_eyre::Result<()> {
+/// How long we wait for the Dongle to answer a HID request
+const HID_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Find the Dongle running `dongle-fw` and open its HID interface
+///
+/// If `serial` is given, only the Dongle with that USB serial number will do.
+/// Otherwise there must be exactly one Dongle connected.
+fn open_dongle(serial: Option<&str>) -> color_eyre::Result<HidDevice> {
     fn check_pid(pid: u16) -> bool {
         pid == consts::USB_PID_DONGLE_UNIFIED
     }
 
     let api = HidApi::new()?;
 
+    let mut dongles = api
+        .device_list()
+        .filter(|d| d.vendor_id() == consts::USB_VID_DEMO && check_pid(d.product_id()))
+        .filter(|d| serial.is_none() || d.serial_number() == serial)
+        .collect::<Vec<_>>();
+    // Some platforms list the same device more than once
+    dongles.sort_by_key(|d| d.serial_number());
+    dongles.dedup_by_key(|d| d.serial_number());
+    let dongle = match &dongles[..] {
+        [] => bail!("device not found"),
+        [dongle] => dongle,
+        _ => {
+            let serials = dongles
+                .iter()
+                .map(|d| d.serial_number().unwrap_or("(none)"))
+                .collect::<Vec<_>>();
+            bail!(
+                "found more than one Dongle, pick one with `--serial SERIAL` (serial numbers: {})",
+                serials.join(", ")
+            )
+        }
+    };
+
     let permission_error =
         anyhow!("permission denied, check the nRF52 Tools chapter for dongle setup instructions");
-    let dev = api
-        .device_list()
-        .find(|d| d.vendor_id() == consts::USB_VID_DEMO && check_pid(d.product_id()))
-        .ok_or_else(|| anyhow!("device not found"))?
-        .open_device(&api)
-        .map_err(|e| match e {
-            HidError::HidApiError { ref message } => {
-                if message.to_lowercase().contains("permission denied") {
-                    return permission_error;
-                }
-                e.into()
+    let dev = dongle.open_device(&api).map_err(|e| match e {
+        HidError::HidApiError { ref message } => {
+            if message.to_lowercase().contains("permission denied") {
+                return permission_error;
             }
-            HidError::IoError { error } if error.kind() == io::ErrorKind::PermissionDenied => {
-                permission_error
-            }
-            other => other.into(),
-        })?;
+            e.into()
+        }
+        HidError::IoError { error } if error.kind() == io::ErrorKind::PermissionDenied => {
+            permission_error
+        }
+        other => other.into(),
+    })?;
+
+    Ok(dev)
+}
+
+/// Send a request to the Dongle and wait for its reply
+///
+/// Fails if the Dongle does not reply, or replies with anything other than
+/// [`hid::Status::Ok`].
+fn hid_request(dev: &HidDevice, request: hid::Request) -> color_eyre::Result<hid::Response> {
+    static SEQ: AtomicU8 = AtomicU8::new(0);
+    const REPORT_ID: u8 = 0;
+
+    let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+    let mut buf = vec![REPORT_ID];
+    buf.extend_from_slice(&request.to_report(seq));
+    dev.write(&buf)?;
+
+    let mut report = [0u8; hid::REPORT_LEN];
+    loop {
+        let n = dev.read_timeout(&mut report, HID_TIMEOUT.as_millis() as i32)?;
+        if n == 0 {
+            bail!("no reply from the Dongle; it may be running an old version of dongle-fw");
+        }
+        let response =
+            hid::Response::parse(&report[..n]).map_err(|e| anyhow!("bad reply: {:?}", e))?;
+        if response.seq != seq {
+            // left over from an earlier request
+            continue;
+        }
+        if response.status != hid::Status::Ok {
+            bail!("the Dongle rejected {:?}: {:?}", request, response.status);
+        }
+        return Ok(response);
+    }
+}
 
+pub fn change_channel(channel: &str, serial: Option<&str>) -> color_eyre::Result<()> {
     let chan = channel.parse::<u8>()?;
-    if !(11..=26).contains(&chan) {
+    if !hid::CHANNELS.contains(&chan) {
         bail!("channel is out of range (`11..=26`)")
     }
-    const REPORT_ID: u8 = 0;
-    dev.write(&[REPORT_ID, chan])?;
+
+    let dev = open_dongle(serial)?;
     println!("requested channel change to channel {}", chan);
+    let response = hid_request(&dev, hid::Request::SetChannel(chan))?;
+    let chan = response
+        .payload()
+        .first()
+        .ok_or_else(|| anyhow!("empty SetChannel reply"))?;
+    println!("now listening on channel {}", chan);
+
+    Ok(())
+}
+
+pub fn set_mode(mode: &str, serial: Option<&str>) -> color_eyre::Result<()> {
+    let mode = match mode {
+        "loopback" => hid::Mode::Loopback,
+        "puzzle" => hid::Mode::Puzzle,
+        "relay" => hid::Mode::Relay,
+        "traffic" => hid::Mode::Traffic,
+        _ => bail!(
+            "unknown mode {:?} (expected `loopback`, `puzzle`, `relay` or `traffic`)",
+            mode
+        ),
+    };
+
+    let dev = open_dongle(serial)?;
+    hid_request(&dev, hid::Request::SetMode(mode))?;
+    println!("Dongle is now in {:?} mode", mode);
+
+    Ok(())
+}
+
+pub fn set_verbosity(verbosity: &str, serial: Option<&str>) -> color_eyre::Result<()> {
+    let verbosity = match verbosity {
+        "quiet" => hid::Verbosity::Quiet,
+        "normal" => hid::Verbosity::Normal,
+        "verbose" => hid::Verbosity::Verbose,
+        _ => bail!(
+            "unknown verbosity {:?} (expected `quiet`, `normal` or `verbose`)",
+            verbosity
+        ),
+    };
+
+    let dev = open_dongle(serial)?;
+    hid_request(&dev, hid::Request::SetVerbosity(verbosity))?;
+    println!("Dongle logging is now {:?}", verbosity);
+
+    Ok(())
+}
+
+pub fn dongle_info(serial: Option<&str>) -> color_eyre::Result<()> {
+    let dev = open_dongle(serial)?;
+
+    let response = hid_request(&dev, hid::Request::GetVersion)?;
+    let version = hid::Version::from_bytes(response.payload())
+        .ok_or_else(|| anyhow!("short GetVersion reply"))?;
+    let response = hid_request(&dev, hid::Request::GetInfo)?;
+    let info =
+        hid::Info::from_bytes(response.payload()).ok_or_else(|| anyhow!("short GetInfo reply"))?;
+    let response = hid_request(&dev, hid::Request::GetStats)?;
+    let stats = hid::Stats::from_bytes(response.payload())
+        .ok_or_else(|| anyhow!("short GetStats reply"))?;
+    let build_info = dongle_build_info(&dev)?;
+
+    println!(
+        "dongle-fw {}.{}.{} (protocol version {})",
+        version.firmware[0], version.firmware[1], version.firmware[2], version.protocol
+    );
+    println!("{}", describe_build(&build_info));
+    println!("deviceid={:016x}", info.device_id);
+    println!("channel={} mode={:?}", info.channel, info.mode);
+    println!("rx={} err={}", stats.rx_count, stats.err_count);
+    println!(
+        "log_dropped={} log_truncated={}",
+        stats.log_bytes_dropped, stats.log_lines_truncated
+    );
+    println!(
+        "traffic_sent={} traffic_cca_busy={}",
+        stats.traffic_sent, stats.traffic_cca_busy
+    );
+    println!(
+        "latency_avg={}us latency_max={}us",
+        stats.latency_avg_us, stats.latency_max_us
+    );
 
     Ok(())
 }
 
-pub fn serial_term() -> color_eyre::Result<()> {
+/// Wait for the USB serial port of the Dongle to show up
+fn find_serial_port(serial: Option<&str>) -> color_eyre::Result<SerialPortInfo> {
     let mut once = true;
     let dongle = loop {
         if let Some(dongle) =
             serialport::available_ports()?
                 .into_iter()
                 .find(|info| match &info.port_type {
-                    SerialPortType::UsbPort(usb) => usb.vid == consts::USB_VID_DEMO,
+                    SerialPortType::UsbPort(usb) => {
+                        usb.vid == consts::USB_VID_DEMO
+                            && (serial.is_none() || usb.serial_number.as_deref() == serial)
+                    }
                     _ => false,
                 })
         {
@@ -64,6 +211,11 @@ pub fn serial_term() -> color_eyre::Result<()> {
             eprintln!("(waiting for the Dongle to be connected)");
         }
     };
+    Ok(dongle)
+}
+
+pub fn serial_term(serial: Option<&str>) -> color_eyre::Result<()> {
+    let dongle = find_serial_port(serial)?;
 
     let mut port = serialport::new(&dongle.port_name, 115200).open()?;
     port.set_timeout(Duration::from_millis(10))?;
@@ -99,21 +251,315 @@ pub fn serial_term() -> color_eyre::Result<()> {
     Ok(())
 }
 
+/// Decode defmt log frames from a serial port, with the ELF file of the
+/// firmware that sent them
+///
+/// Without a `port`, we wait for a workshop device's USB serial port, like
+/// `serial-term` does.
+pub fn defmt_log(elf: &str, port: Option<&str>, serial: Option<&str>) -> color_eyre::Result<()> {
+    let bytes = std::fs::read(elf)?;
+    let table = defmt_decoder::Table::parse(&bytes)
+        .map_err(|e| anyhow!("{e}"))?
+        .ok_or_else(|| anyhow!("{elf} has no defmt data in it; was it built with defmt?"))?;
+    let locations = table.get_locations(&bytes).map_err(|e| anyhow!("{e}"))?;
+
+    let port_name = match port {
+        Some(port) => port.to_string(),
+        None => find_serial_port(serial)?.port_name,
+    };
+    let mut port = serialport::new(&port_name, 115200).open()?;
+    port.set_timeout(Duration::from_millis(10))?;
+
+    static CONTINUE: AtomicBool = AtomicBool::new(true);
+
+    // properly close the serial device on Ctrl-C
+    ctrlc::set_handler(|| CONTINUE.store(false, Ordering::Relaxed))?;
+
+    eprintln!("(decoding defmt logs from {port_name}, Ctrl-C to stop)");
+    // rzCOBS frames end in a zero byte, so a bad one can be skipped
+    let can_recover = table.encoding().can_recover();
+    let mut decoder = table.new_stream_decoder();
+    while CONTINUE.load(Ordering::Relaxed) {
+        let mut read_buf = [0u8; 64];
+        match port.read(&mut read_buf) {
+            Ok(n) => decoder.received(&read_buf[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                // Go around
+                continue;
+            }
+            Err(e) => {
+                println!("Error: {:?}", e);
+                break;
+            }
+        }
+
+        loop {
+            match decoder.decode() {
+                Ok(frame) => {
+                    println!("{}", frame.display(true));
+                    if let Some(location) = locations.get(&frame.index()) {
+                        println!(
+                            "└─ {} @ {}:{}",
+                            location.module,
+                            location.file.display(),
+                            location.line
+                        );
+                    }
+                }
+                Err(defmt_decoder::DecodeError::UnexpectedEof) => break,
+                Err(defmt_decoder::DecodeError::Malformed) if can_recover => {
+                    // we started listening half way through a frame, or the
+                    // firmware doesn't match the ELF file. The decoder has
+                    // dropped everything up to the next frame boundary.
+                    eprintln!("(skipped a frame which didn't decode)");
+                }
+                Err(defmt_decoder::DecodeError::Malformed) => {
+                    // with no frame boundaries we can't find the next frame
+                    bail!("a frame didn't decode, and {elf}'s defmt encoding can't skip it");
+                }
+            }
+        }
+    }
+
+    eprintln!("(closing the serial port)");
+    Ok(())
+}
+
+/// Make the Dongle transmit a stream of frames, and report how that goes
+///
+/// `args` are the frame length and interval in milliseconds, optionally
+/// followed by `--no-cca` and/or `--counter`.
+pub fn traffic(args: &[&str], serial: Option<&str>) -> color_eyre::Result<()> {
+    let mut config = hid::TrafficConfig::default();
+    let mut numbers = Vec::new();
+    for arg in args {
+        match *arg {
+            "--no-cca" => config.cca = false,
+            "--counter" => config.random_payload = false,
+            number => numbers.push(number),
+        }
+    }
+    match numbers[..] {
+        [] => {}
+        [len] => config.len = len.parse()?,
+        [len, interval_ms] => {
+            config.len = len.parse()?;
+            config.interval_ms = interval_ms.parse()?;
+        }
+        _ => bail!("expected at most a frame length and an interval"),
+    }
+    if !hid::TrafficConfig::FRAME_LENS.contains(&config.len) {
+        bail!(
+            "frame length must be in the range {:?}",
+            hid::TrafficConfig::FRAME_LENS
+        );
+    }
+
+    let dev = open_dongle(serial)?;
+    hid_request(&dev, hid::Request::SetTraffic(config))?;
+    hid_request(&dev, hid::Request::SetMode(hid::Mode::Traffic))?;
+    println!(
+        "sending {} byte frames every {} ms (random payload: {}, CCA: {}); Ctrl-C to stop",
+        config.len, config.interval_ms, config.random_payload, config.cca
+    );
+
+    static CONTINUE: AtomicBool = AtomicBool::new(true);
+    ctrlc::set_handler(|| CONTINUE.store(false, Ordering::Relaxed))?;
+
+    let stats = |dev| -> color_eyre::Result<hid::Stats> {
+        let response = hid_request(dev, hid::Request::GetStats)?;
+        hid::Stats::from_bytes(response.payload()).ok_or_else(|| anyhow!("short GetStats reply"))
+    };
+    let start = stats(&dev)?;
+    while CONTINUE.load(Ordering::Relaxed) {
+        std::thread::sleep(Duration::from_secs(1));
+        let now = stats(&dev)?;
+        println!(
+            "sent={} cca_busy={} rx={}",
+            now.traffic_sent.wrapping_sub(start.traffic_sent),
+            now.traffic_cca_busy.wrapping_sub(start.traffic_cca_busy),
+            now.rx_count.wrapping_sub(start.rx_count)
+        );
+    }
+
+    eprintln!("(stopping, back to loopback mode)");
+    hid_request(&dev, hid::Request::SetMode(hid::Mode::Loopback))?;
+    Ok(())
+}
+
+/// Use the Dongle as a radio modem
+///
+/// Lines of hex typed on stdin are transmitted; frames received are printed.
+pub fn relay(serial: Option<&str>) -> color_eyre::Result<()> {
+    let dev = open_dongle(serial)?;
+    hid_request(&dev, hid::Request::SetMode(hid::Mode::Relay))?;
+
+    let dongle = find_serial_port(serial)?;
+    let mut port = serialport::new(&dongle.port_name, 115200).open()?;
+    port.set_timeout(Duration::from_millis(10))?;
+    let mut tx_port = port.try_clone()?;
+
+    static CONTINUE: AtomicBool = AtomicBool::new(true);
+
+    // properly close the serial device on Ctrl-C
+    ctrlc::set_handler(|| CONTINUE.store(false, Ordering::Relaxed))?;
+
+    eprintln!("(relaying; type frames to send as hex, e.g. `41 88 00 ff ff`, Ctrl-C to stop)");
+    std::thread::spawn(move || {
+        for line in io::stdin().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            let psdu = match parse_hex(&line) {
+                Ok(psdu) if psdu.is_empty() => continue,
+                Ok(psdu) if psdu.len() > relay::MAX_PSDU_LEN => {
+                    eprintln!("frame too long (max {} bytes)", relay::MAX_PSDU_LEN);
+                    continue;
+                }
+                Ok(psdu) => psdu,
+                Err(e) => {
+                    eprintln!("{e}");
+                    continue;
+                }
+            };
+            let mut encoded = [0u8; relay::MAX_ENCODED_LEN];
+            if let Some(len) = relay::Frame::Transmit(&psdu).encode(&mut encoded) {
+                if let Err(e) = tx_port.write_all(&encoded[..len]) {
+                    eprintln!("Error: {:?}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    let mut decoder = relay::Decoder::new();
+    while CONTINUE.load(Ordering::Relaxed) {
+        let mut read_buf = [0u8; 64];
+        match port.read(&mut read_buf) {
+            Ok(n) => {
+                for b in &read_buf[..n] {
+                    // Anything else on the port (like log text) is ignored
+                    match decoder.push(*b).and_then(relay::Frame::parse) {
+                        Some(relay::Frame::Received { lqi, psdu }) => {
+                            println!("rx lqi={lqi:3} len={:3}: {:02x?}", psdu.len(), psdu);
+                        }
+                        Some(relay::Frame::Sent { ok: true }) => println!("tx ok"),
+                        Some(relay::Frame::Sent { ok: false }) => {
+                            println!("tx failed, channel busy")
+                        }
+                        Some(relay::Frame::Transmit(_)) | None => {}
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+                // Go around
+            }
+            Err(e) => {
+                println!("Error: {:?}", e);
+                break;
+            }
+        }
+    }
+
+    eprintln!("(closing the serial port, back to loopback mode)");
+    hid_request(&dev, hid::Request::SetMode(hid::Mode::Loopback))?;
+    Ok(())
+}
+
+/// Parse bytes written in hex, with or without spaces between them
+fn parse_hex(line: &str) -> color_eyre::Result<Vec<u8>> {
+    let digits = line.split_whitespace().collect::<String>();
+    // slicing two bytes at a time only lands on char boundaries in ASCII
+    if !digits.is_ascii() {
+        bail!("not hex: {:?}", line.trim());
+    }
+    if digits.len() % 2 != 0 {
+        bail!("odd number of hex digits in {:?}", line.trim());
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| anyhow!("bad hex byte {:?}", &digits[i..i + 2]))
+        })
+        .collect()
+}
+
+fn dongle_build_info(dev: &HidDevice) -> color_eyre::Result<hid::BuildInfo> {
+    let response = hid_request(dev, hid::Request::GetBuildInfo)?;
+    hid::BuildInfo::from_bytes(response.payload())
+        .ok_or_else(|| anyhow!("short GetBuildInfo reply"))
+}
+
+/// One line summary of a [`hid::BuildInfo`]
+fn describe_build(build_info: &hid::BuildInfo) -> String {
+    let flag = |flag, yes, no| {
+        if build_info.flags & flag != 0 {
+            yes
+        } else {
+            no
+        }
+    };
+    format!(
+        "rev={}{} built={} features={} secret={:08x}",
+        build_info.git_rev(),
+        flag(hid::BuildInfo::FLAG_DIRTY, "-dirty", ""),
+        format_unix_time(build_info.timestamp),
+        flag(hid::BuildInfo::FLAG_DK, "dk", "dongle"),
+        build_info.secret_hash
+    )
+}
+
+/// Formats seconds since the Unix epoch as a UTC date and time
+fn format_unix_time(timestamp: u64) -> String {
+    let days = (timestamp / 86_400) as i64;
+    let secs = timestamp % 86_400;
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60
+    )
+}
+
 /// List all the USB VIDs and PIDs, and highlight any we know about
 pub fn usb_list() -> color_eyre::Result<()> {
     for dev in nusb::list_devices()? {
+        let is_dongle_fw = (dev.vendor_id(), dev.product_id())
+            == (consts::USB_VID_DEMO, consts::USB_PID_DONGLE_UNIFIED);
         let suffix = match (dev.vendor_id(), dev.product_id()) {
             (0x1366, pid) if (pid >> 8) == 0x10 || (pid >> 8) == 0x01 => {
-                " <- J-Link on the nRF52840 Development Kit"
+                " <- J-Link on the nRF52840 Development Kit".to_owned()
             }
-            (0x1915, 0x521f) => " <- nRF52840 Dongle (in bootloader mode)",
+            (0x1915, 0x521f) => " <- nRF52840 Dongle (in bootloader mode)".to_owned(),
             (consts::USB_VID_DEMO, consts::USB_PID_DONGLE_UNIFIED) => {
-                " <- nRF52840 Dongle (dongle-fw)"
+                // bcdDevice holds the firmware version as 0xJJMN
+                let version = dev.device_version();
+                format!(
+                    " <- nRF52840 Dongle (dongle-fw {}.{}.{}, serial {})",
+                    (version >> 12) * 10 + ((version >> 8) & 0xF),
+                    (version >> 4) & 0xF,
+                    version & 0xF,
+                    dev.serial_number().unwrap_or("unknown")
+                )
             }
             (consts::USB_VID_DEMO, consts::USB_PID_RTIC_DEMO) => {
-                " <- nRF52840 on the nRF52840 Development Kit"
+                " <- nRF52840 on the nRF52840 Development Kit".to_owned()
             }
-            _ => "",
+            _ => String::new(),
         };
 
         println!(
@@ -124,6 +570,15 @@ pub fn usb_list() -> color_eyre::Result<()> {
             dev.product_id(),
             suffix
         );
+
+        if is_dongle_fw {
+            // Older firmware can't tell us this, so don't complain if it fails
+            if let Ok(build_info) =
+                open_dongle(dev.serial_number()).and_then(|hid| dongle_build_info(&hid))
+            {
+                println!("    {}", describe_build(&build_info));
+            }
+        }
     }
 
     Ok(())